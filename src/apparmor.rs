@@ -0,0 +1,162 @@
+//! An AppArmor profile letting Discord use user namespaces, for `--apparmor-profile`
+//!
+//! Ubuntu 24.04 and later only let programs with a profile saying so create unprivileged user
+//! namespaces, which Electron's sandbox needs, so a tarball install doesn't start without
+//! `--no-sandbox`. Profiles live in /etc, so the helper writes and loads it as root, one for each
+//! channel. It names the binary of the channel's `--system` install itself, since a profile for
+//! whatever the caller named would let anyone allowed to run it hand user namespaces to any
+//! program, so other installs don't get one. `uninstall` takes it out again.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{platform, system, Result, UpdateCheck};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Set to 1 when user namespaces need a profile allowing them
+const RESTRICTED: &str = "/proc/sys/kernel/apparmor_restrict_unprivileged_userns";
+
+fn profile_path(channel: Channel) -> PathBuf {
+    Path::new("/etc/apparmor.d").join(format!("discord_update-{channel}"))
+}
+
+/// Whether the kernel only lets programs with a profile use user namespaces
+pub fn restricted() -> bool {
+    fs::read_to_string(RESTRICTED).is_ok_and(|value| value.trim() == "1")
+}
+
+/// The binary the channel's profile is for, that of its `--system` install
+fn binary(channel: Channel) -> PathBuf {
+    platform::current().binary_path(channel, &system::default_install_path(channel))
+}
+
+/// The profile for `binary`, unconfined but for being allowed user namespaces
+fn profile(channel: Channel, binary: &Path) -> Result<String> {
+    let binary = binary.to_str().unwrap_or_default();
+    // Quoted in the profile, which has no way to escape these, and the rest would be globs
+    // matching more than the binary
+    if binary.is_empty() || binary.contains(['"', '\n', '\0', '*', '?', '[', ']', '{', '}', '^']) {
+        return Err(format!("{binary:?} can't be named in an AppArmor profile").into());
+    }
+    Ok(format!(
+        "# Written by discord_update --apparmor-profile, rewritten when the install moves\n\
+         abi <abi/4.0>,\n\
+         include <tunables/global>\n\n\
+         profile discord_update-{channel} \"{binary}\" flags=(unconfined) {{\n  \
+           userns,\n\n  \
+           include if exists <local/discord_update-{channel}>\n\
+         }}\n"
+    ))
+}
+
+/// Run apparmor_parser as root, failing with what it said
+fn parser(args: &[&str], profile: &Path) -> Result<()> {
+    let output = Command::new("apparmor_parser")
+        .args(args)
+        .arg(profile)
+        .output()
+        .map_err(|e| format!("failed to run apparmor_parser: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "apparmor_parser failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(())
+}
+
+/// `system-helper apparmor-load`, write the channel's profile and load it
+pub fn load(channel: Channel) -> Result<()> {
+    let path = profile_path(channel);
+    let temp = path.with_extension("discord_update-new");
+    fs::write(&temp, profile(channel, &binary(channel))?)?;
+    fs::rename(&temp, &path)?;
+    parser(&["--replace"], &path)
+}
+
+/// `system-helper apparmor-unload`, unload the channel's profile and delete it
+pub fn unload(channel: Channel) -> Result<()> {
+    let path = profile_path(channel);
+    // Not loaded is as good as unloaded
+    let _ = parser(&["--remove"], &path);
+    fs::remove_file(&path)?;
+    Ok(())
+}
+
+/// Make sure the channel's profile names the checked install, where one is needed
+pub fn ensure(prog: &Progress, check: &UpdateCheck) -> Result<()> {
+    let binary = check.binary_path();
+    if !restricted() || !binary.is_file() {
+        return Ok(());
+    }
+    if binary != self::binary(check.channel) {
+        prog.println(format!(
+            "Not installing an AppArmor profile for {}, only the --system install in /opt gets              one, start it with --no-sandbox instead",
+            binary.display()
+        ))?;
+        return Ok(());
+    }
+    let wanted = profile(check.channel, &binary)?;
+    if fs::read_to_string(profile_path(check.channel))
+        .ok()
+        .as_deref()
+        == Some(wanted.as_str())
+    {
+        return Ok(());
+    }
+    prog.println(format!(
+        "Installing an AppArmor profile so Discord {} can sandbox itself, which takes root",
+        check.channel
+    ))?;
+    system::apparmor_load(check.channel)?;
+    prog.println(format!(
+        "Wrote {} for {}",
+        profile_path(check.channel).display(),
+        binary.display()
+    ))?;
+    Ok(())
+}
+
+/// Remove the channel's profile, if there is one
+pub fn remove(prog: &Progress, channel: Channel) -> Result<()> {
+    let path = profile_path(channel);
+    if !path.exists() {
+        return Ok(());
+    }
+    system::apparmor_unload(channel)?;
+    prog.println(format!("Removed {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn profiles_the_system_install() {
+        let profile = profile(Channel::Stable, &binary(Channel::Stable)).unwrap();
+        assert!(
+            profile.contains("\"/opt/Discord/Discord\" flags=(unconfined)"),
+            "{profile}"
+        );
+    }
+
+    #[test]
+    fn refuses_globs() {
+        for binary in [
+            "/opt/*/Discord",
+            "/opt/**",
+            "/opt/Discord/Disc?rd",
+            "/opt/[A-Z]iscord/Discord",
+            "/opt/{Discord,x}/Discord",
+            "/opt/@{HOME}/Discord",
+        ] {
+            assert!(
+                profile(Channel::Stable, Path::new(binary)).is_err(),
+                "{binary}"
+            );
+        }
+    }
+}
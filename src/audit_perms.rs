@@ -0,0 +1,220 @@
+//! The `audit-perms` subcommand, finding files left with the wrong owner or mode
+//!
+//! Mixing runs with and without sudo leaves root-owned files in the user's install or config
+//! directories, which later updates, or Discord itself, then can't write. A user install should
+//! belong to whoever owns the home directory and a `--system` one to root, neither writable by
+//! anyone else, except that a system install's chrome-sandbox has to be setuid for Electron to
+//! use it. A `--shared-group` install can belong to any member, but has to be the group's.
+
+use crate::manifest::metadata_dir;
+use crate::progress::Progress;
+use crate::state::state_dir;
+use crate::{
+    blocking, config, confirm, find_install, home_dir, install, selinux, symlink, system, Options,
+    Result,
+};
+use std::fs;
+use std::io;
+use std::os::unix::fs::{lchown, MetadataExt as _, PermissionsExt as _};
+use std::path::{Path, PathBuf};
+
+/// Write permission for the group and others
+const SHARED_WRITE: u32 = 0o022;
+
+/// What putting a path right takes
+#[derive(Clone, Copy)]
+enum Fix {
+    /// A new owner, group, or both
+    Chown(Option<u32>, Option<u32>),
+    Chmod(u32),
+}
+
+struct Finding {
+    path: PathBuf,
+    problem: String,
+    fix: Fix,
+}
+
+/// What the files under one root are expected to look like
+struct Expected {
+    /// `None` for `--shared-group` installs, which belong to whichever member updated them last
+    owner: Option<u32>,
+    /// `--shared-group`, which also gets to write
+    group: Option<u32>,
+    /// Whether chrome-sandbox should be setuid
+    setuid_sandbox: bool,
+}
+
+fn audit_path(path: &Path, meta: &fs::Metadata, expected: &Expected, found: &mut Vec<Finding>) {
+    let mode = meta.mode() & 0o7777;
+    let mut chowned = false;
+    if let Some(owner) = expected.owner.filter(|&owner| owner != meta.uid()) {
+        found.push(Finding {
+            path: path.to_owned(),
+            problem: format!("belongs to uid {}, not {owner}", meta.uid()),
+            fix: Fix::Chown(Some(owner), None),
+        });
+        chowned = true;
+    }
+    if let Some(group) = expected.group.filter(|&group| group != meta.gid()) {
+        found.push(Finding {
+            path: path.to_owned(),
+            problem: format!("belongs to group {}, not {group}", meta.gid()),
+            fix: Fix::Chown(None, Some(group)),
+        });
+        chowned = true;
+    }
+    if meta.is_symlink() {
+        return;
+    }
+    let is_sandbox = path.file_name().is_some_and(|name| name == system::SANDBOX);
+    let wanted = if is_sandbox && meta.is_file() && expected.setuid_sandbox {
+        system::SANDBOX_MODE
+    } else if expected.group.is_some() {
+        install::group_mode(mode & !0o002, meta.is_dir())
+    } else {
+        mode & !SHARED_WRITE
+    };
+    // Changing the owner or group of a file clears setuid and setgid, which then have to be put
+    // back
+    let after_chown = if chowned && !meta.is_dir() {
+        mode & !0o6000
+    } else {
+        mode
+    };
+    if wanted != after_chown {
+        let problem = if wanted == mode {
+            format!("loses mode {mode:o} when its owner or group is changed")
+        } else {
+            format!("has mode {mode:o}, not {wanted:o}")
+        };
+        found.push(Finding {
+            path: path.to_owned(),
+            problem,
+            fix: Fix::Chmod(wanted),
+        });
+    }
+}
+
+/// Check `path` and everything under it, not following symlinks
+fn audit_tree(path: &Path, expected: &Expected, found: &mut Vec<Finding>) -> io::Result<()> {
+    let meta = match fs::symlink_metadata(path) {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    audit_path(path, &meta, expected, found);
+    if meta.is_dir() {
+        for entry in fs::read_dir(path)? {
+            audit_tree(&entry?.path(), expected, found)?;
+        }
+    }
+    Ok(())
+}
+
+fn apply(finding: &Finding) -> io::Result<()> {
+    match finding.fix {
+        Fix::Chown(uid, gid) => lchown(&finding.path, uid, gid),
+        Fix::Chmod(mode) => fs::set_permissions(&finding.path, fs::Permissions::from_mode(mode)),
+    }
+}
+
+/// Check the channel's install, its launcher symlink, and the config directories, and offer to
+/// fix what's wrong
+pub async fn run(options: &Options) -> Result<()> {
+    let prog = Progress::new(options.non_interactive);
+    let channel = options.channel;
+    let install_path = find_install(&prog, options).await?;
+    // Not our uid, which is root's under sudo
+    let user = fs::metadata(home_dir()?)?.uid();
+    let owner = if options.system { 0 } else { user };
+    let install = Expected {
+        owner: Some(owner).filter(|_| options.shared_group.is_none()),
+        group: options.shared_group,
+        setuid_sandbox: options.system,
+    };
+    // Each member has a link of their own
+    let own = Expected {
+        owner: Some(owner),
+        group: None,
+        setuid_sandbox: false,
+    };
+    let link = symlink::link_path(options)?;
+    let mut user_dirs = vec![
+        channel.config_dir()?,
+        config::path()?.parent().unwrap().to_owned(),
+        state_dir()?,
+        config::cache_home()?.join("discord_update"),
+    ];
+    if !options.system {
+        user_dirs.push(link.parent().unwrap().to_owned());
+    }
+
+    let found = blocking(move || {
+        let mut found = vec![];
+        audit_tree(&install_path, &install, &mut found)?;
+        audit_tree(&metadata_dir(&install_path), &install, &mut found)?;
+        if let Ok(meta) = fs::symlink_metadata(&link) {
+            audit_path(&link, &meta, &own, &mut found);
+        }
+        let user = Expected {
+            owner: Some(user),
+            group: None,
+            setuid_sandbox: false,
+        };
+        for dir in user_dirs {
+            // The link's directory is only checked itself, not whatever else is in it
+            match fs::symlink_metadata(&dir) {
+                Ok(meta) if Some(dir.as_path()) == link.parent() => {
+                    audit_path(&dir, &meta, &user, &mut found)
+                }
+                _ => audit_tree(&dir, &user, &mut found)?,
+            }
+        }
+        Ok(found)
+    })
+    .await?;
+
+    if found.is_empty() {
+        prog.println("Every file has the owner and mode it should")?;
+        return Ok(());
+    }
+    for finding in &found {
+        prog.result(format!("{} {}", finding.path.display(), finding.problem))?;
+    }
+    let question = format!("Fix these {} problems?", found.len());
+    if !options.assume_yes && !confirm(&prog, &question, false)? {
+        return Err(format!("found {} permission problems", found.len()).into());
+    }
+    let (mut failed, mut chowns_failed) = (0, false);
+    for finding in &found {
+        if let Err(e) = apply(finding) {
+            prog.println(format!("Couldn't fix {}: {e}", finding.path.display()))?;
+            failed += 1;
+            chowns_failed |= matches!(finding.fix, Fix::Chown(..));
+        } else if finding
+            .path
+            .file_name()
+            .is_some_and(|name| name == system::SANDBOX)
+        {
+            // The policy may label a setuid helper differently
+            if let Err(e) = selinux::restore(&finding.path) {
+                prog.println(format!("Couldn't restore the SELinux context of it: {e}"))?;
+            }
+        }
+    }
+    if failed > 0 {
+        let hint = if chowns_failed {
+            ", giving files away takes root"
+        } else {
+            ""
+        };
+        return Err(format!(
+            "{failed} of {} problems couldn't be fixed{hint}",
+            found.len()
+        )
+        .into());
+    }
+    prog.println(format!("Fixed {} problems", found.len()))?;
+    Ok(())
+}
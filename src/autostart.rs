@@ -0,0 +1,137 @@
+//! The `autostart` subcommand, an XDG autostart entry that starts Discord on login
+//!
+//! The entry is named after the channel's app id like the menu entry, so it doesn't clash with
+//! the one Discord's own "Open Discord" setting writes. It starts Discord through the launcher if
+//! there is one, and is rewritten after every update so it follows the install when it moves.
+
+use crate::channel::Channel;
+use crate::cli::Matches;
+use crate::config::config_home;
+use crate::desktop::{quote_exec, write_file};
+use crate::progress::Progress;
+use crate::{find_install, launcher, platform, Options, Result};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Discord's own flag for starting in the tray
+const START_MINIMIZED: &str = "--start-minimized";
+
+fn autostart_file(channel: Channel) -> Result<PathBuf> {
+    Ok(config_home()?.join(format!("autostart/{}.desktop", channel.app_id())))
+}
+
+fn entry(channel: Channel, binary: &Path, minimized: bool) -> String {
+    let exec = launcher::wrapper(channel).unwrap_or_else(|| binary.to_owned());
+    let args = if minimized {
+        format!(" {START_MINIMIZED}")
+    } else {
+        String::new()
+    };
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name={title}\n\
+         Exec={exec}{args}\n\
+         Icon={icon}\n\
+         StartupWMClass={wm_class}\n\
+         X-GNOME-Autostart-enabled=true\n",
+        title = channel.title(),
+        exec = quote_exec(&exec),
+        icon = channel.app_id(),
+        wm_class = channel.wm_class(),
+    )
+}
+
+/// The channel's entry, if it has one
+fn current(channel: Channel) -> Result<Option<String>> {
+    match fs::read_to_string(autostart_file(channel)?) {
+        Ok(contents) => Ok(Some(contents)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Whether the channel has an entry
+pub fn enabled(channel: Channel) -> Result<bool> {
+    Ok(current(channel)?.is_some())
+}
+
+fn is_minimized(contents: &str) -> bool {
+    contents
+        .lines()
+        .any(|line| line.starts_with("Exec=") && line.ends_with(START_MINIMIZED))
+}
+
+/// Point an existing entry at the channel's install at `install_path`, or its launcher
+pub fn refresh(channel: Channel, install_path: &Path) -> Result<()> {
+    let Some(contents) = current(channel)? else {
+        return Ok(());
+    };
+    let binary = platform::current().binary_path(channel, install_path);
+    let wanted = entry(channel, &binary, is_minimized(&contents));
+    if contents != wanted {
+        write_file(&autostart_file(channel)?, &wanted)?;
+    }
+    Ok(())
+}
+
+/// Remove the channel's entry, if there is one
+pub fn remove(prog: &Progress, channel: Channel) -> Result<()> {
+    let path = autostart_file(channel)?;
+    match fs::remove_file(&path) {
+        Ok(()) => prog.println(format!("Removed {}", path.display()))?,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+        Err(e) => return Err(e.into()),
+    }
+    Ok(())
+}
+
+/// `autostart enable [--start-minimized]`, `autostart disable`, and `autostart` to show whether
+/// it's on
+pub async fn run(prog: &Progress, options: &Options, matches: &Matches) -> Result<()> {
+    let channel = options.channel;
+    if options.system {
+        return Err(
+            "autostart starts Discord on your login, it can't be used with --system".into(),
+        );
+    }
+    match matches.subcommand(1) {
+        Some("enable") => {
+            let install_path = find_install(prog, options).await?;
+            let binary = platform::current().binary_path(channel, &install_path);
+            if !binary.exists() {
+                return Err(format!(
+                    "Discord {channel} isn't installed at {}, install it first",
+                    install_path.display()
+                )
+                .into());
+            }
+            let minimized = matches.flag("start-minimized");
+            let path = autostart_file(channel)?;
+            write_file(&path, &entry(channel, &binary, minimized))?;
+            let how = if minimized { " in the tray" } else { "" };
+            prog.println(format!(
+                "Discord {channel} now starts{how} when you log in, from {}",
+                path.display()
+            ))?;
+        }
+        Some("disable") => {
+            if current(channel)?.is_none() {
+                prog.println(format!("Discord {channel} doesn't start when you log in"))?;
+            }
+            remove(prog, channel)?;
+        }
+        _ => {
+            let message = match current(channel)? {
+                Some(contents) if is_minimized(&contents) => {
+                    format!("Discord {channel} starts in the tray when you log in")
+                }
+                Some(_) => format!("Discord {channel} starts when you log in"),
+                None => format!("Discord {channel} doesn't start when you log in"),
+            };
+            prog.result(message)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,139 @@
+//! Keeping scheduled runs from hammering the updates API, or all checking at once
+//!
+//! Each failed check pushes the next scheduled one back, twice as far every time up to
+//! `MAX_BACKOFF`, or as far as a 429's Retry-After says. A check that works clears it. Runs
+//! someone started by hand check regardless. `--jitter` spreads a fleet's checks out.
+
+use crate::channel::Channel;
+use crate::fetch::RateLimited;
+use crate::state::{self, ChannelState, State};
+use crate::{Error, Result};
+use ring::rand::{SecureRandom as _, SystemRandom};
+use std::time::Duration;
+
+/// How long the first failure holds off the next check
+const FIRST_BACKOFF: Duration = Duration::from_secs(5 * 60);
+/// Longer than this and a broken API would hide a fixed one for too long
+const MAX_BACKOFF: Duration = Duration::from_secs(6 * 60 * 60);
+
+/// When scheduled checks of the channel may go ahead again, if they're backing off
+pub fn until(channel: Channel) -> Result<Option<u64>> {
+    let state = State::load()?;
+    let until = state.channels.get(&channel).and_then(|c| c.backoff_until);
+    Ok(until.filter(|&until| until > state::now()))
+}
+
+/// Record a failed check, returning how long scheduled ones now wait
+pub fn failed(channel: Channel, error: &Error) -> Result<Duration> {
+    let retry_after = error
+        .downcast_ref::<RateLimited>()
+        .and_then(|e| e.retry_after);
+    let mut wait = Duration::ZERO;
+    State::update(|state| wait = fail(state.channel(channel), state::now(), retry_after))?;
+    Ok(wait)
+}
+
+/// Count a failed check at `now` in the channel's state, returning how long scheduled ones wait
+fn fail(channel: &mut ChannelState, now: u64, retry_after: Option<Duration>) -> Duration {
+    let doubled = FIRST_BACKOFF.saturating_mul(1 << channel.failed_checks.min(16));
+    let wait = doubled
+        .min(MAX_BACKOFF)
+        .max(retry_after.unwrap_or_default());
+    channel.failed_checks += 1;
+    // An earlier Retry-After may ask for longer still
+    let until = channel.backoff_until.unwrap_or(0).max(now + wait.as_secs());
+    channel.backoff_until = Some(until);
+    Duration::from_secs(until.saturating_sub(now))
+}
+
+/// Record a check that worked
+pub fn succeeded(channel: Channel) -> Result<()> {
+    let state = State::load()?;
+    let backing_off = state
+        .channels
+        .get(&channel)
+        .is_some_and(|c| c.failed_checks > 0 || c.backoff_until.is_some());
+    if !backing_off {
+        return Ok(());
+    }
+    State::update(|state| succeed(state.channel(channel)))
+}
+
+fn succeed(channel: &mut ChannelState) {
+    channel.failed_checks = 0;
+    channel.backoff_until = None;
+}
+
+/// Sleep for a random part of `max`
+pub async fn jitter(max: Duration) {
+    let mut bytes = [0; 8];
+    if SystemRandom::new().fill(&mut bytes).is_err() {
+        return;
+    }
+    tokio::time::sleep(jitter_delay(max, u64::from_le_bytes(bytes))).await;
+}
+
+/// The part of `max` a `random` number picks, to the millisecond
+fn jitter_delay(max: Duration, random: u64) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(random % max.as_millis().max(1) as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+
+    #[test]
+    fn doubles_up_to_the_cap() {
+        let mut channel = ChannelState::default();
+        let waits: Vec<_> = (0..10)
+            .map(|_| fail(&mut channel, NOW, None).as_secs() / 60)
+            .collect();
+        assert_eq!(waits, [5, 10, 20, 40, 80, 160, 320, 360, 360, 360]);
+        assert_eq!(channel.failed_checks, 10);
+        assert_eq!(channel.backoff_until, Some(NOW + 6 * 60 * 60));
+        // Still capped with the shift saturating
+        channel.failed_checks = 1000;
+        assert_eq!(fail(&mut channel, NOW, None), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn retry_after_can_ask_for_longer() {
+        let mut channel = ChannelState::default();
+        let day = Duration::from_secs(24 * 60 * 60);
+        assert_eq!(fail(&mut channel, NOW, Some(day)), day);
+        // A later, shorter backoff doesn't cut it short
+        assert_eq!(
+            fail(&mut channel, NOW + 60, None),
+            day - Duration::from_secs(60)
+        );
+        assert_eq!(channel.backoff_until, Some(NOW + day.as_secs()));
+    }
+
+    #[test]
+    fn success_starts_over() {
+        let mut channel = ChannelState::default();
+        for _ in 0..3 {
+            fail(&mut channel, NOW, None);
+        }
+        succeed(&mut channel);
+        assert_eq!(channel.failed_checks, 0);
+        assert_eq!(channel.backoff_until, None);
+        assert_eq!(fail(&mut channel, NOW, None), FIRST_BACKOFF);
+    }
+
+    #[test]
+    fn jitter_stays_under_max() {
+        let max = Duration::from_secs(10);
+        for random in [0, 1, 9_999, 10_000, 123_456_789, u64::MAX] {
+            assert!(jitter_delay(max, random) < max, "{random}");
+        }
+        assert_eq!(jitter_delay(max, 12_345), Duration::from_millis(2_345));
+        assert_eq!(jitter_delay(Duration::ZERO, u64::MAX), Duration::ZERO);
+        assert_eq!(jitter_delay(Duration::from_micros(10), 7), Duration::ZERO);
+    }
+}
@@ -0,0 +1,284 @@
+//! Discord release channels, and where each one lives
+
+use crate::config::config_home;
+use crate::Result;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+/// Discord's own updates API, mirrors serve the same JSON under their own base URL
+pub const DEFAULT_API_BASE: &str = "https://discord.com/api";
+
+/// URLs with `{channel}`, `{arch}`, and `{version}` or `{api_base}` to fill in, and `{{` and `}}`
+/// for literal braces
+pub struct UrlTemplates {
+    /// The updates API endpoint
+    pub api: &'static str,
+    /// The tarball for a version
+    pub download: &'static str,
+}
+
+/// What an `--api-url-template` may have filled in
+pub const API_PLACEHOLDERS: &[&str] = &["api_base", "channel", "arch"];
+
+/// What a download URL template may have filled in
+pub const DOWNLOAD_PLACEHOLDERS: &[&str] = &["channel", "version", "arch"];
+
+/// One `{name}` in a URL template, `None` for a literal brace written `{{` or `}}`, and the rest
+/// of the template after it
+fn placeholder(template: &str) -> std::result::Result<(Option<&str>, &str), ()> {
+    if let Some(rest) = template.strip_prefix("{{").or(template.strip_prefix("}}")) {
+        return Ok((None, rest));
+    }
+    let (name, rest) = template
+        .strip_prefix('{')
+        .and_then(|t| t.split_once('}'))
+        .ok_or(())?;
+    Ok((Some(name), rest))
+}
+
+/// Fill in a URL template's `{name}`s from `values`, see `check_template`
+fn fill(template: &str, values: &[(&str, &str)]) -> String {
+    let mut url = String::new();
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        url += &rest[..i];
+        rest = &rest[i..];
+        let value = match placeholder(rest) {
+            Ok((Some(name), after)) => values
+                .iter()
+                .find(|(n, _)| *n == name)
+                .map(|(_, value)| (*value, after)),
+            Ok((None, after)) => Some((&rest[..1], after)),
+            Err(()) => None,
+        };
+        // Left as written, for a template that wasn't checked
+        let (value, after) = value.unwrap_or((&rest[..1], &rest[1..]));
+        url += value;
+        rest = after;
+    }
+    url + rest
+}
+
+/// Fail if `template`, given as `--{option}`, has a placeholder other than `names`, or a brace
+/// that isn't doubled to be a literal one
+pub fn check_template(option: &str, template: &str, names: &[&str]) -> Result<()> {
+    let mut rest = template;
+    while let Some(i) = rest.find(['{', '}']) {
+        rest = match placeholder(&rest[i..]) {
+            Ok((Some(name), _)) if !names.contains(&name) => {
+                let names: Vec<_> = names.iter().map(|n| format!("{{{n}}}")).collect();
+                return Err(format!(
+                    "--{option} has {{{name}}}, it can have {}",
+                    names.join(", ")
+                )
+                .into());
+            }
+            Ok((_, after)) => after,
+            Err(()) => {
+                return Err(format!(
+                    "--{option} has an unmatched brace, write {{{{ or }}}} for a literal one"
+                )
+                .into())
+            }
+        };
+    }
+    Ok(())
+}
+
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Channel {
+    #[default]
+    Stable,
+    Ptb,
+    Canary,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 3] = [Channel::Stable, Channel::Ptb, Channel::Canary];
+
+    /// The name the updates API and the CDN use for this channel
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::Ptb => "ptb",
+            Self::Canary => "canary",
+        }
+    }
+
+    /// Discord's own URLs for this channel, which `--api-url-template` and
+    /// `--download-url-template` (or per-channel config) override
+    pub fn url_templates(self) -> UrlTemplates {
+        let download = match self {
+            Self::Stable => "https://dl.discordapp.net/apps/linux/{version}/discord-{version}.tar.gz",
+            _ => {
+                "https://dl-{channel}.discordapp.net/apps/linux/{version}/discord-{channel}-{version}.tar.gz"
+            }
+        };
+        UrlTemplates {
+            api: "{api_base}/updates/{channel}?platform=linux",
+            download,
+        }
+    }
+
+    /// Fill in a URL template for a version, or for the updates API at `api_base`
+    fn expand(self, template: &str, version: Option<&Version>, api_base: &str) -> String {
+        let version = version.map(Version::to_string).unwrap_or_default();
+        let values = [
+            ("channel", self.name()),
+            ("arch", std::env::consts::ARCH),
+            ("version", &version),
+            ("api_base", api_base.trim_end_matches('/')),
+        ];
+        fill(template, &values)
+    }
+
+    /// Endpoint reporting the latest version on this channel, `{api_base}` is `api_base`
+    pub fn api_url(self, template: Option<&str>, api_base: &str) -> String {
+        self.expand(template.unwrap_or(self.url_templates().api), None, api_base)
+    }
+
+    /// Where the tarball for the given version can be downloaded from
+    pub fn download_url(self, template: Option<&str>, version: &Version) -> String {
+        let template = template.unwrap_or(self.url_templates().download);
+        self.expand(template, Some(version), "")
+    }
+
+    /// Name of the top-level directory in the tarball, which is also the name of the binary
+    pub fn dir_name(self) -> &'static str {
+        match self {
+            Self::Stable => "Discord",
+            Self::Ptb => "DiscordPTB",
+            Self::Canary => "DiscordCanary",
+        }
+    }
+
+    /// Name of the executable inside an install
+    pub fn binary_name(self) -> &'static str {
+        self.dir_name()
+    }
+
+    /// Command distribution packages install this channel as
+    pub fn command_name(self) -> &'static str {
+        match self {
+            Self::Stable => "discord",
+            Self::Ptb => "discord-ptb",
+            Self::Canary => "discord-canary",
+        }
+    }
+
+    /// The class Discord's windows have, for matching them to the menu entry
+    pub fn wm_class(self) -> String {
+        self.binary_name().to_lowercase()
+    }
+
+    /// Name shown to people, in menus and software centres
+    pub fn title(self) -> &'static str {
+        match self {
+            Self::Stable => "Discord",
+            Self::Ptb => "Discord PTB",
+            Self::Canary => "Discord Canary",
+        }
+    }
+
+    /// Reverse-DNS id for desktop integration, following Flathub's `com.discordapp.Discord`
+    pub fn app_id(self) -> &'static str {
+        match self {
+            Self::Stable => "com.discordapp.Discord",
+            Self::Ptb => "com.discordapp.DiscordPTB",
+            Self::Canary => "com.discordapp.DiscordCanary",
+        }
+    }
+
+    /// Directory Discord keeps this channel's settings and caches in
+    pub fn config_dir(self) -> Result<PathBuf> {
+        let config_home = config_home()?;
+        let name = match self {
+            Self::Stable => "discord",
+            Self::Ptb => "discordptb",
+            Self::Canary => "discordcanary",
+        };
+        Ok(config_home.join(name))
+    }
+}
+
+impl fmt::Display for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|c| c.name() == s.to_lowercase())
+            .ok_or_else(|| "expected stable, ptb, or canary".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn download(template: &str) -> String {
+        Channel::Canary.download_url(Some(template), &Version::new(0, 0, 42))
+    }
+
+    #[test]
+    fn fills_in_templates() {
+        assert_eq!(
+            download("https://m/{channel}/{version}/discord-{channel}-{version}.tar.gz"),
+            "https://m/canary/0.0.42/discord-canary-0.0.42.tar.gz"
+        );
+        assert_eq!(
+            download("https://m/{arch}"),
+            format!("https://m/{}", std::env::consts::ARCH)
+        );
+        assert_eq!(
+            Channel::Ptb.api_url(None, "https://mirror/api/"),
+            "https://mirror/api/updates/ptb?platform=linux"
+        );
+        assert_eq!(
+            Channel::Stable.download_url(None, &Version::new(0, 0, 42)),
+            "https://dl.discordapp.net/apps/linux/0.0.42/discord-0.0.42.tar.gz"
+        );
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(
+            download("https://m/{{version}}/{version}"),
+            "https://m/{version}/0.0.42"
+        );
+        assert_eq!(download("https://m/?q={{}}"), "https://m/?q={}");
+        // A filled in value isn't filled in again
+        assert_eq!(
+            Channel::Stable.api_url(Some("{api_base}/{channel}"), "https://m/{channel}"),
+            "https://m/{channel}/stable"
+        );
+    }
+
+    #[test]
+    fn checks_placeholders() {
+        let check =
+            |template| check_template("download-url-template", template, DOWNLOAD_PLACEHOLDERS);
+        assert!(check("https://m/{channel}/{version}/{version}-{arch}").is_ok());
+        assert!(check("https://m/{{literal}}").is_ok());
+        let error = check("https://m/{verison}").unwrap_err().to_string();
+        assert!(error.contains("{verison}"), "{error}");
+        assert!(check("https://m/{api_base}").is_err());
+        assert!(check("https://m/{version").is_err());
+        assert!(check("https://m/version}").is_err());
+        assert!(check_template("api-url-template", "{api_base}/x", API_PLACEHOLDERS).is_ok());
+        // Unchecked ones are left as they were
+        assert_eq!(download("https://m/{verison}/{"), "https://m/{verison}/{");
+    }
+}
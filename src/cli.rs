@@ -0,0 +1,1206 @@
+//! Command line parsing
+//!
+//! The command line is described by static `Command` tables so that the help output always
+//! matches what is actually accepted.
+
+use crate::{Error, Result};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// A `--flag` or `--option <VALUE>`
+pub struct Opt {
+    pub long: &'static str,
+    /// The placeholder shown in help, options without one are plain flags
+    pub value: Option<&'static str>,
+    pub help: &'static str,
+}
+
+/// A (sub)command, with the options and further subcommands it accepts
+pub struct Command {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub opts: &'static [Opt],
+    /// Names of the positional arguments, shown in help, ones in `[]` being optional
+    pub args: &'static [&'static str],
+    pub subcommands: &'static [Command],
+}
+
+const HELP: Opt = Opt {
+    long: "help",
+    value: None,
+    help: "Print help",
+};
+
+/// For both of `harden`'s sandboxes
+const ALLOW: Opt = Opt {
+    long: "allow",
+    value: Some("PATH"),
+    help: "Let Discord write to a path inside the sandbox too, can be given more than once",
+};
+
+pub static COMMAND: Command = Command {
+    name: "discord_update",
+    about: "Install or update Discord from the official Linux tarballs",
+    opts: &[
+        Opt {
+            long: "channel",
+            value: Some("CHANNEL"),
+            help: "Which Discord to manage: stable, ptb, or canary",
+        },
+        Opt {
+            long: "profile",
+            value: Some("NAME"),
+            help: "Use the named set of settings from the config file's \"profiles\"",
+        },
+        Opt {
+            long: "install-path",
+            value: Some("DIR"),
+            help: "Manage the install in this directory instead of finding it on the PATH",
+        },
+        Opt {
+            long: "all",
+            value: None,
+            help: "Update every install of the channel that can be found, one after another",
+        },
+        Opt {
+            long: "all-channels",
+            value: None,
+            help: "Update every channel that's installed, all at once",
+        },
+        Opt {
+            long: "api-url",
+            value: Some("URL"),
+            help: "Base URL of the updates API, for mirrors. Defaults to https://discord.com/api",
+        },
+        Opt {
+            long: "api-url-template",
+            value: Some("TEMPLATE"),
+            help: "The updates API URL, with {api_base}, {channel}, and {arch} filled in",
+        },
+        Opt {
+            long: "download-url-template",
+            value: Some("TEMPLATE"),
+            help: "Where official tarballs are downloaded from, with {channel}, {version}, and \
+                   {arch} filled in. Set per channel in the config for mirrors that differ",
+        },
+        Opt {
+            long: "source-url",
+            value: Some("TEMPLATE"),
+            help:
+                "Download unofficial builds from here, like for aarch64, with {channel}, \
+                   {version}, and {arch} filled in. Usually set per architecture in the config file",
+        },
+        Opt {
+            long: "source-key",
+            value: Some("HEX"),
+            help: "Ed25519 public key that --source-url builds must be signed with, in <url>.sig",
+        },
+        Opt {
+            long: "proxy",
+            value: Some("URL"),
+            help: "Make every request through this proxy, instead of the one in HTTPS_PROXY",
+        },
+        Opt {
+            long: "pin-keys",
+            value: Some("HASHES"),
+            help: "Only talk to servers whose public key has one of these comma separated hashes",
+        },
+        Opt {
+            long: "print-pins",
+            value: None,
+            help: "Print the public key hashes of the API and download servers, to use as pins",
+        },
+        Opt {
+            long: "output",
+            value: Some("FORMAT"),
+            help: "How to report the result on stdout: text or json. See --schema for the JSON",
+        },
+        Opt {
+            long: "ensure",
+            value: Some("latest|X.Y.Z"),
+            help: "Install exactly this version without prompting. Exits 0 if nothing \
+                   changed, 2 if it installed something, and 1 on failure",
+        },
+        Opt {
+            long: "print-latest-version",
+            value: None,
+            help: "Print the channel's latest version and exit",
+        },
+        Opt {
+            long: "print-url",
+            value: None,
+            help: "Print the download URL of the channel's latest version and exit",
+        },
+        Opt {
+            long: "schema",
+            value: None,
+            help: "Print JSON Schemas for --output json and notification events and exit",
+        },
+        Opt {
+            long: "close-running",
+            value: None,
+            help: "Ask a running Discord to quit before updating it, without asking first",
+        },
+        Opt {
+            long: "when-closed",
+            value: None,
+            help: "Leave a running Discord be, and install the update once it's closed",
+        },
+        Opt {
+            long: "when-closed-timeout",
+            value: Some("DURATION"),
+            help: "Give up on --when-closed if Discord is still running after this long",
+        },
+        Opt {
+            long: "yes",
+            value: None,
+            help: "Install updates without asking for confirmation",
+        },
+        Opt {
+            long: "clear-cache",
+            value: None,
+            help: "Clear Discord's caches after installing an update",
+        },
+        Opt {
+            long: "purge-modules",
+            value: None,
+            help: "After an update, delete downloaded modules (voice, krisp) that don't match \
+                   the new version so Discord fetches matching ones",
+        },
+        Opt {
+            long: "build-dirs",
+            value: Some("keep|migrate|clean"),
+            help: "After an update, what to do with older versions' directories in \
+                   ~/.config/discord. migrate moves their settings to the new one",
+        },
+        Opt {
+            long: "auto-repair",
+            value: None,
+            help: "Reinstall the current version if the install is missing files",
+        },
+        Opt {
+            long: "exclude",
+            value: Some("PATTERNS"),
+            help: "Leave files out of the install to save space, comma separated paths inside \
+                   it with * and ? wildcards and ** for any directories, like \
+                   locales/de.pak,swiftshader",
+        },
+        Opt {
+            long: "max-download-size",
+            value: Some("SIZE"),
+            help: "Refuse downloads bigger than this, like 200M, for metered connections",
+        },
+        Opt {
+            long: "no-range-requests",
+            value: None,
+            help: "Start a download that broke off over instead of resuming it, for caching \
+                   proxies that get ranges wrong",
+        },
+        Opt {
+            long: "show-diff",
+            value: None,
+            help: "List the files an update adds, removes, or changes before installing it",
+        },
+        Opt {
+            long: "store-versions",
+            value: Some("N"),
+            help: "Keep the files of the last N installed versions in ~/.cache, so going back \
+                   to one or repairing it needs no download",
+        },
+        Opt {
+            long: "snapshot",
+            value: None,
+            help: "Snapshot the btrfs subvolume or ZFS dataset holding the install before \
+                   updating, for rollback --snapshot",
+        },
+        Opt {
+            long: "watch-first-launch",
+            value: Some("DURATION"),
+            help: "After updating, start Discord and roll back if it fails within this long",
+        },
+        Opt {
+            long: "check-interval",
+            value: Some("DURATION"),
+            help: "Skip the run if the channel was checked less than this long ago, like 1d. \
+                   Useful per channel in the config file",
+        },
+        Opt {
+            long: "wait",
+            value: None,
+            help: "When another discord_update is updating the channel, wait for it to finish \
+                   and then see what's left to do, instead of failing",
+        },
+        Opt {
+            long: "jitter",
+            value: Some("DURATION"),
+            help: "Wait a random part of this before checking in --non-interactive runs, so \
+                   machines on the same schedule don't all check at once",
+        },
+        Opt {
+            long: "notify-only",
+            value: None,
+            help: "Report available updates, with a desktop notification, without installing",
+        },
+        Opt {
+            long: "dry-run",
+            value: None,
+            help: "List the steps an update would take, in order, without taking them",
+        },
+        Opt {
+            long: "version-feed",
+            value: Some("URL"),
+            help: "A JSON feed of versions known to be broken or good on Linux, broken ones \
+                   aren't installed without asking",
+        },
+        Opt {
+            long: "version-feed-strict",
+            value: None,
+            help: "Also hold back versions the --version-feed hasn't marked good yet",
+        },
+        Opt {
+            long: "skip-version",
+            value: Some("X.Y.Z|none"),
+            help: "Don't offer or install this version, until a newer one comes out. \
+                   Remembered for later runs, none goes back to offering every update",
+        },
+        Opt {
+            long: "assume-installed-version",
+            value: Some("X.Y.Z"),
+            help: "Take the install to be this version instead of reading its build_info.json, \
+                   for repacked builds where that's missing or wrong",
+        },
+        Opt {
+            long: "metrics-file",
+            value: Some("PATH"),
+            help: "Write node_exporter textfile collector metrics about the run here",
+        },
+        Opt {
+            long: "notify",
+            value: Some("BACKENDS"),
+            help: "Where to report updates and failures, any of desktop, webhook, command, and \
+                   log separated by commas. Defaults to the ones configured below, and desktop \
+                   with --notify-only",
+        },
+        Opt {
+            long: "notify-command",
+            value: Some("COMMAND"),
+            help: "Shell command for --notify command, the message is $1 and the event JSON is \
+                   on stdin",
+        },
+        Opt {
+            long: "notify-log",
+            value: Some("PATH"),
+            help: "File that --notify log appends events to as lines of JSON",
+        },
+        Opt {
+            long: "webhook-url",
+            value: Some("URL"),
+            help: "For --notify webhook, the URL events are POSTed to as JSON",
+        },
+        Opt {
+            long: "webhook-format",
+            value: Some("json|discord"),
+            help: "Send --webhook-url events as plain JSON or as a Discord webhook message. \
+                   Defaults to discord for Discord's webhook URLs",
+        },
+        Opt {
+            long: "notes-url",
+            value: Some("URL"),
+            help: "Fetch release notes from here, {version} is replaced with the new version",
+        },
+        Opt {
+            long: "no-symlink",
+            value: None,
+            help: "Don't create or update the launcher symlink, for managing your own launcher",
+        },
+        Opt {
+            long: "no-desktop-entry",
+            value: None,
+            help: "Don't add Discord to the applications menu",
+        },
+        Opt {
+            long: "no-icon",
+            value: None,
+            help: "Don't add Discord's icon to the icon theme",
+        },
+        Opt {
+            long: "no-url-handler",
+            value: None,
+            help: "Don't make Discord the handler for discord:// links",
+        },
+        Opt {
+            long: "force-symlink",
+            value: None,
+            help: "Replace the launcher symlink even if it's a regular file or points at \
+                   another install",
+        },
+        Opt {
+            long: "symlink-dir",
+            value: Some("DIR"),
+            help: "Where to put the launcher symlink (discord, discord-ptb, or discord-canary), \
+                   like ~/.local/bin. Defaults to the config file's symlink_dir, then wherever \
+                   it went last time, then ~/bin",
+        },
+        Opt {
+            long: "temp-dir",
+            value: Some("DIR"),
+            help: "Stage --system and --update-strategy in-place downloads here before \
+                   they're copied into place, instead of $TMPDIR or /tmp, for when that's a \
+                   small tmpfs. Other installs are staged next to the install, to be renamed \
+                   into place",
+        },
+        Opt {
+            long: "update-strategy",
+            value: Some("STRATEGY"),
+            help: "swap (the default) to replace the whole install at once, keeping the old one \
+                   to roll back to, or in-place to write only the files that changed, for flash \
+                   and network filesystems",
+        },
+        Opt {
+            long: "apparmor-profile",
+            value: None,
+            help: "Where AppArmor restricts user namespaces, like on Ubuntu 24.04, install a \
+                   profile letting a --system install use them for its sandbox, which asks for \
+                   root. uninstall removes it",
+        },
+        Opt {
+            long: "shared-group",
+            value: Some("GROUP"),
+            help: "Give the install to GROUP and make it group-writable, so any of its members \
+                   can update it. Its directory has to be writable by the group too",
+        },
+        Opt {
+            long: "system",
+            value: None,
+            help: "Manage a root-owned install in /opt with its symlink in /usr/local/bin, \
+                   authenticating through polkit to change it",
+        },
+        Opt {
+            long: "non-interactive",
+            value: None,
+            help: "Never prompt and print plain log lines instead of progress bars. The \
+                   default when stdin or stderr isn't a terminal",
+        },
+        Opt {
+            long: "timeout",
+            value: Some("DURATION"),
+            help: "Give up after this long, like 90s, 30m, or 2h. Defaults to 30m when \
+                   non-interactive, except with --when-closed",
+        },
+    ],
+    args: &[],
+    subcommands: &[
+        Command {
+            name: "update",
+            about: "Update Discord if a newer version is available (the default)",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "config",
+            about: "Back up or restore Discord's settings",
+            opts: &[],
+            args: &[],
+            subcommands: &[
+                Command {
+                    name: "backup",
+                    about: "Save settings, keybinds, and local storage (not caches) to a tar file",
+                    opts: &[],
+                    args: &["ARCHIVE"],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "restore",
+                    about: "Restore settings from a backup, keeping the current ones as .bak",
+                    opts: &[],
+                    args: &["ARCHIVE"],
+                    subcommands: &[],
+                },
+            ],
+        },
+        Command {
+            name: "settings",
+            about: "Show common settings, or get, set, or unset one in Discord's settings.json",
+            opts: &[],
+            args: &[],
+            subcommands: &[
+                Command {
+                    name: "get",
+                    about: "Print a setting's value",
+                    opts: &[],
+                    args: &["KEY"],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "set",
+                    about: "Change a setting, keeping the old file as settings.json.bak",
+                    opts: &[],
+                    args: &["KEY", "VALUE"],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "unset",
+                    about: "Remove a setting so Discord uses its default",
+                    opts: &[],
+                    args: &["KEY"],
+                    subcommands: &[],
+                },
+            ],
+        },
+        Command {
+            name: "maintenance",
+            about: "Clear the channel's caches (Cache, Code Cache, GPU caches)",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "switch-channel",
+            about: "Install another channel and point the discord symlink at it",
+            opts: &[
+                Opt {
+                    long: "from",
+                    value: Some("CHANNEL"),
+                    help: "The channel being switched away from, defaults to --channel",
+                },
+                Opt {
+                    long: "copy-settings",
+                    value: None,
+                    help: "Copy settings and keybinds over from the old channel",
+                },
+            ],
+            args: &["CHANNEL"],
+            subcommands: &[],
+        },
+        Command {
+            name: "enable-cron",
+            about: "Add a crontab entry that checks for updates periodically",
+            opts: &[
+                Opt {
+                    long: "schedule",
+                    value: Some("CRON"),
+                    help: "When to run, in crontab syntax. Defaults to every six hours",
+                },
+                Opt {
+                    long: "policy",
+                    value: Some("POLICY"),
+                    help: "now updates a running Discord right away (the default), on-exit \
+                           stages the update and installs it as soon as Discord exits",
+                },
+            ],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "disable-cron",
+            about: "Remove the crontab entry added by enable-cron",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "defer",
+            about: "Stop automatic runs updating the channel for a while, or show until when",
+            opts: &[
+                Opt {
+                    long: "for",
+                    value: Some("DURATION"),
+                    help: "Defer updates for this long from now, like 7d",
+                },
+                Opt {
+                    long: "until",
+                    value: Some("DATE"),
+                    help: "Defer updates until the start of this day, like 2024-12-31",
+                },
+                Opt {
+                    long: "cancel",
+                    value: None,
+                    help: "Let automatic runs update again",
+                },
+            ],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "pin",
+            about: "Never update the channel past a version, or show the pin. --ensure X.Y.Z \
+                    still installs any version",
+            opts: &[Opt {
+                long: "max",
+                value: Some("X.Y.Z"),
+                help: "The newest version to update to",
+            }],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "unpin",
+            about: "Let the channel update to the latest version again",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "running",
+            about: "Print the version of the channel's running client, and whether it's the \
+                    installed one",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "network-check",
+            about: "Test reaching the updates API and the download CDN, DNS, TCP, TLS, and \
+                    HTTP separately, direct and through the proxy",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "flags",
+            about: "Start Discord with a preset of Electron flags, through the launcher",
+            opts: &[],
+            args: &[],
+            subcommands: &[
+                Command {
+                    name: "list",
+                    about: "Show the presets, * marking the applied ones",
+                    opts: &[],
+                    args: &[],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "apply",
+                    about:
+                        "Add a preset: wayland, x11-fallback, no-hardware-acceleration, or hidpi",
+                    opts: &[],
+                    args: &["PRESET"],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "clear",
+                    about: "Take off a preset, or all of them",
+                    opts: &[],
+                    args: &["[PRESET]"],
+                    subcommands: &[],
+                },
+            ],
+        },
+        Command {
+            name: "autostart",
+            about: "Start Discord when you log in, or show whether it does",
+            opts: &[],
+            args: &[],
+            subcommands: &[
+                Command {
+                    name: "enable",
+                    about: "Add an XDG autostart entry for the channel",
+                    opts: &[Opt {
+                        long: "start-minimized",
+                        value: None,
+                        help: "Start in the tray rather than with a window",
+                    }],
+                    args: &[],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "disable",
+                    about: "Remove the autostart entry",
+                    opts: &[],
+                    args: &[],
+                    subcommands: &[],
+                },
+            ],
+        },
+        Command {
+            name: "harden",
+            about: "Start Discord inside bubblewrap or firejail, through the launcher",
+            opts: &[],
+            args: &[],
+            subcommands: &[
+                Command {
+                    name: "bwrap",
+                    about: "Confine Discord with bubblewrap",
+                    opts: &[ALLOW],
+                    args: &[],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "firejail",
+                    about: "Confine Discord with firejail",
+                    opts: &[ALLOW],
+                    args: &[],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "off",
+                    about: "Start Discord without a sandbox again",
+                    opts: &[],
+                    args: &[],
+                    subcommands: &[],
+                },
+            ],
+        },
+        Command {
+            name: "rollback",
+            about: "Put back the install from before the last update",
+            opts: &[Opt {
+                long: "snapshot",
+                value: None,
+                help: "From the snapshot --snapshot took, needs the same permissions",
+            }],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "install-launcher",
+            about: "Replace the launcher symlink with a script that updates Discord before \
+                    starting it, at most once per --check-interval (default 1h)",
+            opts: &[
+                Opt {
+                    long: "env",
+                    value: Some("NAME=VALUE"),
+                    help: "Set an environment variable for Discord, can be given more than once",
+                },
+                Opt {
+                    long: "no-update-check",
+                    value: None,
+                    help: "Only set the environment, without checking for updates first",
+                },
+            ],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "uninstall",
+            about: "Remove the install, its launcher symlink, and its menu entry, keeping settings",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "integrate-user",
+            about: "Give yourself a launcher symlink, menu entry, and flags for the --system \
+                    install, which root keeps updating",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "audit-perms",
+            about: "Check the owners and modes of the install, chrome-sandbox, the launcher \
+                    symlink, and the config directories, and offer to fix them",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        Command {
+            name: "man",
+            about: "Write man pages for discord_update and each of its commands into DIR",
+            opts: &[],
+            args: &["DIR"],
+            subcommands: &[],
+        },
+        Command {
+            name: "system-helper",
+            about: "The parts of --system run as another user, for internal use",
+            opts: &[],
+            args: &[],
+            subcommands: &[
+                Command {
+                    name: "install",
+                    about:
+                        "Copy a staged install into the channel's install in /opt, owned by root",
+                    opts: &[],
+                    args: &["CHANNEL", "STAGED", "MANIFEST"],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "stage",
+                    about: "Download and extract a version unprivileged, for root to install",
+                    opts: &[],
+                    args: &["VERSION", "STAGED", "MANIFEST"],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "link",
+                    about: "Point the channel's launcher symlink in /usr/local/bin at its install",
+                    opts: &[],
+                    args: &["CHANNEL"],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "apparmor-load",
+                    about: "Write and load the AppArmor profile for the channel's install in /opt",
+                    opts: &[],
+                    args: &["CHANNEL"],
+                    subcommands: &[],
+                },
+                Command {
+                    name: "apparmor-unload",
+                    about: "Unload and delete the channel's AppArmor profile",
+                    opts: &[],
+                    args: &["CHANNEL"],
+                    subcommands: &[],
+                },
+            ],
+        },
+        #[cfg(feature = "gui")]
+        Command {
+            name: "gui",
+            about: "A window showing each channel's versions, to update and roll back from",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+        #[cfg(feature = "tui")]
+        Command {
+            name: "tui",
+            about: "Interactive terminal interface",
+            opts: &[],
+            args: &[],
+            subcommands: &[],
+        },
+    ],
+};
+
+/// Every option accepted anywhere in the command tree
+fn all_opts(command: &'static Command) -> Box<dyn Iterator<Item = &'static Opt>> {
+    Box::new(
+        command
+            .opts
+            .iter()
+            .chain(command.subcommands.iter().flat_map(all_opts)),
+    )
+}
+
+/// The environment variable that sets an option, `DISCORD_UPDATE_SYMLINK_DIR` for `--symlink-dir`
+pub fn env_var(long: &str) -> String {
+    format!("DISCORD_UPDATE_{}", long.to_uppercase().replace('-', "_"))
+}
+
+/// Parse the value of a flag given somewhere other than the command line
+fn parse_flag(value: &str) -> Option<bool> {
+    match value.to_lowercase().as_str() {
+        "1" | "true" | "yes" | "on" => Some(true),
+        "" | "0" | "false" | "no" | "off" => Some(false),
+        _ => None,
+    }
+}
+
+/// The result of parsing the command line
+#[derive(Clone, Default, Debug)]
+pub struct Matches {
+    /// Names of the subcommands given, outermost first
+    pub subcommands: Vec<&'static str>,
+    pub positionals: Vec<String>,
+    values: HashMap<&'static str, Vec<String>>,
+    /// Options whose values came from the config file
+    from_config: HashSet<&'static str>,
+    /// Flags an environment variable turned off, which the config file can't turn on
+    turned_off: HashSet<&'static str>,
+}
+
+impl Matches {
+    /// The subcommand given at the given depth, if any
+    pub fn subcommand(&self, depth: usize) -> Option<&'static str> {
+        self.subcommands.get(depth).copied()
+    }
+
+    /// Set an option as if it was given on the command line
+    pub fn set(&mut self, long: &'static str, value: String) {
+        self.values.insert(long, vec![value]);
+        self.from_config.remove(long);
+    }
+
+    pub fn flag(&self, long: &str) -> bool {
+        self.values.contains_key(long)
+    }
+
+    /// Every value given for an option, for ones that can be repeated
+    pub fn values(&self, long: &str) -> &[String] {
+        self.values.get(long).map_or(&[], Vec::as_slice)
+    }
+
+    /// The last value given for an option
+    pub fn value(&self, long: &str) -> Option<&str> {
+        self.values.get(long)?.last().map(String::as_str)
+    }
+
+    /// Whether an option was set on the command line or in the environment, rather than only
+    /// in the config file
+    pub fn explicit(&self, long: &str) -> bool {
+        self.values.contains_key(long) && !self.from_config.contains(long)
+    }
+
+    /// Fill in options not given on the command line from `DISCORD_UPDATE_*` variables
+    pub fn apply_env(&mut self, vars: impl IntoIterator<Item = (String, String)>) -> Result<()> {
+        let vars: HashMap<_, _> = vars.into_iter().collect();
+        for opt in all_opts(&COMMAND) {
+            let name = env_var(opt.long);
+            let Some(value) = vars.get(&name) else {
+                continue;
+            };
+            if self.values.contains_key(opt.long) {
+                continue;
+            }
+            match opt.value {
+                Some(_) => {
+                    self.values.insert(opt.long, vec![value.clone()]);
+                }
+                None => match parse_flag(value) {
+                    Some(true) => {
+                        self.values.insert(opt.long, vec![String::new()]);
+                    }
+                    Some(false) => {
+                        self.turned_off.insert(opt.long);
+                    }
+                    None => return Err(format!("invalid value {value:?} for {name}").into()),
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Fill in options still missing from the config file's `"option": value` pairs
+    ///
+    /// Keys are option names, with `_` accepted in place of `-`.
+    pub fn apply_config(
+        &mut self,
+        config: &BTreeMap<String, serde_json::Value>,
+        source: &Path,
+    ) -> Result<()> {
+        for (key, value) in config {
+            let long = key.replace('_', "-");
+            let opt = all_opts(&COMMAND)
+                .find(|o| o.long == long)
+                .ok_or_else(|| format!("unknown setting {key:?} in {}", source.display()))?;
+            if self.values.contains_key(opt.long) || self.turned_off.contains(opt.long) {
+                continue;
+            }
+            let value = match (opt.value, value) {
+                (Some(_), serde_json::Value::String(s)) => s.clone(),
+                (Some(_), serde_json::Value::Number(n)) => n.to_string(),
+                (None, serde_json::Value::Bool(false)) => continue,
+                (None, serde_json::Value::Bool(true)) => String::new(),
+                _ => {
+                    let expected = if opt.value.is_some() {
+                        "a string"
+                    } else {
+                        "true or false"
+                    };
+                    return Err(
+                        format!("{key:?} in {} should be {expected}", source.display()).into(),
+                    );
+                }
+            };
+            self.values.insert(opt.long, vec![value]);
+            self.from_config.insert(opt.long);
+        }
+        Ok(())
+    }
+
+    /// Parse the value of an option
+    pub fn parse_value<T>(&self, long: &str) -> Result<Option<T>>
+    where
+        T: FromStr,
+        T::Err: std::fmt::Display,
+    {
+        self.value(long)
+            .map(|v| {
+                v.parse()
+                    .map_err(|e| Error::from(format!("invalid value {v:?} for --{long}: {e}")))
+            })
+            .transpose()
+    }
+}
+
+/// A length of time given on the command line, in seconds or with an s/m/h/d suffix
+#[derive(Clone, Copy, Debug)]
+pub struct DurationArg(pub Duration);
+
+/// The longest `DurationArg`, a century, well short of overflowing a time it's added to
+const MAX_DURATION: u64 = 100 * 365 * 24 * 60 * 60;
+
+impl FromStr for DurationArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let expected = || "expected a number of seconds, optionally with s, m, h, or d".to_owned();
+        let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => s.split_at(i),
+            None => (s, "s"),
+        };
+        let scale = match unit {
+            "s" => 1,
+            "m" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            _ => return Err(expected()),
+        };
+        let number: u64 = number.parse().map_err(|_| expected())?;
+        match number
+            .checked_mul(scale)
+            .filter(|&secs| secs <= MAX_DURATION)
+        {
+            Some(secs) => Ok(Self(Duration::from_secs(secs))),
+            None => Err(format!("{s} is more than 100 years")),
+        }
+    }
+}
+
+/// A `--max-download-size` like `300M`, in bytes
+#[derive(Clone, Copy, Debug)]
+pub struct SizeArg(pub u64);
+
+impl FromStr for SizeArg {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let expected = || "expected a number of bytes, optionally with K, M, or G".to_owned();
+        let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+            Some(i) => s.split_at(i),
+            None => (s, ""),
+        };
+        let unit = unit.to_uppercase();
+        let scale = match unit
+            .strip_suffix("IB")
+            .or(unit.strip_suffix('B'))
+            .unwrap_or(&unit)
+        {
+            "" => 1,
+            "K" => 1 << 10,
+            "M" => 1 << 20,
+            "G" => 1 << 30,
+            _ => return Err(expected()),
+        };
+        let number: u64 = number.parse().map_err(|_| expected())?;
+        let bytes = number
+            .checked_mul(scale)
+            .ok_or_else(|| format!("{s} is too big"))?;
+        Ok(Self(bytes))
+    }
+}
+
+/// Render the help for the given command
+pub fn help(path: &[&Command]) -> String {
+    let command = path.last().unwrap();
+    let name: Vec<_> = path.iter().map(|c| c.name).collect();
+    let mut out = format!("{}\n\nUsage: {}", command.about, name.join(" "));
+    if !command.opts.is_empty() || path.len() > 1 {
+        out += " [OPTIONS]";
+    }
+    if !command.subcommands.is_empty() {
+        out += " [COMMAND]";
+    }
+    for arg in command.args {
+        if arg.starts_with('[') {
+            out += &format!(" {arg}");
+        } else {
+            out += &format!(" <{arg}>");
+        }
+    }
+    out += "\n";
+
+    if !command.subcommands.is_empty() {
+        out += "\nCommands:\n";
+        for sub in command.subcommands {
+            out += &format!("  {:<24}{}\n", sub.name, sub.about);
+        }
+    }
+
+    out += "\nOptions:\n";
+    let opts = path.iter().rev().flat_map(|c| c.opts).chain([&HELP]);
+    for opt in opts {
+        let usage = match opt.value {
+            Some(value) => format!("--{} <{value}>", opt.long),
+            None => format!("--{}", opt.long),
+        };
+        out += &format!("  {usage:<24}{}\n", opt.help);
+    }
+    out += &format!(
+        "\nOptions can also be set with environment variables like {}, or in \
+         ~/.config/discord_update/config.json like {{\"channel\": \"ptb\"}}, and for one \
+         channel like {{\"channels\": {{\"stable\": {{\"notify_only\": true}}}}}}, or \
+         together under a name for --profile like {{\"profiles\": {{\"family\": {{\"channel\": \
+         \"stable\", \"system\": true}}}}}}\n",
+        env_var("channel")
+    );
+    out
+}
+
+/// Parse the given arguments (excluding the program name)
+///
+/// Options belonging to any command along the subcommand path are accepted, so global options
+/// may come before or after the subcommand. `--help` prints help and exits.
+pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Matches> {
+    let mut matches = Matches::default();
+    let mut path = vec![&COMMAND];
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let command = *path.last().unwrap();
+        if let Some(long) = arg.strip_prefix("--") {
+            let (long, inline) = match long.split_once('=') {
+                Some((long, value)) => (long, Some(value.to_owned())),
+                None => (long, None),
+            };
+            if long == HELP.long {
+                print!("{}", help(&path));
+                std::process::exit(0);
+            }
+            let opt = path
+                .iter()
+                .rev()
+                .flat_map(|c| c.opts)
+                .find(|o| o.long == long)
+                .ok_or_else(|| format!("unknown option --{long}, see --help"))?;
+            let value = match (opt.value, inline) {
+                (Some(_), Some(value)) => value,
+                (Some(name), None) => args
+                    .next()
+                    .ok_or_else(|| format!("--{long} requires a value <{name}>"))?,
+                (None, Some(_)) => return Err(format!("--{long} doesn't take a value").into()),
+                (None, None) => String::new(),
+            };
+            matches.values.entry(opt.long).or_default().push(value);
+        } else if let Some(sub) = command.subcommands.iter().find(|c| c.name == arg) {
+            matches.subcommands.push(sub.name);
+            path.push(sub);
+        } else if matches.positionals.len() < command.args.len() {
+            matches.positionals.push(arg);
+        } else {
+            return Err(format!("unexpected argument {arg:?}, see --help").into());
+        }
+    }
+
+    let command = *path.last().unwrap();
+    let mut required = command.args.iter().filter(|a| !a.starts_with('['));
+    if let Some(missing) = required.nth(matches.positionals.len()) {
+        return Err(format!("missing argument <{missing}>, see --help").into());
+    }
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::channel::Channel;
+
+    /// The options from `args`, `env`, and a config file of `config`, layered like a run does
+    fn layered(args: &[&str], env: &[(&str, &str)], config: &str) -> crate::Options {
+        let mut matches = parse(args.iter().map(|a| a.to_string())).unwrap();
+        let env = env.iter().map(|(k, v)| (k.to_string(), v.to_string()));
+        matches.apply_env(env).unwrap();
+        let config = serde_json::from_str(config).unwrap();
+        matches
+            .apply_config(&config, Path::new("config.json"))
+            .unwrap();
+        crate::Options::from_matches(&matches).unwrap()
+    }
+
+    #[test]
+    fn flags_win_over_the_environment_and_it_over_the_config() {
+        let config = r#"{"channel": "ptb", "install-path": "/config", "api_url": "https://config",
+            "check-interval": "1d", "notify-only": true}"#;
+        let env = [
+            ("DISCORD_UPDATE_CHANNEL", "canary"),
+            ("DISCORD_UPDATE_INSTALL_PATH", "/env"),
+            ("DISCORD_UPDATE_API_URL", "https://env"),
+            ("DISCORD_UPDATE_CHECK_INTERVAL", "1h"),
+            ("DISCORD_UPDATE_NOTIFY_ONLY", "0"),
+        ];
+        let args = [
+            "--channel=stable",
+            "--install-path=/flag",
+            "--api-url=https://flag",
+            "--check-interval=1m",
+            "--notify-only",
+        ];
+        let options = layered(&[], &[], config);
+        assert_eq!(options.channel, Channel::Ptb);
+        assert_eq!(options.install_path.as_deref(), Some(Path::new("/config")));
+        assert_eq!(options.api_base, "https://config");
+        assert_eq!(
+            options.check_interval,
+            Some(Duration::from_secs(24 * 60 * 60))
+        );
+        assert!(options.notify_only);
+
+        let options = layered(&[], &env, config);
+        assert_eq!(options.channel, Channel::Canary);
+        assert_eq!(options.install_path.as_deref(), Some(Path::new("/env")));
+        assert_eq!(options.api_base, "https://env");
+        assert_eq!(options.check_interval, Some(Duration::from_secs(60 * 60)));
+        assert!(!options.notify_only);
+
+        let options = layered(&args, &env, config);
+        assert_eq!(options.channel, Channel::Stable);
+        assert_eq!(options.install_path.as_deref(), Some(Path::new("/flag")));
+        assert_eq!(options.api_base, "https://flag");
+        assert_eq!(options.check_interval, Some(Duration::from_secs(60)));
+        assert!(options.notify_only);
+
+        // Each layer only fills in what the ones above it left out
+        let options = layered(&["--channel=stable"], &env[1..2], config);
+        assert_eq!(options.channel, Channel::Stable);
+        assert_eq!(options.install_path.as_deref(), Some(Path::new("/env")));
+        assert_eq!(options.api_base, "https://config");
+    }
+
+    #[test]
+    fn parses_durations() {
+        let cases = [
+            ("90", Some(90)),
+            ("90s", Some(90)),
+            ("5m", Some(5 * 60)),
+            ("2h", Some(2 * 60 * 60)),
+            ("7d", Some(7 * 24 * 60 * 60)),
+            ("0", Some(0)),
+            ("36500d", Some(MAX_DURATION)),
+            ("36501d", None),
+            ("99999999999d", None),
+            ("99999999999999999999", None),
+            ("", None),
+            ("d", None),
+            ("-5", None),
+            ("-5m", None),
+            ("5M", None),
+            ("5ms", None),
+            ("1.5h", None),
+            (" 5", None),
+        ];
+        for (arg, secs) in cases {
+            let parsed = arg.parse::<DurationArg>().ok().map(|d| d.0.as_secs());
+            assert_eq!(parsed, secs, "{arg:?}");
+        }
+    }
+
+    #[test]
+    fn parses_sizes() {
+        let cases = [
+            ("300", Some(300)),
+            ("300B", Some(300)),
+            ("4k", Some(4 << 10)),
+            ("4KB", Some(4 << 10)),
+            ("300M", Some(300 << 20)),
+            ("300MiB", Some(300 << 20)),
+            ("2g", Some(2 << 30)),
+            ("0", Some(0)),
+            ("99999999999G", None),
+            ("99999999999999999999", None),
+            ("", None),
+            ("M", None),
+            ("-300M", None),
+            ("300T", None),
+            ("300MBB", None),
+            ("1.5G", None),
+        ];
+        for (arg, bytes) in cases {
+            assert_eq!(arg.parse::<SizeArg>().ok().map(|s| s.0), bytes, "{arg:?}");
+        }
+    }
+}
@@ -0,0 +1,145 @@
+//! Asking a running client to quit before it's updated underneath it
+//!
+//! Neither Discord's RPC socket nor Chromium's singleton socket take a quit command from
+//! outside, so a quit is asked for with SIGTERM, which Electron handles like closing the app
+//! from the tray: pending messages are sent and local storage flushed. It's never killed
+//! outright, a client that doesn't quit in time is left to the user.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{confirm, platform, running, Result};
+use indicatif::{HumanDuration, ProgressBar};
+use semver::Version;
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+/// How long the client gets to quit after being asked
+const QUIT_TIMEOUT: Duration = Duration::from_secs(15);
+/// How often `--when-closed` looks for the client having exited
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Whether process `pid` was started from a binary in `install_path`
+///
+/// The SingletonLock only names a pid, which by now may be any other process.
+fn runs_from(pid: i32, install_path: &Path) -> bool {
+    let Ok(exe) = fs::read_link(Path::new("/proc").join(pid.to_string()).join("exe")) else {
+        return false;
+    };
+    let exe = exe.to_string_lossy();
+    let exe = Path::new(exe.strip_suffix(running::DELETED).unwrap_or(&exe));
+    fs::canonicalize(install_path).is_ok_and(|install| exe.starts_with(install))
+}
+
+/// Ask the client to quit and wait for it, whether it did
+async fn quit(channel: Channel, install_path: &Path) -> Result<bool> {
+    let platform = platform::current();
+    let pid = platform.running_pid(channel).ok_or_else(|| {
+        format!("can't tell which process Discord {channel} is, so can't ask it to quit")
+    })?;
+    if !runs_from(pid, install_path) {
+        return Err(format!(
+            "process {pid} isn't Discord from {}, so it isn't asked to quit",
+            install_path.display()
+        )
+        .into());
+    }
+    // SAFETY: kill takes no pointers
+    if unsafe { libc::kill(pid, libc::SIGTERM) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    let started = tokio::time::Instant::now();
+    while started.elapsed() < QUIT_TIMEOUT {
+        tokio::time::sleep(Duration::from_millis(250)).await;
+        if !platform.is_running(channel) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Close the channel's client ahead of an update, returning whether it's still running
+///
+/// Interactive runs ask first unless `--close-running`, non-interactive ones only close it with
+/// `--close-running`. When it won't quit, interactive runs ask whether to update anyway.
+pub async fn before_update(
+    prog: &Progress,
+    channel: Channel,
+    install_path: &Path,
+    interactive: bool,
+    close_running: bool,
+) -> Result<bool> {
+    if !platform::current().is_running(channel) {
+        return Ok(false);
+    }
+    let close = close_running
+        || (interactive
+            && confirm(
+                prog,
+                &format!("Discord {channel} is running, close it?"),
+                true,
+            )?);
+    if !close {
+        return Ok(true);
+    }
+    prog.println(format!("Asking Discord {channel} to quit"))?;
+    let error = match quit(channel, install_path).await {
+        Ok(true) => return Ok(false),
+        Ok(false) => format!(
+            "Discord {channel} didn't quit within {}s",
+            QUIT_TIMEOUT.as_secs()
+        ),
+        Err(e) => format!("Couldn't close Discord {channel}: {e}"),
+    };
+    prog.println(error)?;
+    if interactive && !confirm(prog, "Update it while it's running?", false)? {
+        return Err(format!("Discord {channel} is still running").into());
+    }
+    Ok(true)
+}
+
+/// `--when-closed`, hold on to the staged update until the channel's client exits
+pub async fn wait_for_exit(
+    prog: &Progress,
+    spinner: &ProgressBar,
+    channel: Channel,
+    version: &Version,
+    timeout: Option<Duration>,
+) -> Result<()> {
+    let platform = platform::current();
+    if !platform.is_running(channel) {
+        return Ok(());
+    }
+    prog.println(format!(
+        "Discord {version} is ready, it'll be installed once Discord {channel} is closed"
+    ))?;
+    spinner.set_message(format!("Waiting for Discord {channel} to be closed"));
+    let started = tokio::time::Instant::now();
+    while platform.is_running(channel) {
+        if let Some(timeout) = timeout.filter(|&t| started.elapsed() >= t) {
+            return Err(format!(
+                "Discord {channel} was still running after {}, not installing {version}",
+                HumanDuration(timeout)
+            )
+            .into());
+        }
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_processes_of_the_install_run_from_it() {
+        let exe = fs::canonicalize(std::env::current_exe().unwrap()).unwrap();
+        let pid = std::process::id() as i32;
+        assert!(runs_from(pid, exe.parent().unwrap()));
+        let elsewhere = tempfile::tempdir().unwrap();
+        assert!(!runs_from(pid, elsewhere.path()));
+        assert!(!runs_from(pid, Path::new("/nonexistent")));
+        assert!(!runs_from(i32::MAX, exe.parent().unwrap()));
+    }
+}
@@ -0,0 +1,146 @@
+//! The optional config file, `$XDG_CONFIG_HOME/discord_update/config.json`
+//!
+//! It holds defaults for command line options, like `{"channel": "ptb", "yes": true}`.
+//! `DISCORD_UPDATE_*` environment variables take precedence over it, and flags over both.
+//!
+//! Defaults for just one channel go under `"channels"`, and win over the general ones:
+//! `{"channels": {"canary": {"check_interval": "1d"}, "stable": {"notify_only": true}}}`.
+//! Likewise for one architecture under `"architectures"`, keyed by names like `aarch64`.
+//!
+//! Named profiles under `"profiles"` are picked with `--profile`, or `"profile"` for a default,
+//! and can set anything including the channel: `{"profiles": {"family": {"system": true}}}`.
+//! A profile wins over the rest of the file.
+
+use crate::channel::Channel;
+use crate::{home_dir, Result};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// `$XDG_CONFIG_HOME`, usually `~/.config`
+pub fn config_home() -> Result<PathBuf> {
+    match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => Ok(home_dir()?.join(".config")),
+    }
+}
+
+/// `$XDG_CACHE_HOME`, usually `~/.cache`
+pub fn cache_home() -> Result<PathBuf> {
+    match std::env::var_os("XDG_CACHE_HOME") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => Ok(home_dir()?.join(".cache")),
+    }
+}
+
+/// `$XDG_DATA_HOME`, usually `~/.local/share`
+pub fn data_home() -> Result<PathBuf> {
+    match std::env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => Ok(PathBuf::from(dir)),
+        _ => Ok(home_dir()?.join(".local/share")),
+    }
+}
+
+/// Expand a leading `~/`, which the shell won't have done for config values or `--opt=~/...`
+pub fn expand_home(path: &Path) -> Result<PathBuf> {
+    match path.strip_prefix("~") {
+        Ok(rest) => Ok(home_dir()?.join(rest)),
+        Err(_) => Ok(path.to_owned()),
+    }
+}
+
+pub fn path() -> Result<PathBuf> {
+    Ok(config_home()?.join("discord_update/config.json"))
+}
+
+/// A section of settings for one of something, like a channel
+pub type Section = BTreeMap<String, Value>;
+
+/// Split the `key` sections, `{"<name>": {settings...}}`, out of a loaded config file
+fn take_sections(
+    config: &mut BTreeMap<String, Value>,
+    key: &str,
+    source: &Path,
+) -> Result<BTreeMap<String, Section>> {
+    let Some(sections) = config.remove(key) else {
+        return Ok(BTreeMap::new());
+    };
+    let bad = || {
+        format!(
+            "{key:?} in {} should map names to settings",
+            source.display()
+        )
+    };
+    let Value::Object(sections) = sections else {
+        return Err(bad().into());
+    };
+    sections
+        .into_iter()
+        .map(|(name, section)| match section {
+            Value::Object(section) => Ok((name, section.into_iter().collect())),
+            _ => Err(bad().into()),
+        })
+        .collect()
+}
+
+/// Split the per-channel sections out of a loaded config file
+pub fn take_channels(
+    config: &mut BTreeMap<String, Value>,
+    source: &Path,
+) -> Result<BTreeMap<Channel, Section>> {
+    let mut channels = BTreeMap::new();
+    for (name, section) in take_sections(config, "channels", source)? {
+        let channel: Channel = name
+            .parse()
+            .map_err(|e| format!("bad channel {name:?} in {}: {e}", source.display()))?;
+        if section.contains_key("channel") {
+            return Err(format!(
+                "\"channel\" can't be set per channel in {}",
+                source.display()
+            )
+            .into());
+        }
+        channels.insert(channel, section);
+    }
+    Ok(channels)
+}
+
+/// Split the per-architecture sections out, keyed by names like `aarch64`
+pub fn take_architectures(
+    config: &mut BTreeMap<String, Value>,
+    source: &Path,
+) -> Result<BTreeMap<String, Section>> {
+    take_sections(config, "architectures", source)
+}
+
+/// Split the named profiles out of a loaded config file
+pub fn take_profiles(
+    config: &mut BTreeMap<String, Value>,
+    source: &Path,
+) -> Result<BTreeMap<String, Section>> {
+    let profiles = take_sections(config, "profiles", source)?;
+    if let Some(name) = profiles.iter().find_map(|(name, profile)| {
+        ["profile", "channels", "architectures"]
+            .iter()
+            .any(|key| profile.contains_key(*key))
+            .then_some(name)
+    }) {
+        return Err(format!(
+            "profile {name:?} in {} can't pick a profile or have its own sections",
+            source.display()
+        )
+        .into());
+    }
+    Ok(profiles)
+}
+
+/// Load the config file, a missing one is the same as an empty one
+pub fn load() -> Result<BTreeMap<String, Value>> {
+    let path = path()?;
+    match std::fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| format!("bad config file {}: {e}", path.display()).into()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e.into()),
+    }
+}
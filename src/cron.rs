@@ -0,0 +1,220 @@
+//! The `enable-cron` and `disable-cron` subcommands, for systems without systemd
+//!
+//! With `--policy on-exit` the job doesn't update a running Discord underneath it. It downloads
+//! and stages the update, waits for Discord to exit, and installs it straight away, so the next
+//! launch is current. The wait holds the job's lock, so later runs skip until it's done.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::state::state_dir;
+use crate::{Options, Result};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::str::FromStr;
+use tokio::io::AsyncWriteExt as _;
+use tokio::process::Command;
+
+/// When to check for updates if `--schedule` isn't given
+pub const DEFAULT_SCHEDULE: &str = "23 */6 * * *";
+
+/// What the job does when Discord is running, from `--policy`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Policy {
+    /// Update right away, running or not
+    #[default]
+    Now,
+    /// Stage the update and install it once Discord exits, `--when-closed`
+    OnExit,
+}
+
+impl FromStr for Policy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "now" => Ok(Self::Now),
+            "on-exit" => Ok(Self::OnExit),
+            _ => Err("expected now or on-exit".into()),
+        }
+    }
+}
+
+/// Comment marking the entry we manage for the given channel
+fn marker(options: &Options) -> String {
+    format!("# discord_update --channel {}", options.channel)
+}
+
+/// The lock held while a scheduled update of the channel runs
+pub fn lock_path(channel: Channel) -> Result<PathBuf> {
+    Ok(state_dir()?.join(format!("cron-{channel}.lock")))
+}
+
+/// Quote a string for the shell cron runs the command with
+///
+/// `%` means newline to cron, so it's escaped too.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''").replace('%', r"\%"))
+}
+
+/// The user's current crontab, empty if they don't have one
+async fn read_crontab() -> Result<String> {
+    let output = Command::new("crontab")
+        .arg("-l")
+        .stderr(Stdio::piped())
+        .output()
+        .await
+        .map_err(|e| format!("failed to run crontab: {e}"))?;
+    if output.status.success() {
+        Ok(String::from_utf8(output.stdout)?)
+    } else if String::from_utf8_lossy(&output.stderr).contains("no crontab") {
+        Ok(String::new())
+    } else {
+        Err(format!(
+            "crontab -l failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into())
+    }
+}
+
+/// Replace the user's crontab
+async fn write_crontab(contents: &str) -> Result<()> {
+    let mut child = Command::new("crontab")
+        .arg("-")
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("failed to run crontab: {e}"))?;
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(contents.as_bytes()).await?;
+    drop(stdin);
+    if !child.wait().await?.success() {
+        return Err("crontab rejected the new entries".into());
+    }
+    Ok(())
+}
+
+/// The arguments a scheduled run needs to update the same install as this one, after
+/// `--channel`
+fn forwarded(options: &Options, profile: Option<&str>) -> String {
+    let mut args = String::new();
+    if let Some(path) = &options.install_path {
+        args += &format!(" --install-path {}", shell_quote(&path.to_string_lossy()));
+    }
+    if options.system {
+        args += " --system";
+    }
+    if let Some(profile) = profile {
+        args += &format!(" --profile {}", shell_quote(profile));
+    }
+    args
+}
+
+/// The crontab without our entry for this channel
+fn without_entry(crontab: &str, marker: &str) -> String {
+    crontab
+        .lines()
+        .filter(|line| !line.ends_with(marker))
+        .map(|line| format!("{line}\n"))
+        .collect()
+}
+
+/// Add (or replace) a crontab entry that periodically updates this channel
+///
+/// The job holds a lock so a slow run can't overlap the next one, and its output is appended to
+/// a log file in the state directory. `--install-path`, `--system`, and the `--profile` given
+/// are passed on to it.
+pub async fn enable(
+    prog: &Progress,
+    options: &Options,
+    profile: Option<&str>,
+    schedule: &str,
+    policy: Policy,
+) -> Result<()> {
+    if schedule.split_whitespace().count() != 5 && !schedule.starts_with('@') {
+        return Err(format!("{schedule:?} isn't a cron schedule like {DEFAULT_SCHEDULE:?}").into());
+    }
+    let exe = std::env::current_exe()?;
+    let dir = state_dir()?;
+    tokio::fs::create_dir_all(&dir).await?;
+    let channel = options.channel;
+    let lock = lock_path(channel)?;
+    let log = dir.join(format!("cron-{channel}.log"));
+
+    let marker = marker(options);
+    let when = match policy {
+        Policy::Now => "",
+        Policy::OnExit => " --when-closed",
+    };
+    let entry = format!(
+        "{schedule} flock -n {} {} --non-interactive --channel {channel}{}{when} >> {} 2>&1 \
+         {marker}",
+        shell_quote(&lock.to_string_lossy()),
+        shell_quote(&exe.to_string_lossy()),
+        forwarded(options, profile),
+        shell_quote(&log.to_string_lossy()),
+    );
+    let mut crontab = without_entry(&read_crontab().await?, &marker);
+    crontab += &entry;
+    crontab += "\n";
+    write_crontab(&crontab).await?;
+    prog.println(format!("Added crontab entry: {entry}"))?;
+    prog.println(format!("Output will be logged to {}", log.display()))?;
+    Ok(())
+}
+
+/// Remove the crontab entry added by `enable`
+pub async fn disable(prog: &Progress, options: &Options) -> Result<()> {
+    let marker = marker(options);
+    let crontab = read_crontab().await?;
+    if !crontab.lines().any(|line| line.ends_with(&marker)) {
+        prog.println(format!("No crontab entry for {}", options.channel))?;
+        return Ok(());
+    }
+    write_crontab(&without_entry(&crontab, &marker)).await?;
+    prog.println(format!("Removed crontab entry for {}", options.channel))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quotes_for_the_shell_and_cron() {
+        assert_eq!(shell_quote("plain"), "'plain'");
+        assert_eq!(shell_quote("with space"), "'with space'");
+        assert_eq!(shell_quote("it's"), r"'it'\''s'");
+        assert_eq!(shell_quote("$HOME `id`"), "'$HOME `id`'");
+        assert_eq!(shell_quote("100%"), r"'100\%'");
+        assert_eq!(shell_quote(""), "''");
+    }
+
+    #[test]
+    fn removes_only_the_channels_entry() {
+        let marker = "# discord_update --channel stable";
+        let crontab = format!(
+            "MAILTO=me\n0 * * * * backup\n23 */6 * * * update {marker}\n\
+             23 */6 * * * update # discord_update --channel canary\n@reboot other\n"
+        );
+        assert_eq!(
+            without_entry(&crontab, marker),
+            "MAILTO=me\n0 * * * * backup\n\
+             23 */6 * * * update # discord_update --channel canary\n@reboot other\n"
+        );
+        assert_eq!(without_entry("", marker), "");
+    }
+
+    #[test]
+    fn forwards_what_picks_the_install() {
+        let options = Options {
+            install_path: Some("/home/me/my discord".into()),
+            system: true,
+            ..Options::default()
+        };
+        assert_eq!(
+            forwarded(&options, Some("family")),
+            " --install-path '/home/me/my discord' --system --profile 'family'"
+        );
+        assert_eq!(forwarded(&Options::default(), None), "");
+    }
+}
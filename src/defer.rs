@@ -0,0 +1,160 @@
+//! Holding off updates for a while, like ahead of travel or when a release is known to be bad
+//!
+//! Automatic runs don't install anything while a channel is deferred. Interactive ones still
+//! offer updates, since someone is there to say no.
+
+use crate::channel::Channel;
+use crate::cli::{DurationArg, Matches};
+use crate::progress::Progress;
+use crate::state::{self, State};
+use crate::Result;
+use std::ffi::CStr;
+use std::time::Duration;
+
+/// Seconds since the epoch at local midnight starting a YYYY-MM-DD date
+fn parse_date(date: &str) -> Result<u64> {
+    let invalid = || format!("{date:?} isn't a date like 2024-12-31");
+    let parts: Vec<_> = date.split('-').collect();
+    let [year, month, day] = parts[..] else {
+        return Err(invalid().into());
+    };
+    let field = |s: &str| s.parse::<libc::c_int>().map_err(|_| invalid());
+    // SAFETY: tm is plain data, all zero is a valid value
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    tm.tm_year = field(year)? - 1900;
+    tm.tm_mon = field(month)? - 1;
+    tm.tm_mday = field(day)?;
+    tm.tm_isdst = -1;
+    let (mon, mday) = (tm.tm_mon, tm.tm_mday);
+    // SAFETY: tm is a valid struct tm
+    let time = unsafe { libc::mktime(&mut tm) };
+    // mktime normalizes out of range fields, so 2024-02-30 would come back as March
+    if time < 0 || tm.tm_mon != mon || tm.tm_mday != mday {
+        return Err(invalid().into());
+    }
+    Ok(time as u64)
+}
+
+/// A time as a local date and time, for messages
+pub fn format_time(secs: u64) -> String {
+    let time = secs as libc::time_t;
+    // SAFETY: tm is plain data, and localtime_r and strftime get valid pointers and sizes
+    unsafe {
+        let mut tm: libc::tm = std::mem::zeroed();
+        libc::localtime_r(&time, &mut tm);
+        let mut buf = [0 as libc::c_char; 64];
+        libc::strftime(buf.as_mut_ptr(), buf.len(), c"%Y-%m-%d %H:%M".as_ptr(), &tm);
+        CStr::from_ptr(buf.as_ptr()).to_string_lossy().into_owned()
+    }
+}
+
+/// When updates to the channel are deferred until, if that's still to come
+pub fn deferred_until(channel: Channel) -> Result<Option<u64>> {
+    let state = State::load()?;
+    let until = state.channels.get(&channel).and_then(|c| c.deferred_until);
+    Ok(active(until, state::now()))
+}
+
+/// The deferral recorded as being until `until`, if it hasn't run out by `now`
+fn active(until: Option<u64>, now: u64) -> Option<u64> {
+    until.filter(|&until| until > now)
+}
+
+/// When `--for` or `--until` says to defer until, from `now`
+fn until(now: u64, duration: Option<Duration>, date: Option<&str>) -> Result<u64> {
+    let until = match (duration, date) {
+        (Some(_), Some(_)) => return Err("give one of --for and --until, not both".into()),
+        (Some(duration), None) => now + duration.as_secs(),
+        (None, Some(date)) => parse_date(date)?,
+        (None, None) => return Err("give --for or --until".into()),
+    };
+    if until <= now {
+        return Err("that's already passed".into());
+    }
+    Ok(until)
+}
+
+/// `defer`, set, cancel, or show how long the channel's updates are held off
+pub fn run(prog: &Progress, channel: Channel, matches: &Matches) -> Result<()> {
+    let duration = matches.parse_value::<DurationArg>("for")?.map(|d| d.0);
+    let date = matches.value("until");
+    let until = if matches.flag("cancel") {
+        None
+    } else if duration.is_none() && date.is_none() {
+        match deferred_until(channel)? {
+            Some(until) => prog.result(format!(
+                "Discord {channel} updates are deferred until {}",
+                format_time(until)
+            ))?,
+            None => prog.result(format!("Discord {channel} updates aren't deferred"))?,
+        }
+        return Ok(());
+    } else {
+        Some(until(state::now(), duration, date)?)
+    };
+    // Replacing what's there, so a deferral can be cut short as well as made longer
+    State::update(|state| state.channel(channel).deferred_until = until)?;
+    match until {
+        Some(until) => prog.println(format!(
+            "Automatic runs won't update Discord {channel} until {}",
+            format_time(until)
+        ))?,
+        None => prog.println(format!("Discord {channel} updates are no longer deferred"))?,
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: u64 = 1_700_000_000;
+    const DAY: u64 = 24 * 60 * 60;
+
+    #[test]
+    fn defers_for_a_while() {
+        let day = Duration::from_secs(DAY);
+        assert_eq!(until(NOW, Some(day), None).unwrap(), NOW + DAY);
+        assert_eq!(until(NOW, Some(day * 7), None).unwrap(), NOW + 7 * DAY);
+        assert!(until(NOW, Some(Duration::ZERO), None).is_err());
+        assert!(until(NOW, Some(day), Some("2099-01-01")).is_err());
+        assert!(until(NOW, None, None).is_err());
+    }
+
+    #[test]
+    fn defers_until_a_date() {
+        let until = until(NOW, None, Some("2099-12-31")).unwrap();
+        assert_eq!(format_time(until), "2099-12-31 00:00");
+        // Midnight starting it, whatever the time zone
+        assert_eq!(parse_date("2100-01-01").unwrap() - until, DAY);
+    }
+
+    #[test]
+    fn refuses_a_date_thats_passed() {
+        let error = until(NOW, None, Some("2020-01-01")).unwrap_err();
+        assert_eq!(error.to_string(), "that's already passed");
+    }
+
+    #[test]
+    fn refuses_what_isnt_a_date() {
+        for date in [
+            "",
+            "tomorrow",
+            "2099-12",
+            "2099-13-01",
+            "2099-02-30",
+            "2099-1-x",
+        ] {
+            assert!(parse_date(date).is_err(), "{date:?}");
+        }
+        assert!(parse_date("2096-02-29").is_ok());
+    }
+
+    #[test]
+    fn already_deferred_until_it_runs_out() {
+        assert_eq!(active(Some(NOW + 1), NOW), Some(NOW + 1));
+        assert_eq!(active(Some(NOW), NOW), None);
+        assert_eq!(active(Some(NOW - DAY), NOW), None);
+        assert_eq!(active(None, NOW), None);
+    }
+}
@@ -0,0 +1,267 @@
+//! Desktop integration: a menu entry with an icon, the `discord://` URL handler, and AppStream
+//! metainfo so software centres (GNOME Software, KDE Discover) can show the install
+
+use crate::channel::Channel;
+use crate::config::{config_home, data_home};
+use crate::progress::Progress;
+use crate::{launcher, Result, UpdateCheck};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// The MIME type of links Discord opens, like invites
+const URL_SCHEME: &str = "x-scheme-handler/discord";
+
+fn desktop_id(channel: Channel) -> String {
+    format!("{}.desktop", channel.app_id())
+}
+
+pub fn desktop_file(channel: Channel) -> Result<PathBuf> {
+    Ok(data_home()?.join(format!("applications/{}.desktop", channel.app_id())))
+}
+
+fn metainfo_file(channel: Channel) -> Result<PathBuf> {
+    Ok(data_home()?.join(format!("metainfo/{}.metainfo.xml", channel.app_id())))
+}
+
+/// The icon theme we put icons in, which every theme falls back to
+fn hicolor() -> Result<PathBuf> {
+    Ok(data_home()?.join("icons/hicolor"))
+}
+
+/// The tarball's icon is 256 pixels square
+fn icon_file(channel: Channel) -> Result<PathBuf> {
+    Ok(hicolor()?.join(format!("256x256/apps/{}.png", channel.app_id())))
+}
+
+/// Quote an argument for the Exec key of a desktop entry
+pub fn quote_exec(path: &Path) -> String {
+    let mut quoted = String::from("\"");
+    for c in path.to_string_lossy().chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+fn desktop_entry(check: &UpdateCheck) -> String {
+    let channel = check.channel;
+    format!(
+        "[Desktop Entry]\n\
+         Type=Application\n\
+         Name={title}\n\
+         GenericName=Internet Messenger\n\
+         Comment=Chat, talk, and hang out\n\
+         Exec={exec} %U\n\
+         Icon={icon}\n\
+         Categories=Network;InstantMessaging;\n\
+         MimeType={URL_SCHEME};\n\
+         StartupWMClass={wm_class}\n",
+        title = channel.title(),
+        exec = quote_exec(&launcher::wrapper(channel).unwrap_or_else(|| check.binary_path())),
+        icon = channel.app_id(),
+        wm_class = channel.wm_class(),
+    )
+}
+
+fn metainfo(channel: Channel, version: &semver::Version) -> String {
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<component type="desktop-application">
+  <id>{id}</id>
+  <name>{title}</name>
+  <summary>Chat, talk, and hang out</summary>
+  <metadata_license>CC0-1.0</metadata_license>
+  <project_license>LicenseRef-proprietary</project_license>
+  <developer id="com.discordapp">
+    <name>Discord Inc.</name>
+  </developer>
+  <url type="homepage">https://discord.com</url>
+  <launchable type="desktop-id">{id}.desktop</launchable>
+  <categories>
+    <category>Network</category>
+    <category>InstantMessaging</category>
+  </categories>
+  <releases>
+    <release version="{version}"/>
+  </releases>
+</component>
+"#,
+        id = channel.app_id(),
+        title = channel.title(),
+    )
+}
+
+/// Write `contents` to `path`, replacing it atomically
+pub fn write_file(path: &Path, contents: &str) -> io::Result<()> {
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let temp = tempfile::NamedTempFile::new_in(dir)?;
+    fs::write(temp.path(), contents)?;
+    temp.persist(path)?;
+    Ok(())
+}
+
+/// Make `desktop_id` the handler for Discord links in mimeapps.list, or stop it being one
+fn set_url_handler(desktop_id: &str, register: bool) -> Result<()> {
+    const DEFAULTS: &str = "[Default Applications]";
+    let path = config_home()?.join("mimeapps.list");
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+        Err(e) => return Err(e.into()),
+    };
+    let entry = format!("{URL_SCHEME}={desktop_id};");
+    let mut lines = vec![];
+    let mut section = "";
+    let mut registered = false;
+    for line in contents.lines() {
+        if line.starts_with('[') {
+            if register && section == DEFAULTS && !registered {
+                lines.push(entry.clone());
+                registered = true;
+            }
+            section = line.trim();
+        } else if let Some(handlers) = line.strip_prefix(&format!("{URL_SCHEME}=")) {
+            if register && section == DEFAULTS {
+                if !registered {
+                    lines.push(entry.clone());
+                    registered = true;
+                }
+                continue;
+            }
+            let others: Vec<_> = handlers
+                .split(';')
+                .filter(|h| !h.is_empty() && (register || *h != desktop_id))
+                .collect();
+            if !others.is_empty() {
+                lines.push(format!("{URL_SCHEME}={};", others.join(";")));
+            }
+            continue;
+        }
+        lines.push(line.to_owned());
+    }
+    if register && !registered {
+        if section != DEFAULTS {
+            lines.push(DEFAULTS.to_owned());
+        }
+        lines.push(entry);
+    }
+    let mut new_contents = lines.join("\n");
+    new_contents.push('\n');
+    if new_contents.trim() != contents.trim() {
+        write_file(&path, &new_contents)?;
+    }
+    Ok(())
+}
+
+/// Let desktop environments know entries and icons changed, where they keep caches of them
+///
+/// The tools are optional so failures are ignored.
+fn refresh_caches() -> Result<()> {
+    let applications = data_home()?.join("applications");
+    let _ = Command::new("update-desktop-database")
+        .arg("-q")
+        .arg(applications)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    let _ = Command::new("gtk-update-icon-cache")
+        .args(["-q", "-f", "-t"])
+        .arg(hicolor()?)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+    Ok(())
+}
+
+/// Which parts of the integration to set up, each one can be turned off
+#[derive(Clone, Copy, Debug)]
+pub struct Parts {
+    /// The menu entry and metainfo
+    pub entry: bool,
+    pub icon: bool,
+    /// Only possible with the entry, it's what handles the links
+    pub url_handler: bool,
+}
+
+/// Write (or refresh) the parts of the integration wanted for the checked install, now at
+/// `version`
+///
+/// Parts that are turned off are left as they are, `uninstall` removes them all.
+pub fn install(check: &UpdateCheck, version: &semver::Version, parts: Parts) -> Result<()> {
+    let channel = check.channel;
+    let (icon, tarball_icon) = (icon_file(channel)?, check.install_path.join("discord.png"));
+    // Only what's different is written, so running it again after a failed update is cheap
+    let mut changed = false;
+    if parts.icon && tarball_icon.exists() && fs::read(&icon).ok() != Some(fs::read(&tarball_icon)?)
+    {
+        fs::create_dir_all(icon.parent().unwrap())?;
+        fs::copy(tarball_icon, icon)?;
+        changed = true;
+    }
+    if parts.entry {
+        changed |= write_changed(&desktop_file(channel)?, &desktop_entry(check))?;
+        changed |= write_changed(&metainfo_file(channel)?, &metainfo(channel, version))?;
+        if parts.url_handler {
+            set_url_handler(&desktop_id(channel), true)?;
+        }
+    }
+    if changed {
+        refresh_caches()?;
+    }
+    Ok(())
+}
+
+/// `write_file` unless `path` already has `contents`, returning whether it wrote it
+fn write_changed(path: &Path, contents: &str) -> io::Result<bool> {
+    if fs::read_to_string(path).ok().as_deref() == Some(contents) {
+        return Ok(false);
+    }
+    write_file(path, contents)?;
+    Ok(true)
+}
+
+/// Have an existing menu entry start Discord through `exec`
+pub fn set_exec(channel: Channel, exec: &Path) -> Result<()> {
+    let path = desktop_file(channel)?;
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    let lines: Vec<_> = contents
+        .lines()
+        .map(|line| {
+            if line.starts_with("Exec=") {
+                format!("Exec={} %U", quote_exec(exec))
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect();
+    write_file(&path, &(lines.join("\n") + "\n"))?;
+    refresh_caches()
+}
+
+/// Undo everything `install` did for the channel
+pub fn remove(prog: &Progress, channel: Channel) -> Result<()> {
+    set_url_handler(&desktop_id(channel), false)?;
+    let files = [
+        desktop_file(channel)?,
+        metainfo_file(channel)?,
+        icon_file(channel)?,
+    ];
+    for path in files {
+        match fs::remove_file(&path) {
+            Ok(()) => prog.println(format!("Removed {}", path.display()))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+    refresh_caches()
+}
@@ -0,0 +1,134 @@
+//! Files left out of the install to save space, from `--exclude`
+//!
+//! Patterns are matched against whole paths inside the install, like `locales/de.pak` or
+//! `swiftshader`. `*` and `?` don't match `/`, `**` does, so `**/de.pak` is a `de.pak` anywhere,
+//! and excluding a directory excludes everything in it. What was excluded is recorded in the manifest, so its absence isn't mistaken for damage.
+
+use crate::{Result, KEY_FILES};
+use std::path::Path;
+
+/// Whether `name` matches the glob `pattern`
+fn glob(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', _)), _) if pattern.starts_with(b"**") => {
+            let rest = &pattern[2..];
+            // `**/` matches no directories too
+            rest.strip_prefix(b"/")
+                .is_some_and(|after| glob(after, name))
+                || glob(rest, name)
+                || (!name.is_empty() && glob(pattern, &name[1..]))
+        }
+        (Some((b'*', rest)), _) => {
+            glob(rest, name)
+                || name
+                    .first()
+                    .is_some_and(|&c| c != b'/' && glob(pattern, &name[1..]))
+        }
+        (Some((b'?', rest)), Some((&c, name))) => c != b'/' && glob(rest, name),
+        (Some((p, rest)), Some((c, name))) => p == c && glob(rest, name),
+        _ => false,
+    }
+}
+
+/// Parsed `--exclude` patterns
+#[derive(Clone, Debug, Default)]
+pub struct Excludes {
+    pub patterns: Vec<String>,
+}
+
+impl Excludes {
+    /// Parse a comma separated list of patterns, refusing ones that would break Discord
+    pub fn parse(patterns: &str, binary_name: &str) -> Result<Self> {
+        let excludes = Self {
+            patterns: patterns
+                .split(',')
+                .map(|p| p.trim().trim_matches('/').to_owned())
+                .filter(|p| !p.is_empty())
+                .collect(),
+        };
+        for file in [binary_name].iter().chain(KEY_FILES) {
+            if excludes.matches(Path::new(file)) {
+                return Err(format!("--exclude can't leave out {file}, Discord needs it").into());
+            }
+        }
+        Ok(excludes)
+    }
+
+    /// Whether the path, relative to the install, or any directory it's in is excluded
+    pub fn matches(&self, path: &Path) -> bool {
+        let path = path.as_os_str().as_encoded_bytes();
+        let prefixes = path
+            .iter()
+            .enumerate()
+            .filter(|(_, &c)| c == b'/')
+            .map(|(i, _)| &path[..i])
+            .chain([path]);
+        prefixes
+            .into_iter()
+            .any(|prefix| self.patterns.iter().any(|p| glob(p.as_bytes(), prefix)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn excludes(patterns: &str) -> Excludes {
+        Excludes::parse(patterns, "Discord").unwrap()
+    }
+
+    #[test]
+    fn patterns_are_anchored_at_the_install() {
+        let e = excludes("de.pak,/swiftshader");
+        assert!(e.matches(Path::new("de.pak")));
+        assert!(!e.matches(Path::new("locales/de.pak")));
+        assert!(e.matches(Path::new("swiftshader/libEGL.so")));
+        assert!(!e.matches(Path::new("lib/swiftshader")));
+    }
+
+    #[test]
+    fn single_stars_stay_in_a_directory() {
+        let e = excludes("locales/*.pak,lib?.so");
+        assert!(e.matches(Path::new("locales/de.pak")));
+        assert!(!e.matches(Path::new("locales/extra/de.pak")));
+        assert!(e.matches(Path::new("libx.so")));
+        assert!(!e.matches(Path::new("lib/.so")));
+    }
+
+    #[test]
+    fn double_stars_cross_directories() {
+        let e = excludes("**/de.pak,locales/**");
+        assert!(e.matches(Path::new("de.pak")));
+        assert!(e.matches(Path::new("a/b/de.pak")));
+        assert!(!e.matches(Path::new("a/b/fr.pak")));
+        assert!(e.matches(Path::new("locales/a/b")));
+        assert!(!e.matches(Path::new("localesx")));
+    }
+
+    #[test]
+    fn a_trailing_slash_is_the_directory() {
+        let e = excludes("locales/");
+        assert_eq!(e.patterns, ["locales"]);
+        assert!(e.matches(Path::new("locales")));
+        assert!(e.matches(Path::new("locales/de.pak")));
+        assert!(!e.matches(Path::new("locales.pak")));
+    }
+
+    #[test]
+    fn a_parent_directory_excludes_what_is_in_it() {
+        let e = excludes("swift*");
+        assert!(e.matches(Path::new("swiftshader/a/libEGL.so")));
+        assert!(!e.matches(Path::new("lib/swiftshader/libEGL.so")));
+    }
+
+    #[test]
+    fn refuses_what_discord_needs() {
+        assert!(Excludes::parse("Disc*", "Discord").is_err());
+        assert!(Excludes::parse("**", "Discord").is_err());
+        assert!(Excludes::parse(" , ", "Discord")
+            .unwrap()
+            .patterns
+            .is_empty());
+    }
+}
@@ -0,0 +1,67 @@
+//! A secondary feed of versions known to be broken or good on Linux, from `--version-feed`
+//!
+//! The feed is JSON keyed by channel and version:
+//! `{"stable": {"0.0.98": {"status": "broken", "reason": "crashes on start under Wayland"}}}`.
+//! Runs without anyone to ask hold back broken versions, interactive ones warn and ask as usual.
+//! With `--version-feed-strict`, versions the feed hasn't marked good yet are held back too.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{fetch, Result};
+use semver::Version;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Broken,
+    Good,
+}
+
+#[derive(Debug, Deserialize)]
+struct Entry {
+    status: Status,
+    reason: Option<String>,
+}
+
+type Feed = BTreeMap<Channel, BTreeMap<Version, Entry>>;
+
+/// Why the feed at `url` says not to install `version`, if it does
+pub async fn hold_reason(
+    prog: &Progress,
+    url: &str,
+    strict: bool,
+    channel: Channel,
+    version: &Version,
+) -> Result<Option<String>> {
+    let feed: Feed = match fetch::get(url).await {
+        Ok(resp) => resp
+            .json()
+            .await
+            .map_err(|e| format!("bad version feed from {url}: {e}"))?,
+        Err(e) if strict => return Ok(Some(format!("the version feed couldn't be read: {e}"))),
+        Err(e) => {
+            prog.println(format!("Couldn't read the version feed, ignoring it: {e}"))?;
+            return Ok(None);
+        }
+    };
+    let entry = feed
+        .get(&channel)
+        .and_then(|versions| versions.get(version));
+    Ok(match entry {
+        Some(Entry {
+            status: Status::Broken,
+            reason,
+        }) => Some(match reason {
+            Some(reason) => format!("the version feed flags it as broken: {reason}"),
+            None => "the version feed flags it as broken".to_owned(),
+        }),
+        Some(Entry {
+            status: Status::Good,
+            ..
+        }) => None,
+        None if strict => Some("the version feed hasn't cleared it yet".to_owned()),
+        None => None,
+    })
+}
@@ -0,0 +1,264 @@
+//! HTTP requests, behind a trait so they can be served from somewhere other than the network
+
+use crate::{integrity, pinning, Result};
+use bytes::Bytes;
+use futures::future::LocalBoxFuture;
+use futures::stream::{LocalBoxStream, StreamExt as _, TryStreamExt as _};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// A successful response
+pub struct Response {
+    pub content_length: Option<u64>,
+    /// The strong ETag, or failing that Last-Modified, for resuming with If-Range
+    pub validator: Option<String>,
+    /// The body starts part way, at the offset asked for with `get_from`
+    pub partial: bool,
+    /// The MD5 of the whole body, when the headers give it
+    pub md5: Option<[u8; 16]>,
+    pub body: LocalBoxStream<'static, Result<Bytes>>,
+}
+
+impl Response {
+    pub async fn bytes(self) -> Result<Vec<u8>> {
+        self.body
+            .try_fold(vec![], |mut all, chunk| async move {
+                all.extend_from_slice(&chunk);
+                Ok(all)
+            })
+            .await
+    }
+
+    pub async fn text(self) -> Result<String> {
+        Ok(String::from_utf8(self.bytes().await?)?)
+    }
+
+    pub async fn json<T: DeserializeOwned>(self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.bytes().await?)?)
+    }
+}
+
+/// A 429 response, with how long the server asked us to wait
+#[derive(Debug)]
+pub struct RateLimited {
+    pub url: String,
+    /// From Retry-After, when it's in seconds
+    pub retry_after: Option<Duration>,
+}
+
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is rate limiting us", self.url)?;
+        if let Some(after) = self.retry_after {
+            write!(f, ", it asks to wait {}s", after.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Makes GET requests, failing on error statuses
+pub trait HttpFetcher: Send + Sync {
+    fn get<'a>(&'a self, url: &'a str) -> LocalBoxFuture<'a, Result<Response>>;
+
+    /// GET the rest of `url` from `offset`, if it's still what `validator` came with
+    ///
+    /// A server that doesn't do ranges, or whose file changed, sends all of it instead.
+    fn get_from<'a>(
+        &'a self,
+        url: &'a str,
+        _offset: u64,
+        _validator: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Response>> {
+        self.get(url)
+    }
+
+    /// How big the body at `url` is, without fetching it, when the server says
+    fn content_length<'a>(&'a self, _url: &'a str) -> LocalBoxFuture<'a, Result<Option<u64>>> {
+        Box::pin(async { Ok(None) })
+    }
+
+    /// POST a JSON body, for notifications, ignoring the response body
+    fn post_json<'a>(
+        &'a self,
+        url: &'a str,
+        _body: &'a serde_json::Value,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move { Err(format!("can't POST to {url}").into()) })
+    }
+}
+
+/// Fetches from the network
+pub struct ReqwestFetcher {
+    client: reqwest::Client,
+    /// Public key pins servers must match, from `--pin-keys`
+    pins: Vec<String>,
+}
+
+/// The pin of the certificate a response came over
+fn response_pin(resp: &reqwest::Response) -> Result<String> {
+    let cert = resp
+        .extensions()
+        .get::<reqwest::tls::TlsInfo>()
+        .and_then(|info| info.peer_certificate())
+        .ok_or_else(|| format!("{} wasn't fetched over HTTPS", resp.url()))?;
+    pinning::pin(cert)
+}
+
+impl ReqwestFetcher {
+    /// Make every request through `proxy` if given, otherwise the environment's proxy settings
+    /// apply
+    ///
+    /// With `pins`, only servers with one of those public keys are talked to.
+    pub fn new(proxy: Option<&str>, pins: Vec<String>) -> Result<Self> {
+        let mut builder = reqwest::Client::builder().tls_info(true);
+        if let Some(proxy) = proxy {
+            builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+        }
+        Ok(Self {
+            client: builder.build()?,
+            pins,
+        })
+    }
+
+    /// The pin of the server at `url`, whatever it's pinned to now
+    pub async fn server_pin(&self, url: &str) -> Result<String> {
+        response_pin(&self.client.head(url).send().await?)
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<Response> {
+        let resp = request.send().await?;
+        self.check_pin(&resp)?;
+        if resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|after| after.to_str().ok()?.trim().parse().ok())
+                .map(Duration::from_secs);
+            return Err(Box::new(RateLimited {
+                url: resp.url().to_string(),
+                retry_after,
+            }));
+        }
+        let resp = resp.error_for_status()?;
+        let header = |name: &str| {
+            resp.headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_owned)
+        };
+        // Weak ETags can't be used with If-Range
+        let etag = header("etag").filter(|etag| !etag.starts_with("W/"));
+        let partial = resp.status() == reqwest::StatusCode::PARTIAL_CONTENT;
+        Ok(Response {
+            content_length: resp.content_length(),
+            validator: etag.or_else(|| header("last-modified")),
+            partial,
+            // A part's MD5 would only be of that part
+            md5: integrity::advertised_md5(header).filter(|_| !partial),
+            body: resp.bytes_stream().map_err(Into::into).boxed_local(),
+        })
+    }
+
+    /// Fail unless the response came from a pinned server, when there are pins
+    fn check_pin(&self, resp: &reqwest::Response) -> Result<()> {
+        if self.pins.is_empty() {
+            return Ok(());
+        }
+        let pin = response_pin(resp)?;
+        if !self.pins.contains(&pin) {
+            return Err(format!(
+                "{} presented key {pin}, which isn't one of --pin-keys. If the server's \
+                 key was rotated, check the new one before pinning it",
+                resp.url().host_str().unwrap_or_default()
+            )
+            .into());
+        }
+        Ok(())
+    }
+}
+
+impl HttpFetcher for ReqwestFetcher {
+    fn get<'a>(&'a self, url: &'a str) -> LocalBoxFuture<'a, Result<Response>> {
+        Box::pin(async move { self.send(self.client.get(url)).await })
+    }
+
+    fn get_from<'a>(
+        &'a self,
+        url: &'a str,
+        offset: u64,
+        validator: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Response>> {
+        Box::pin(async move {
+            let request = self
+                .client
+                .get(url)
+                .header(reqwest::header::RANGE, format!("bytes={offset}-"))
+                .header(reqwest::header::IF_RANGE, validator);
+            self.send(request).await
+        })
+    }
+
+    fn content_length<'a>(&'a self, url: &'a str) -> LocalBoxFuture<'a, Result<Option<u64>>> {
+        Box::pin(async move {
+            let resp = self.client.head(url).send().await?;
+            self.check_pin(&resp)?;
+            let resp = resp.error_for_status()?;
+            // The body of a HEAD response is empty, so its length is only in the header
+            Ok(resp
+                .headers()
+                .get(reqwest::header::CONTENT_LENGTH)
+                .and_then(|len| len.to_str().ok()?.parse().ok()))
+        })
+    }
+
+    fn post_json<'a>(
+        &'a self,
+        url: &'a str,
+        body: &'a serde_json::Value,
+    ) -> LocalBoxFuture<'a, Result<()>> {
+        Box::pin(async move {
+            let resp = self.client.post(url).json(body).send().await?;
+            self.check_pin(&resp)?;
+            resp.error_for_status()?;
+            Ok(())
+        })
+    }
+}
+
+static FETCHER: OnceLock<Box<dyn HttpFetcher>> = OnceLock::new();
+
+/// Send every request through `fetcher`, this only has an effect before the first request
+pub fn set_fetcher(fetcher: Box<dyn HttpFetcher>) {
+    let _ = FETCHER.set(fetcher);
+}
+
+/// The fetcher requests go through
+pub fn fetcher() -> &'static dyn HttpFetcher {
+    FETCHER
+        .get_or_init(|| Box::new(ReqwestFetcher::new(None, vec![]).unwrap()))
+        .as_ref()
+}
+
+/// GET a URL
+pub async fn get(url: &str) -> Result<Response> {
+    fetcher().get(url).await
+}
+
+/// GET the rest of a URL from `offset`, see `HttpFetcher::get_from`
+pub async fn get_from(url: &str, offset: u64, validator: &str) -> Result<Response> {
+    fetcher().get_from(url, offset, validator).await
+}
+
+/// The size of what's at a URL, from a HEAD request
+pub async fn content_length(url: &str) -> Result<Option<u64>> {
+    fetcher().content_length(url).await
+}
+
+/// POST JSON to a URL
+pub async fn post_json(url: &str, body: &serde_json::Value) -> Result<()> {
+    fetcher().post_json(url, body).await
+}
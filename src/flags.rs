@@ -0,0 +1,115 @@
+//! Curated sets of Electron flags, applied with `flags apply PRESET`
+//!
+//! Discord doesn't read a flags file, so they go on the command line in the launcher, which the
+//! menu entry starts Discord through. Without a launcher, applying a preset makes one that only
+//! passes the flags on.
+
+use crate::cli::Matches;
+use crate::launcher::{self, Settings};
+use crate::progress::Progress;
+use crate::{Options, Result};
+
+pub struct Preset {
+    pub name: &'static str,
+    pub about: &'static str,
+    pub flags: &'static [&'static str],
+    /// Presets this one can't be combined with, applying it takes them off
+    pub conflicts: &'static [&'static str],
+}
+
+pub const PRESETS: &[Preset] = &[
+    Preset {
+        name: "wayland",
+        about: "Run natively on Wayland, with window decorations",
+        flags: &[
+            "--enable-features=UseOzonePlatform,WaylandWindowDecorations",
+            "--ozone-platform=wayland",
+        ],
+        conflicts: &["x11-fallback"],
+    },
+    Preset {
+        name: "x11-fallback",
+        about: "Run through XWayland, for when native Wayland misbehaves",
+        flags: &["--ozone-platform=x11"],
+        conflicts: &["wayland"],
+    },
+    Preset {
+        name: "no-hardware-acceleration",
+        about: "Render without the GPU, for drivers that show a black or flickering window",
+        flags: &["--disable-gpu", "--disable-gpu-compositing"],
+        conflicts: &[],
+    },
+    Preset {
+        name: "hidpi",
+        about: "Scale the window up 2x, for high DPI screens on X11",
+        flags: &["--force-device-scale-factor=2"],
+        conflicts: &[],
+    },
+];
+
+fn find(name: &str) -> Result<&'static Preset> {
+    PRESETS.iter().find(|p| p.name == name).ok_or_else(|| {
+        let names: Vec<_> = PRESETS.iter().map(|p| p.name).collect();
+        format!("no preset {name:?}, there's {}", names.join(", ")).into()
+    })
+}
+
+/// The flags of the named presets, ones no longer known are left out
+pub fn of(presets: &[String]) -> impl Iterator<Item = &'static str> + '_ {
+    presets
+        .iter()
+        .filter_map(|name| PRESETS.iter().find(|p| p.name == name))
+        .flat_map(|p| p.flags.iter().copied())
+}
+
+/// `flags list`, `flags apply PRESET`, and `flags clear [PRESET]`
+pub async fn run(prog: &Progress, options: &Options, matches: &Matches) -> Result<()> {
+    let channel = options.channel;
+    let current = launcher::settings(channel)?;
+    let applied = current.as_ref().map_or(&[][..], |s| &s.presets[..]);
+    let name = matches.positionals.first().map(String::as_str);
+    let (presets, message) = match (matches.subcommand(1), name) {
+        (Some("apply"), Some(name)) => {
+            let preset = find(name)?;
+            let mut presets: Vec<_> = applied
+                .iter()
+                .filter(|p| *p != name && !preset.conflicts.contains(&p.as_str()))
+                .cloned()
+                .collect();
+            presets.push(name.to_owned());
+            (
+                presets,
+                format!(
+                    "Discord {channel} now starts with {}",
+                    preset.flags.join(" ")
+                ),
+            )
+        }
+        (Some("clear"), Some(name)) => {
+            find(name)?;
+            let presets = applied.iter().filter(|p| *p != name).cloned().collect();
+            (presets, format!("Took {name} off Discord {channel}"))
+        }
+        (Some("clear"), None) => (vec![], format!("Discord {channel} starts without presets")),
+        _ => {
+            for preset in PRESETS {
+                let mark = if applied.iter().any(|p| p == preset.name) {
+                    "*"
+                } else {
+                    " "
+                };
+                prog.result(format!("{mark} {:<26}{}", preset.name, preset.about))?;
+            }
+            return Ok(());
+        }
+    };
+    let (install_path, link) = launcher::locate(prog, options).await?;
+    let settings = Settings {
+        presets,
+        ..current.unwrap_or_else(|| Settings::wrapper(link))
+    };
+    let path = settings.path.clone();
+    launcher::save(channel, &install_path, settings)?;
+    prog.println(format!("{message}, through {}", path.display()))?;
+    Ok(())
+}
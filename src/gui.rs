@@ -0,0 +1,292 @@
+//! A small window for people who never open a terminal, with the `gui` feature
+//!
+//! The dialogs are zenity's, GTK ones that fit in on most desktops, so there's no toolkit to
+//! build against. The window lists each channel's installed and latest versions. Update installs
+//! the selected channel's update behind a progress dialog, History says what's been done to
+//! each channel, and Rollback puts back the snapshot from before the last update.
+
+use crate::channel::Channel;
+use crate::progress::{Progress, Steps};
+use crate::state::State;
+use crate::{
+    apply_update, check_for_update, defer, lock_and_check, platform, rollback_snapshot, Options,
+    Result,
+};
+use std::io::Write as _;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use tokio::process::Command;
+
+const TITLE: &str = "--title=Discord Updater";
+
+/// Run zenity, returning whether OK was clicked and what it printed, like the selected row
+async fn zenity(args: &[&str]) -> Result<(bool, String)> {
+    let output = Command::new("zenity")
+        .arg(TITLE)
+        .args(args)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .map_err(|e| format!("the gui needs zenity, which failed to run: {e}"))?;
+    let printed = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    Ok((output.status.success(), printed))
+}
+
+async fn message(kind: &str, text: &str) -> Result<()> {
+    zenity(&[
+        kind,
+        "--no-markup",
+        "--width=420",
+        &format!("--text={text}"),
+    ])
+    .await?;
+    Ok(())
+}
+
+async fn question(text: &str) -> Result<bool> {
+    let (yes, _) = zenity(&["--question", "--no-markup", &format!("--text={text}")]).await?;
+    Ok(yes)
+}
+
+/// The options for `channel`, `--install-path` only being for `--channel`'s install
+fn for_channel(options: &Options, channel: Channel) -> Options {
+    Options {
+        channel,
+        install_path: options
+            .install_path
+            .clone()
+            .filter(|_| channel == options.channel),
+        ..options.clone()
+    }
+}
+
+/// What's been done to each channel, from the state
+fn history() -> Result<String> {
+    let state = State::load()?;
+    let mut text = String::new();
+    for channel in Channel::ALL {
+        let Some(c) = state
+            .channels
+            .get(&channel)
+            .filter(|c| c.last_update.is_some() || c.last_check.is_some())
+        else {
+            continue;
+        };
+        let when = |time: Option<u64>| time.map_or("never".to_owned(), defer::format_time);
+        text += &format!(
+            "Discord {channel}\n  Last checked: {}\n  Last updated: {}\n",
+            when(c.last_check),
+            when(c.last_update)
+        );
+        if let Some(taken) = &c.snapshot {
+            text += &format!(
+                "  Snapshot of {} from {}\n",
+                taken.version,
+                defer::format_time(taken.time)
+            );
+        }
+        if let Some(skipped) = &c.skipped_version {
+            text += &format!("  Skipping {skipped}\n");
+        }
+        if let Some(until) = defer::deferred_until(channel)? {
+            text += &format!("  Deferred until {}\n", defer::format_time(until));
+        }
+    }
+    if text.is_empty() {
+        text = "Nothing has been installed yet".to_owned();
+    }
+    Ok(text)
+}
+
+/// Install the channel's update, showing how it goes
+async fn update(options: &Options) -> Result<()> {
+    let channel = options.channel;
+    let prog = Progress::new(true);
+    // Held until the update is in, so a timer's run can't start one alongside it
+    let (_lock, check) = lock_and_check(&prog, options).await?;
+    if !check.update_available() {
+        return message("--info", &format!("Discord {channel} is up to date")).await;
+    }
+    // Anything there is to ask is asked with a dialog
+    let mut options = Options {
+        non_interactive: true,
+        ..options.clone()
+    };
+    if platform::current().is_running(channel) {
+        let text = format!("Discord {channel} is running, close it to update it?");
+        if !question(&text).await? {
+            return Ok(());
+        }
+        options.close_running = true;
+    }
+    let mut dialog = std::process::Command::new("zenity")
+        .args([
+            TITLE,
+            "--progress",
+            "--auto-close",
+            "--no-cancel",
+            "--width=420",
+        ])
+        .arg(format!(
+            "--text=Updating Discord {channel} to {}",
+            check.latest_version
+        ))
+        .stdin(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("the gui needs zenity, which failed to run: {e}"))?;
+    // With what it was last told, as the download says how far along it is for every chunk
+    let input = Arc::new(Mutex::new((dialog.stdin.take().unwrap(), String::new())));
+    let steps: Steps = {
+        let input = input.clone();
+        Arc::new(move |phase, percent| {
+            let (input, shown) = &mut *input.lock().unwrap();
+            // zenity takes a percentage, and lines starting with # as the text to show
+            let step = format!("{percent}\n# {phase}");
+            if *shown != step {
+                let _ = writeln!(input, "{step}");
+                *shown = step;
+            }
+        })
+    };
+    let prog = prog.with_steps(steps);
+    let spinner = prog.spinner();
+    let result = apply_update(&prog, &spinner, &options, &check).await;
+    let _ = writeln!(input.lock().unwrap().0, "100");
+    drop(input);
+    drop(prog);
+    tokio::task::spawn_blocking(move || dialog.wait()).await??;
+    match result {
+        Ok(()) => {
+            let text = format!("Updated Discord {channel} to {}", check.latest_version);
+            message("--info", &text).await
+        }
+        Err(e) => {
+            message(
+                "--error",
+                &format!("Updating Discord {channel} failed: {e}"),
+            )
+            .await
+        }
+    }
+}
+
+/// Put back a channel's install from before its last update, after asking
+async fn rollback(options: &Options) -> Result<()> {
+    let state = State::load()?;
+    let snapshots: Vec<_> = state
+        .channels
+        .iter()
+        .filter_map(|(&channel, c)| Some((channel, c.snapshot.clone()?)))
+        .collect();
+    let (channel, taken) = match &snapshots[..] {
+        [] => {
+            let text = "There's nothing to roll back to, updates only keep a snapshot with \
+                        --snapshot on btrfs or ZFS";
+            return message("--info", text).await;
+        }
+        [only] => only.clone(),
+        _ => {
+            let mut args = vec![
+                "--list",
+                "--text=Which channel should be rolled back?",
+                "--column=Channel",
+                "--column=Snapshot of",
+            ];
+            let rows: Vec<_> = snapshots
+                .iter()
+                .flat_map(|(channel, taken)| [channel.to_string(), taken.version.to_string()])
+                .collect();
+            args.extend(rows.iter().map(String::as_str));
+            let (chosen, row) = zenity(&args).await?;
+            match snapshots
+                .iter()
+                .find(|(c, _)| chosen && c.to_string() == row)
+            {
+                Some(found) => found.clone(),
+                None => return Ok(()),
+            }
+        }
+    };
+    let text = format!(
+        "Put back Discord {channel} {} from the snapshot taken {}?",
+        taken.version,
+        defer::format_time(taken.time)
+    );
+    if !question(&text).await? {
+        return Ok(());
+    }
+    let prog = Progress::new(true);
+    match rollback_snapshot(&prog, &for_channel(options, channel), true).await {
+        Ok(()) => {
+            message(
+                "--info",
+                &format!("Rolled Discord {channel} back to {}", taken.version),
+            )
+            .await
+        }
+        Err(e) => message("--error", &format!("Rolling back failed: {e}")).await,
+    }
+}
+
+/// Show the channels until the window is closed
+pub async fn run(options: &Options) -> Result<()> {
+    loop {
+        let prog = Progress::new(true);
+        let mut rows = vec![];
+        for channel in Channel::ALL {
+            let (installed, latest, status) =
+                match check_for_update(&prog, &for_channel(options, channel)).await {
+                    Ok(check) => {
+                        let status = match &check.installed_version {
+                            None => "not installed",
+                            Some(_) if check.update_available() => "update available",
+                            Some(_) => "up to date",
+                        };
+                        let installed = check
+                            .installed_version
+                            .as_ref()
+                            .map_or("-".to_owned(), |v| v.to_string());
+                        (
+                            installed,
+                            check.latest_version.to_string(),
+                            status.to_owned(),
+                        )
+                    }
+                    Err(e) => (
+                        "?".to_owned(),
+                        "?".to_owned(),
+                        format!("couldn't check: {e}"),
+                    ),
+                };
+            rows.extend([channel.to_string(), installed, latest, status]);
+        }
+        let mut args = vec![
+            "--list",
+            "--width=640",
+            "--height=260",
+            "--text=Pick a channel and click Update to install its latest version",
+            "--column=Channel",
+            "--column=Installed",
+            "--column=Latest",
+            "--column=Status",
+            "--ok-label=Update",
+            "--extra-button=History",
+            "--extra-button=Rollback",
+            "--cancel-label=Close",
+        ];
+        args.extend(rows.iter().map(String::as_str));
+        let (ok, printed) = zenity(&args).await?;
+        match printed.as_str() {
+            "History" if !ok => message("--info", &history()?).await?,
+            "Rollback" if !ok => rollback(options).await?,
+            _ if !ok => return Ok(()),
+            "" => message("--info", "Pick a channel to update first").await?,
+            row => {
+                let channel = row.parse::<Channel>().map_err(|e| e.to_string())?;
+                update(&for_channel(options, channel)).await?;
+            }
+        }
+    }
+}
@@ -0,0 +1,655 @@
+//! A streaming gzip (RFC 1952) / DEFLATE (RFC 1951) decoder.
+
+use std::io::{self, Read};
+
+const WINDOW_SIZE: usize = 1 << 15;
+const WINDOW_MASK: usize = WINDOW_SIZE - 1;
+const MAX_BITS: usize = 15;
+const FAST_BITS: u32 = 10;
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+/// The order code length code lengths are stored in for dynamic blocks
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut c = i as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xedb88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[i] = c;
+        i += 1;
+    }
+    table
+}
+
+/// Update a running CRC-32 with the given bytes
+pub fn crc32(crc: u32, data: &[u8]) -> u32 {
+    let mut c = !crc;
+    for &b in data {
+        c = CRC_TABLE[((c ^ u32::from(b)) & 0xff) as usize] ^ (c >> 8);
+    }
+    !c
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("gzip: {msg}"))
+}
+
+fn truncated() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "gzip: unexpected end of stream",
+    )
+}
+
+/// Reads the input LSB-first, as DEFLATE requires
+struct BitReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    pos: usize,
+    len: usize,
+    bits: u64,
+    count: u32,
+    /// How many of the buffered bits are zero padding past the end of the input
+    padding: u32,
+}
+
+impl<R: Read> BitReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            buf: vec![0; 64 * 1024].into_boxed_slice(),
+            pos: 0,
+            len: 0,
+            bits: 0,
+            count: 0,
+            padding: 0,
+        }
+    }
+
+    fn next_byte(&mut self) -> io::Result<Option<u8>> {
+        if self.pos == self.len {
+            self.len = loop {
+                match self.inner.read(&mut self.buf) {
+                    Ok(n) => break n,
+                    Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                    Err(e) => return Err(e),
+                }
+            };
+            self.pos = 0;
+            if self.len == 0 {
+                return Ok(None);
+            }
+        }
+        self.pos += 1;
+        Ok(Some(self.buf[self.pos - 1]))
+    }
+
+    /// Make at least `n` bits available, padding with zeros past the end of the input
+    fn fill(&mut self, n: u32) -> io::Result<()> {
+        while self.count < n {
+            let byte = match self.next_byte()? {
+                Some(byte) if self.padding == 0 => byte,
+                _ => {
+                    self.padding += 8;
+                    0
+                }
+            };
+            self.bits |= u64::from(byte) << self.count;
+            self.count += 8;
+        }
+        Ok(())
+    }
+
+    fn peek(&mut self, n: u32) -> io::Result<u32> {
+        self.fill(n)?;
+        Ok((self.bits & ((1 << n) - 1)) as u32)
+    }
+
+    fn consume(&mut self, n: u32) -> io::Result<()> {
+        if n > self.count - self.padding {
+            return Err(truncated());
+        }
+        self.bits >>= n;
+        self.count -= n;
+        Ok(())
+    }
+
+    fn bits(&mut self, n: u32) -> io::Result<u32> {
+        let value = self.peek(n)?;
+        self.consume(n)?;
+        Ok(value)
+    }
+
+    /// Discard bits up to the next byte boundary
+    fn align(&mut self) {
+        let drop = self.count % 8;
+        self.bits >>= drop;
+        self.count -= drop;
+    }
+
+    /// Read a whole byte, the reader must be byte aligned
+    fn byte(&mut self) -> io::Result<u8> {
+        if self.count > 0 {
+            return Ok(self.bits(8)? as u8);
+        }
+        self.next_byte()?.ok_or_else(truncated)
+    }
+
+    fn u16_le(&mut self) -> io::Result<u16> {
+        Ok(u16::from_le_bytes([self.byte()?, self.byte()?]))
+    }
+
+    fn u32_le(&mut self) -> io::Result<u32> {
+        Ok(u32::from_le_bytes([
+            self.byte()?,
+            self.byte()?,
+            self.byte()?,
+            self.byte()?,
+        ]))
+    }
+}
+
+/// A canonical Huffman code
+#[derive(Clone)]
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+    /// Lookup table for codes of up to `FAST_BITS` bits, entries are `symbol << 4 | length`
+    fast: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> io::Result<Self> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                return Err(invalid("over-subscribed huffman code"));
+            }
+        }
+
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for len in 1..=MAX_BITS {
+            offsets[len + 1] = offsets[len] + counts[len];
+        }
+        let mut symbols = vec![0; offsets[MAX_BITS + 1] as usize];
+        for (symbol, &len) in lengths.iter().enumerate() {
+            if len != 0 {
+                symbols[offsets[len as usize] as usize] = symbol as u16;
+                offsets[len as usize] += 1;
+            }
+        }
+
+        let mut fast = vec![0; 1 << FAST_BITS];
+        let mut code = 0u32;
+        let mut index = 0;
+        for len in 1..=MAX_BITS as u32 {
+            for _ in 0..counts[len as usize] {
+                if len <= FAST_BITS {
+                    let reversed = code.reverse_bits() >> (32 - len);
+                    let entry = symbols[index] << 4 | len as u16;
+                    for slot in (reversed as usize..fast.len()).step_by(1 << len) {
+                        fast[slot] = entry;
+                    }
+                }
+                code += 1;
+                index += 1;
+            }
+            code <<= 1;
+        }
+
+        Ok(Self {
+            counts,
+            symbols,
+            fast,
+        })
+    }
+
+    fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> io::Result<u16> {
+        let entry = self.fast[reader.peek(FAST_BITS)? as usize];
+        if entry != 0 {
+            reader.consume(u32::from(entry & 0xf))?;
+            return Ok(entry >> 4);
+        }
+
+        // Longer codes are decoded a bit at a time
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for &count in &self.counts[1..] {
+            code |= reader.bits(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad huffman code"))
+    }
+
+    fn fixed() -> (Self, Self) {
+        let mut lengths = [0u8; 288];
+        lengths[..144].fill(8);
+        lengths[144..256].fill(9);
+        lengths[256..280].fill(7);
+        lengths[280..].fill(8);
+        (Self::new(&lengths).unwrap(), Self::new(&[5; 30]).unwrap())
+    }
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    BlockHeader,
+    Stored(usize),
+    Codes,
+    Copy { len: usize, dist: usize },
+    Done,
+}
+
+/// Decompresses a raw DEFLATE stream
+pub struct Inflate<R> {
+    reader: BitReader<R>,
+    window: Box<[u8]>,
+    window_pos: usize,
+    total_out: u64,
+    state: State,
+    last_block: bool,
+    fixed: (Huffman, Huffman),
+    lit: Huffman,
+    dist: Huffman,
+}
+
+impl<R: Read> Inflate<R> {
+    pub fn new(inner: R) -> Self {
+        let fixed = Huffman::fixed();
+        Self {
+            reader: BitReader::new(inner),
+            window: vec![0; WINDOW_SIZE].into_boxed_slice(),
+            window_pos: 0,
+            total_out: 0,
+            state: State::BlockHeader,
+            last_block: false,
+            lit: fixed.0.clone(),
+            dist: fixed.1.clone(),
+            fixed,
+        }
+    }
+
+    fn push(&mut self, byte: u8) {
+        self.window[self.window_pos] = byte;
+        self.window_pos = (self.window_pos + 1) & WINDOW_MASK;
+        self.total_out += 1;
+    }
+
+    fn read_dynamic_codes(&mut self) -> io::Result<()> {
+        let r = &mut self.reader;
+        let num_lit = r.bits(5)? as usize + 257;
+        let num_dist = r.bits(5)? as usize + 1;
+        let num_code_len = r.bits(4)? as usize + 4;
+        if num_lit > 286 || num_dist > 30 {
+            return Err(invalid("bad dynamic block counts"));
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &i in &CODE_LENGTH_ORDER[..num_code_len] {
+            code_lengths[i] = r.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&code_lengths)?;
+
+        let mut lengths = vec![0u8; num_lit + num_dist];
+        let mut i = 0;
+        while i < lengths.len() {
+            let symbol = code_length_code.decode(r)?;
+            let (value, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    let prev = *lengths[..i]
+                        .last()
+                        .ok_or_else(|| invalid("repeat with no previous length"))?;
+                    (prev, 3 + r.bits(2)? as usize)
+                }
+                17 => (0, 3 + r.bits(3)? as usize),
+                _ => (0, 11 + r.bits(7)? as usize),
+            };
+            if i + repeat > lengths.len() {
+                return Err(invalid("too many code lengths"));
+            }
+            lengths[i..i + repeat].fill(value);
+            i += repeat;
+        }
+        if lengths[256] == 0 {
+            return Err(invalid("missing end-of-block code"));
+        }
+
+        self.lit = Huffman::new(&lengths[..num_lit])?;
+        self.dist = Huffman::new(&lengths[num_lit..])?;
+        Ok(())
+    }
+
+    fn read_block_header(&mut self) -> io::Result<State> {
+        if self.last_block {
+            return Ok(State::Done);
+        }
+        self.last_block = self.reader.bits(1)? == 1;
+        match self.reader.bits(2)? {
+            0 => {
+                self.reader.align();
+                let len = self.reader.u16_le()?;
+                let nlen = self.reader.u16_le()?;
+                if len != !nlen {
+                    return Err(invalid("stored block length mismatch"));
+                }
+                Ok(State::Stored(len.into()))
+            }
+            1 => {
+                self.lit = self.fixed.0.clone();
+                self.dist = self.fixed.1.clone();
+                Ok(State::Codes)
+            }
+            2 => {
+                self.read_dynamic_codes()?;
+                Ok(State::Codes)
+            }
+            _ => Err(invalid("bad block type")),
+        }
+    }
+
+    fn read_symbol(&mut self) -> io::Result<Result<u8, State>> {
+        let symbol = self.lit.decode(&mut self.reader)? as usize;
+        if symbol < 256 {
+            return Ok(Ok(symbol as u8));
+        }
+        if symbol == 256 {
+            return Ok(Err(State::BlockHeader));
+        }
+
+        let symbol = symbol - 257;
+        if symbol >= LENGTH_BASE.len() {
+            return Err(invalid("bad length code"));
+        }
+        let len =
+            LENGTH_BASE[symbol] as usize + self.reader.bits(LENGTH_EXTRA[symbol].into())? as usize;
+
+        let symbol = self.dist.decode(&mut self.reader)? as usize;
+        if symbol >= DIST_BASE.len() {
+            return Err(invalid("bad distance code"));
+        }
+        let dist =
+            DIST_BASE[symbol] as usize + self.reader.bits(DIST_EXTRA[symbol].into())? as usize;
+        if dist as u64 > self.total_out {
+            return Err(invalid("distance too far back"));
+        }
+        Ok(Err(State::Copy { len, dist }))
+    }
+
+    fn into_reader(self) -> BitReader<R> {
+        self.reader
+    }
+}
+
+impl<R: Read> Read for Inflate<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let mut n = 0;
+        while n < out.len() {
+            self.state = match self.state {
+                State::BlockHeader => self.read_block_header()?,
+                State::Stored(0) => State::BlockHeader,
+                State::Stored(remaining) => {
+                    let byte = self.reader.byte()?;
+                    self.push(byte);
+                    out[n] = byte;
+                    n += 1;
+                    State::Stored(remaining - 1)
+                }
+                State::Codes => match self.read_symbol()? {
+                    Ok(byte) => {
+                        self.push(byte);
+                        out[n] = byte;
+                        n += 1;
+                        State::Codes
+                    }
+                    Err(state) => state,
+                },
+                State::Copy { mut len, dist } => {
+                    while len > 0 && n < out.len() {
+                        let byte =
+                            self.window[(self.window_pos + WINDOW_SIZE - dist) & WINDOW_MASK];
+                        self.push(byte);
+                        out[n] = byte;
+                        n += 1;
+                        len -= 1;
+                    }
+                    if len == 0 {
+                        State::Codes
+                    } else {
+                        State::Copy { len, dist }
+                    }
+                }
+                State::Done => break,
+            };
+        }
+        Ok(n)
+    }
+}
+
+/// Decompresses a gzip stream, verifying the CRC and length in the trailer
+pub struct GzDecoder<R> {
+    inflate: Option<Inflate<R>>,
+    header_read: bool,
+    crc: u32,
+    size: u32,
+}
+
+impl<R: Read> GzDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inflate: Some(Inflate::new(inner)),
+            header_read: false,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    fn read_header(&mut self) -> io::Result<()> {
+        const FHCRC: u8 = 1 << 1;
+        const FEXTRA: u8 = 1 << 2;
+        const FNAME: u8 = 1 << 3;
+        const FCOMMENT: u8 = 1 << 4;
+
+        let r = &mut self.inflate.as_mut().unwrap().reader;
+        if r.byte()? != 0x1f || r.byte()? != 0x8b {
+            return Err(invalid("not in gzip format"));
+        }
+        if r.byte()? != 8 {
+            return Err(invalid("unknown compression method"));
+        }
+        let flags = r.byte()?;
+        // mtime, extra flags, and OS
+        for _ in 0..6 {
+            r.byte()?;
+        }
+        if flags & FEXTRA != 0 {
+            for _ in 0..r.u16_le()? {
+                r.byte()?;
+            }
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                while r.byte()? != 0 {}
+            }
+        }
+        if flags & FHCRC != 0 {
+            r.u16_le()?;
+        }
+        Ok(())
+    }
+
+    fn check_trailer(&mut self) -> io::Result<()> {
+        let mut reader = self.inflate.take().unwrap().into_reader();
+        reader.align();
+        if reader.u32_le()? != self.crc {
+            return Err(invalid("crc mismatch"));
+        }
+        if reader.u32_le()? != self.size {
+            return Err(invalid("length mismatch"));
+        }
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        let Some(inflate) = &mut self.inflate else {
+            return Ok(0);
+        };
+        if !self.header_read {
+            self.header_read = true;
+            self.read_header()?;
+            return self.read(out);
+        }
+        let n = inflate.read(out)?;
+        if n == 0 && !out.is_empty() {
+            self.check_trailer()?;
+        }
+        self.crc = crc32(self.crc, &out[..n]);
+        self.size = self.size.wrapping_add(n as u32);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write as _;
+    use std::process::{Command, Stdio};
+
+    fn gzip(data: &[u8], args: &[&str]) -> Vec<u8> {
+        let mut child = Command::new("gzip")
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdin = child.stdin.take().unwrap();
+        let data = data.to_vec();
+        let writer = std::thread::spawn(move || stdin.write_all(&data).unwrap());
+        let output = child.wait_with_output().unwrap();
+        writer.join().unwrap();
+        assert!(output.status.success());
+        output.stdout
+    }
+
+    fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        GzDecoder::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    fn inflate(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![];
+        Inflate::new(data).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    /// Text that compresses well, with long matches far back, and noise that doesn't
+    fn sample() -> Vec<u8> {
+        let mut data = vec![];
+        for i in 0..20_000 {
+            data.extend_from_slice(format!("line {} of the sample\n", i % 700).as_bytes());
+        }
+        let mut state = 0x1234_5678u32;
+        data.extend((0..100_000).map(|_| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        }));
+        data
+    }
+
+    #[test]
+    fn decodes_what_gzip_writes() {
+        let data = sample();
+        for level in ["-1", "-6", "-9"] {
+            let compressed = gzip(&data, &[level, "-c"]);
+            assert_eq!(gunzip(&compressed).unwrap(), data, "gzip {level}");
+        }
+    }
+
+    #[test]
+    fn decodes_headers_with_a_name() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("named.txt");
+        std::fs::write(&path, b"named").unwrap();
+        let status = Command::new("gzip").arg("-N").arg(&path).status().unwrap();
+        assert!(status.success());
+        let compressed = std::fs::read(dir.path().join("named.txt.gz")).unwrap();
+        assert_eq!(gunzip(&compressed).unwrap(), b"named");
+    }
+
+    #[test]
+    fn decodes_empty_input() {
+        assert_eq!(gunzip(&gzip(b"", &["-c"])).unwrap(), b"");
+    }
+
+    #[test]
+    fn known_deflate_streams() {
+        // An empty fixed block, a fixed block with a match, and a stored block
+        assert_eq!(inflate(&[0x03, 0x00]), b"");
+        let fixed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0x01];
+        assert_eq!(inflate(&fixed), b"hello hello hello hello");
+        let stored = [0x01, 0x03, 0x00, 0xfc, 0xff, b'a', b'b', b'c'];
+        assert_eq!(inflate(&stored), b"abc");
+    }
+
+    #[test]
+    fn crc32_check_value() {
+        assert_eq!(crc32(0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(crc32(0, b"1234"), b"56789"), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn rejects_corruption() {
+        let mut compressed = gzip(&sample(), &["-c"]);
+        let len = compressed.len();
+        // The CRC in the trailer
+        compressed[len - 8] ^= 1;
+        assert!(gunzip(&compressed).is_err());
+        assert!(gunzip(&compressed[..len / 2]).is_err());
+        assert!(gunzip(b"not gzip at all").is_err());
+    }
+}
@@ -0,0 +1,198 @@
+//! The `harden` subcommand, starting Discord inside bubblewrap or firejail
+//!
+//! The launcher starts Discord through the sandbox with a template kept here: the rest of the
+//! system read-only, the home directory hidden but for Discord's settings, Downloads, fonts, and
+//! whatever `--allow` adds, and only the GPU and sound devices. The paths are filled in whenever
+//! the launcher is made again, which every update and move of the install does, so they follow
+//! the install and pick up changes to the template.
+
+use crate::channel::Channel;
+use crate::cli::Matches;
+use crate::launcher::{self, Settings};
+use crate::progress::Progress;
+use crate::{config, home_dir, Options, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// What Discord is started inside
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Sandbox {
+    Bwrap,
+    Firejail,
+}
+
+impl Sandbox {
+    fn program(self) -> &'static str {
+        match self {
+            Self::Bwrap => "bwrap",
+            Self::Firejail => "firejail",
+        }
+    }
+}
+
+/// Whether `program` is somewhere on the PATH
+fn installed(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .is_some_and(|path| std::env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+}
+
+/// The home directories Discord reads but shouldn't write, for looking right
+fn appearance(home: &Path) -> Vec<PathBuf> {
+    [
+        ".Xauthority",
+        ".fonts",
+        ".local/share/fonts",
+        ".config/fontconfig",
+        ".config/gtk-3.0",
+        ".icons",
+    ]
+    .iter()
+    .map(|dir| home.join(dir))
+    .collect()
+}
+
+fn bwrap(install_path: &Path, config_dir: &Path, allowed: &[PathBuf]) -> Result<Vec<String>> {
+    let home = home_dir()?;
+    // SAFETY: getuid can't fail
+    let runtime = format!("/run/user/{}", unsafe { libc::getuid() });
+    let mut args: Vec<String> = [
+        "bwrap",
+        "--ro-bind",
+        "/",
+        "/",
+        "--dev",
+        "/dev",
+        "--dev-bind-try",
+        "/dev/dri",
+        "/dev/dri",
+        "--dev-bind-try",
+        "/dev/snd",
+        "/dev/snd",
+        "--proc",
+        "/proc",
+        "--tmpfs",
+        "/tmp",
+        "--ro-bind-try",
+        "/tmp/.X11-unix",
+        "/tmp/.X11-unix",
+        "--bind-try",
+        &runtime,
+        &runtime,
+    ]
+    .map(str::to_owned)
+    .into();
+    // Mounted in order, so the home directory is hidden before parts of it are put back
+    args.extend(["--tmpfs".to_owned(), home.display().to_string()]);
+    let mut mount = |how: &str, path: &Path| {
+        let path = path.display().to_string();
+        args.extend([how.to_owned(), path.clone(), path]);
+    };
+    mount("--ro-bind", install_path);
+    mount("--bind", config_dir);
+    mount("--bind-try", &home.join("Downloads"));
+    for path in appearance(&home) {
+        mount("--ro-bind-try", &path);
+    }
+    for path in allowed {
+        mount("--bind", path);
+    }
+    args.extend(["--unshare-pid", "--die-with-parent", "--new-session", "--"].map(str::to_owned));
+    Ok(args)
+}
+
+fn firejail(install_path: &Path, config_dir: &Path, allowed: &[PathBuf]) -> Result<Vec<String>> {
+    let home = home_dir()?;
+    let mut args: Vec<String> = [
+        "firejail",
+        "--quiet",
+        "--noprofile",
+        "--caps.drop=all",
+        "--nonewprivs",
+        // Electron's own sandbox needs chroot, which firejail's filter takes away by default
+        "--seccomp=!chroot",
+        "--private-dev",
+        "--private-tmp",
+    ]
+    .map(str::to_owned)
+    .into();
+    let mut whitelist = vec![config_dir.to_owned(), home.join("Downloads")];
+    // Whitelisting only narrows down the home directory, anywhere else stays visible anyway
+    if install_path.starts_with(&home) {
+        whitelist.push(install_path.to_owned());
+    }
+    whitelist.extend(appearance(&home));
+    whitelist.extend(allowed.iter().cloned());
+    for path in &whitelist {
+        args.push(format!("--whitelist={}", path.display()));
+    }
+    args.push(format!("--read-only={}", install_path.display()));
+    for path in appearance(&home) {
+        args.push(format!("--read-only={}", path.display()));
+    }
+    args.push("--".to_owned());
+    Ok(args)
+}
+
+/// What the launcher puts in front of Discord's binary to start it inside `sandbox`
+pub fn command(
+    sandbox: Sandbox,
+    channel: Channel,
+    install_path: &Path,
+    allowed: &[PathBuf],
+) -> Result<Vec<String>> {
+    let config_dir = channel.config_dir()?;
+    match sandbox {
+        Sandbox::Bwrap => bwrap(install_path, &config_dir, allowed),
+        Sandbox::Firejail => firejail(install_path, &config_dir, allowed),
+    }
+}
+
+/// `harden bwrap`, `harden firejail`, `harden off`, and `harden` to show which is used
+pub async fn run(prog: &Progress, options: &Options, matches: &Matches) -> Result<()> {
+    let channel = options.channel;
+    if options.system {
+        return Err("harden confines one user's Discord, it can't be used with --system".into());
+    }
+    let current = launcher::settings(channel)?;
+    let sandbox = match matches.subcommand(1) {
+        Some("bwrap") => Some(Sandbox::Bwrap),
+        Some("firejail") => Some(Sandbox::Firejail),
+        Some("off") => None,
+        _ => {
+            let message = match current.as_ref().and_then(|s| s.sandbox) {
+                Some(sandbox) => format!("Discord {channel} starts inside {}", sandbox.program()),
+                None => format!("Discord {channel} isn't hardened"),
+            };
+            prog.result(message)?;
+            return Ok(());
+        }
+    };
+    if let Some(sandbox) = sandbox.filter(|s| !installed(s.program())) {
+        return Err(format!("{} isn't installed", sandbox.program()).into());
+    }
+    let allow = matches
+        .values("allow")
+        .iter()
+        .map(|path| Ok(std::path::absolute(config::expand_home(Path::new(path))?)?))
+        .collect::<Result<Vec<_>>>()?;
+    if let Some(path) = allow.iter().find(|path| !path.exists()) {
+        return Err(format!("{} doesn't exist, so it can't be allowed", path.display()).into());
+    }
+    let (install_path, link) = launcher::locate(prog, options).await?;
+    // Bound into the sandbox, so it has to be there before Discord's first start
+    std::fs::create_dir_all(channel.config_dir()?)?;
+    let settings = Settings {
+        sandbox,
+        allow,
+        ..current.unwrap_or_else(|| Settings::wrapper(link))
+    };
+    let path = settings.path.clone();
+    launcher::save(channel, &install_path, settings)?;
+    let message = match sandbox {
+        Some(sandbox) => format!("Discord {channel} now starts inside {}", sandbox.program()),
+        None => format!("Discord {channel} now starts without a sandbox"),
+    };
+    prog.println(format!("{message}, through {}", path.display()))?;
+    Ok(())
+}
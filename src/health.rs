@@ -0,0 +1,85 @@
+//! Checking a new version works before the install it replaced is thrown away
+
+use crate::channel::Channel;
+use crate::state::state_dir;
+use crate::{missing_files, platform, selinux, Result};
+use std::os::unix::process::CommandExt as _;
+use std::path::Path;
+use std::process::Stdio;
+use std::time::Duration;
+use tokio::process::Command;
+
+/// Libraries the binary needs that the dynamic linker can't find, empty when `ldd` isn't there
+async fn missing_libraries(binary: &Path) -> Vec<String> {
+    let Ok(output) = Command::new("ldd")
+        .arg(binary)
+        .stderr(Stdio::null())
+        .output()
+        .await
+    else {
+        return vec![];
+    };
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter(|line| line.contains("not found"))
+        .filter_map(|line| line.split_whitespace().next().map(str::to_owned))
+        .collect()
+}
+
+/// Start Discord and make sure it doesn't exit with an error within `window`
+///
+/// If it's still going at the end it's left running for the user, its output goes to a log in
+/// the state directory either way.
+async fn watch_launch(channel: Channel, binary: &Path, window: Duration) -> Result<()> {
+    let log = state_dir()?.join(format!("first-launch-{channel}.log"));
+    tokio::fs::create_dir_all(log.parent().unwrap()).await?;
+    let output = std::fs::File::create(&log)?;
+    let mut command = std::process::Command::new(binary);
+    // Its own process group, so it isn't killed along with us by a ^C
+    command
+        .stdin(Stdio::null())
+        .stdout(output.try_clone()?)
+        .stderr(output)
+        .process_group(0);
+    let mut child = Command::from(command)
+        .spawn()
+        .map_err(|e| format!("failed to start {}: {e}", binary.display()))?;
+    // Still running at the end of the window is a pass
+    let Ok(status) = tokio::time::timeout(window, child.wait()).await else {
+        return Ok(());
+    };
+    if !status?.success() {
+        return Err(format!("it exited straight after starting, see {}", log.display()).into());
+    }
+    Ok(())
+}
+
+/// Whether the install at `install_path` looks like it works, the error says why not
+///
+/// With `watch` set and a display to show it on, Discord is also launched and watched for that
+/// long.
+pub async fn check(channel: Channel, install_path: &Path, watch: Option<Duration>) -> Result<()> {
+    let missing = missing_files(channel, install_path).await?;
+    if !missing.is_empty() {
+        return Err(format!("it's missing {}", missing.join(", ")).into());
+    }
+    let binary = platform::current().binary_path(channel, install_path);
+    let libraries = missing_libraries(&binary).await;
+    if !libraries.is_empty() {
+        return Err(format!(
+            "it needs libraries that aren't installed: {}",
+            libraries.join(", ")
+        )
+        .into());
+    }
+    let has_display =
+        std::env::var_os("DISPLAY").is_some() || std::env::var_os("WAYLAND_DISPLAY").is_some();
+    let launched = match watch {
+        Some(window) if has_display => watch_launch(channel, &binary, window).await,
+        _ => Ok(()),
+    };
+    launched.map_err(|e| match selinux::hint(&binary) {
+        Some(hint) => format!("{e}. {hint}").into(),
+        None => e,
+    })
+}
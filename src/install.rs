@@ -0,0 +1,408 @@
+//! Where updates are installed to
+
+use crate::manifest::{EntryKind, Manifest};
+use crate::{fsync_path, tar, Error, Result};
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd as _;
+use std::os::unix::ffi::OsStrExt as _;
+use std::os::unix::fs::{symlink, MetadataExt as _, PermissionsExt as _};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+use tempfile::TempDir;
+
+/// How an update replaces the current install, from `--update-strategy`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Stage next to the install and swap the two, see `DirectoryTarget`
+    #[default]
+    Swap,
+    /// Stage elsewhere and write only what changed, see `InPlaceTarget`
+    InPlace,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "swap" => Ok(Self::Swap),
+            "in-place" => Ok(Self::InPlace),
+            _ => Err("expected swap or in-place".into()),
+        }
+    }
+}
+
+/// A directory to stage a download in, in `--temp-dir` or else `$TMPDIR`
+pub fn staging_dir(temp_dir: Option<&Path>) -> io::Result<TempDir> {
+    let temp_dir = temp_dir.map_or_else(std::env::temp_dir, Path::to_owned);
+    fs::create_dir_all(&temp_dir)?;
+    tempfile::Builder::new()
+        .prefix("discord_update-")
+        .tempdir_in(temp_dir)
+}
+
+/// The id of the group named `name`, for `--shared-group`
+pub fn group_id(name: &str) -> Result<u32> {
+    let c_name = CString::new(name)?;
+    // SAFETY: the name is a valid C string, and the entry is read before any other group call
+    let entry = unsafe { libc::getgrnam(c_name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("there's no group called {name:?}").into());
+    }
+    // SAFETY: checked it isn't null
+    Ok(unsafe { (*entry).gr_gid })
+}
+
+/// `mode` with the group given whatever the owner has, and directories setgid so what's created
+/// in them later gets the group too
+pub fn group_mode(mode: u32, is_dir: bool) -> u32 {
+    let mode = mode | ((mode & 0o700) >> 3);
+    if is_dir {
+        mode | 0o2000
+    } else {
+        mode
+    }
+}
+
+/// Give everything under `root` to the group `gid`, with `group_mode`, for `--shared-group`
+pub fn share_with_group(root: &Path, gid: u32) -> io::Result<()> {
+    let meta = fs::symlink_metadata(root)?;
+    // Only what needs it, only the owner can change what another member made
+    if meta.gid() != gid {
+        std::os::unix::fs::lchown(root, None, Some(gid))?;
+    }
+    if meta.is_symlink() {
+        return Ok(());
+    }
+    let mode = group_mode(meta.mode() & 0o7777, meta.is_dir());
+    if mode != meta.mode() & 0o7777 {
+        fs::set_permissions(root, fs::Permissions::from_mode(mode))?;
+    }
+    if meta.is_dir() {
+        for entry in fs::read_dir(root)? {
+            share_with_group(&entry?.path(), gid)?;
+        }
+    }
+    Ok(())
+}
+
+/// Somewhere a new version can be extracted and then put in place of the current one
+pub trait InstallTarget: Send {
+    /// Where the install ends up
+    fn path(&self) -> &Path;
+
+    /// The (empty) directory to extract the new version into
+    fn staged(&self) -> &Path;
+
+    /// Replace the current install with the staged one, recording its manifest
+    ///
+    /// The replaced install is handed back if it can be put back later.
+    fn commit(self: Box<Self>, manifest: &Manifest) -> io::Result<Option<Previous>>;
+
+    /// Leave the staged copy for a later run to install, where one can, rather than delete it
+    fn keep(self: Box<Self>) {}
+}
+
+/// Something to pass every target an update installs through, like a test's fake one
+pub type Wrapper = Box<dyn Fn(Box<dyn InstallTarget>) -> Box<dyn InstallTarget> + Send + Sync>;
+
+static WRAPPER: OnceLock<Wrapper> = OnceLock::new();
+
+/// Install through what `wrapper` makes of each target, this only has an effect before the first
+/// install
+pub fn wrap_targets(wrapper: Wrapper) {
+    let _ = WRAPPER.set(wrapper);
+}
+
+/// `target`, or what the wrapper makes of it
+pub fn wrapped(target: Box<dyn InstallTarget>) -> Box<dyn InstallTarget> {
+    match WRAPPER.get() {
+        Some(wrapper) => wrapper(target),
+        None => target,
+    }
+}
+
+/// The install an update replaced, deleted when dropped unless it's restored
+pub struct Previous {
+    install_path: PathBuf,
+    /// Holds the old install at `install`
+    staging: TempDir,
+    manifest: Option<Manifest>,
+}
+
+impl Previous {
+    /// Put the old install back in place of the new one, which is deleted
+    pub fn restore(self) -> io::Result<()> {
+        swap_in(&self.staging.path().join("install"), &self.install_path)?;
+        fsync_path(self.install_path.parent().unwrap())?;
+        match &self.manifest {
+            Some(manifest) => manifest.save(&self.install_path)?,
+            None => Manifest::remove(&self.install_path)?,
+        }
+        self.staging.close()
+    }
+
+    /// Delete the old install, which dropping it does too, but without a way to say it failed
+    pub fn discard(self) -> io::Result<()> {
+        self.staging.close()
+    }
+}
+
+/// An install in a directory on the local filesystem
+pub struct DirectoryTarget {
+    install_path: PathBuf,
+    /// Next to the install so the new version can be renamed into place
+    staging: TempDir,
+    staged: PathBuf,
+}
+
+impl DirectoryTarget {
+    pub fn new(install_path: &Path) -> Result<Self> {
+        let parent = install_path
+            .parent()
+            .ok_or_else(|| Error::from("bad discord install path"))?;
+        fs::create_dir_all(parent)?;
+        let staging = tempfile::Builder::new()
+            .prefix(".discord_update-")
+            .tempdir_in(parent)?;
+        let staged = staging.path().join("install");
+        fs::create_dir(&staged)?;
+        Ok(Self {
+            install_path: install_path.to_owned(),
+            staging,
+            staged,
+        })
+    }
+}
+
+impl InstallTarget for DirectoryTarget {
+    fn path(&self) -> &Path {
+        &self.install_path
+    }
+
+    fn staged(&self) -> &Path {
+        &self.staged
+    }
+
+    fn commit(self: Box<Self>, manifest: &Manifest) -> io::Result<Option<Previous>> {
+        let fresh = !self.install_path.exists();
+        // A broken manifest shouldn't stop the update, it'd only be needed to roll back
+        let old_manifest = Manifest::load(&self.install_path).ok().flatten();
+        swap_in(&self.staged, &self.install_path)?;
+        fsync_path(self.install_path.parent().unwrap())?;
+        manifest.save(&self.install_path)?;
+        if fresh {
+            self.staging.close()?;
+            return Ok(None);
+        }
+        // Whatever is left in staging is the old install
+        Ok(Some(Previous {
+            install_path: self.install_path,
+            staging: self.staging,
+            manifest: old_manifest,
+        }))
+    }
+
+    fn keep(self: Box<Self>) {
+        let _ = self.staging.into_path();
+    }
+}
+
+/// An install updated file by file, writing only what changed
+///
+/// For flash and network filesystems, where rewriting the whole install for a small update
+/// costs. It's staged away from the install, so the unchanged files aren't written there
+/// either. Each file is replaced atomically but the install as a whole isn't, an interrupted
+/// update leaves a mix the manifest won't match, and there's no old copy to roll back to.
+pub struct InPlaceTarget {
+    install_path: PathBuf,
+    staging: TempDir,
+    staged: PathBuf,
+}
+
+impl InPlaceTarget {
+    pub fn new(install_path: &Path, temp_dir: Option<&Path>) -> Result<Self> {
+        let staging = staging_dir(temp_dir)?;
+        let staged = staging.path().join("install");
+        fs::create_dir(&staged)?;
+        Ok(Self {
+            install_path: install_path.to_owned(),
+            staging,
+            staged,
+        })
+    }
+}
+
+/// Copy a file, sharing its blocks with the original where the filesystem can, like btrfs and XFS
+///
+/// Elsewhere it's `fs::copy`, which still copies in the kernel with `copy_file_range`.
+pub fn clone_file(from: &Path, to: &Path) -> io::Result<()> {
+    let source = fs::File::open(from)?;
+    let dest = fs::File::create(to)?;
+    // SAFETY: both fds are open for the call
+    if unsafe { libc::ioctl(dest.as_raw_fd(), libc::FICLONE as _, source.as_raw_fd()) } == 0 {
+        return dest.set_permissions(source.metadata()?.permissions());
+    }
+    drop(dest);
+    fs::copy(from, to)?;
+    Ok(())
+}
+
+/// Move a file from staging, renaming it on the same filesystem and cloning it across them
+fn promote_file(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::rename(from, to) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => clone_file(from, to),
+        result => result,
+    }
+}
+
+/// Delete whatever is at `path`, if anything
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+impl InstallTarget for InPlaceTarget {
+    fn path(&self) -> &Path {
+        &self.install_path
+    }
+
+    fn staged(&self) -> &Path {
+        &self.staged
+    }
+
+    fn commit(self: Box<Self>, manifest: &Manifest) -> io::Result<Option<Previous>> {
+        write_changes(&self.staged, &self.install_path, manifest)?;
+        self.staging.close()?;
+        Ok(None)
+    }
+
+    fn keep(self: Box<Self>) {
+        let _ = self.staging.into_path();
+    }
+}
+
+/// Write what differs between the install at `install_path` and `staged` over the install,
+/// recording `manifest`, the commit of an `InPlaceTarget`
+///
+/// Running it again after it was interrupted finishes the job, what was moved over already is
+/// no longer in `staged`.
+pub fn write_changes(staged: &Path, install_path: &Path, manifest: &Manifest) -> io::Result<()> {
+    // Without a manifest of ours, what's there has to be looked at
+    let old = match Manifest::load(install_path).ok().flatten() {
+        Some(old) => old,
+        None if install_path.exists() => {
+            Manifest::generate(install_path, manifest.version.clone())?
+        }
+        None => Manifest {
+            version: manifest.version.clone(),
+            entries: vec![],
+            excluded: vec![],
+        },
+    };
+    let recorded: HashMap<_, _> = old.entries.iter().map(|e| (&e.path, e)).collect();
+    fs::create_dir_all(install_path)?;
+    let mut directories = vec![];
+    let mut written = BTreeSet::new();
+    for entry in &manifest.entries {
+        let path = install_path.join(&entry.path);
+        let found = fs::symlink_metadata(&path).ok();
+        // Trusting the manifest only as far as a look at the file agrees with it
+        let unchanged = recorded
+            .get(&entry.path)
+            .is_some_and(|old| old.kind == entry.kind && old.mode == entry.mode)
+            && found.as_ref().is_some_and(|meta| match &entry.kind {
+                EntryKind::File { size, .. } => meta.is_file() && meta.len() == *size,
+                EntryKind::Symlink { .. } => meta.is_symlink(),
+                EntryKind::Directory => meta.is_dir(),
+            });
+        if let EntryKind::Directory = entry.kind {
+            if !found.is_some_and(|meta| meta.is_dir()) {
+                remove_existing(&path)?;
+                fs::create_dir(&path)?;
+            }
+            directories.push((path, entry.mode));
+            continue;
+        }
+        if unchanged {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let temp = path.with_file_name(format!(".{name}.discord_update-new"));
+        remove_existing(&temp)?;
+        let staged = staged.join(&entry.path);
+        let meta = match fs::symlink_metadata(&staged) {
+            Ok(meta) => meta,
+            // Moved over already, by a run that was interrupted
+            Err(e) if e.kind() == io::ErrorKind::NotFound && found.is_some() => continue,
+            Err(e) => return Err(e),
+        };
+        match &entry.kind {
+            EntryKind::Symlink { target } => symlink(target, &temp)?,
+            _ => {
+                promote_file(&staged, &temp)?;
+                fs::set_permissions(&temp, fs::Permissions::from_mode(entry.mode))?;
+            }
+        }
+        // The archive's mtime, like the other strategy leaves
+        tar::set_mtime(&temp, meta.mtime(), meta.mtime_nsec() as u32)?;
+        if meta.is_file() {
+            fsync_path(&temp)?;
+        }
+        if found.is_some_and(|meta| meta.is_dir()) {
+            fs::remove_dir_all(&path)?;
+        }
+        fs::rename(&temp, &path)?;
+        written.insert(path.parent().unwrap().to_owned());
+    }
+    let wanted: HashSet<_> = manifest.entries.iter().map(|e| &e.path).collect();
+    // Deepest first, so directories are empty by the time they're reached
+    for entry in old.entries.iter().rev() {
+        if !wanted.contains(&entry.path) {
+            let path = install_path.join(&entry.path);
+            remove_existing(&path)?;
+            written.insert(path.parent().unwrap().to_owned());
+        }
+    }
+    for (path, mode) in directories.into_iter().rev() {
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+    }
+    for dir in written {
+        fsync_path(&dir)?;
+    }
+    manifest.save(install_path)
+}
+
+/// Put the staged install in place of the current one
+///
+/// When there is a current install the two are exchanged atomically, so the old one ends up at
+/// `staged` for the caller to delete.
+pub fn swap_in(staged: &Path, install_path: &Path) -> io::Result<()> {
+    if !install_path.exists() {
+        return fs::rename(staged, install_path);
+    }
+    let from = CString::new(staged.as_os_str().as_bytes())?;
+    let to = CString::new(install_path.as_os_str().as_bytes())?;
+    // SAFETY: both paths are valid C strings
+    let res = unsafe {
+        libc::renameat2(
+            libc::AT_FDCWD,
+            from.as_ptr(),
+            libc::AT_FDCWD,
+            to.as_ptr(),
+            libc::RENAME_EXCHANGE,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
@@ -0,0 +1,80 @@
+//! The `integrate-user` subcommand, one shared install with a launcher for each user
+//!
+//! On machines with several users, Discord can be installed once with `--system`, kept up to date
+//! by root, and each user who wants it gets their own symlink, menu entry, and `flags`. The
+//! install is recorded as shared in the user's state, so their own runs leave updating it to root.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::state::State;
+use crate::{desktop, platform, profile, symlink, system, Options, Result, UpdateCheck};
+use std::path::{Path, PathBuf};
+
+/// Set up the channel's shared system install for the user running this
+pub async fn run(options: &Options) -> Result<()> {
+    let prog = Progress::new(options.non_interactive);
+    let channel = options.channel;
+    if options.system {
+        return Err(
+            "integrate-user sets up a system install for one user, run it without --system".into(),
+        );
+    }
+    let install_path = options
+        .install_path
+        .clone()
+        .unwrap_or_else(|| system::default_install_path(channel));
+    let platform = platform::current();
+    if !platform.binary_path(channel, &install_path).is_file() {
+        return Err(format!(
+            "there's no Discord {channel} at {}, install it with --system first",
+            install_path.display()
+        )
+        .into());
+    }
+    // How installs found through the PATH are compared
+    let install_path = tokio::fs::canonicalize(&install_path).await?;
+    let version = platform.installed_version(&install_path).await?;
+    let check = UpdateCheck {
+        channel,
+        install_path: install_path.clone(),
+        installed_version: Some(version.clone()),
+        latest_version: version.clone(),
+        release_notes: None,
+        symlink: symlink::link_path(options)?,
+        system: false,
+        damaged: false,
+    };
+    let parts = desktop::Parts {
+        entry: options.desktop_entry,
+        icon: options.icon,
+        url_handler: options.url_handler,
+    };
+    desktop::install(&check, &version, parts)?;
+    State::update(|state| state.channel(channel).shared_install = Some(install_path.clone()))?;
+    if options.symlink {
+        let ask = !options.non_interactive;
+        if symlink::sync(&prog, &check, true, options.force_symlink, ask).await? {
+            profile::check_on_path(&prog, check.symlink.parent().unwrap(), ask).await?;
+        }
+    }
+    prog.println(format!(
+        "Set up Discord {channel} {version} from {} for you. It's updated by discord_update \
+         --system, and flags apply sets flags of your own",
+        install_path.display()
+    ))?;
+    Ok(())
+}
+
+/// The shared install `integrate-user` set the channel up with, if it's still there
+pub fn shared_install(channel: Channel) -> Result<Option<PathBuf>> {
+    Ok(State::load()?
+        .channels
+        .get(&channel)
+        .and_then(|c| c.shared_install.clone())
+        .filter(|path| platform::current().binary_path(channel, path).is_file()))
+}
+
+/// Whether `install_path` is the shared install `integrate-user` set the channel up with
+pub fn is_shared(options: &Options, install_path: &Path) -> Result<bool> {
+    Ok(!options.system && shared_install(options.channel)?.as_deref() == Some(install_path))
+}
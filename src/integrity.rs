@@ -0,0 +1,223 @@
+//! Checking downloads against the MD5 the server says they have
+//!
+//! Google Cloud Storage sends `x-goog-hash: md5=...`, and other servers `Content-MD5` or an ETag
+//! that's the MD5 in hex. Whichever there is, the download has to match it, to catch
+//! corruption on the way that TLS doesn't, from a proxy or a bad cache. MD5 isn't there to stop
+//! tampering, HTTPS and `--pin-keys` are for that.
+
+/// Decode standard base64, with or without padding
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let value = |c: u8| match c {
+        b'A'..=b'Z' => Some(c - b'A'),
+        b'a'..=b'z' => Some(c - b'a' + 26),
+        b'0'..=b'9' => Some(c - b'0' + 52),
+        b'+' => Some(62),
+        b'/' => Some(63),
+        _ => None,
+    };
+    let mut out = vec![];
+    let (mut bits, mut count) = (0u32, 0);
+    for &c in text.trim().trim_end_matches('=').as_bytes() {
+        bits = (bits << 6) | value(c)? as u32;
+        count += 6;
+        if count >= 8 {
+            count -= 8;
+            out.push((bits >> count) as u8);
+        }
+    }
+    Some(out)
+}
+
+fn md5_from(bytes: Option<Vec<u8>>) -> Option<[u8; 16]> {
+    bytes?.try_into().ok()
+}
+
+/// The MD5 a response's headers give for its body, looked up with `header`
+///
+/// Only ETags that are exactly 32 hex digits count, ones like S3's multipart `hash-parts` aren't
+/// the MD5 of the whole body.
+pub fn advertised_md5(header: impl Fn(&str) -> Option<String>) -> Option<[u8; 16]> {
+    let goog = header("x-goog-hash").and_then(|hashes| {
+        hashes
+            .split(',')
+            .find_map(|hash| hash.trim().strip_prefix("md5=").map(decode_base64))
+            .flatten()
+    });
+    let content_md5 = header("content-md5").and_then(|md5| decode_base64(&md5));
+    let etag = header("etag").and_then(|etag| {
+        let hex = etag.trim_matches('"');
+        (hex.len() == 32 && hex.is_ascii())
+            .then(|| {
+                (0..32)
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok())
+                    .collect()
+            })
+            .flatten()
+    });
+    md5_from(goog)
+        .or_else(|| md5_from(content_md5))
+        .or_else(|| md5_from(etag))
+}
+
+/// Per-round shift amounts
+const SHIFTS: [u32; 16] = [7, 12, 17, 22, 5, 9, 14, 20, 4, 11, 16, 23, 6, 10, 15, 21];
+
+/// An MD5 (RFC 1321) of data fed to it in pieces
+///
+/// ring doesn't do MD5, being for cryptography, which this isn't.
+pub struct Md5 {
+    /// floor(abs(sin(i + 1)) * 2^32), as the RFC's table has it
+    constants: [u32; 64],
+    state: [u32; 4],
+    /// Bytes of a block still waiting for the rest of it
+    pending: Vec<u8>,
+    length: u64,
+}
+
+impl Md5 {
+    pub fn new() -> Self {
+        Self {
+            constants: std::array::from_fn(|i| {
+                ((i as f64 + 1.0).sin().abs() * 4_294_967_296.0) as u32
+            }),
+            state: [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476],
+            pending: Vec::with_capacity(64),
+            length: 0,
+        }
+    }
+
+    fn block(&mut self, block: &[u8]) {
+        let words: [u32; 16] = std::array::from_fn(|i| {
+            u32::from_le_bytes(block[i * 4..i * 4 + 4].try_into().unwrap())
+        });
+        let [mut a, mut b, mut c, mut d] = self.state;
+        for i in 0..64 {
+            let (f, g) = match i / 16 {
+                0 => ((b & c) | (!b & d), i),
+                1 => ((d & b) | (!d & c), (5 * i + 1) % 16),
+                2 => (b ^ c ^ d, (3 * i + 5) % 16),
+                _ => (c ^ (b | !d), (7 * i) % 16),
+            };
+            let rotated = a
+                .wrapping_add(f)
+                .wrapping_add(self.constants[i])
+                .wrapping_add(words[g])
+                .rotate_left(SHIFTS[i / 16 * 4 + i % 4]);
+            (a, b, c, d) = (d, b.wrapping_add(rotated), b, c);
+        }
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+        if !self.pending.is_empty() {
+            let take = data.len().min(64 - self.pending.len());
+            self.pending.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.pending.len() < 64 {
+                return;
+            }
+            let pending = std::mem::take(&mut self.pending);
+            self.block(&pending);
+        }
+        let mut blocks = data.chunks_exact(64);
+        for block in &mut blocks {
+            self.block(block);
+        }
+        self.pending.extend_from_slice(blocks.remainder());
+    }
+
+    pub fn finish(mut self) -> [u8; 16] {
+        let bits = self.length.wrapping_mul(8);
+        let padding = (55usize.wrapping_sub(self.pending.len()) % 64) + 1;
+        let mut tail = vec![0; padding];
+        tail[0] = 0x80;
+        tail.extend_from_slice(&bits.to_le_bytes());
+        self.update(&tail);
+        let mut digest = [0; 16];
+        for (out, word) in digest.chunks_exact_mut(4).zip(self.state) {
+            out.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+}
+
+/// Lowercase hex, how MD5s are usually shown
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn md5(data: &[u8]) -> String {
+        let mut md5 = Md5::new();
+        md5.update(data);
+        hex(&md5.finish())
+    }
+
+    #[test]
+    fn rfc_1321_test_suite() {
+        let suite: [(&[u8], &str); 7] = [
+            (b"", "d41d8cd98f00b204e9800998ecf8427e"),
+            (b"a", "0cc175b9c0f1b6a831c399e269772661"),
+            (b"abc", "900150983cd24fb0d6963f7d28e17f72"),
+            (b"message digest", "f96b697d7cb7938d525a2f31aaf161d0"),
+            (
+                b"abcdefghijklmnopqrstuvwxyz",
+                "c3fcd3d76192e4007dfb496cca67e13b",
+            ),
+            (
+                b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789",
+                "d174ab98d277d9f5a5611c2c9f419d9f",
+            ),
+            (
+                b"12345678901234567890123456789012345678901234567890123456789012345678901234567890",
+                "57edf4a22be3c955ac49da2e2107b67a",
+            ),
+        ];
+        for (data, digest) in suite {
+            assert_eq!(md5(data), digest, "{:?}", String::from_utf8_lossy(data));
+        }
+    }
+
+    #[test]
+    fn pieces_hash_like_the_whole() {
+        let data: Vec<u8> = (0..1000u32).map(|i| (i % 256) as u8).collect();
+        for piece in [1, 7, 63, 64, 65, 300] {
+            let mut md5 = Md5::new();
+            for chunk in data.chunks(piece) {
+                md5.update(chunk);
+            }
+            assert_eq!(hex(&md5.finish()), self::md5(&data), "{piece} byte pieces");
+        }
+    }
+
+    #[test]
+    fn finds_the_advertised_md5() {
+        let abc = "900150983cd24fb0d6963f7d28e17f72";
+        let found = |name: &'static str, value: &'static str| {
+            advertised_md5(move |header| (header == name).then(|| value.to_owned()))
+                .map(|md5| hex(&md5))
+        };
+        let goog = found(
+            "x-goog-hash",
+            "crc32c=n7PZhA==, md5=kAFQmDzST7DWlj99KOF/cg==",
+        );
+        assert_eq!(goog.as_deref(), Some(abc));
+        let content_md5 = found("content-md5", "kAFQmDzST7DWlj99KOF/cg==");
+        assert_eq!(content_md5.as_deref(), Some(abc));
+        assert_eq!(
+            found("etag", "\"900150983cd24fb0d6963f7d28e17f72\"").as_deref(),
+            Some(abc)
+        );
+        assert_eq!(
+            found("etag", "\"900150983cd24fb0d6963f7d28e17f72-3\""),
+            None
+        );
+    }
+}
@@ -0,0 +1,41 @@
+//! Stopping cleanly on ^C and SIGTERM
+//!
+//! The run is dropped, so the guards it holds undo what they were doing, like the launch block
+//! and the run lock. A run stuck where it can't be dropped, like at a prompt, is given a moment
+//! and then exits, with what the state says is blocked put back first.
+
+use crate::{launch_block, Result};
+use std::future::Future;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
+
+/// How long a run has to unwind before it's exited
+const GRACE: Duration = Duration::from_secs(1);
+
+/// The exit status of a run ended by a signal it couldn't unwind from
+const INTERRUPTED: i32 = 130;
+
+/// Run `work`, stopping it with an error on SIGINT or SIGTERM
+pub async fn unwinding<T>(work: impl Future<Output = Result<T>>) -> Result<T> {
+    // Handled from here on, so one that comes before the watcher starts isn't missed
+    let mut interrupt = signal(SignalKind::interrupt())?;
+    let mut terminate = signal(SignalKind::terminate())?;
+    let (tx, rx) = oneshot::channel();
+    let watcher = tokio::spawn(async move {
+        let why = tokio::select! {
+            _ = interrupt.recv() => "interrupted",
+            _ = terminate.recv() => "terminated",
+        };
+        let _ = tx.send(why);
+        tokio::time::sleep(GRACE).await;
+        launch_block::undo_all();
+        std::process::exit(INTERRUPTED);
+    });
+    let result = tokio::select! {
+        result = work => result,
+        Ok(why) = rx => Err(why.into()),
+    };
+    watcher.abort();
+    result
+}
@@ -0,0 +1,217 @@
+//! Stopping Discord being launched while it's being updated
+//!
+//! For the length of an update the launcher symlink and the menu entry run a script saying an
+//! update is in progress, instead of a Discord that's about to be swapped out from under it.
+//! What to put back is saved in the state before anything is changed, so a run that's killed
+//! has it put back by the next one.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::state::{state_dir, State};
+use crate::{desktop, launcher, Result, UpdateCheck};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::{Path, PathBuf};
+
+const SCRIPT: &str = "#!/bin/sh
+msg='Discord is being updated, try again in a moment'
+notify-send --app-name=Discord Discord \"$msg\" 2>/dev/null || echo \"$msg\" >&2
+exit 1
+";
+
+/// How to put something back once the update is done
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Restore {
+    /// Point the symlink back at `target`
+    Symlink { link: PathBuf, target: PathBuf },
+    /// Write back these contents
+    Contents { path: PathBuf, contents: String },
+}
+
+impl Restore {
+    fn undo(&self) -> std::io::Result<()> {
+        match self {
+            Self::Symlink { link, target } => repoint(link, target),
+            Self::Contents { path, contents } => fs::write(path, contents),
+        }
+    }
+}
+
+/// While this is alive, launching Discord shows a message, dropping it undoes that
+pub struct LaunchBlock {
+    channel: Channel,
+    restore: Vec<Restore>,
+}
+
+impl LaunchBlock {
+    /// Save `restore` in the state, then make the change it undoes
+    fn change(&mut self, restore: Restore, change: impl FnOnce() -> Result<()>) -> Result<()> {
+        self.restore.push(restore);
+        let saved = self.restore.clone();
+        State::update(|state| state.channel(self.channel).launch_block = saved)?;
+        change()
+    }
+}
+
+/// Replace `link` with a symlink to `target`, atomically
+fn repoint(link: &Path, target: &Path) -> std::io::Result<()> {
+    let temp = link.with_file_name(".discord.discord_update-new");
+    let _ = fs::remove_file(&temp);
+    std::os::unix::fs::symlink(target, &temp)?;
+    fs::rename(&temp, link)
+}
+
+/// The program a .desktop entry's first Exec= line runs
+fn exec_program(contents: &str) -> Option<PathBuf> {
+    let exec = contents
+        .lines()
+        .find_map(|line| line.strip_prefix("Exec="))?;
+    let program = match exec.strip_prefix('"') {
+        Some(quoted) => {
+            let mut program = String::new();
+            let mut chars = quoted.chars();
+            loop {
+                match chars.next()? {
+                    '"' => break,
+                    '\\' => program.push(chars.next()?),
+                    c => program.push(c),
+                }
+            }
+            program
+        }
+        None => exec.split_whitespace().next()?.to_owned(),
+    };
+    Some(PathBuf::from(program))
+}
+
+/// Whether running `program` launches the checked install
+fn launches(check: &UpdateCheck, program: &Path) -> bool {
+    let Ok(program) = fs::canonicalize(program) else {
+        return false;
+    };
+    let launcher = launcher::wrapper(check.channel).and_then(|path| fs::canonicalize(path).ok());
+    fs::canonicalize(check.binary_path()).ok() == Some(program.clone()) || launcher == Some(program)
+}
+
+/// Redirect launches of the checked install to the "updating" script until the returned block
+/// is dropped
+///
+/// Only what already launches this install is redirected.
+pub fn block(check: &UpdateCheck) -> Result<LaunchBlock> {
+    // Built up as we go, so an error part way undoes what was done
+    let mut block = LaunchBlock {
+        channel: check.channel,
+        restore: vec![],
+    };
+    if check.system {
+        return Ok(block);
+    }
+    let script = state_dir()?.join("updating.sh");
+    fs::create_dir_all(script.parent().unwrap())?;
+    fs::write(&script, SCRIPT)?;
+    fs::set_permissions(&script, fs::Permissions::from_mode(0o755))?;
+
+    let link = &check.symlink;
+    if link.is_symlink() && launches(check, link) {
+        let target = fs::read_link(link)?;
+        let restore = Restore::Symlink {
+            link: link.clone(),
+            target,
+        };
+        block.change(restore, || Ok(repoint(link, &script)?))?;
+    }
+
+    let entry = desktop::desktop_file(check.channel)?;
+    let contents = fs::read_to_string(&entry).ok();
+    if let Some(contents) =
+        contents.filter(|c| exec_program(c).is_some_and(|p| launches(check, &p)))
+    {
+        let blocked: Vec<_> = contents
+            .lines()
+            .map(|line| {
+                if line.starts_with("Exec=") {
+                    format!("Exec={}", desktop::quote_exec(&script))
+                } else {
+                    line.to_owned()
+                }
+            })
+            .collect();
+        let restore = Restore::Contents {
+            path: entry.clone(),
+            contents,
+        };
+        block.change(restore, || {
+            Ok(fs::write(&entry, blocked.join("\n") + "\n")?)
+        })?;
+    }
+    Ok(block)
+}
+
+/// Put back what a run that was killed left redirected to the "updating" script
+pub fn recover(prog: &Progress, channel: Channel) -> Result<()> {
+    let state = State::load()?;
+    let Some(saved) = state.channels.get(&channel).map(|c| &c.launch_block) else {
+        return Ok(());
+    };
+    if saved.is_empty() {
+        return Ok(());
+    }
+    prog.println(format!(
+        "Letting Discord {channel} be launched again, an interrupted update left it blocked"
+    ))?;
+    for restore in saved {
+        restore.undo()?;
+    }
+    State::update(|state| state.channel(channel).launch_block.clear())?;
+    Ok(())
+}
+
+/// Put back what every channel has blocked, for when the run can't unwind
+pub fn undo_all() {
+    let Ok(state) = State::load() else {
+        return;
+    };
+    for channel in state.channels.values() {
+        for restore in &channel.launch_block {
+            let _ = restore.undo();
+        }
+    }
+    let _ = State::update(|state| {
+        for channel in state.channels.values_mut() {
+            channel.launch_block.clear();
+        }
+    });
+}
+
+impl Drop for LaunchBlock {
+    fn drop(&mut self) {
+        for restore in &self.restore {
+            let _ = restore.undo();
+        }
+        if !self.restore.is_empty() {
+            let _ = State::update(|state| state.channel(self.channel).launch_block.clear());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exec_program_reads_the_first_exec_line() {
+        let entry = "[Desktop Entry]\nName=Discord\nExec=/home/a/bin/discord %U\nExec=/other\n";
+        assert_eq!(exec_program(entry), Some("/home/a/bin/discord".into()));
+        let quoted = "Exec=\"/home/a b/\\\"Discord\\\"\" %U\n";
+        assert_eq!(exec_program(quoted), Some("/home/a b/\"Discord\"".into()));
+        let quoted = desktop::quote_exec(Path::new("/a $b/c"));
+        assert_eq!(
+            exec_program(&format!("Exec={quoted}")),
+            Some("/a $b/c".into())
+        );
+        assert_eq!(exec_program("Exec=\"/unterminated"), None);
+        assert_eq!(exec_program("Name=Discord\n"), None);
+    }
+}
@@ -0,0 +1,266 @@
+//! The `install-launcher` subcommand, a wrapper that updates Discord before starting it
+//!
+//! It can also set environment variables for Discord with `--env`, for workarounds like
+//! `--env ELECTRON_OZONE_PLATFORM_HINT=x11`, with or without the update check. What it was made
+//! with is kept in the state, so it's rewritten to match after every update.
+
+use crate::channel::Channel;
+use crate::cli::Matches;
+use crate::harden::Sandbox;
+use crate::progress::Progress;
+use crate::state::{state_dir, State};
+use crate::{
+    autostart, cron, desktop, find_install, flags, harden, platform, symlink, Options, Result,
+    UpdateCheck,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead as _, BufReader};
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Second line of every launcher, so we can tell ours apart from other scripts
+const MARKER: &str = "# discord_update launcher";
+
+/// How often the launcher checks for updates unless `--check-interval` says otherwise
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How long the launcher lets a check hold up Discord unless `--timeout` says otherwise
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// What a launcher was made with, to make it again
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Settings {
+    /// Where it is, standing in for the symlink
+    pub path: PathBuf,
+    /// Variables exported before starting Discord
+    pub env: BTreeMap<String, String>,
+    /// Whether it checks for updates first, `--no-update-check` makes it only a wrapper
+    pub update_check: bool,
+    pub check_interval: u64,
+    pub timeout: u64,
+    /// `flags apply`, names of the presets whose flags Discord is started with
+    #[serde(default)]
+    pub presets: Vec<String>,
+    /// `harden`, what Discord is started inside, if anything
+    #[serde(default)]
+    pub sandbox: Option<Sandbox>,
+    /// `harden --allow`, more paths Discord can write to inside the sandbox
+    #[serde(default)]
+    pub allow: Vec<PathBuf>,
+}
+
+impl Settings {
+    /// A launcher that only starts Discord, for `flags apply` when there isn't one yet
+    pub fn wrapper(path: PathBuf) -> Self {
+        Self {
+            path,
+            env: BTreeMap::new(),
+            update_check: false,
+            check_interval: DEFAULT_INTERVAL.as_secs(),
+            timeout: DEFAULT_TIMEOUT.as_secs(),
+            presets: vec![],
+            sandbox: None,
+            allow: vec![],
+        }
+    }
+}
+
+/// Quote a string for sh
+fn quote(s: impl AsRef<std::ffi::OsStr>) -> String {
+    format!("'{}'", s.as_ref().to_string_lossy().replace('\'', r"'\''"))
+}
+
+/// Parse `--env NAME=VALUE`
+fn parse_env(var: &str) -> Result<(String, String)> {
+    let (name, value) = var
+        .split_once('=')
+        .ok_or_else(|| format!("--env {var:?} should look like NAME=VALUE"))?;
+    let valid = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(format!("{name:?} can't be the name of an environment variable").into());
+    }
+    Ok((name.to_owned(), value.to_owned()))
+}
+
+/// Whether the file at `path` is a launcher we wrote
+pub fn is_launcher(path: &Path) -> bool {
+    let Ok(file) = fs::File::open(path) else {
+        return false;
+    };
+    BufReader::new(file)
+        .lines()
+        .nth(1)
+        .is_some_and(|line| line.is_ok_and(|line| line.starts_with(MARKER)))
+}
+
+/// The channel's launcher, if it has one that's still there
+pub fn wrapper(channel: Channel) -> Option<PathBuf> {
+    let state = State::load().ok()?;
+    let path = state
+        .channels
+        .get(&channel)?
+        .launcher
+        .as_ref()?
+        .path
+        .clone();
+    is_launcher(&path).then_some(path)
+}
+
+/// The script for a launcher of the install at `install_path`
+fn script(channel: Channel, install_path: &Path, settings: &Settings) -> Result<String> {
+    let binary = platform::current().binary_path(channel, install_path);
+    let mut script = format!(
+        "#!/bin/sh\n\
+         {MARKER} for {channel}, made by `discord_update install-launcher`\n"
+    );
+    for (name, value) in &settings.env {
+        script += &format!("export {name}={}\n", quote(value));
+    }
+    if settings.update_check {
+        script += &format!(
+            "flock -n {lock} {exe} --non-interactive --channel {channel} --install-path {install} \
+             --check-interval {interval}s --timeout {timeout}s >> {log} 2>&1\n",
+            lock = quote(cron::lock_path(channel)?),
+            exe = quote(std::env::current_exe()?),
+            install = quote(install_path),
+            interval = settings.check_interval,
+            timeout = settings.timeout,
+            log = quote(state_dir()?.join(format!("launcher-{channel}.log"))),
+        );
+    }
+    script += "exec";
+    if let Some(sandbox) = settings.sandbox {
+        for arg in harden::command(sandbox, channel, install_path, &settings.allow)? {
+            script += &format!(" {}", quote(arg));
+        }
+    }
+    script += &format!(" {}", quote(binary));
+    for flag in flags::of(&settings.presets) {
+        script += &format!(" {}", quote(flag));
+    }
+    script += " \"$@\"\n";
+    Ok(script)
+}
+
+/// Write the script in place of whatever's at `link`, keeping a regular file as .bak
+fn write(link: &Path, script: &str) -> Result<()> {
+    let is_link = link.is_symlink();
+    let parent = link.parent().unwrap();
+    fs::create_dir_all(parent)?;
+    let temp = tempfile::NamedTempFile::new_in(parent)?;
+    fs::write(temp.path(), script)?;
+    fs::set_permissions(temp.path(), fs::Permissions::from_mode(0o755))?;
+    if link.exists() && !is_link && !is_launcher(link) {
+        let mut backup = link.as_os_str().to_owned();
+        backup.push(".bak");
+        fs::rename(link, backup)?;
+    }
+    temp.persist(link)?;
+    Ok(())
+}
+
+/// The channel's install and where its launcher goes, checking a launcher can go there
+pub async fn locate(prog: &Progress, options: &Options) -> Result<(PathBuf, PathBuf)> {
+    let channel = options.channel;
+    let install_path = find_install(prog, options).await?;
+    let binary = platform::current().binary_path(channel, &install_path);
+    if !binary.exists() {
+        return Err(format!(
+            "Discord {channel} isn't installed at {}, install it first",
+            install_path.display()
+        )
+        .into());
+    }
+    let link = symlink::link_path(options)?;
+    if link.exists() && !link.is_symlink() && !is_launcher(&link) && !options.force_symlink {
+        return Err(format!(
+            "{} exists and isn't a symlink, use --force-symlink to replace it",
+            link.display()
+        )
+        .into());
+    }
+    Ok((install_path, link))
+}
+
+/// The channel's launcher settings, if `install-launcher` made one
+pub fn settings(channel: Channel) -> Result<Option<Settings>> {
+    Ok(State::load()?
+        .channels
+        .get(&channel)
+        .and_then(|c| c.launcher.clone()))
+}
+
+/// Write the channel's launcher and remember how it was made
+pub fn save(channel: Channel, install_path: &Path, settings: Settings) -> Result<()> {
+    fs::create_dir_all(state_dir()?)?;
+    write(&settings.path, &script(channel, install_path, &settings)?)?;
+    // So the environment applies when it's started from the menu too
+    desktop::set_exec(channel, &settings.path)?;
+    State::update(|state| state.channel(channel).launcher = Some(settings))?;
+    // Read back from the state, so the entry picks up the launcher
+    autostart::refresh(channel, install_path)
+}
+
+/// Put a launcher where the channel's symlink goes
+///
+/// The check is skipped if one ran recently, and shares the cron job's lock so the two never
+/// update at once. Output goes to a log in the state directory so it doesn't hold Discord up.
+pub async fn install(prog: &Progress, options: &Options, matches: &Matches) -> Result<()> {
+    let channel = options.channel;
+    let (install_path, link) = locate(prog, options).await?;
+    let interval = options.check_interval.unwrap_or(DEFAULT_INTERVAL);
+    let settings = Settings {
+        path: link.clone(),
+        env: matches
+            .values("env")
+            .iter()
+            .map(|var| parse_env(var))
+            .collect::<Result<_>>()?,
+        update_check: !matches.flag("no-update-check"),
+        check_interval: interval.as_secs(),
+        timeout: options.timeout.unwrap_or(DEFAULT_TIMEOUT).as_secs(),
+        // Presets and the sandbox are managed with `flags` and `harden`, so they survive making
+        // the launcher again
+        ..settings(channel)?.unwrap_or_else(|| Settings::wrapper(link.clone()))
+    };
+    let update_check = settings.update_check;
+    save(channel, &install_path, settings)?;
+    if update_check {
+        prog.println(format!(
+            "{} now checks for updates at most every {} before starting Discord",
+            link.display(),
+            indicatif::HumanDuration(interval)
+        ))?;
+    } else {
+        prog.println(format!(
+            "{} now starts Discord through a wrapper",
+            link.display()
+        ))?;
+    }
+    Ok(())
+}
+
+/// After an update, make the channel's launcher again so it matches the install and us
+pub fn refresh(check: &UpdateCheck) -> Result<()> {
+    let state = State::load()?;
+    let Some(settings) = state
+        .channels
+        .get(&check.channel)
+        .and_then(|c| c.launcher.as_ref())
+    else {
+        return Ok(());
+    };
+    // Replaced by something else since, which isn't ours to touch
+    if !is_launcher(&settings.path) {
+        return Ok(());
+    }
+    let script = script(check.channel, &check.install_path, settings)?;
+    if fs::read_to_string(&settings.path).ok().as_deref() != Some(script.as_str()) {
+        write(&settings.path, &script)?;
+    }
+    Ok(())
+}
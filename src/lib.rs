@@ -0,0 +1,2214 @@
+//! Install and update Discord from the official Linux tarballs
+
+use bytes::Bytes;
+use channel::Channel;
+use futures::stream::TryStreamExt as _;
+use futures::Future;
+use indicatif::{HumanBytes, HumanDuration, ProgressBar, ProgressStyle};
+use install::InstallTarget;
+use manifest::Manifest;
+use progress::Progress;
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use serde_with::{serde_as, DisplayFromStr};
+use state::State;
+use std::env;
+use std::io::IsTerminal as _;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::process::{Child, ChildStdin, Command};
+use tokio::sync::{mpsc, oneshot};
+
+mod apparmor;
+mod audit_perms;
+mod autostart;
+mod backoff;
+mod channel;
+mod cli;
+mod close;
+mod config;
+mod cron;
+mod defer;
+mod desktop;
+mod exclude;
+mod feed;
+pub mod fetch;
+mod flags;
+#[cfg(feature = "gui")]
+mod gui;
+mod gzip;
+mod harden;
+mod health;
+pub mod install;
+mod integrate;
+mod integrity;
+mod interrupt;
+mod launch_block;
+mod launcher;
+mod maintenance;
+mod man;
+pub mod manifest;
+mod metrics;
+mod netcheck;
+mod notify;
+mod pin;
+mod pinning;
+mod plan;
+mod platform;
+mod profile;
+mod progress;
+mod run_lock;
+mod running;
+mod sandbox;
+mod schema;
+mod selinux;
+mod settings;
+mod setup;
+mod snapshot;
+mod source;
+mod state;
+mod store;
+mod switch_channel;
+mod symlink;
+mod system;
+mod tar;
+mod transaction;
+#[cfg(feature = "tui")]
+mod tui;
+mod uninstall;
+mod user_data;
+mod webhook;
+mod writable;
+
+// Beyond what `platform` covers, installs lean on Linux: renameat2, unix symlinks and
+// permissions, bash, crontab, and XDG directories. Windows would need its own updates endpoint,
+// Squirrel packages, the %LocalAppData%\Discord layout, and Start Menu shortcuts in place of the
+// symlink. macOS would need the .dmg or zip builds, the version from Info.plist, and an atomic
+// swap of Discord.app.
+#[cfg(not(target_os = "linux"))]
+compile_error!("discord_update only supports Linux so far");
+
+pub type Error = Box<dyn std::error::Error + 'static>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[serde_as]
+#[derive(Deserialize)]
+struct VersionPayload {
+    #[serde(alias = "name")]
+    #[serde_as(as = "DisplayFromStr")]
+    version: Version,
+    #[serde(default)]
+    notes: Option<String>,
+}
+
+/// How results are reported on stdout
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+enum OutputFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("expected text or json".into()),
+        }
+    }
+}
+
+/// How a run that didn't fail went, for `main` to exit with
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Done,
+    /// `--ensure` had to install something
+    Changed,
+}
+
+impl Outcome {
+    /// The exit status, 2 for `Changed` as 1 means we failed, like Puppet's
+    /// `--detailed-exitcodes`
+    pub fn code(self) -> i32 {
+        match self {
+            Self::Done => 0,
+            Self::Changed => 2,
+        }
+    }
+}
+
+/// What `--ensure` should make sure is installed
+#[derive(Clone)]
+enum EnsureTarget {
+    Latest,
+    Version(Version),
+}
+
+impl FromStr for EnsureTarget {
+    type Err = semver::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "latest" => Ok(Self::Latest),
+            version => Ok(Self::Version(version.parse()?)),
+        }
+    }
+}
+
+/// What `--skip-version` should skip, `none` stops skipping
+#[derive(Clone)]
+enum SkipTarget {
+    Nothing,
+    Version(Version),
+}
+
+impl FromStr for SkipTarget {
+    type Err = semver::Error;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Self::Nothing),
+            version => Ok(Self::Version(version.parse()?)),
+        }
+    }
+}
+
+/// How long unattended runs get before we give up, so a stuck download can't pile up cron jobs
+const NON_INTERACTIVE_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// Settings for this run, from the command line
+#[derive(Clone, Default)]
+struct Options {
+    channel: Channel,
+    output: OutputFormat,
+    /// Converge on this version instead of running interactively
+    ensure: Option<EnsureTarget>,
+    /// Install updates without asking first
+    assume_yes: bool,
+    /// Ask a running client to quit before updating it, without asking first
+    close_running: bool,
+    /// Wait for the client to be closed before installing, giving up after the timeout if any
+    when_closed: Option<Option<Duration>>,
+    /// Clear Discord's caches after an update
+    clear_cache: bool,
+    /// Delete downloaded modules that don't match the new version after an update
+    purge_modules: bool,
+    /// Reinstall when the install is missing files
+    auto_repair: bool,
+    /// List the files an update adds, removes, and changes before putting it in place
+    show_diff: bool,
+    /// Refuse to download more than this many bytes
+    max_download_size: Option<u64>,
+    /// Files to leave out of the install
+    excludes: exclude::Excludes,
+    /// Don't ask the API again until this long after the last check
+    check_interval: Option<Duration>,
+    /// Where to ask whether a version is known to be broken, before installing it
+    version_feed: Option<String>,
+    /// Hold back versions the feed doesn't say are good
+    version_feed_strict: bool,
+    /// Scheduled runs wait a random part of this first, so machines don't all check at once
+    jitter: Option<Duration>,
+    /// Wait for another run updating the channel to finish, instead of failing
+    wait: bool,
+    /// Say when there's an update, but don't install it
+    notify_only: bool,
+    /// Print what an update would do instead of doing it
+    dry_run: bool,
+    /// Change the version the channel's updates skip, remembered for later runs
+    skip_version: Option<SkipTarget>,
+    /// The version to take the install for, rather than what its build_info.json says
+    assume_installed_version: Option<Version>,
+    /// Download builds from here rather than from Discord
+    source: Option<source::BuildSource>,
+    /// What to do with older versions' per-build config directories after an update
+    build_dirs: maintenance::BuildDirs,
+    /// Where to write node_exporter textfile metrics
+    metrics_file: Option<PathBuf>,
+    /// Where to fetch release notes from, `{version}` is replaced with the new version
+    notes_url: Option<String>,
+    /// Nobody is watching, so never prompt and don't draw progress bars
+    non_interactive: bool,
+    /// `--timeout`, give up on the run after this long
+    timeout: Option<Duration>,
+    /// Base URL of the updates API
+    api_base: String,
+    /// Our own URLs for the channel rather than Discord's, see `Channel::url_templates`
+    api_url_template: Option<String>,
+    download_url_template: Option<String>,
+    /// Use the install here rather than looking for one
+    install_path: Option<PathBuf>,
+    /// Where --system and in-place downloads are staged, instead of `$TMPDIR`
+    temp_dir: Option<PathBuf>,
+    /// Swap the whole install for the new one, or write only the files that changed
+    update_strategy: install::Strategy,
+    /// Install an AppArmor profile for Discord where user namespaces need one
+    apparmor_profile: bool,
+    /// `--shared-group`, the group the install belongs to, so any of its members can update it
+    shared_group: Option<u32>,
+    /// Directory for the launcher symlink
+    symlink_dir: Option<PathBuf>,
+    /// `--symlink-dir` was given, so the symlink there should launch this channel
+    claim_symlink: bool,
+    /// Replace the launcher symlink even when it's a regular file or points at another install
+    force_symlink: bool,
+    /// Manage the root-owned install under /opt
+    system: bool,
+    /// Resume downloads that break off with a range request, which some proxies mishandle
+    range_requests: bool,
+    /// Keep a launcher symlink pointing at the install
+    symlink: bool,
+    /// Add the install to the applications menu
+    desktop_entry: bool,
+    /// Add Discord's icon to the icon theme
+    icon: bool,
+    /// Make the menu entry the handler for `discord://` links
+    url_handler: bool,
+    /// `--proxy` and `--pin-keys`, for passing on to `system-helper stage`
+    proxy: Option<String>,
+    pin_keys: Option<String>,
+    /// After updating, launch Discord and roll back if it exits with an error within this long
+    watch_first_launch: Option<Duration>,
+    /// Update every install of the channel that can be found
+    all: bool,
+    /// Snapshot the filesystem holding the install before updating it
+    snapshot: bool,
+    /// Where installed versions are kept to be put back together without a download
+    store: Option<store::Store>,
+    /// Where to send update events, from `--notify` and the settings of each backend
+    notifiers: Vec<Arc<dyn notify::Notifier>>,
+}
+
+/// The URL template given as `--{long}`, if it only has the `names` placeholders
+fn url_template(matches: &cli::Matches, long: &str, names: &[&str]) -> Result<Option<String>> {
+    let Some(template) = matches.value(long) else {
+        return Ok(None);
+    };
+    channel::check_template(long, template, names)?;
+    Ok(Some(template.to_owned()))
+}
+
+impl Options {
+    fn from_matches(matches: &cli::Matches) -> Result<Self> {
+        let non_interactive = matches.flag("non-interactive")
+            || !std::io::stdin().is_terminal()
+            || !std::io::stderr().is_terminal();
+        let timeout = matches
+            .parse_value::<cli::DurationArg>("timeout")?
+            .map(|timeout| timeout.0);
+        let channel: Channel = matches.parse_value("channel")?.unwrap_or_default();
+        Ok(Self {
+            channel,
+            output: matches.parse_value("output")?.unwrap_or_default(),
+            ensure: matches.parse_value("ensure")?,
+            assume_yes: matches.flag("yes") || non_interactive,
+            close_running: matches.flag("close-running"),
+            when_closed: match matches.parse_value::<cli::DurationArg>("when-closed-timeout")? {
+                _ if !matches.flag("when-closed") => None,
+                _ if matches.flag("system") => return Err(
+                    "--when-closed can't see other users' Discord, so doesn't work with --system"
+                        .into(),
+                ),
+                timeout => Some(timeout.map(|t| t.0)),
+            },
+            clear_cache: matches.flag("clear-cache"),
+            purge_modules: matches.flag("purge-modules"),
+            auto_repair: matches.flag("auto-repair"),
+            show_diff: matches.flag("show-diff"),
+            excludes: matches
+                .value("exclude")
+                .map(|patterns| exclude::Excludes::parse(patterns, channel.binary_name()))
+                .transpose()?
+                .unwrap_or_default(),
+            max_download_size: matches
+                .parse_value::<cli::SizeArg>("max-download-size")?
+                .map(|size| size.0),
+            version_feed: matches.value("version-feed").map(str::to_owned),
+            version_feed_strict: matches.flag("version-feed-strict"),
+            jitter: matches
+                .parse_value::<cli::DurationArg>("jitter")?
+                .map(|jitter| jitter.0),
+            wait: matches.flag("wait"),
+            check_interval: matches
+                .parse_value::<cli::DurationArg>("check-interval")?
+                .map(|interval| interval.0),
+            notify_only: matches.flag("notify-only"),
+            dry_run: matches.flag("dry-run"),
+            skip_version: matches.parse_value("skip-version")?,
+            assume_installed_version: matches.parse_value("assume-installed-version")?,
+            source: matches
+                .value("source-url")
+                .map(|url| source::BuildSource::new(url, matches.value("source-key")))
+                .transpose()?,
+            build_dirs: matches.parse_value("build-dirs")?.unwrap_or_default(),
+            notes_url: matches.value("notes-url").map(str::to_owned),
+            metrics_file: matches.value("metrics-file").map(PathBuf::from),
+            non_interactive,
+            timeout,
+            api_url_template: url_template(matches, "api-url-template", channel::API_PLACEHOLDERS)?,
+            download_url_template: url_template(
+                matches,
+                "download-url-template",
+                channel::DOWNLOAD_PLACEHOLDERS,
+            )?,
+            api_base: matches
+                .value("api-url")
+                .unwrap_or(channel::DEFAULT_API_BASE)
+                .to_owned(),
+            install_path: matches
+                .value("install-path")
+                .map(|dir| config::expand_home(dir.as_ref()))
+                .transpose()?,
+            update_strategy: match matches.parse_value("update-strategy")? {
+                Some(install::Strategy::InPlace) if matches.flag("system") => {
+                    return Err("--update-strategy in-place doesn't work with --system".into())
+                }
+                strategy => strategy.unwrap_or_default(),
+            },
+            apparmor_profile: matches.flag("apparmor-profile"),
+            shared_group: match matches.value("shared-group") {
+                Some(_) if matches.flag("system") => {
+                    return Err("--shared-group doesn't work with --system".into())
+                }
+                group => group.map(install::group_id).transpose()?,
+            },
+            temp_dir: matches
+                .value("temp-dir")
+                .map(|dir| config::expand_home(dir.as_ref()))
+                .transpose()?,
+            symlink_dir: matches
+                .value("symlink-dir")
+                .map(|dir| config::expand_home(dir.as_ref()))
+                .transpose()?,
+            claim_symlink: matches.explicit("symlink-dir"),
+            force_symlink: matches.flag("force-symlink"),
+            system: matches.flag("system"),
+            range_requests: !matches.flag("no-range-requests"),
+            symlink: !matches.flag("no-symlink"),
+            desktop_entry: !matches.flag("no-desktop-entry"),
+            icon: !matches.flag("no-icon"),
+            url_handler: !matches.flag("no-url-handler"),
+            proxy: matches.value("proxy").map(str::to_owned),
+            pin_keys: matches.value("pin-keys").map(str::to_owned),
+            watch_first_launch: matches
+                .parse_value::<cli::DurationArg>("watch-first-launch")?
+                .map(|window| window.0),
+            all: matches.flag("all"),
+            snapshot: matches.flag("snapshot"),
+            store: match matches.parse_value::<usize>("store-versions")? {
+                Some(keep) if keep > 0 && !matches.flag("system") => Some(store::Store::new(keep)?),
+                _ => None,
+            },
+            notifiers: notify::from_matches(matches, non_interactive)?,
+        })
+    }
+}
+
+/// Run a bash script
+async fn bash(s: &str) -> Result<String> {
+    let output = Command::new("/bin/bash").arg("-c").arg(s).output().await?;
+    if !output.status.success() {
+        Err(format!("script failed: {s:?}").into())
+    } else {
+        Ok(String::from_utf8(output.stdout)?)
+    }
+}
+
+/// The updates API endpoint for the channel
+fn api_url(options: &Options, channel: Channel) -> String {
+    channel.api_url(options.api_url_template.as_deref(), &options.api_base)
+}
+
+/// Get latest discord version (and any notes the API has about it) from the internet
+async fn get_latest_discord_version(options: &Options, channel: Channel) -> Result<VersionPayload> {
+    let url = api_url(options, channel);
+    let r: VersionPayload = fetch::get(&url)
+        .await?
+        .json()
+        .await
+        .map_err(|e| format!("bad response from {url}: {e}"))?;
+    Ok(r)
+}
+
+/// Fetch the release notes for the given version from a user supplied URL
+async fn fetch_release_notes(notes_url: &str, version: &Version) -> Result<String> {
+    let url = notes_url.replace("{version}", &version.to_string());
+    fetch::get(&url).await?.text().await
+}
+
+/// Ask the user a yes/no question, an empty answer picks `default`
+fn confirm(prog: &Progress, question: &str, default: bool) -> Result<bool> {
+    prog.suspend(|| {
+        let choices = if default { "[Y/n]" } else { "[y/N]" };
+        eprint!("{question} {choices} ");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        let answer = answer.trim().to_lowercase();
+        Ok(match answer.as_str() {
+            "" => default,
+            answer => answer == "y" || answer == "yes",
+        })
+    })
+}
+
+/// Ask the user for some text, an empty answer picks `default`
+fn ask(prog: &Progress, question: &str, default: &str) -> Result<String> {
+    prog.suspend(|| {
+        eprint!("{question} [{default}] ");
+        let mut answer = String::new();
+        std::io::stdin().read_line(&mut answer)?;
+        Ok(match answer.trim() {
+            "" => default.to_owned(),
+            answer => answer.to_owned(),
+        })
+    })
+}
+
+/// Ask the user to pick one of `choices`, an empty answer picks the first
+fn choose(prog: &Progress, question: &str, choices: &[String]) -> Result<usize> {
+    prog.suspend(|| {
+        for (i, choice) in choices.iter().enumerate() {
+            eprintln!("  {}) {choice}", i + 1);
+        }
+        loop {
+            eprint!("{question} [1-{}] ", choices.len());
+            let mut answer = String::new();
+            if std::io::stdin().read_line(&mut answer)? == 0 {
+                return Err("no choice was made".into());
+            }
+            match answer.trim() {
+                "" => return Ok(0),
+                answer => match answer.parse::<usize>() {
+                    Ok(n) if (1..=choices.len()).contains(&n) => return Ok(n - 1),
+                    _ => eprintln!("Answer with a number from 1 to {}", choices.len()),
+                },
+            }
+        }
+    })
+}
+
+/// Files every working install has, so a cheap check for a damaged one
+const KEY_FILES: &[&str] = &["resources/app.asar", BUILD_INFO];
+
+/// Where an install says which version it is
+const BUILD_INFO: &str = "resources/build_info.json";
+
+/// The key files missing from an install, the binary included
+async fn missing_files(channel: Channel, install_path: &Path) -> Result<Vec<&'static str>> {
+    let mut missing = vec![];
+    for name in [channel.binary_name()].iter().chain(KEY_FILES) {
+        if !tokio::fs::try_exists(install_path.join(name)).await? {
+            missing.push(*name);
+        }
+    }
+    Ok(missing)
+}
+
+/// How many downloaded chunks can wait for the extractor before the download waits for it
+///
+/// Chunks are usually 8-16 KiB, so this keeps at most a few MiB in memory however slow the disk.
+const EXTRACT_BACKLOG: usize = 256;
+
+/// How many times a download that broke off is picked up again
+const MAX_RESUMES: u32 = 3;
+
+/// Reads the chunks of a download as they arrive, blocking until the next one does or the
+/// download is done (drops its sender)
+struct ChunkReader<'a> {
+    chunks: mpsc::Receiver<Bytes>,
+    current: Bytes,
+    hashes: &'a mut Hashes,
+}
+
+impl Read for ChunkReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        while self.current.is_empty() {
+            match self.chunks.blocking_recv() {
+                Some(chunk) => {
+                    self.hashes.update(&chunk);
+                    self.current = chunk;
+                }
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.current.len());
+        buf[..len].copy_from_slice(&self.current.split_to(len));
+        Ok(len)
+    }
+}
+
+/// Hashes of a whole download, worked out on the extractor's thread so the download isn't held
+/// up by them
+#[derive(Default)]
+struct Hashes {
+    sha256: Option<ring::digest::Context>,
+    md5: Option<integrity::Md5>,
+}
+
+impl Hashes {
+    fn update(&mut self, chunk: &[u8]) {
+        if let Some(sha256) = &mut self.sha256 {
+            sha256.update(chunk);
+        }
+        if let Some(md5) = &mut self.md5 {
+            md5.update(chunk);
+        }
+    }
+}
+
+/// Extract a gzipped tar stream as it is downloaded, dropping the top-level directory
+///
+/// Send the downloaded chunks to the returned sender, nothing goes through a file on disk on
+/// the way. The gzip CRC and length are checked as the data goes by, so the task only succeeds
+/// if the whole download was intact.
+fn spawn_tar_xf(
+    prog: &Progress,
+    dest: &Path,
+    excludes: exclude::Excludes,
+    mut hashes: Hashes,
+    mut files: progress::FileProgress,
+) -> (
+    mpsc::Sender<Bytes>,
+    oneshot::Receiver<std::io::Result<(tar::Index, Hashes)>>,
+) {
+    let (tx, chunks) = mpsc::channel(EXTRACT_BACKLOG);
+    let (done, task) = oneshot::channel();
+    let (prog, dest) = (prog.clone(), dest.to_owned());
+    // A thread of its own, the sandbox stays with it until it exits
+    std::thread::spawn(move || {
+        let extract = || {
+            let missing = sandbox::confine(&dest)?;
+            if !missing.is_empty() {
+                prog.println(format!(
+                    "Extracting unconfined, the kernel doesn't support {}",
+                    missing.join(" or ")
+                ))?;
+            }
+            let reader = ChunkReader {
+                chunks,
+                current: Bytes::new(),
+                hashes: &mut hashes,
+            };
+            // The decoder does its own buffering
+            let skip = |path: &Path| excludes.matches(path);
+            let written = |path: &Path, written, size| files.update(path, written, size);
+            let index = tar::extract(gzip::GzDecoder::new(reader), &dest, 1, skip, written)?;
+            Ok((index, std::mem::take(&mut hashes)))
+        };
+        let _ = done.send(extract());
+    });
+    (tx, task)
+}
+
+/// Flush a file or directory to disk
+fn fsync_path(path: &Path) -> std::io::Result<()> {
+    std::fs::File::open(path)?.sync_all()
+}
+
+/// Recursively fsync every file and directory under the given path
+///
+/// Directories are synced after their contents so the entries pointing at the new data are
+/// durable too. Symlinks are not followed.
+fn fsync_tree(path: &Path) -> std::io::Result<()> {
+    let file_type = std::fs::symlink_metadata(path)?.file_type();
+    if file_type.is_dir() {
+        for entry in std::fs::read_dir(path)? {
+            fsync_tree(&entry?.path())?;
+        }
+        fsync_path(path)
+    } else if file_type.is_file() {
+        fsync_path(path)
+    } else {
+        Ok(())
+    }
+}
+
+/// Holds a systemd-inhibit lock that blocks sleep and shutdown until dropped.
+///
+/// The inhibitor runs `cat` reading from a pipe we own, so the lock is released as soon as the
+/// pipe is closed, including when this process dies unexpectedly.
+struct SleepInhibitor {
+    _stdin: ChildStdin,
+    _child: Child,
+}
+
+/// Ask logind to block sleep and shutdown while we modify the install
+///
+/// This is best-effort, on systems without systemd-inhibit we just carry on without it.
+fn inhibit_sleep(why: &str) -> Option<SleepInhibitor> {
+    let mut child = Command::new("systemd-inhibit")
+        .arg("--what=sleep:shutdown")
+        .arg("--who=discord_update")
+        .arg(format!("--why={why}"))
+        .arg("--mode=block")
+        .arg("cat")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .ok()?;
+    let stdin = child.stdin.take()?;
+    Some(SleepInhibitor {
+        _stdin: stdin,
+        _child: child,
+    })
+}
+
+/// Where the tarball for the given version comes from, Discord or `--source-url`
+fn download_url(options: &Options, channel: Channel, version: &Version) -> String {
+    match &options.source {
+        Some(source) => source.url(channel, version),
+        None => channel.download_url(options.download_url_template.as_deref(), version),
+    }
+}
+
+/// Fail if `size` is more than `--max-download-size`
+fn check_download_size(options: &Options, version: &Version, size: u64) -> Result<()> {
+    match options.max_download_size {
+        Some(max) if size > max => Err(format!(
+            "Discord {version} is a {} download, more than --max-download-size {}",
+            HumanBytes(size),
+            HumanBytes(max)
+        )
+        .into()),
+        _ => Ok(()),
+    }
+}
+
+/// Free space on the filesystem `path` is or would be created on
+fn free_space(path: &Path) -> Option<u64> {
+    let existing = path.ancestors().find(|p| p.exists())?;
+    let path = std::ffi::CString::new(existing.as_os_str().as_encoded_bytes()).ok()?;
+    // SAFETY: statvfs is plain data, and path is a valid C string
+    unsafe {
+        let mut stat: libc::statvfs = std::mem::zeroed();
+        if libc::statvfs(path.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+        Some(stat.f_bavail as u64 * stat.f_frsize as u64)
+    }
+}
+
+/// Ask whether to install the update, saying how big it is when the server tells us
+///
+/// An update bigger than `--max-download-size` fails before anything is asked.
+async fn confirm_update(prog: &Progress, options: &Options, check: &UpdateCheck) -> Result<bool> {
+    let version = &check.latest_version;
+    let url = download_url(options, check.channel, version);
+    let size = match fetch::content_length(&url).await.ok().flatten() {
+        None => String::new(),
+        Some(size) => {
+            check_download_size(options, version, size)?;
+            match free_space(&check.install_path) {
+                Some(free) => format!(
+                    " ({} download, {} free)",
+                    HumanBytes(size),
+                    HumanBytes(free)
+                ),
+                None => format!(" ({} download)", HumanBytes(size)),
+            }
+        }
+    };
+    confirm(prog, &format!("Install Discord {version}{size}?"), true)
+}
+
+/// Download a version and extract it into `staged` as it arrives, returning what the archive
+/// said it wrote
+async fn download(
+    multi_prog: &Progress,
+    spinner: &ProgressBar,
+    staged: &Path,
+    channel: Channel,
+    version: &Version,
+    options: &Options,
+) -> Result<tar::Index> {
+    let download_url = download_url(options, channel, version);
+    if let Some(source) = &options.source {
+        multi_prog.println(format!(
+            "WARNING: {download_url} is not an official Discord build. It's only as trustworthy \
+             as whoever holds key {}",
+            source.key
+        ))?;
+    }
+    let resp = fetch::get(&download_url).await?;
+    let download_size = resp.content_length;
+    if let Some(size) = download_size {
+        check_download_size(options, version, size)?;
+    }
+    let mut download_stream = resp.body;
+
+    let pb = multi_prog.add(
+        ProgressBar::new(download_size.unwrap_or(0))
+            .with_style(
+                ProgressStyle::with_template(
+                    "{msg} {wide_bar} {binary_bytes}/{binary_total_bytes} \
+                     {binary_bytes_per_sec} eta {eta}",
+                )
+                .unwrap(),
+            )
+            .with_message(format!("Discord {version}")),
+    );
+    spinner.set_message(format!("Extracting Discord {version}"));
+    let started = Instant::now();
+    let hashes = Hashes {
+        // Only other sources' builds are signed
+        sha256: options
+            .source
+            .as_ref()
+            .map(|_| ring::digest::Context::new(&ring::digest::SHA256)),
+        md5: resp.md5.map(|_| integrity::Md5::new()),
+    };
+    let files = multi_prog.file_progress();
+    let (chunks, extraction) =
+        spawn_tar_xf(multi_prog, staged, options.excludes.clone(), hashes, files);
+    let validator = resp.validator.filter(|_| options.range_requests);
+    let download = async {
+        let mut downloaded = 0;
+        let mut resumes = 0;
+        loop {
+            let broke_off = match download_stream.try_next().await {
+                Ok(Some(chunk)) => Ok(chunk),
+                Ok(None) if download_size.is_none_or(|size| downloaded >= size) => break,
+                Ok(None) => Err(format!("the download of {download_url} was cut short").into()),
+                Err(e) => Err(e),
+            };
+            let chunk = match (broke_off, &validator) {
+                (Ok(chunk), _) => chunk,
+                // Picking up where it stopped, which a caching proxy can serve from its copy
+                (Err(e), Some(validator)) if resumes < MAX_RESUMES => {
+                    resumes += 1;
+                    multi_prog.println(format!(
+                        "The download broke off after {}, resuming it: {e}",
+                        HumanBytes(downloaded)
+                    ))?;
+                    let resp = fetch::get_from(&download_url, downloaded, validator).await?;
+                    if !resp.partial {
+                        return Err(format!(
+                            "couldn't resume {download_url}, it changed or the server doesn't \
+                             do ranges"
+                        )
+                        .into());
+                    }
+                    download_stream = resp.body;
+                    continue;
+                }
+                (Err(e), _) => return Err(e),
+            };
+            downloaded += chunk.len() as u64;
+            pb.inc(chunk.len() as u64);
+            // Most of the time goes on the download, the rest of the notification's bar is for
+            // putting it in place
+            let done = download_size.map_or(0, |size| downloaded.min(size) * 80 / size.max(1));
+            multi_prog.step(channel, "Downloading", done);
+            // In case the server didn't say how big it is up front
+            check_download_size(options, version, downloaded)?;
+            // The extractor only stops early on a bad archive, which it reports below
+            if chunks.send(chunk).await.is_err() {
+                break;
+            }
+        }
+        Result::Ok(downloaded)
+    }
+    .await;
+    drop(chunks);
+    let extracted = extraction.await?;
+    pb.finish_and_clear();
+    let downloaded = download?;
+    let (index, hashes) =
+        extracted.map_err(|e| format!("bad Discord {version} archive from {download_url}: {e}"))?;
+    if download_size.is_some_and(|size| size != downloaded) {
+        return Err(format!("download of {download_url} was cut short").into());
+    }
+    if let (Some(expected), Some(md5)) = (resp.md5, hashes.md5) {
+        let found = md5.finish();
+        if found != expected {
+            return Err(format!(
+                "the download of {download_url} was damaged on the way, its MD5 is {} but the \
+                 server says {}",
+                integrity::hex(&found),
+                integrity::hex(&expected)
+            )
+            .into());
+        }
+    }
+    if let (Some(source), Some(digest)) = (&options.source, hashes.sha256) {
+        let signature_url = format!("{download_url}.sig");
+        let signature = fetch::get(&signature_url).await?.text().await?;
+        source
+            .verify(digest.finish().as_ref(), &signature)
+            .map_err(|e| format!("refusing {download_url}: {e}"))?;
+        multi_prog.println(format!("Verified the signature from {signature_url}"))?;
+    }
+
+    // The bar is gone now, so leave a summary of the download behind
+    let elapsed = started.elapsed();
+    let speed = (downloaded as f64 / elapsed.as_secs_f64().max(0.001)) as u64;
+    multi_prog.println(format!(
+        "Downloaded Discord {version}: {} in {} ({}/s)",
+        HumanBytes(downloaded),
+        HumanDuration(elapsed),
+        HumanBytes(speed)
+    ))?;
+
+    Ok(index)
+}
+
+/// Download the latest version of discord and extract at given path
+///
+/// The archive is extracted while it downloads, into the target's staging directory. Only once
+/// the download has been verified is the staged copy committed, so a failed update leaves the
+/// current install untouched.
+async fn update_discord(
+    multi_prog: &Progress,
+    spinner: &ProgressBar,
+    target: Box<dyn InstallTarget>,
+    channel: Channel,
+    version: Version,
+    options: &Options,
+) -> Result<Option<install::Previous>> {
+    let manifest = stage_discord(multi_prog, spinner, &*target, channel, version, options).await?;
+    commit_discord(multi_prog, spinner, target, manifest, options).await
+}
+
+/// The first half of `update_discord`, getting the version into the target's staging directory
+async fn stage_discord(
+    multi_prog: &Progress,
+    spinner: &ProgressBar,
+    target: &dyn InstallTarget,
+    channel: Channel,
+    version: Version,
+    options: &Options,
+) -> Result<Manifest> {
+    let staged = target.staged().to_owned();
+    let stored = match &options.store {
+        Some(store) => {
+            let (store, staged, version) = (store.clone(), staged.clone(), version.clone());
+            let excluded = options.excludes.patterns.clone();
+            tokio::task::spawn_blocking(move || {
+                store.restore(channel, &version, &excluded, &staged)
+            })
+            .await??
+        }
+        None => None,
+    };
+    let index = if stored.is_some() {
+        multi_prog.println(format!(
+            "Putting Discord {version} together from the local store, nothing to download"
+        ))?;
+        None
+    } else {
+        Some(download(multi_prog, spinner, &staged, channel, &version, options).await?)
+    };
+
+    if let Some(gid) = options.shared_group {
+        let staged = staged.clone();
+        tokio::task::spawn_blocking(move || install::share_with_group(&staged, gid)).await??;
+    }
+
+    // Make sure the new files actually hit the disk before they replace the old ones
+    spinner.set_message("Syncing Discord to disk");
+    multi_prog.step(channel, "Syncing to disk", 85);
+    let sync_path = staged.clone();
+    tokio::task::spawn_blocking(move || fsync_tree(&sync_path)).await??;
+
+    // The store already checked its copy against the manifest it kept
+    let manifest = match stored {
+        Some(mut manifest) => {
+            if options.shared_group.is_some() {
+                for entry in &mut manifest.entries {
+                    if !matches!(entry.kind, manifest::EntryKind::Symlink { .. }) {
+                        let is_dir = entry.kind == manifest::EntryKind::Directory;
+                        entry.mode = install::group_mode(entry.mode, is_dir);
+                    }
+                }
+            }
+            manifest
+        }
+        None => {
+            spinner.set_message("Writing the install manifest");
+            let (manifest_path, generated) = (staged.clone(), version.clone());
+            let manifest =
+                tokio::task::spawn_blocking(move || Manifest::generate(&manifest_path, generated))
+                    .await??;
+            // Short writes and the like that the filesystem didn't report
+            if let Some(index) = &index {
+                manifest
+                    .check_extracted(index)
+                    .map_err(|e| format!("extracting Discord {version} went wrong, {e}"))?;
+            }
+            Manifest {
+                excluded: options.excludes.patterns.clone(),
+                ..manifest
+            }
+        }
+    };
+    if options.show_diff {
+        print_diff(multi_prog, target.path(), &manifest).await?;
+    }
+    Ok(manifest)
+}
+
+/// The second half of `update_discord`, swapping the staged copy in
+async fn commit_discord(
+    multi_prog: &Progress,
+    spinner: &ProgressBar,
+    target: Box<dyn InstallTarget>,
+    manifest: Manifest,
+    options: &Options,
+) -> Result<Option<install::Previous>> {
+    spinner.set_message(format!("Installing Discord to {}", target.path().display()));
+    multi_prog.step(options.channel, "Installing", 90);
+    let (install_path, group) = (target.path().to_owned(), options.shared_group);
+    // The helper sees to root's files itself
+    let relabel = !options.system;
+    let (previous, relabeled) = tokio::task::spawn_blocking(move || {
+        let previous = target.commit(&manifest)?;
+        // The manifest too, the next member to update has to replace it
+        if let Some(gid) = group {
+            install::share_with_group(&manifest::metadata_dir(&install_path), gid)?;
+        }
+        let relabeled = if relabel {
+            selinux::restore(&install_path)
+        } else {
+            Ok(())
+        };
+        std::io::Result::Ok((previous, relabeled))
+    })
+    .await??;
+    if let Err(e) = relabeled {
+        multi_prog.println(format!("Couldn't restore the SELinux contexts: {e}"))?;
+    }
+    multi_prog.finish_spinner(spinner, "Discord extracted")?;
+    Ok(previous)
+}
+
+/// List what installing `new` changes about the install at `install_path`
+async fn print_diff(prog: &Progress, install_path: &Path, new: &Manifest) -> Result<()> {
+    let old = match Manifest::load(install_path)? {
+        Some(old) => old,
+        // Installed by something else, so look at what's there
+        None if install_path.exists() => {
+            let path = install_path.to_owned();
+            let version = Version::new(0, 0, 0);
+            tokio::task::spawn_blocking(move || Manifest::generate(&path, version)).await??
+        }
+        None => Manifest {
+            version: Version::new(0, 0, 0),
+            entries: vec![],
+            excluded: vec![],
+        },
+    };
+    let changes = old.diff(new);
+    prog.suspend(|| {
+        for (change, path) in &changes {
+            eprintln!("{change} {}", path.display());
+        }
+    });
+    let count = |c| changes.iter().filter(|(change, _)| *change == c).count();
+    prog.println(format!(
+        "Discord {} -> {}: {} added, {} removed, {} changed",
+        old.version,
+        new.version,
+        count('+'),
+        count('-'),
+        count('~')
+    ))?;
+    Ok(())
+}
+
+/// The path to the user's home directory
+fn home_dir() -> Result<PathBuf> {
+    Ok(PathBuf::from(env::var("HOME")?))
+}
+
+/// Where Discord is installed, and which versions are involved
+#[derive(Clone, Serialize)]
+struct UpdateCheck {
+    channel: Channel,
+    install_path: PathBuf,
+    /// `None` when nothing is installed at `install_path` yet
+    installed_version: Option<Version>,
+    latest_version: Version,
+    /// What changed in `latest_version`, when we know
+    release_notes: Option<String>,
+    /// The launcher symlink that should point at the install
+    symlink: PathBuf,
+    /// The install is root-owned, so changes go through the system helper
+    system: bool,
+    /// The install is missing files, `installed_version` is then what our manifest recorded
+    damaged: bool,
+}
+
+impl UpdateCheck {
+    /// The Discord executable inside the install
+    fn binary_path(&self) -> PathBuf {
+        platform::current().binary_path(self.channel, &self.install_path)
+    }
+
+    fn update_available(&self) -> bool {
+        self.installed_version
+            .as_ref()
+            .is_none_or(|installed| self.latest_version > *installed)
+    }
+}
+
+/// Locate Discord in the system and get the path or use the default path
+///
+/// If there's more than one install the user picks, or when nobody can be asked we go with the
+/// one on the PATH.
+async fn find_install(prog: &Progress, options: &Options) -> Result<PathBuf> {
+    let (channel, platform) = (options.channel, platform::current());
+    let default_install_path = platform.default_install_path(channel, options.system)?;
+    let install_path = match &options.install_path {
+        Some(install_path) => install_path.clone(),
+        None if options.system => default_install_path,
+        None => match integrate::shared_install(channel)? {
+            // Not looked for through the launcher, which `flags` may have made a script since
+            Some(shared) => shared,
+            None => {
+                let candidates = platform.candidate_installs(channel).await;
+                match candidates.as_slice() {
+                    [] => {
+                        prog.println("Failed to locate Discord. Will use the default path")?;
+                        default_install_path
+                    }
+                    [only] => only.clone(),
+                    [first, ..] if options.non_interactive => {
+                        prog.println(format!(
+                            "Found {} installs of Discord {channel}, using {}. Pick another with \
+                             --install-path, or use --all",
+                            candidates.len(),
+                            first.display()
+                        ))?;
+                        first.clone()
+                    }
+                    _ => {
+                        let names: Vec<_> =
+                            candidates.iter().map(|c| c.display().to_string()).collect();
+                        let question = format!("Which install of Discord {channel}?");
+                        candidates[choose(prog, &question, &names)?].clone()
+                    }
+                }
+            }
+        },
+    };
+    prog.println(format!(
+        "Found discord install at {}",
+        install_path.display()
+    ))?;
+    Ok(install_path)
+}
+
+/// Fail early when Discord's builds wouldn't run here and there's nowhere else to get one
+fn check_platform(options: &Options) -> Result<()> {
+    let (arch, official) = (env::consts::ARCH, platform::current().official_archs());
+    if official.contains(&arch) || options.source.is_some() {
+        return Ok(());
+    }
+    Err(format!(
+        "official Discord Linux tarballs are {} only; detected {arch}. To use \
+         community builds set source_url and source_key for {arch:?} under \"architectures\" \
+         in {}",
+        official.join(", "),
+        config::path()?.display()
+    )
+    .into())
+}
+
+/// Locate the installed Discord and compare its version against the latest one
+async fn check_for_update(prog: &Progress, options: &Options) -> Result<UpdateCheck> {
+    check_platform(options)?;
+    let channel = options.channel;
+    let install_path = find_install(prog, options).await?;
+
+    let latest = match get_latest_discord_version(options, channel).await {
+        Ok(latest) => {
+            backoff::succeeded(channel)?;
+            latest
+        }
+        Err(e) => {
+            let wait = backoff::failed(channel, &e)?;
+            if options.non_interactive {
+                prog.println(format!(
+                    "Scheduled checks will wait {} before trying again",
+                    HumanDuration(wait)
+                ))?;
+            }
+            return Err(e);
+        }
+    };
+    let mut latest_version = latest.version;
+    if let Some(max) = pin::max_version(channel)?.filter(|max| latest_version > *max) {
+        prog.println(format!(
+            "Discord {latest_version} is out, but {channel} is pinned to {max} or older"
+        ))?;
+        latest_version = max;
+    }
+    let mut damaged = false;
+    let assumed = &options.assume_installed_version;
+    let installed_version = if !tokio::fs::try_exists(&install_path).await? {
+        None
+    } else {
+        let mut missing = missing_files(channel, &install_path).await?;
+        // Repacked builds may not have it, which is what the version is given for
+        if assumed.is_some() {
+            missing.retain(|&name| name != BUILD_INFO);
+        }
+        if !missing.is_empty() {
+            prog.println(format!(
+                "The install is damaged, it's missing {}",
+                missing.join(", ")
+            ))?;
+            damaged = true;
+            assumed
+                .clone()
+                .or(Manifest::load(&install_path)?.map(|m| m.version))
+        } else if let Some(version) = assumed {
+            Some(version.clone())
+        } else {
+            Some(platform::current().installed_version(&install_path).await?)
+        }
+    };
+    let current_version = installed_version.clone().unwrap_or(Version::new(0, 0, 0));
+    prog.println(format!("Latest version: {latest_version}"))?;
+    prog.println(format!("Current version: {current_version}"))?;
+
+    let mut release_notes = latest.notes.filter(|notes| !notes.trim().is_empty());
+    if let Some(notes_url) = &options.notes_url {
+        if latest_version > current_version {
+            match fetch_release_notes(notes_url, &latest_version).await {
+                Ok(notes) => release_notes = Some(notes),
+                Err(e) => prog.println(format!("Failed to fetch release notes: {e}"))?,
+            }
+        }
+    }
+
+    Ok(UpdateCheck {
+        channel,
+        install_path,
+        installed_version,
+        latest_version,
+        release_notes,
+        symlink: symlink::link_path(options)?,
+        system: options.system,
+        damaged,
+    })
+}
+
+/// Reinstall a damaged install at the version it was, if `--auto-repair`
+///
+/// There's no copy of the old tarball kept, so it's downloaded again. Without a manifest we
+/// don't know what version it was, so it gets the latest.
+async fn repair(
+    prog: &Progress,
+    spinner: &ProgressBar,
+    options: &Options,
+    check: &mut UpdateCheck,
+) -> Result<Option<plan::Plan>> {
+    if !options.auto_repair {
+        prog.println("Use --auto-repair to reinstall it")?;
+        return Ok(None);
+    }
+    let version = check
+        .installed_version
+        .clone()
+        .unwrap_or_else(|| check.latest_version.clone());
+    if options.dry_run {
+        return dry_run(prog, spinner, options, check, &version).map(Some);
+    }
+    prog.println(format!("Reinstalling Discord {version}"))?;
+    install_version(prog, spinner, options, check, &version).await?;
+    check.installed_version = Some(version);
+    check.damaged = false;
+    Ok(None)
+}
+
+/// Work out how `version` would be installed and say so, for `--dry-run`
+fn dry_run(
+    prog: &Progress,
+    spinner: &ProgressBar,
+    options: &Options,
+    check: &UpdateCheck,
+    version: &Version,
+) -> Result<plan::Plan> {
+    spinner.finish_and_clear();
+    let plan = plan::install(options, check, version)?;
+    if options.output == OutputFormat::Text {
+        plan::print(prog, check, &plan)?;
+    }
+    Ok(plan)
+}
+
+/// Deal with an update that was interrupted, unless this is a `--dry-run`
+async fn recover(prog: &Progress, options: &Options) -> Result<transaction::Recovered> {
+    if options.dry_run {
+        return Ok(transaction::Recovered::Nothing);
+    }
+    transaction::recover(prog, options).await
+}
+
+/// Finish what `recover` found, returning whether it installed something
+async fn integrate_recovered(
+    prog: &Progress,
+    spinner: &ProgressBar,
+    options: &Options,
+    check: &mut UpdateCheck,
+    recovered: transaction::Recovered,
+) -> Result<bool> {
+    match recovered {
+        transaction::Recovered::Nothing => Ok(false),
+        transaction::Recovered::Installed {
+            version,
+            integrated,
+        } => {
+            let mut plan = plan::integration(options, check, &version)?;
+            plan.steps.retain(|step| {
+                !integrated
+                    .iter()
+                    .any(|done| step.integration() == Some(done.as_str()))
+            });
+            apply_plan(prog, spinner, options, check, &plan).await?;
+            State::update(|state| state.channel(check.channel).last_update = Some(state::now()))?;
+            prog.println(format!("Installed Discord {} {version}", check.channel))?;
+            Ok(true)
+        }
+        transaction::Recovered::Broken => {
+            check.damaged = true;
+            Ok(false)
+        }
+    }
+}
+
+/// Lock the channel, waiting for whoever has it, and finish what an interrupted run left, for
+/// the gui and the others that then install what the check found themselves
+pub(crate) async fn lock_and_check(
+    prog: &Progress,
+    options: &Options,
+) -> Result<(run_lock::RunLock, UpdateCheck)> {
+    let lock = run_lock::acquire(prog, options.channel, true).await?;
+    let spinner = prog.spinner();
+    let recovered = recover(prog, options).await?;
+    let mut check = check_for_update(prog, options).await?;
+    integrate_recovered(prog, &spinner, options, &mut check, recovered).await?;
+    spinner.finish_and_clear();
+    Ok((lock, check))
+}
+
+/// `rollback --snapshot`, holding the channel's lock
+pub(crate) async fn rollback_snapshot(
+    prog: &Progress,
+    options: &Options,
+    wait: bool,
+) -> Result<()> {
+    let _lock = run_lock::acquire(prog, options.channel, wait).await?;
+    // The snapshot takes the place of whatever an interrupted run got in
+    if let transaction::Recovered::Installed { .. } = recover(prog, options).await? {
+        transaction::end(options.channel)?;
+    }
+    snapshot::rollback(prog, options.channel).await
+}
+
+/// Print the release notes for an update
+fn print_release_notes(prog: &Progress, check: &UpdateCheck) -> Result<()> {
+    if let Some(notes) = &check.release_notes {
+        prog.println(format!("What's new in {}:", check.latest_version))?;
+        for line in notes.trim().lines() {
+            prog.println(format!("  {line}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// Install the latest version found by `check_for_update`
+async fn apply_update(
+    prog: &Progress,
+    spinner: &ProgressBar,
+    options: &Options,
+    check: &UpdateCheck,
+) -> Result<()> {
+    install_version(prog, spinner, options, check, &check.latest_version).await
+}
+
+/// Install a specific version in place of whatever `check_for_update` found
+async fn install_version(
+    prog: &Progress,
+    spinner: &ProgressBar,
+    options: &Options,
+    check: &UpdateCheck,
+    version: &Version,
+) -> Result<()> {
+    let plan = plan::install(options, check, version)?;
+    apply_plan(prog, spinner, options, check, &plan).await
+}
+
+/// Take the steps of `plan`, in order
+async fn apply_plan(
+    prog: &Progress,
+    spinner: &ProgressBar,
+    options: &Options,
+    check: &UpdateCheck,
+    plan: &plan::Plan,
+) -> Result<()> {
+    // Everything that installs goes through here, so this catches a caller that forgot the lock
+    if !run_lock::held(check.channel) {
+        return Err(format!(
+            "updating Discord {} without holding its lock",
+            check.channel
+        )
+        .into());
+    }
+    let result = take_steps(prog, spinner, options, check, plan).await;
+    // Failing cleans up after itself, but for a staged copy kept for the next run
+    let kept =
+        result.is_err() && transaction::phase(check.channel)? == Some(transaction::Phase::Staged);
+    if !kept {
+        transaction::end(check.channel)?;
+    }
+    result
+}
+
+async fn take_steps(
+    prog: &Progress,
+    spinner: &ProgressBar,
+    options: &Options,
+    check: &UpdateCheck,
+    plan: &plan::Plan,
+) -> Result<()> {
+    let version = &plan.version;
+    let _inhibitor = inhibit_sleep("Updating Discord");
+    notify::begin(&options.notifiers, check.channel, version).await;
+    let mut was_running = false;
+    let mut block = None;
+    let mut staged = None;
+    let mut previous = None;
+    for step in &plan.steps {
+        match step {
+            // Checked first, the health check may leave the new one running
+            plan::Step::CloseDiscord => {
+                was_running = close::before_update(
+                    prog,
+                    check.channel,
+                    &check.install_path,
+                    !options.non_interactive,
+                    options.close_running,
+                )
+                .await?;
+            }
+            plan::Step::BlockLaunches => block = Some(launch_block::block(check)?),
+            plan::Step::Snapshot { path, version } => {
+                snapshot::before_update(prog, check.channel, path, version).await?;
+            }
+            plan::Step::DownloadUnprivileged { path, .. } => {
+                spinner.set_message(format!(
+                    "Downloading Discord {version} as an unprivileged user"
+                ));
+                system::install_unprivileged(options, version, path).await?;
+                drop(block.take());
+            }
+            plan::Step::Download { commit, .. } => {
+                let path = &check.install_path;
+                let temp_dir = options.temp_dir.as_deref();
+                let target: Box<dyn InstallTarget> = match commit {
+                    plan::Commit::SystemHelper => {
+                        Box::new(system::SystemTarget::new(check.channel, path, temp_dir)?)
+                    }
+                    plan::Commit::InPlace => Box::new(install::InPlaceTarget::new(path, temp_dir)?),
+                    plan::Commit::Swap => Box::new(install::DirectoryTarget::new(path)?),
+                };
+                let target = install::wrapped(target);
+                let recorded = *commit != plan::Commit::SystemHelper;
+                let reused = if recorded {
+                    let (channel, version, commit) = (check.channel, version.clone(), *commit);
+                    let (path, into) = (path.clone(), target.staged().to_owned());
+                    blocking(move || {
+                        transaction::take_staged(channel, &version, &path, commit, &into)
+                    })
+                    .await?
+                } else {
+                    None
+                };
+                if recorded {
+                    transaction::begin(check.channel, version, path, target.staged(), *commit)?;
+                }
+                let manifest = match reused {
+                    Some(manifest) => {
+                        prog.println(format!(
+                            "Using the copy of Discord {version} an earlier run downloaded"
+                        ))?;
+                        if options.show_diff {
+                            print_diff(prog, path, &manifest).await?;
+                        }
+                        manifest
+                    }
+                    None => {
+                        stage_discord(
+                            prog,
+                            spinner,
+                            &*target,
+                            check.channel,
+                            version.clone(),
+                            options,
+                        )
+                        .await?
+                    }
+                };
+                if recorded {
+                    // Kept with the staged copy, to check it against before resuming
+                    manifest.save(target.staged())?;
+                    transaction::advance(check.channel, transaction::Phase::Staged)?;
+                }
+                staged = Some((target, manifest));
+            }
+            // With --when-closed Discord can still be launched until the update is ready
+            plan::Step::WaitForExit { .. } => {
+                let timeout = options.when_closed.flatten();
+                prog.step(check.channel, "Waiting for Discord to be closed", 80);
+                let waited =
+                    close::wait_for_exit(prog, spinner, check.channel, version, timeout).await;
+                if let Err(e) = waited {
+                    // Left for the next run, which can install it without downloading it again
+                    if let Some((target, _)) = staged.take() {
+                        target.keep();
+                    }
+                    return Err(e);
+                }
+            }
+            plan::Step::Install { .. } => {
+                let (target, manifest) = staged.take().ok_or("nothing was staged to install")?;
+                transaction::advance(check.channel, transaction::Phase::Swapping)?;
+                previous = commit_discord(prog, spinner, target, manifest, options).await?;
+                transaction::advance(check.channel, transaction::Phase::Swapped)?;
+                drop(block.take());
+            }
+            plan::Step::HealthCheck { .. } => {
+                let Some(previous) = previous.take() else {
+                    continue;
+                };
+                let watch = options.watch_first_launch;
+                prog.step(check.channel, "Checking it starts", 95);
+                if let Err(e) = health::check(check.channel, &check.install_path, watch).await {
+                    prog.println(format!("Discord {version} doesn't work, {e}"))?;
+                    tokio::task::spawn_blocking(move || previous.restore()).await??;
+                    // A damaged install can be missing its version
+                    return Err(match &check.installed_version {
+                        Some(old) => format!("rolled back to Discord {old}").into(),
+                        None => "rolled back to the previous install".into(),
+                    });
+                }
+                // A whole install to delete
+                tokio::task::spawn_blocking(move || previous.discard()).await??;
+            }
+            plan::Step::KeepInStore { .. } => {
+                if let Some(store) = &options.store {
+                    if let Err(e) = add_to_store(store, check).await {
+                        prog.println(format!("Couldn't keep Discord {version} in the store: {e}"))?;
+                    }
+                }
+            }
+            plan::Step::Desktop {
+                entry,
+                icon,
+                url_handler,
+            } => {
+                let parts = desktop::Parts {
+                    entry: *entry,
+                    icon: *icon,
+                    url_handler: *url_handler,
+                };
+                desktop::install(check, version, parts)?;
+            }
+            plan::Step::Autostart => autostart::refresh(check.channel, &check.install_path)?,
+            plan::Step::Launcher { .. } => launcher::refresh(check)?,
+            plan::Step::Symlink { .. } => {
+                symlink::sync(prog, check, true, false, false).await?;
+            }
+            plan::Step::AppArmor { .. } => apparmor::ensure(prog, check)?,
+        }
+        if let Some(step) = step.integration() {
+            transaction::integrated(check.channel, step)?;
+        }
+    }
+    if was_running {
+        prog.println(format!(
+            "Discord {} was running during the update, restart it to use {version}",
+            check.channel
+        ))?;
+    }
+    Ok(())
+}
+
+/// Keep the files of the install `check` is about in the store
+async fn add_to_store(store: &store::Store, check: &UpdateCheck) -> Result<()> {
+    let (store, channel, install_path) = (store.clone(), check.channel, check.install_path.clone());
+    let manifest = Manifest::load(&install_path)?.ok_or("the install has no manifest")?;
+    tokio::task::spawn_blocking(move || store.add(channel, &install_path, &manifest)).await??;
+    Ok(())
+}
+
+/// Deal with what older versions left in Discord's config directory after updating to `version`
+async fn tidy_config_dir(
+    prog: &Progress,
+    options: &Options,
+    channel: Channel,
+    version: &Version,
+) -> Result<()> {
+    let (prog, version) = (prog.clone(), version.clone());
+    let (build_dirs, purge_modules) = (options.build_dirs, options.purge_modules);
+    blocking(move || {
+        maintenance::tidy_build_dirs(&prog, channel, &version, build_dirs)?;
+        maintenance::check_modules(&prog, channel, &version, purge_modules)
+    })
+    .await
+}
+
+/// Run filesystem work that can take a while on a slow disk off the runtime, so downloads,
+/// progress, and timeouts carry on meanwhile
+async fn blocking<T: Send + 'static>(
+    work: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    // Our errors aren't Send, what they say is all that's kept
+    let result = tokio::task::spawn_blocking(move || work().map_err(|e| e.to_string())).await?;
+    Ok(result?)
+}
+
+/// The outcome of a run, for `--output json`
+#[derive(Serialize)]
+struct RunReport {
+    /// `schema::VERSION`
+    schema_version: u32,
+    #[serde(flatten)]
+    check: UpdateCheck,
+    update_available: bool,
+    updated: bool,
+    /// What would have been done, with `--dry-run`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    plan: Option<plan::Plan>,
+}
+
+/// Check for an update and install it if there is one
+async fn run_update(prog: &Progress, options: &Options) -> Result<RunReport> {
+    let spinner = prog.spinner();
+
+    if let Some(skip) = &options.skip_version {
+        let skipped = match skip {
+            SkipTarget::Nothing => None,
+            SkipTarget::Version(version) => Some(version.clone()),
+        };
+        State::update(|state| state.channel(options.channel).skipped_version = skipped)?;
+    }
+    let recovered = recover(prog, options).await?;
+    let mut check = check_for_update(prog, options).await?;
+    let mut updated = integrate_recovered(prog, &spinner, options, &mut check, recovered).await?;
+    let mut skipped = None;
+    State::update(|state| {
+        let channel = state.channel(check.channel);
+        channel.last_check = Some(state::now());
+        skipped = channel.skipped_version.clone();
+    })?;
+    // An update replaces the damaged install anyway
+    let mut plan = None;
+    if check.damaged && !check.update_available() {
+        plan = repair(prog, &spinner, options, &mut check).await?;
+    }
+
+    // Check if the latest version is greater than the current version and update if necessary
+    let skipping = skipped.as_ref() == Some(&check.latest_version);
+    let update_available = check.update_available() && !skipping;
+    let deferred = defer::deferred_until(check.channel)?;
+    let held = match &options.version_feed {
+        Some(url) if update_available => {
+            let (channel, latest) = (check.channel, &check.latest_version);
+            feed::hold_reason(prog, url, options.version_feed_strict, channel, latest).await?
+        }
+        _ => None,
+    };
+    if skipping && check.update_available() {
+        spinner.finish_and_clear();
+        prog.println(format!(
+            "Skipping Discord {}, use --skip-version none to install it",
+            check.latest_version
+        ))?;
+    } else if let Some(until) = deferred.filter(|_| update_available && options.non_interactive) {
+        spinner.finish_and_clear();
+        prog.println(format!(
+            "Not installing Discord {}, updates are deferred until {}",
+            check.latest_version,
+            defer::format_time(until)
+        ))?;
+    } else if update_available && integrate::is_shared(options, &check.install_path)? {
+        spinner.finish_and_clear();
+        prog.println(format!(
+            "Discord {} is available, {} is shared and updated by discord_update --system",
+            check.latest_version,
+            check.install_path.display()
+        ))?;
+    } else if let Some(reason) = held.as_ref().filter(|_| options.non_interactive) {
+        spinner.finish_and_clear();
+        prog.println(format!(
+            "Holding back Discord {}, {reason}",
+            check.latest_version
+        ))?;
+    } else if update_available {
+        if let Some(reason) = &held {
+            prog.println(format!("Warning: {reason}"))?;
+        }
+        if let Some(until) = deferred {
+            prog.println(format!(
+                "Updates are deferred until {}",
+                defer::format_time(until)
+            ))?;
+        }
+        prog.println("Update available")?;
+        print_release_notes(prog, &check)?;
+        if options.notify_only {
+            spinner.finish_and_clear();
+            prog.println(format!(
+                "Discord {} is available, not installing it because of --notify-only",
+                check.latest_version
+            ))?;
+        } else if options.dry_run {
+            plan = Some(dry_run(
+                prog,
+                &spinner,
+                options,
+                &check,
+                &check.latest_version,
+            )?);
+        } else if let Some(options) = writable::remedy(prog, options, &check)? {
+            spinner.finish_and_clear();
+            return Box::pin(run_update(prog, &options)).await;
+        } else if options.assume_yes || confirm_update(prog, options, &check).await? {
+            apply_update(prog, &spinner, options, &check).await?;
+            updated = true;
+            State::update(|state| state.channel(check.channel).last_update = Some(state::now()))?;
+            if check.installed_version.is_none() && options.symlink {
+                let bin_dir = check.symlink.parent().unwrap();
+                profile::check_on_path(prog, bin_dir, !options.non_interactive).await?;
+            }
+            if options.clear_cache {
+                let (prog, channel) = (prog.clone(), check.channel);
+                blocking(move || maintenance::clear_cache(&prog, channel)).await?;
+            }
+            tidy_config_dir(prog, options, check.channel, &check.latest_version).await?;
+        } else {
+            spinner.finish_and_clear();
+            prog.println("Update skipped")?;
+        }
+    } else {
+        prog.println("No update available")?;
+    }
+    // An update says so itself when Discord was running through it
+    if !updated && !options.dry_run {
+        running::warn_if_stale(prog, &check)?;
+    }
+    if options.symlink && !options.dry_run {
+        let ask = !options.non_interactive;
+        symlink::sync(
+            prog,
+            &check,
+            options.claim_symlink,
+            options.force_symlink,
+            ask,
+        )
+        .await?;
+    }
+    if options.apparmor_profile && !options.dry_run {
+        apparmor::ensure(prog, &check)?;
+    }
+
+    Ok(RunReport {
+        schema_version: schema::VERSION,
+        check,
+        update_available,
+        updated,
+        plan,
+    })
+}
+
+/// Make sure the wanted version is installed, without asking any questions
+async fn ensure_version(options: &Options, target: &EnsureTarget) -> Result<RunReport> {
+    let prog = Progress::new(options.non_interactive);
+    let spinner = prog.spinner();
+
+    let recovered = recover(&prog, options).await?;
+    let mut check = check_for_update(&prog, options).await?;
+    let recovered = integrate_recovered(&prog, &spinner, options, &mut check, recovered).await?;
+    State::update(|state| state.channel(check.channel).last_check = Some(state::now()))?;
+
+    let wanted = match target {
+        EnsureTarget::Latest => &check.latest_version,
+        EnsureTarget::Version(version) => version,
+    };
+    let changed = check.installed_version.as_ref() != Some(wanted) || check.damaged;
+    let mut plan = None;
+    if changed && options.dry_run {
+        plan = Some(dry_run(&prog, &spinner, options, &check, wanted)?);
+    } else if changed {
+        writable::check(options, &check)?;
+        install_version(&prog, &spinner, options, &check, wanted).await?;
+        State::update(|state| state.channel(check.channel).last_update = Some(state::now()))?;
+        if check.installed_version.is_none() && options.symlink {
+            profile::check_on_path(&prog, check.symlink.parent().unwrap(), false).await?;
+        }
+        tidy_config_dir(&prog, options, check.channel, wanted).await?;
+    } else {
+        spinner.finish_and_clear();
+        running::warn_if_stale(&prog, &check)?;
+    }
+    if options.symlink && !options.dry_run {
+        symlink::sync(
+            &prog,
+            &check,
+            options.claim_symlink,
+            options.force_symlink,
+            false,
+        )
+        .await?;
+    }
+    if options.apparmor_profile && !options.dry_run {
+        apparmor::ensure(&prog, &check)?;
+    }
+
+    if options.output == OutputFormat::Text {
+        let channel = check.channel;
+        let outcome = if options.dry_run {
+            "would change"
+        } else {
+            "changed"
+        };
+        match &check.installed_version {
+            _ if !changed => prog.result(format!("compliant: discord {channel} {wanted}"))?,
+            Some(old) => prog.result(format!("{outcome}: discord {channel} {old} -> {wanted}"))?,
+            None => prog.result(format!("{outcome}: discord {channel} installed {wanted}"))?,
+        }
+    }
+    Ok(RunReport {
+        schema_version: schema::VERSION,
+        update_available: check.update_available(),
+        check,
+        updated: (changed && !options.dry_run) || recovered,
+        plan,
+    })
+}
+
+/// Run `fut`, failing if it takes longer than `--timeout`
+async fn with_timeout<T>(options: &Options, fut: impl Future<Output = Result<T>>) -> Result<T> {
+    // Waiting for Discord to be closed can rightly take all day, it has --when-closed-timeout
+    let default = (options.non_interactive && options.when_closed.is_none())
+        .then_some(NON_INTERACTIVE_TIMEOUT);
+    match options.timeout.or(default) {
+        Some(limit) => tokio::time::timeout(limit, fut)
+            .await
+            .map_err(|_| format!("gave up after {}", HumanDuration(limit)))?,
+        None => fut.await,
+    }
+}
+
+/// How long ago the channel was checked, if that's too recent to check again
+fn checked_recently(options: &Options) -> Result<Option<Duration>> {
+    let Some(interval) = options.check_interval else {
+        return Ok(None);
+    };
+    let state = State::load()?;
+    let last_check = state
+        .channels
+        .get(&options.channel)
+        .and_then(|c| c.last_check);
+    Ok(last_check
+        .map(|last| Duration::from_secs(state::now().saturating_sub(last)))
+        .filter(|ago| *ago < interval))
+}
+
+/// Run the default command, reporting the outcome as requested
+async fn update_command(options: &Options) -> Result<Outcome> {
+    // Taken first, so a run that waited sees what the other one did
+    let lock = run_lock::acquire(
+        &Progress::new(options.non_interactive),
+        options.channel,
+        options.wait,
+    )
+    .await?;
+    if let Some(ago) = checked_recently(options)? {
+        Progress::new(options.non_interactive).println(format!(
+            "Checked for {} updates {} ago, --check-interval says to wait",
+            options.channel,
+            HumanDuration(ago)
+        ))?;
+        return Ok(Outcome::Done);
+    }
+    // --ensure is asked for by something that wants an answer now, not on a schedule
+    if options.non_interactive && options.ensure.is_none() {
+        if let Some(until) = backoff::until(options.channel)? {
+            Progress::new(true).println(format!(
+                "Checking for {} updates has been failing, waiting until {} to try again",
+                options.channel,
+                defer::format_time(until)
+            ))?;
+            return Ok(Outcome::Done);
+        }
+        if let Some(jitter) = options.jitter {
+            backoff::jitter(jitter).await;
+        }
+    }
+    let result = match &options.ensure {
+        Some(target) => with_timeout(options, ensure_version(options, target)).await,
+        None => {
+            let prog = Progress::new(options.non_interactive);
+            with_timeout(options, run_update(&prog, options)).await
+        }
+    };
+
+    if let Some(metrics_file) = &options.metrics_file {
+        let state = State::load().unwrap_or_default();
+        metrics::write(metrics_file, options.channel, result.as_ref().ok(), &state)?;
+    }
+    let prog = Progress::new(options.non_interactive);
+    // Not held while a notification waits for a click, which can take an hour
+    drop(lock);
+    let action = notify::send(&prog, &options.notifiers, options.channel, &result).await?;
+    let result = match action {
+        Some(action) => notification_action(&prog, options, action, result).await,
+        None => result,
+    };
+
+    let report = result?;
+    if options.output == OutputFormat::Json {
+        prog.result(serde_json::to_string(&report)?)?;
+    }
+    if options.ensure.is_some() && report.updated {
+        return Ok(Outcome::Changed);
+    }
+    Ok(Outcome::Done)
+}
+
+/// Do what was clicked on the notification of an update, returning how the run went now
+async fn notification_action(
+    prog: &Progress,
+    options: &Options,
+    action: notify::Action,
+    result: Result<RunReport>,
+) -> Result<RunReport> {
+    let Ok(report) = &result else {
+        return result;
+    };
+    let (channel, latest) = (options.channel, &report.check.latest_version);
+    match action {
+        notify::Action::Install => {
+            prog.println(format!(
+                "Installing Discord {latest}, as asked from the notification"
+            ))?;
+            let options = Options {
+                notify_only: false,
+                ..options.clone()
+            };
+            // Another run may have come along while the notification waited
+            let _lock = run_lock::acquire(prog, channel, true).await?;
+            let result = with_timeout(&options, run_update(prog, &options)).await;
+            notify::send(prog, &options.notifiers, channel, &result).await?;
+            return result;
+        }
+        notify::Action::Skip => {
+            State::update(|state| state.channel(channel).skipped_version = Some(latest.clone()))?;
+            prog.println(format!(
+                "Skipping Discord {latest}, use --skip-version none to install it"
+            ))?;
+        }
+        notify::Action::Later => {
+            prog.println(format!("Leaving Discord {latest} for the next check"))?
+        }
+    }
+    result
+}
+
+/// Run the default command on each install of the channel in turn
+async fn update_all(options: &Options) -> Result<()> {
+    if options.ensure.is_some() || options.install_path.is_some() {
+        return Err("--all can't be combined with --ensure or --install-path".into());
+    }
+    let installs = platform::current()
+        .candidate_installs(options.channel)
+        .await;
+    if installs.is_empty() {
+        return Err(format!("no installs of Discord {} were found", options.channel).into());
+    }
+    // Checked once for all of them, the first run records a check which would skip the rest
+    if let Some(ago) = checked_recently(options)? {
+        Progress::new(options.non_interactive).println(format!(
+            "Checked for {} updates {} ago, --check-interval says to wait",
+            options.channel,
+            HumanDuration(ago)
+        ))?;
+        return Ok(());
+    }
+    for install_path in installs {
+        let options = Options {
+            install_path: Some(install_path),
+            check_interval: None,
+            ..options.clone()
+        };
+        update_command(&options).await?;
+    }
+    Ok(())
+}
+
+/// The channels worth updating with `--all-channels`: ones we've run for before, have settings
+/// for, or that are installed
+async fn managed_channels(configured: impl Fn(Channel) -> bool) -> Result<Vec<Channel>> {
+    let state = State::load()?;
+    let mut channels = vec![];
+    for channel in Channel::ALL {
+        if state.channels.contains_key(&channel)
+            || configured(channel)
+            || !platform::current()
+                .candidate_installs(channel)
+                .await
+                .is_empty()
+        {
+            channels.push(channel);
+        }
+    }
+    Ok(channels)
+}
+
+/// Check and update each channel concurrently, with their output in the one progress display
+async fn update_channels(all: Vec<Options>) -> Result<()> {
+    let Some(first) = all.first() else {
+        return Err("no installed channels were found".into());
+    };
+    if first.ensure.is_some() || first.metrics_file.is_some() || first.all {
+        return Err(
+            "--all-channels can't be combined with --ensure, --metrics-file, or --all".into(),
+        );
+    }
+    let prog = Progress::new(first.non_interactive);
+    let runs = all.iter().map(|options| {
+        let prog = prog.prefixed(format!("[{}]", options.channel));
+        async move {
+            let lock = run_lock::acquire(&prog, options.channel, options.wait)
+                .await
+                .inspect_err(|e| {
+                    let _ = prog.println(format!("Error: {e}"));
+                })?;
+            let result = match checked_recently(options)? {
+                Some(ago) => {
+                    prog.println(format!(
+                        "Checked {} ago, --check-interval says to wait",
+                        HumanDuration(ago)
+                    ))?;
+                    return Ok(None);
+                }
+                None => with_timeout(options, run_update(&prog, options)).await,
+            };
+            drop(lock);
+            let action = notify::send(&prog, &options.notifiers, options.channel, &result).await?;
+            let result = match action {
+                Some(action) => notification_action(&prog, options, action, result).await,
+                None => result,
+            };
+            result.map(Some).inspect_err(|e| {
+                let _ = prog.println(format!("Error: {e}"));
+            })
+        }
+    });
+    let results: Vec<Result<_>> = futures::future::join_all(runs).await;
+    let failed = results.iter().filter(|r| r.is_err()).count();
+    if first.output == OutputFormat::Json {
+        for report in results.iter().flatten().flatten() {
+            prog.result(serde_json::to_string(report)?)?;
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{failed} of {} channels failed to update", all.len()).into());
+    }
+    Ok(())
+}
+
+/// Run the command line interface
+pub async fn run() -> Result<Outcome> {
+    interrupt::unwinding(run_with_args(env::args().skip(1))).await
+}
+
+/// Run the command line interface with `args`, the ones after the program name
+pub async fn run_with_args(args: impl IntoIterator<Item = String>) -> Result<Outcome> {
+    let mut matches = cli::parse(args)?;
+    let vars =
+        env::vars_os().filter_map(|(k, v)| Some((k.into_string().ok()?, v.into_string().ok()?)));
+    matches.apply_env(vars)?;
+    if matches.flag("schema") {
+        println!("{}", serde_json::to_string_pretty(&schema::describe())?);
+        return Ok(Outcome::Done);
+    }
+    let (mut config, config_path) = (config::load()?, config::path()?);
+    let mut schedule = None;
+    if setup::wanted(&matches, &config_path).await {
+        schedule = setup::run(&Progress::new(false), &matches, &config_path)?;
+        config = config::load()?;
+    }
+    let mut profiles = config::take_profiles(&mut config, &config_path)?;
+    let profile = matches
+        .value("profile")
+        .or(config.get("profile").and_then(|p| p.as_str()))
+        .map(str::to_owned);
+    if let Some(profile) = profile {
+        let section = profiles.remove(&profile).ok_or_else(|| {
+            let known: Vec<_> = profiles.keys().map(String::as_str).collect();
+            format!(
+                "no profile {profile:?} in {}, it has: {}",
+                config_path.display(),
+                if known.is_empty() {
+                    "none".into()
+                } else {
+                    known.join(", ")
+                }
+            )
+        })?;
+        matches.apply_config(&section, &config_path)?;
+    }
+    let channels = config::take_channels(&mut config, &config_path)?;
+    // A bad channel is reported when the options are parsed
+    let channel = matches
+        .value("channel")
+        .or(config.get("channel").and_then(|c| c.as_str()))
+        .and_then(|c| c.parse().ok())
+        .unwrap_or_default();
+    let architectures = config::take_architectures(&mut config, &config_path)?;
+    // The rest of the config for one channel, under what the command line and profile say
+    let configure = |mut matches: cli::Matches, channel| -> Result<cli::Matches> {
+        if let Some(section) = channels.get(&channel) {
+            matches.apply_config(section, &config_path)?;
+        }
+        if let Some(section) = architectures.get(env::consts::ARCH) {
+            matches.apply_config(section, &config_path)?;
+        }
+        matches.apply_config(&config, &config_path)?;
+        Ok(matches)
+    };
+    let base = matches;
+    let matches = configure(base.clone(), channel)?;
+    let pins = matches
+        .value("pin-keys")
+        .map(pinning::parse_pins)
+        .transpose()?;
+    let fetcher = fetch::ReqwestFetcher::new(matches.value("proxy"), pins.unwrap_or_default())?;
+    let options = Options::from_matches(&matches)?;
+    if matches.flag("print-pins") {
+        let download = download_url(&options, options.channel, &Version::new(0, 0, 0));
+        for url in [api_url(&options, options.channel), download] {
+            let host = url.split('/').nth(2).unwrap_or_default().to_owned();
+            println!("{host} {}", fetcher.server_pin(&url).await?);
+        }
+        return Ok(Outcome::Done);
+    }
+    fetch::set_fetcher(Box::new(fetcher));
+    if matches.flag("print-latest-version") || matches.flag("print-url") {
+        let channel = options.channel;
+        let latest = get_latest_discord_version(&options, channel).await?.version;
+        if matches.flag("print-url") {
+            println!("{}", download_url(&options, channel, &latest));
+        } else {
+            println!("{latest}");
+        }
+        return Ok(Outcome::Done);
+    }
+    let prog = Progress::new(options.non_interactive);
+    if let Some(schedule) = schedule {
+        if let Err(e) = cron::enable(
+            &prog,
+            &options,
+            matches.value("profile"),
+            &schedule,
+            cron::Policy::Now,
+        )
+        .await
+        {
+            prog.println(format!("Couldn't set up automatic updates: {e}"))?;
+        }
+    }
+    let result = match matches.subcommand(0) {
+        #[cfg(feature = "gui")]
+        Some("gui") => gui::run(&options).await,
+        #[cfg(feature = "tui")]
+        Some("tui") if options.non_interactive => Err("the tui needs a terminal".into()),
+        #[cfg(feature = "tui")]
+        Some("tui") => tui::run(&options).await,
+        Some("config") => match matches.subcommand(1) {
+            Some("backup") => {
+                user_data::backup(&prog, options.channel, matches.positionals[0].as_ref())
+            }
+            Some("restore") => {
+                user_data::restore(&prog, options.channel, matches.positionals[0].as_ref())
+            }
+            _ => Err("expected `config backup` or `config restore`, see --help".into()),
+        },
+        Some("settings") => settings::run(
+            &prog,
+            options.channel,
+            matches.subcommand(1),
+            &matches.positionals,
+        ),
+        Some("maintenance") => {
+            let channel = options.channel;
+            blocking(move || maintenance::clear_cache(&prog, channel)).await?;
+            Ok(())
+        }
+        Some("enable-cron") => {
+            let schedule = matches.value("schedule").unwrap_or(cron::DEFAULT_SCHEDULE);
+            let policy = matches.parse_value("policy")?.unwrap_or_default();
+            cron::enable(&prog, &options, matches.value("profile"), schedule, policy).await
+        }
+        Some("disable-cron") => cron::disable(&prog, &options).await,
+        Some("defer") => defer::run(&prog, options.channel, &matches),
+        Some("pin") => pin::run(&prog, options.channel, &matches),
+        Some("unpin") => pin::unpin(&prog, options.channel),
+        Some("rollback") if matches.flag("snapshot") => {
+            rollback_snapshot(&prog, &options, options.wait).await
+        }
+        Some("rollback") => Err("only rollback --snapshot is supported, see --help".into()),
+        Some("network-check") => netcheck::run(&prog, &options, options.channel).await,
+        Some("running") => running::run(&prog, &options).await,
+        Some("flags") => flags::run(&prog, &options, &matches).await,
+        Some("autostart") => autostart::run(&prog, &options, &matches).await,
+        Some("harden") => harden::run(&prog, &options, &matches).await,
+        Some("install-launcher") => launcher::install(&prog, &options, &matches).await,
+        Some("uninstall") => uninstall::run(&options).await,
+        Some("audit-perms") => audit_perms::run(&options).await,
+        Some("integrate-user") => integrate::run(&options).await,
+        Some("man") => man::run(&prog, Path::new(&matches.positionals[0])),
+        Some("system-helper") if matches.subcommand(1) == Some("stage") => {
+            system::stage(&options, &matches.positionals).await
+        }
+        Some("system-helper") => system::helper(matches.subcommand(1), &matches.positionals),
+        Some("switch-channel") => {
+            with_timeout(&options, switch_channel::run(&options, &matches)).await
+        }
+        _ if matches.flag("all-channels") => {
+            let mut all = vec![];
+            for channel in managed_channels(|c| channels.contains_key(&c)).await? {
+                let mut matches = base.clone();
+                matches.set("channel", channel.name().to_owned());
+                all.push(Options::from_matches(&configure(matches, channel)?)?);
+            }
+            update_channels(all).await
+        }
+        _ if options.all => update_all(&options).await,
+        _ => return update_command(&options).await,
+    };
+    result.map(|()| Outcome::Done)
+}
+
+/// Print an error and everything that caused it
+pub fn report_error(error: &dyn std::error::Error) {
+    eprintln!("Error: {error}");
+    let mut source = error.source();
+    while let Some(cause) = source {
+        eprintln!("  caused by: {cause}");
+        source = cause.source();
+    }
+}
@@ -0,0 +1,207 @@
+//! Housekeeping of Discord's config directory
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::Result;
+use indicatif::HumanBytes;
+use semver::Version;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Cache directories Discord (Electron) recreates on demand. Stale ones are a common cause of
+/// rendering and login problems after an update.
+const CACHE_DIRS: &[&str] = &[
+    "Cache",
+    "Code Cache",
+    "GPUCache",
+    "DawnCache",
+    "DawnGraphiteCache",
+    "DawnWebGPUCache",
+];
+
+/// What to do with the per-build directories (`~/.config/discord/0.0.x`) of older host versions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BuildDirs {
+    #[default]
+    Keep,
+    /// Move what's in the newest old one into the new version's, then delete the old ones
+    Migrate,
+    /// Delete the old ones
+    Clean,
+}
+
+impl FromStr for BuildDirs {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(Self::Keep),
+            "migrate" => Ok(Self::Migrate),
+            "clean" => Ok(Self::Clean),
+            _ => Err("expected keep, migrate, or clean".into()),
+        }
+    }
+}
+
+/// Total size of the files under a path
+fn disk_usage(path: &Path) -> io::Result<u64> {
+    let meta = fs::symlink_metadata(path)?;
+    if !meta.is_dir() {
+        return Ok(meta.len());
+    }
+    let mut total = 0;
+    for entry in fs::read_dir(path)? {
+        total += disk_usage(&entry?.path())?;
+    }
+    Ok(total)
+}
+
+/// Delete the channel's caches, returning how many bytes were freed
+pub fn clear_cache(prog: &Progress, channel: Channel) -> Result<u64> {
+    let config_dir = channel.config_dir()?;
+    let mut freed = 0;
+    for name in CACHE_DIRS {
+        let dir = config_dir.join(name);
+        if !dir.is_dir() {
+            continue;
+        }
+        let size = disk_usage(&dir)?;
+        fs::remove_dir_all(&dir)?;
+        freed += size;
+        prog.println(format!("Removed {} ({})", dir.display(), HumanBytes(size)))?;
+    }
+    prog.println(format!("Cleared {} of {channel} caches", HumanBytes(freed)))?;
+    Ok(freed)
+}
+
+/// The channel's per-build directories, oldest first
+fn build_dirs(channel: Channel) -> Result<Vec<(Version, PathBuf)>> {
+    let entries = match fs::read_dir(channel.config_dir()?) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let mut dirs = vec![];
+    for entry in entries {
+        let entry = entry?;
+        let version = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| Version::parse(name).ok());
+        if let Some(version) = version {
+            if entry.file_type()?.is_dir() {
+                dirs.push((version, entry.path()));
+            }
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// Migrate or clean the per-build directories left by host versions other than `host`
+///
+/// Downloaded modules are never migrated, they only work with the host they came with.
+pub fn tidy_build_dirs(
+    prog: &Progress,
+    channel: Channel,
+    host: &Version,
+    mode: BuildDirs,
+) -> Result<()> {
+    if mode == BuildDirs::Keep {
+        return Ok(());
+    }
+    let old: Vec<_> = build_dirs(channel)?
+        .into_iter()
+        .filter(|(version, _)| version != host)
+        .collect();
+    let current = channel.config_dir()?.join(host.to_string());
+    let mut moved = 0;
+    if let (BuildDirs::Migrate, Some((_, newest))) = (mode, old.last()) {
+        fs::create_dir_all(&current)?;
+        for entry in fs::read_dir(newest)? {
+            let entry = entry?;
+            let to = current.join(entry.file_name());
+            if entry.file_name() == "modules" || fs::symlink_metadata(&to).is_ok() {
+                continue;
+            }
+            fs::rename(entry.path(), &to)?;
+            prog.println(format!(
+                "Moved {} to {}",
+                entry.path().display(),
+                to.display()
+            ))?;
+            moved += 1;
+        }
+    }
+    let mut freed = 0;
+    for (_, dir) in &old {
+        let size = disk_usage(dir)?;
+        fs::remove_dir_all(dir)?;
+        freed += size;
+        prog.println(format!("Deleted {} ({})", dir.display(), HumanBytes(size)))?;
+    }
+    prog.println(format!(
+        "Moved {moved} entries and deleted {} old build directories ({})",
+        old.len(),
+        HumanBytes(freed)
+    ))?;
+    Ok(())
+}
+
+/// Module directories (voice, krisp, and the like) that don't belong to the `host` version, with
+/// what's wrong with each
+///
+/// Discord downloads modules into `<config>/<host version>/modules`. Ones left behind by other
+/// host versions are stale, and so is the current one if a module it lists is missing, which
+/// happens when a download is cut short and leaves voice broken.
+fn stale_modules(channel: Channel, host: &Version) -> Result<Vec<(PathBuf, String)>> {
+    let mut stale = vec![];
+    for (version, dir) in build_dirs(channel)? {
+        let modules = dir.join("modules");
+        if !modules.is_dir() {
+            continue;
+        }
+        if version != *host {
+            stale.push((modules, format!("left from Discord {version}")));
+        } else if !modules_complete(&modules)? {
+            stale.push((modules, "incomplete".into()));
+        }
+    }
+    Ok(stale)
+}
+
+/// Whether every module `installed.json` lists has its directory
+fn modules_complete(modules: &Path) -> Result<bool> {
+    let installed = match fs::read_to_string(modules.join("installed.json")) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+        Err(e) => return Err(e.into()),
+    };
+    let installed: serde_json::Map<String, serde_json::Value> = serde_json::from_str(&installed)?;
+    Ok(installed.keys().all(|name| modules.join(name).is_dir()))
+}
+
+/// Report stale module directories after an update to `host`, deleting them if `purge`
+///
+/// Discord fetches matching modules again the next time it starts.
+pub fn check_modules(prog: &Progress, channel: Channel, host: &Version, purge: bool) -> Result<()> {
+    let stale = stale_modules(channel, host)?;
+    if stale.is_empty() {
+        return Ok(());
+    }
+    for (modules, problem) in &stale {
+        let size = HumanBytes(disk_usage(modules)?);
+        if purge {
+            fs::remove_dir_all(modules)?;
+        }
+        let what = if purge { "Removed" } else { "Stale" };
+        let modules = modules.display();
+        prog.println(format!("{what} modules {modules} ({size}), {problem}"))?;
+    }
+    if !purge {
+        prog.println("Use --purge-modules to remove them so Discord downloads matching ones")?;
+    }
+    Ok(())
+}
@@ -0,0 +1,131 @@
+//! Man pages, for `man DIR`
+//!
+//! Like `--help`, they're rendered from the `cli::COMMAND` tables, so they can't drift from what's
+//! accepted. There's a page for the tool and one for each subcommand, named like git's,
+//! `discord_update-config-backup.1`.
+
+use crate::cli::{self, Command, COMMAND};
+use crate::progress::Progress;
+use crate::Result;
+use std::path::Path;
+
+/// Escape text for roff, where `\` starts an escape and `-` is a hyphen, not a minus
+fn escape(text: &str) -> String {
+    let text = text.replace('\\', r"\e").replace('-', r"\-");
+    // A line starting with these would be taken for a request
+    if text.starts_with(['.', '\'']) {
+        format!(r"\&{text}")
+    } else {
+        text
+    }
+}
+
+/// The page name for the command at the end of `path`
+fn page_name(path: &[&Command]) -> String {
+    path.iter().map(|c| c.name).collect::<Vec<_>>().join("-")
+}
+
+/// Render the page for the command at the end of `path`
+fn page(path: &[&Command]) -> String {
+    let command = path.last().unwrap();
+    let name = page_name(path);
+    let mut out = format!(
+        ".TH {} 1 \"\" \"discord_update {}\"\n",
+        escape(&name.to_uppercase()),
+        env!("CARGO_PKG_VERSION")
+    );
+    out += &format!(
+        ".SH NAME\n{} \\- {}\n",
+        escape(&name),
+        escape(command.about)
+    );
+
+    out += ".SH SYNOPSIS\n";
+    let words: Vec<_> = path.iter().map(|c| c.name).collect();
+    out += &format!(".B {}\n", escape(&words.join(" ")));
+    if !command.opts.is_empty() || path.len() > 1 {
+        out += "[\\fIOPTIONS\\fR]\n";
+    }
+    if !command.subcommands.is_empty() {
+        out += "[\\fICOMMAND\\fR]\n";
+    }
+    for arg in command.args {
+        match arg.strip_prefix('[').and_then(|a| a.strip_suffix(']')) {
+            Some(arg) => out += &format!("[\\fI{}\\fR]\n", escape(arg)),
+            None => out += &format!("\\fI{}\\fR\n", escape(arg)),
+        }
+    }
+
+    out += &format!(".SH DESCRIPTION\n{}\n", escape(command.about));
+
+    if !command.opts.is_empty() || path.len() == 1 {
+        out += ".SH OPTIONS\n";
+        for opt in command.opts {
+            out += &format!(".TP\n\\fB\\-\\-{}\\fR", escape(opt.long));
+            if let Some(value) = opt.value {
+                out += &format!(" \\fI{}\\fR", escape(value));
+            }
+            out += &format!("\n{}\n", escape(opt.help));
+        }
+        if path.len() == 1 {
+            out += ".TP\n\\fB\\-\\-help\\fR\nPrint help\n";
+        }
+    }
+    if path.len() > 1 {
+        out += &format!(
+            ".PP\nThe options of \\fB{}\\fR(1) are accepted too.\n",
+            escape(&page_name(&path[..path.len() - 1]))
+        );
+    }
+
+    if !command.subcommands.is_empty() {
+        out += ".SH COMMANDS\n";
+        for sub in command.subcommands {
+            let sub_path: Vec<_> = path.iter().copied().chain([sub]).collect();
+            out += &format!(
+                ".TP\n\\fB{}\\fR\n{} See \\fB{}\\fR(1).\n",
+                escape(sub.name),
+                escape(sub.about),
+                escape(&page_name(&sub_path))
+            );
+        }
+    }
+
+    if path.len() == 1 {
+        out += &format!(
+            ".SH ENVIRONMENT\nEvery option can also be set with an environment variable, \
+             named like \\fB{}\\fR for \\fB\\-\\-channel\\fR.\n",
+            escape(&cli::env_var("channel"))
+        );
+        out += ".SH FILES\n.TP\n\\fI~/.config/discord_update/config.json\\fR\n\
+                Options, like {\"channel\": \"ptb\"}, for one channel under \"channels\", or \
+                together under a name for \\fB\\-\\-profile\\fR under \"profiles\".\n";
+    }
+    out
+}
+
+/// Write the pages for the command at the end of `path` and everything under it into `dir`
+fn write_pages(
+    dir: &Path,
+    path: &mut Vec<&'static Command>,
+    written: &mut usize,
+) -> std::io::Result<()> {
+    std::fs::write(dir.join(format!("{}.1", page_name(path))), page(path))?;
+    *written += 1;
+    let command = *path.last().unwrap();
+    for sub in command.subcommands {
+        path.push(sub);
+        write_pages(dir, path, written)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+/// `man DIR`, write a man page for the tool and each of its subcommands into `DIR`
+pub fn run(prog: &Progress, dir: &Path) -> Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let mut written = 0;
+    write_pages(dir, &mut vec![&COMMAND], &mut written)?;
+    prog.println(format!("Wrote {written} man pages to {}", dir.display()))?;
+    Ok(())
+}
@@ -0,0 +1,202 @@
+//! A record of every file in an install, written alongside it at install time
+
+use crate::{tar, Result};
+use ring::digest::{Context, SHA256};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write as _;
+use std::fs;
+use std::io::{self, Read as _};
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::{Path, PathBuf};
+
+/// What the install was and everything in it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: Version,
+    /// Sorted by path, parents before their contents
+    pub entries: Vec<ManifestEntry>,
+    /// `--exclude` patterns the install was extracted with, what matches is meant to be missing
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub excluded: Vec<String>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Relative to the install directory
+    pub path: PathBuf,
+    pub mode: u32,
+    #[serde(flatten)]
+    pub kind: EntryKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum EntryKind {
+    Directory,
+    File { size: u64, sha256: String },
+    Symlink { target: PathBuf },
+}
+
+/// Where we keep our own files about an install, next to (not inside) it so Discord never sees
+/// them and replacing the install doesn't lose them
+pub fn metadata_dir(install_path: &Path) -> PathBuf {
+    let name = install_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    install_path.with_file_name(format!(".{name}.discord_update"))
+}
+
+/// The hex SHA-256 of a file's contents
+pub fn sha256_file(path: &Path) -> io::Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut context = Context::new(&SHA256);
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            n => context.update(&buf[..n]),
+        }
+    }
+    let mut hex = String::new();
+    for byte in context.finish().as_ref() {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    Ok(hex)
+}
+
+fn walk(root: &Path, relative: &Path, entries: &mut Vec<ManifestEntry>) -> io::Result<()> {
+    let mut children = fs::read_dir(root.join(relative))?.collect::<io::Result<Vec<_>>>()?;
+    children.sort_by_key(|c| c.file_name());
+    for child in children {
+        let path = relative.join(child.file_name());
+        let meta = fs::symlink_metadata(child.path())?;
+        let mode = meta.permissions().mode() & 0o7777;
+        let kind = if meta.is_dir() {
+            EntryKind::Directory
+        } else if meta.is_symlink() {
+            EntryKind::Symlink {
+                target: fs::read_link(child.path())?,
+            }
+        } else {
+            EntryKind::File {
+                size: meta.len(),
+                sha256: sha256_file(&child.path())?,
+            }
+        };
+        let is_dir = kind == EntryKind::Directory;
+        entries.push(ManifestEntry {
+            path: path.clone(),
+            mode,
+            kind,
+        });
+        if is_dir {
+            walk(root, &path, entries)?;
+        }
+    }
+    Ok(())
+}
+
+impl Manifest {
+    /// Hash everything under `install_path`
+    pub fn generate(install_path: &Path, version: Version) -> io::Result<Self> {
+        let mut entries = vec![];
+        walk(install_path, Path::new(""), &mut entries)?;
+        Ok(Self {
+            version,
+            entries,
+            excluded: vec![],
+        })
+    }
+
+    fn path(install_path: &Path) -> PathBuf {
+        metadata_dir(install_path).join("manifest.json")
+    }
+
+    /// The manifest written when the install was made, if it was made by us
+    pub fn load(install_path: &Path) -> Result<Option<Self>> {
+        match fs::read_to_string(Self::path(install_path)) {
+            Ok(contents) => Ok(Some(serde_json::from_str(&contents)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// What changes going from `self` to `new`, as `+`, `-`, or `~` and the path
+    ///
+    /// Entries are changed when their contents, type, or permissions differ.
+    pub fn diff<'a>(&'a self, new: &'a Self) -> Vec<(char, &'a Path)> {
+        let old: HashMap<_, _> = self.entries.iter().map(|e| (&e.path, e)).collect();
+        let new_paths: HashSet<_> = new.entries.iter().map(|e| &e.path).collect();
+        let mut changes = vec![];
+        for entry in &new.entries {
+            match old.get(&entry.path) {
+                None => changes.push(('+', entry.path.as_path())),
+                Some(old) if old.kind != entry.kind || old.mode != entry.mode => {
+                    changes.push(('~', entry.path.as_path()))
+                }
+                Some(_) => {}
+            }
+        }
+        for entry in &self.entries {
+            if !new_paths.contains(&entry.path) {
+                changes.push(('-', entry.path.as_path()));
+            }
+        }
+        changes.sort_by_key(|(_, path)| *path);
+        changes
+    }
+
+    /// Check what's on disk, as hashed into this manifest, against what extraction wrote
+    ///
+    /// The error says what doesn't match, a file gone missing or cut short on the way to disk
+    /// that the write didn't report.
+    pub fn check_extracted(&self, index: &tar::Index) -> std::result::Result<(), String> {
+        let found: HashMap<_, _> = self.entries.iter().map(|e| (&e.path, &e.kind)).collect();
+        for (path, (kind, size)) in index {
+            let problem = match (kind, found.get(path)) {
+                (_, None) => "missing".to_owned(),
+                (tar::EntryKind::File, Some(EntryKind::File { size: found, .. })) => {
+                    if found == size {
+                        continue;
+                    }
+                    format!("{found} bytes instead of {size}")
+                }
+                (tar::EntryKind::Directory, Some(EntryKind::Directory))
+                | (tar::EntryKind::Symlink, Some(EntryKind::Symlink { .. })) => continue,
+                (_, Some(_)) => "the wrong type of file".to_owned(),
+            };
+            return Err(format!("{} is {problem}", path.display()));
+        }
+        let extra = self.entries.iter().find(|e| {
+            // Directories can be made for an entry without one of their own
+            e.kind != EntryKind::Directory && !index.contains_key(&e.path)
+        });
+        if let Some(extra) = extra {
+            return Err(format!("{} isn't in the archive", extra.path.display()));
+        }
+        Ok(())
+    }
+
+    /// Forget the manifest of the install at `install_path`, for when it wasn't made by us
+    pub fn remove(install_path: &Path) -> io::Result<()> {
+        match fs::remove_file(Self::path(install_path)) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+            _ => Ok(()),
+        }
+    }
+
+    /// Record this as the manifest of the install at `install_path`
+    pub fn save(&self, install_path: &Path) -> io::Result<()> {
+        let path = Self::path(install_path);
+        let dir = path.parent().unwrap();
+        fs::create_dir_all(dir)?;
+        let temp = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer_pretty(temp.as_file(), self)?;
+        temp.as_file().sync_all()?;
+        temp.persist(&path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,110 @@
+//! Metrics for the node_exporter textfile collector
+
+use crate::channel::Channel;
+use crate::state::{now, State};
+use crate::{Result, RunReport};
+use std::fmt::Write as _;
+use std::os::unix::fs::PermissionsExt as _;
+use std::path::Path;
+
+fn metric(out: &mut String, name: &str, kind: &str, help: &str, samples: &[(String, u64)]) {
+    let _ = writeln!(out, "# HELP discord_update_{name} {help}");
+    let _ = writeln!(out, "# TYPE discord_update_{name} {kind}");
+    for (labels, value) in samples {
+        let _ = writeln!(out, "discord_update_{name}{{{labels}}} {value}");
+    }
+}
+
+/// Escape a label value for the text exposition format
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Write the result of this run to `path`
+///
+/// The file is replaced atomically, so the collector never reads a partial file.
+pub fn write(
+    path: &Path,
+    channel: Channel,
+    report: Option<&RunReport>,
+    state: &State,
+) -> Result<()> {
+    let labels = format!("channel=\"{channel}\"");
+    let mut out = String::new();
+
+    if let Some(report) = report {
+        let check = &report.check;
+        if let Some(installed) = &check.installed_version {
+            let version = escape(&installed.to_string());
+            metric(
+                &mut out,
+                "installed_info",
+                "gauge",
+                "The installed Discord version",
+                &[(format!("{labels},version=\"{version}\""), 1)],
+            );
+        }
+        let latest = escape(&check.latest_version.to_string());
+        metric(
+            &mut out,
+            "latest_info",
+            "gauge",
+            "The latest Discord version available",
+            &[(format!("{labels},version=\"{latest}\""), 1)],
+        );
+        // After a successful update the install is current again
+        let available = report.update_available && !report.updated;
+        metric(
+            &mut out,
+            "update_available",
+            "gauge",
+            "Whether a newer Discord version than the installed one is available",
+            &[(labels.clone(), available.into())],
+        );
+    }
+
+    let channel_state = state.channels.get(&channel).cloned().unwrap_or_default();
+    if let Some(last_check) = channel_state.last_check {
+        metric(
+            &mut out,
+            "last_check_timestamp_seconds",
+            "gauge",
+            "When the latest version was last checked",
+            &[(labels.clone(), last_check)],
+        );
+    }
+    if let Some(last_update) = channel_state.last_update {
+        metric(
+            &mut out,
+            "last_update_timestamp_seconds",
+            "gauge",
+            "When Discord was last updated",
+            &[(labels.clone(), last_update)],
+        );
+    }
+    metric(
+        &mut out,
+        "last_run_success",
+        "gauge",
+        "Whether the last run completed without error",
+        &[(labels.clone(), report.is_some().into())],
+    );
+    metric(
+        &mut out,
+        "last_run_timestamp_seconds",
+        "gauge",
+        "When discord_update last ran",
+        &[(labels, now())],
+    );
+
+    let dir = path.parent().filter(|p| !p.as_os_str().is_empty());
+    let temp = tempfile::NamedTempFile::new_in(dir.unwrap_or(Path::new(".")))?;
+    std::fs::write(temp.path(), out)?;
+    // The collector usually runs as a different user
+    std::fs::set_permissions(temp.path(), std::fs::Permissions::from_mode(0o644))?;
+    temp.persist(path)?;
+    Ok(())
+}
@@ -0,0 +1,152 @@
+//! The `network-check` subcommand, for working out why update checks fail
+//!
+//! The updates API and the download CDN are each tried step by step, DNS, then TCP, TLS, and
+//! HTTP, going direct, and then once more through the proxy if there is one. A proxy resolves
+//! and connects on our behalf, so through it there's only the HTTP step.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{api_url, download_url, get_latest_discord_version, Options, Result};
+use semver::Version;
+use std::future::Future;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+
+/// How long each step gets
+const STEP_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run a step with the timeout, printing how it went and how long it took
+async fn step<T>(
+    prog: &Progress,
+    name: &str,
+    future: impl Future<Output = Result<(T, String)>>,
+) -> Result<Option<T>> {
+    let started = Instant::now();
+    let result = match tokio::time::timeout(STEP_TIMEOUT, future).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {}s", STEP_TIMEOUT.as_secs()).into()),
+    };
+    let ms = started.elapsed().as_millis();
+    match result {
+        Ok((value, detail)) => {
+            prog.println(format!("  {name:<6} ok      {ms:>5}ms  {detail}"))?;
+            Ok(Some(value))
+        }
+        Err(e) => {
+            // reqwest's own message rarely says what went wrong, its sources do
+            let mut message = e.to_string();
+            let mut source = e.source();
+            while let Some(e) = source {
+                let text = e.to_string();
+                if !message.contains(&text) {
+                    message += &format!(": {text}");
+                }
+                source = e.source();
+            }
+            prog.println(format!("  {name:<6} FAILED  {ms:>5}ms  {message}"))?;
+            Ok(None)
+        }
+    }
+}
+
+/// Ask for `url` with `client`, any answer counts as reaching the server
+async fn http(client: &reqwest::Client, url: &str) -> Result<((), String)> {
+    let resp = client.head(url).send().await?;
+    Ok(((), format!("{:?} {}", resp.version(), resp.status())))
+}
+
+/// Check one URL step by step, whether every step passed
+async fn check(prog: &Progress, what: &str, url: &str, proxy: Option<&str>) -> Result<bool> {
+    prog.println(format!("{what} ({url})"))?;
+    let parsed = reqwest::Url::parse(url)?;
+    let host = parsed.host_str().ok_or("the URL has no host")?.to_owned();
+    let port = parsed
+        .port_or_known_default()
+        .ok_or("the URL has no port")?;
+
+    let dns = step(prog, "DNS", async {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host((host.as_str(), port))
+            .await?
+            .collect();
+        let first = *addrs.first().ok_or("no addresses")?;
+        let shown: Vec<_> = addrs.iter().map(|a| a.ip().to_string()).collect();
+        Ok((first, format!("{host} is {}", shown.join(", "))))
+    })
+    .await?;
+    let mut ok = dns.is_some();
+    let tcp = match dns {
+        Some(addr) => {
+            step(prog, "TCP", async {
+                Ok((
+                    TcpStream::connect(addr).await?,
+                    format!("connected to {addr}"),
+                ))
+            })
+            .await?
+        }
+        None => None,
+    };
+    ok &= tcp.is_some();
+    if let Some(tcp) = tcp.filter(|_| parsed.scheme() == "https") {
+        let tls = step(prog, "TLS", async {
+            let connector = tokio_native_tls::native_tls::TlsConnector::new()?;
+            let stream = tokio_native_tls::TlsConnector::from(connector)
+                .connect(&host, tcp)
+                .await?;
+            let detail = match stream.get_ref().peer_certificate()? {
+                Some(_) => "handshake done, certificate verified",
+                None => "handshake done",
+            };
+            Ok(((), detail.to_owned()))
+        })
+        .await?;
+        ok &= tls.is_some();
+    }
+    let direct = reqwest::Client::builder().no_proxy().build()?;
+    ok &= step(prog, "HTTP", http(&direct, url)).await?.is_some();
+
+    if let Some(proxy) = proxy {
+        let client = reqwest::Client::builder()
+            .proxy(reqwest::Proxy::all(proxy)?)
+            .build()?;
+        ok &= step(prog, "proxy", async {
+            let ((), detail) = http(&client, url).await?;
+            Ok(((), format!("{detail} through {proxy}")))
+        })
+        .await?
+        .is_some();
+    }
+    Ok(ok)
+}
+
+/// The proxy requests go through, `--proxy` or the usual environment variables
+fn configured_proxy(options: &Options) -> Option<String> {
+    options.proxy.clone().or_else(|| {
+        ["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]
+            .iter()
+            .find_map(|var| std::env::var(var).ok().filter(|v| !v.is_empty()))
+    })
+}
+
+/// `network-check`, try reaching the updates API and the CDN
+pub async fn run(prog: &Progress, options: &Options, channel: Channel) -> Result<()> {
+    let proxy = configured_proxy(options);
+    let api = api_url(options, channel);
+    let mut ok = check(prog, "Updates API", &api, proxy.as_deref()).await?;
+    // A real download URL when the API answers, the CDN's host is the same either way
+    let (version, what) = match get_latest_discord_version(options, channel).await {
+        Ok(latest) => (latest.version, "Download CDN"),
+        Err(_) => (Version::new(0, 0, 0), "Download CDN, for a made up version"),
+    };
+    let download = download_url(options, channel, &version);
+    ok &= check(prog, what, &download, proxy.as_deref()).await?;
+    if proxy.is_none() {
+        prog.println("No proxy is configured, so only direct connections were tried")?;
+    }
+    if !ok {
+        return Err("some network checks failed, see above".into());
+    }
+    prog.println("Everything's reachable")?;
+    Ok(())
+}
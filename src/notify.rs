@@ -0,0 +1,532 @@
+//! Telling people about updates, through whichever backends `--notify` picks
+//!
+//! Every backend gets the same events: `update_available`, `updated`, and `failed`. Runs with
+//! nothing new don't send anything.
+//!
+//! Desktop notifications of an update from automatic runs come with buttons to install it, skip
+//! it, or be reminded on the next check. notify-send waits for the notification server to say
+//! which was clicked, and the run that sent it then does that itself, with the same options. It
+//! lets go of the channel's run lock while it waits, so other runs aren't held up for the hour
+//! the buttons last.
+//! While such a run installs an update, one notification says how far along it is, and is
+//! replaced by the one saying how it went.
+
+use crate::channel::Channel;
+use crate::cli::Matches;
+use crate::progress::{self, Progress};
+use crate::state::now;
+use crate::{config, schema, webhook, Result, RunReport};
+use futures::future::LocalBoxFuture;
+use semver::Version;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncWriteExt as _;
+use tokio::process::Command;
+use tokio::task::JoinHandle;
+
+/// Something that happened in a run
+#[derive(Serialize)]
+pub struct Event {
+    /// `schema::VERSION`
+    pub schema_version: u32,
+    pub event: &'static str,
+    pub channel: Channel,
+    pub installed_version: Option<Version>,
+    pub latest_version: Option<Version>,
+    pub error: Option<String>,
+    /// A sentence saying what happened, for backends that show text
+    pub message: String,
+}
+
+impl Event {
+    /// What there is to say about a run, if anything
+    pub fn from_run(channel: Channel, result: &Result<RunReport>) -> Option<Self> {
+        let report = match result {
+            Err(error) => {
+                return Some(Self {
+                    schema_version: schema::VERSION,
+                    event: "failed",
+                    channel,
+                    installed_version: None,
+                    latest_version: None,
+                    error: Some(error.to_string()),
+                    message: format!("Updating Discord {channel} failed: {error}"),
+                })
+            }
+            Ok(report) => report,
+        };
+        let latest = &report.check.latest_version;
+        let (event, message) = if report.updated {
+            let message = match &report.check.installed_version {
+                Some(old) if old != latest => {
+                    format!("Updated Discord {channel} from {old} to {latest}")
+                }
+                Some(_) => format!("Reinstalled Discord {channel} {latest}"),
+                None => format!("Installed Discord {channel} {latest}"),
+            };
+            ("updated", message)
+        } else if report.update_available {
+            (
+                "update_available",
+                format!("Discord {channel} {latest} is available"),
+            )
+        } else {
+            return None;
+        };
+        Some(Self {
+            schema_version: schema::VERSION,
+            event,
+            channel,
+            installed_version: report.check.installed_version.clone(),
+            latest_version: Some(latest.clone()),
+            error: None,
+            message,
+        })
+    }
+}
+
+/// How long an update notification's buttons keep working, holding up the run that sent it
+const ACTION_TIMEOUT: Duration = Duration::from_secs(60 * 60);
+
+/// What desktop notifications are sent with
+const NOTIFY_SEND: &str = "notify-send";
+
+/// How often the notification of an update in progress is replaced, at most, as the download
+/// goes on
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(2);
+
+/// The notification of an unattended update in progress
+struct Ongoing {
+    /// The notification server's id for it, to replace it
+    id: u32,
+    summary: String,
+    phase: String,
+    percent: u64,
+    shown: Instant,
+    /// The notify-send replacing it, which the next one waits for
+    showing: Option<JoinHandle<()>>,
+    /// The notify-send to run, `NOTIFY_SEND` but in tests
+    program: PathBuf,
+}
+
+/// Each channel's update in progress, for `--all-channels` updating several at once
+static ONGOING: Mutex<BTreeMap<Channel, Ongoing>> = Mutex::new(BTreeMap::new());
+
+/// Show or replace a notification, returning its id when notify-send is new enough to say
+async fn show_progress(
+    program: &Path,
+    replace: Option<u32>,
+    summary: &str,
+    body: &str,
+    percent: u64,
+) -> Option<u32> {
+    let mut command = Command::new(program);
+    command.args([
+        "--app-name=discord_update",
+        "--urgency=low",
+        "--print-id",
+        &format!("--hint=int:value:{percent}"),
+    ]);
+    if let Some(id) = replace {
+        command.arg(format!("--replace-id={id}"));
+    }
+    let output = command
+        .arg(summary)
+        .arg(body)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .await
+        .ok()
+        .filter(|output| output.status.success())?;
+    String::from_utf8_lossy(&output.stdout).trim().parse().ok()
+}
+
+/// Start showing how installing `version` goes in a notification, if desktop ones are sent
+pub async fn begin(notifiers: &[Arc<dyn Notifier>], channel: Channel, version: &Version) {
+    let Some(program) = notifiers.iter().find_map(|n| n.shows_progress()) else {
+        return;
+    };
+    let summary = format!("Updating Discord {channel} to {version}");
+    let phase = "Starting".to_owned();
+    if let Some(id) = show_progress(program, None, &summary, &phase, 0).await {
+        let ongoing = Ongoing {
+            id,
+            summary,
+            phase,
+            percent: 0,
+            shown: Instant::now(),
+            showing: None,
+            program: program.to_owned(),
+        };
+        ONGOING.lock().unwrap().insert(channel, ongoing);
+    }
+}
+
+/// Say what the channel's update is doing now, and how far along it is, if it's being shown
+///
+/// Only a new phase, or a new percentage once enough time has passed, replaces the
+/// notification, so calling this for every chunk of a download is fine. notify-send runs in
+/// the background, the caller doesn't wait for it.
+pub fn step(channel: Channel, phase: &str, percent: u64) {
+    let mut ongoing = ONGOING.lock().unwrap();
+    let Some(shown) = ongoing.get_mut(&channel) else {
+        return;
+    };
+    let changed = shown.phase != phase
+        || (shown.percent != percent && shown.shown.elapsed() >= PROGRESS_INTERVAL);
+    if !changed {
+        return;
+    }
+    let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+        return;
+    };
+    shown.phase = phase.to_owned();
+    shown.percent = percent;
+    shown.shown = Instant::now();
+    let previous = shown.showing.take();
+    let (summary, body) = (shown.summary.clone(), format!("{phase}, {percent}%"));
+    let program = shown.program.clone();
+    shown.showing = Some(runtime.spawn(async move {
+        // In order, so an older one can't replace a newer one
+        if let Some(previous) = previous {
+            let _ = previous.await;
+        }
+        let Some(id) = ONGOING.lock().unwrap().get(&channel).map(|o| o.id) else {
+            return;
+        };
+        let shown = show_progress(&program, Some(id), &summary, &body, percent).await;
+        let mut ongoing = ONGOING.lock().unwrap();
+        match shown {
+            Some(id) => {
+                if let Some(ongoing) = ongoing.get_mut(&channel) {
+                    ongoing.id = id;
+                }
+            }
+            None => {
+                ongoing.remove(&channel);
+            }
+        }
+    }));
+}
+
+/// What was clicked on an update notification
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Install,
+    /// Don't offer this version again, like `--skip-version`
+    Skip,
+    /// Leave it for the next check to bring up again
+    Later,
+}
+
+/// A place events can be sent
+pub trait Notifier: Send + Sync {
+    /// What `--notify` calls it
+    fn name(&self) -> &'static str;
+
+    /// Send the event, returning what was done about it, for backends that ask
+    fn notify<'a>(&'a self, event: &'a Event) -> LocalBoxFuture<'a, Result<Option<Action>>>;
+
+    /// The notify-send to show updates with while they happen too, see `begin`
+    fn shows_progress(&self) -> Option<&Path> {
+        None
+    }
+}
+
+/// A desktop notification, through `notify-send`
+struct Desktop {
+    /// Whether updates come with buttons, for runs with no one at a terminal to ask
+    actions: bool,
+    program: PathBuf,
+}
+
+impl Desktop {
+    /// Show an update with its buttons and wait for one to be clicked, if any is
+    ///
+    /// `None` when it couldn't be shown with buttons, like when notify-send is too old for them.
+    async fn ask(&self, event: &Event) -> Option<Option<Action>> {
+        let output = Command::new(&self.program)
+            .args([
+                "--app-name=discord_update",
+                "--action=install=Install now",
+                "--action=skip=Skip this version",
+                "--action=later=Remind me later",
+                "--wait",
+            ])
+            .arg(&event.message)
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .kill_on_drop(true)
+            .output();
+        let Ok(output) = tokio::time::timeout(ACTION_TIMEOUT, output).await else {
+            return Some(None);
+        };
+        let output = output.ok().filter(|output| output.status.success())?;
+        Some(match String::from_utf8_lossy(&output.stdout).trim() {
+            "install" => Some(Action::Install),
+            "skip" => Some(Action::Skip),
+            "later" => Some(Action::Later),
+            _ => None,
+        })
+    }
+}
+
+impl Notifier for Desktop {
+    fn name(&self) -> &'static str {
+        "desktop"
+    }
+
+    fn shows_progress(&self) -> Option<&Path> {
+        self.actions.then_some(&*self.program)
+    }
+
+    fn notify<'a>(&'a self, event: &'a Event) -> LocalBoxFuture<'a, Result<Option<Action>>> {
+        Box::pin(async move {
+            if self.actions && event.event == "update_available" {
+                if let Some(action) = self.ask(event).await {
+                    return Ok(action);
+                }
+            }
+            let body = match event.event {
+                "update_available" => "Run discord_update to install it",
+                _ => "",
+            };
+            // Taking the place of the one saying how the update was going, once it's shown
+            let showing = (ONGOING.lock().unwrap().get_mut(&event.channel))
+                .and_then(|ongoing| ongoing.showing.take());
+            if let Some(showing) = showing {
+                let _ = showing.await;
+            }
+            let ongoing = ONGOING.lock().unwrap().remove(&event.channel);
+            let mut command = Command::new(&self.program);
+            command.arg("--app-name=discord_update");
+            if let Some(ongoing) = ongoing {
+                command.arg(format!("--replace-id={}", ongoing.id));
+            }
+            // No desktop session to show it in isn't worth complaining about
+            let _ = command
+                .arg(&event.message)
+                .arg(body)
+                .stdout(Stdio::null())
+                .stderr(Stdio::null())
+                .status()
+                .await;
+            Ok(None)
+        })
+    }
+}
+
+/// Run a shell command with the message as `$1` and the event as JSON on stdin
+struct Exec {
+    command: String,
+}
+
+impl Notifier for Exec {
+    fn name(&self) -> &'static str {
+        "command"
+    }
+
+    fn notify<'a>(&'a self, event: &'a Event) -> LocalBoxFuture<'a, Result<Option<Action>>> {
+        Box::pin(async move {
+            let mut child = Command::new("/bin/sh")
+                .args(["-c", &self.command, "sh", &event.message])
+                .stdin(Stdio::piped())
+                .stdout(progress::stderr()?)
+                .spawn()?;
+            let mut stdin = child.stdin.take().unwrap();
+            // It's fine for the command not to read it
+            let _ = stdin.write_all(&serde_json::to_vec(event)?).await;
+            drop(stdin);
+            let status = child.wait().await?;
+            if !status.success() {
+                return Err(format!("{:?} failed with {status}", self.command).into());
+            }
+            Ok(None)
+        })
+    }
+}
+
+/// Append the event as a line of JSON, with the time it happened
+struct Log {
+    path: PathBuf,
+}
+
+impl Notifier for Log {
+    fn name(&self) -> &'static str {
+        "log"
+    }
+
+    fn notify<'a>(&'a self, event: &'a Event) -> LocalBoxFuture<'a, Result<Option<Action>>> {
+        Box::pin(async move {
+            #[derive(Serialize)]
+            struct Line<'a> {
+                time: u64,
+                #[serde(flatten)]
+                event: &'a Event,
+            }
+            let line = serde_json::to_string(&Line { time: now(), event })?;
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            writeln!(file, "{line}")?;
+            Ok(None)
+        })
+    }
+}
+
+/// The backends picked by `--notify`
+///
+/// Without it, each one that's been configured is used, and desktop notifications come with
+/// `--notify-only`.
+pub fn from_matches(matches: &Matches, non_interactive: bool) -> Result<Vec<Arc<dyn Notifier>>> {
+    let implied;
+    let names: Vec<&str> = match matches.value("notify") {
+        Some(names) => names.split(',').map(str::trim).collect(),
+        None => {
+            implied = [
+                ("desktop", matches.flag("notify-only")),
+                ("webhook", matches.value("webhook-url").is_some()),
+                ("command", matches.value("notify-command").is_some()),
+                ("log", matches.value("notify-log").is_some()),
+            ];
+            implied
+                .iter()
+                .filter(|(_, on)| *on)
+                .map(|(name, _)| *name)
+                .collect()
+        }
+    };
+    let needs = |long: &str, name: &str| {
+        matches
+            .value(long)
+            .ok_or_else(|| format!("--notify {name} needs --{long}"))
+    };
+    names
+        .into_iter()
+        .filter(|name| !name.is_empty())
+        .map(|name| -> Result<Arc<dyn Notifier>> {
+            Ok(match name {
+                "desktop" => Arc::new(Desktop {
+                    actions: non_interactive,
+                    program: NOTIFY_SEND.into(),
+                }),
+                "webhook" => Arc::new(webhook::Webhook::new(
+                    needs("webhook-url", name)?,
+                    matches.parse_value("webhook-format")?,
+                )),
+                "command" => Arc::new(Exec {
+                    command: needs("notify-command", name)?.to_owned(),
+                }),
+                "log" => Arc::new(Log {
+                    path: config::expand_home(needs("notify-log", name)?.as_ref())?,
+                }),
+                _ => {
+                    return Err(format!(
+                        "unknown --notify backend {name:?}, expected desktop, webhook, \
+                         command, or log"
+                    )
+                    .into())
+                }
+            })
+        })
+        .collect()
+}
+
+/// Send the run's event to every backend, failures only get a warning
+///
+/// Returns what was clicked on the notification, if it asked.
+pub async fn send(
+    prog: &Progress,
+    notifiers: &[Arc<dyn Notifier>],
+    channel: Channel,
+    result: &Result<RunReport>,
+) -> Result<Option<Action>> {
+    if notifiers.is_empty() {
+        return Ok(None);
+    }
+    let Some(event) = Event::from_run(channel, result) else {
+        return Ok(None);
+    };
+    let sends = notifiers.iter().map(|n| n.notify(&event));
+    let results = futures::future::join_all(sends).await;
+    let mut action = None;
+    for (notifier, result) in notifiers.iter().zip(results) {
+        match result {
+            Ok(done) => action = action.or(done),
+            Err(e) => prog.println(format!("Couldn't notify through {}: {e}", notifier.name()))?,
+        }
+    }
+    Ok(action)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt as _;
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn progress_is_shown_in_the_background_and_only_when_it_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (calls, release) = (dir.path().join("calls"), dir.path().join("release"));
+        let script = dir.path().join("notify-send");
+        // Held until the test says, giving up in case a step waits for it
+        let body = format!(
+            "#!/bin/sh
+for _ in $(seq 500); do
+  if [ -e {} ]; then
+    echo \"$@\" >> {}
+    \
+             echo 7
+    exit
+  fi
+  sleep 0.02
+done
+exit 1
+",
+            release.display(),
+            calls.display()
+        );
+        std::fs::write(&script, body).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let ongoing = Ongoing {
+            id: 7,
+            summary: "Updating".into(),
+            phase: "Starting".into(),
+            percent: 0,
+            shown: Instant::now(),
+            showing: None,
+            program: script,
+        };
+        ONGOING.lock().unwrap().insert(Channel::Canary, ongoing);
+        for percent in 0..1000 {
+            step(Channel::Canary, "Downloading", percent / 10);
+        }
+        step(Channel::Canary, "Installing", 100);
+        // Every step returned with notify-send still held
+        assert!(!calls.exists());
+        std::fs::write(&release, "").unwrap();
+        let showing = ONGOING
+            .lock()
+            .unwrap()
+            .get_mut(&Channel::Canary)
+            .unwrap()
+            .showing
+            .take();
+        showing.unwrap().await.unwrap();
+
+        let calls = std::fs::read_to_string(calls).unwrap();
+        let calls: Vec<_> = calls.lines().collect();
+        assert_eq!(calls.len(), 2, "{calls:?}");
+        assert!(calls[0].contains("Downloading, 0%"), "{calls:?}");
+        assert!(calls[1].contains("Installing, 100%"), "{calls:?}");
+        ONGOING.lock().unwrap().remove(&Channel::Canary);
+    }
+}
@@ -0,0 +1,42 @@
+//! Keeping a channel at or below a chosen version, like when a newer release breaks a mod
+//!
+//! Updates go as far as the pin and no further, `--ensure X.Y.Z` still installs anything.
+
+use crate::channel::Channel;
+use crate::cli::Matches;
+use crate::progress::Progress;
+use crate::state::State;
+use crate::Result;
+use semver::Version;
+
+/// The newest version the channel is allowed to update to, if it's pinned
+pub fn max_version(channel: Channel) -> Result<Option<Version>> {
+    Ok(State::load()?
+        .channels
+        .get(&channel)
+        .and_then(|c| c.max_version.clone()))
+}
+
+/// `pin`, set or show the channel's maximum version
+pub fn run(prog: &Progress, channel: Channel, matches: &Matches) -> Result<()> {
+    let Some(max) = matches.parse_value::<Version>("max")? else {
+        match max_version(channel)? {
+            Some(max) => prog.result(format!("Discord {channel} is pinned to {max} or older"))?,
+            None => prog.result(format!("Discord {channel} isn't pinned"))?,
+        }
+        return Ok(());
+    };
+    prog.println(format!(
+        "Discord {channel} won't be updated past {max}, use unpin to undo"
+    ))?;
+    State::update(|state| state.channel(channel).max_version = Some(max))
+}
+
+/// `unpin`, let the channel update to the latest version again
+pub fn unpin(prog: &Progress, channel: Channel) -> Result<()> {
+    State::update(|state| state.channel(channel).max_version = None)?;
+    prog.println(format!(
+        "Discord {channel} will be updated to the latest version again"
+    ))?;
+    Ok(())
+}
@@ -0,0 +1,76 @@
+//! Opt-in certificate pinning, for when the system's trust store isn't trusted enough
+//!
+//! With `--pin-keys` set every HTTPS server we talk to has to present a certificate whose public
+//! key hashes to one of the pins, whatever CA signed it. The pins are SHA-256 hashes of the
+//! DER SubjectPublicKeyInfo in hex, as printed by `--print-pins`.
+//!
+//! Discord rotates its keys now and then, which shows up as every request failing with the new
+//! key's hash in the error. To update, check that hash out of band (`--print-pins` from a
+//! network you trust, or `openssl x509 -pubkey | openssl pkey -pubin -outform der | sha256sum`)
+//! and add it to `pin_keys`, keeping the old one until the rotation is done.
+
+use crate::Result;
+use ring::digest::{digest, SHA256};
+use std::fmt::Write as _;
+
+/// Split a DER element off the front of `der`, giving the whole element, its contents, and what
+/// follows it
+fn element(der: &[u8]) -> Option<(&[u8], &[u8], &[u8])> {
+    let (_tag, rest) = der.split_first()?;
+    let (&first, rest) = rest.split_first()?;
+    let (len, rest) = if first < 0x80 {
+        (first as usize, rest)
+    } else {
+        let count = (first & 0x7f) as usize;
+        if count == 0 || count > 4 || rest.len() < count {
+            return None;
+        }
+        let len = rest[..count]
+            .iter()
+            .fold(0, |len, &b| (len << 8) | b as usize);
+        (len, &rest[count..])
+    };
+    if rest.len() < len {
+        return None;
+    }
+    let header = der.len() - rest.len();
+    Some((&der[..header + len], &rest[..len], &rest[len..]))
+}
+
+/// The SubjectPublicKeyInfo of a DER X.509 certificate
+fn spki(cert: &[u8]) -> Option<&[u8]> {
+    let (_, cert, _) = element(cert)?;
+    let (_, mut tbs, _) = element(cert)?;
+    // The version is optional and explicitly tagged
+    if tbs.first() == Some(&0xa0) {
+        tbs = element(tbs)?.2;
+    }
+    // Serial number, signature algorithm, issuer, validity, and subject come first
+    for _ in 0..5 {
+        tbs = element(tbs)?.2;
+    }
+    Some(element(tbs)?.0)
+}
+
+/// The pin for a DER certificate, the hex SHA-256 of its public key
+pub fn pin(cert: &[u8]) -> Result<String> {
+    let spki = spki(cert).ok_or("couldn't read the server's certificate")?;
+    let mut hex = String::new();
+    for byte in digest(&SHA256, spki).as_ref() {
+        let _ = write!(hex, "{byte:02x}");
+    }
+    Ok(hex)
+}
+
+/// Parse `--pin-keys`, a comma separated list of hex SHA-256 hashes
+pub fn parse_pins(pins: &str) -> Result<Vec<String>> {
+    pins.split(',')
+        .map(|pin| {
+            let pin = pin.trim().to_lowercase();
+            if pin.len() != 64 || !pin.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!("{pin:?} isn't a hex SHA-256 public key pin").into());
+            }
+            Ok(pin)
+        })
+        .collect()
+}
@@ -0,0 +1,303 @@
+//! What installing a version does, as a list of steps worked out before any of them is taken
+//!
+//! `install_version` carries out the plan step by step, and `--dry-run` prints it instead, or
+//! puts it in the `--output json` report, so what's shown is what would have been done. Each
+//! step says what it acts on. Some only turn out to have nothing to do once they run, like a
+//! download the store can stand in for.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{
+    apparmor, autostart, download_url, install, launcher, system, Options, Result, UpdateCheck,
+};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+/// How the staged copy replaces the install
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Commit {
+    /// Staged next to the install, and the two directories swapped
+    Swap,
+    /// Staged in the temp directory, and only what changed written over the install
+    InPlace,
+    /// Staged in the temp directory, and moved into place by the system helper
+    SystemHelper,
+}
+
+/// One thing installing does, with what it acts on
+#[derive(Clone, Debug, Serialize)]
+#[serde(tag = "action", rename_all = "kebab-case")]
+pub enum Step {
+    /// Close Discord if it's running
+    CloseDiscord,
+    /// Keep Discord from being started until the new version is in place
+    BlockLaunches,
+    /// Snapshot the install as it is, for `rollback`
+    Snapshot { path: PathBuf, version: Version },
+    /// Have the system helper download and install it as an unprivileged user
+    DownloadUnprivileged { url: String, path: PathBuf },
+    /// Download the tarball and extract it into a staging directory, unless the store has it
+    Download {
+        url: String,
+        store: Option<PathBuf>,
+        commit: Commit,
+        group: Option<u32>,
+    },
+    /// Wait for Discord to be closed, for `--when-closed`
+    WaitForExit { timeout_secs: Option<u64> },
+    /// Replace the install with the staged copy
+    Install { path: PathBuf, commit: Commit },
+    /// Check the new version starts, putting the previous one back if it doesn't
+    HealthCheck { watch_secs: Option<u64> },
+    /// Keep the new version's files in the store
+    KeepInStore { store: PathBuf },
+    /// Write the menu entry, icon, and link handler that are wanted
+    Desktop {
+        entry: bool,
+        icon: bool,
+        url_handler: bool,
+    },
+    /// Point the autostart entry at the install
+    Autostart,
+    /// Write the launcher again, for the install and its sandbox
+    Launcher { path: PathBuf },
+    /// Make the launcher symlink point at the install
+    Symlink { path: PathBuf },
+    /// Make the AppArmor profile name the install's binary
+    #[serde(rename = "apparmor")]
+    AppArmor { binary: PathBuf },
+}
+
+impl Step {
+    /// What the transaction calls it, for a step that integrates an install that's in place
+    pub fn integration(&self) -> Option<&'static str> {
+        Some(match self {
+            Self::KeepInStore { .. } => "keep-in-store",
+            Self::Desktop { .. } => "desktop",
+            Self::Autostart => "autostart",
+            Self::Launcher { .. } => "launcher",
+            Self::Symlink { .. } => "symlink",
+            Self::AppArmor { .. } => "apparmor",
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let staging = |commit: &Commit| match commit {
+            Commit::Swap => "next to the install",
+            Commit::InPlace | Commit::SystemHelper => "in the temp directory",
+        };
+        match self {
+            Self::CloseDiscord => write!(f, "Close Discord if it's running"),
+            Self::BlockLaunches => write!(f, "Keep Discord from starting until it's installed"),
+            Self::Snapshot { path, version } => {
+                write!(f, "Snapshot Discord {version} at {}", path.display())
+            }
+            Self::DownloadUnprivileged { url, path } => write!(
+                f,
+                "Download {url} as an unprivileged user and install it to {}",
+                path.display()
+            ),
+            Self::Download {
+                url,
+                store,
+                commit,
+                group,
+            } => {
+                write!(f, "Download {url} and extract it {}", staging(commit))?;
+                if let Some(store) = store {
+                    write!(f, ", unless {} has it", store.display())?;
+                }
+                if let Some(gid) = group {
+                    write!(f, ", giving it to group {gid}")?;
+                }
+                Ok(())
+            }
+            Self::WaitForExit { timeout_secs } => {
+                write!(f, "Wait for Discord to be closed")?;
+                if let Some(secs) = timeout_secs {
+                    write!(f, ", for up to {secs}s")?;
+                }
+                Ok(())
+            }
+            Self::Install { path, commit } => {
+                let how = match commit {
+                    Commit::Swap => "by moving the staged copy into place",
+                    Commit::InPlace => "writing only what changed",
+                    Commit::SystemHelper => "through the system helper, setuid chrome-sandbox",
+                };
+                write!(f, "Install it to {} {how}", path.display())
+            }
+            Self::HealthCheck { watch_secs } => {
+                write!(f, "Check it starts, putting the old version back if not")?;
+                if let Some(secs) = watch_secs {
+                    write!(f, ", watching it for {secs}s")?;
+                }
+                Ok(())
+            }
+            Self::KeepInStore { store } => write!(f, "Keep it in {}", store.display()),
+            Self::Desktop {
+                entry,
+                icon,
+                url_handler,
+            } => {
+                let parts: Vec<_> = [
+                    (*entry, "menu entry"),
+                    (*icon, "icon"),
+                    (*url_handler, "link handler"),
+                ]
+                .into_iter()
+                .filter_map(|(wanted, part)| wanted.then_some(part))
+                .collect();
+                match &parts[..] {
+                    [] => write!(f, "Update the desktop integration there is"),
+                    [only] => write!(f, "Write the {only}"),
+                    [rest @ .., last] => write!(f, "Write the {} and {last}", rest.join(", ")),
+                }
+            }
+            Self::Autostart => write!(f, "Point the autostart entry at it"),
+            Self::Launcher { path } => write!(f, "Write the launcher {} again", path.display()),
+            Self::Symlink { path } => write!(f, "Link {} to it", path.display()),
+            Self::AppArmor { binary } => {
+                write!(f, "Write an AppArmor profile for {}", binary.display())
+            }
+        }
+    }
+}
+
+/// The steps installing `version` takes, in order
+#[derive(Clone, Debug, Serialize)]
+pub struct Plan {
+    pub channel: Channel,
+    pub version: Version,
+    pub steps: Vec<Step>,
+}
+
+/// The steps for installing `version` in place of what `check` found
+pub fn install(options: &Options, check: &UpdateCheck, version: &Version) -> Result<Plan> {
+    let channel = check.channel;
+    let path = check.install_path.clone();
+    let url = download_url(options, channel, version);
+    let mut steps = vec![];
+    if options.when_closed.is_none() {
+        steps.extend([Step::CloseDiscord, Step::BlockLaunches]);
+    }
+    if let Some(installed) = check
+        .installed_version
+        .as_ref()
+        .filter(|_| options.snapshot)
+    {
+        steps.push(Step::Snapshot {
+            path: path.clone(),
+            version: installed.clone(),
+        });
+    }
+    if check.system && system::is_root() {
+        steps.push(Step::DownloadUnprivileged { url, path });
+    } else {
+        let commit = if check.system {
+            Commit::SystemHelper
+        } else if options.update_strategy == install::Strategy::InPlace {
+            Commit::InPlace
+        } else {
+            Commit::Swap
+        };
+        steps.push(Step::Download {
+            url,
+            store: options.store.as_ref().map(|store| store.root().to_owned()),
+            commit,
+            group: options.shared_group,
+        });
+        if let Some(timeout) = options.when_closed {
+            steps.extend([
+                Step::WaitForExit {
+                    timeout_secs: timeout.map(|t| t.as_secs()),
+                },
+                Step::BlockLaunches,
+            ]);
+        }
+        // With nothing there before, there's nothing to go back to
+        let replacing = path.exists();
+        steps.push(Step::Install { path, commit });
+        if replacing {
+            steps.push(Step::HealthCheck {
+                watch_secs: options.watch_first_launch.map(|t| t.as_secs()),
+            });
+        }
+    }
+    if let Some(store) = &options.store {
+        steps.push(Step::KeepInStore {
+            store: store.root().to_owned(),
+        });
+    }
+    // Root-owned installs would need root-owned entries, which the helper doesn't write
+    if !check.system {
+        steps.push(Step::Desktop {
+            entry: options.desktop_entry,
+            icon: options.icon,
+            url_handler: options.url_handler,
+        });
+    }
+    if autostart::enabled(channel)? {
+        steps.push(Step::Autostart);
+    }
+    if let Some(settings) = launcher::settings(channel)? {
+        steps.push(Step::Launcher {
+            path: settings.path,
+        });
+    }
+    // If we installed it fresh, create the launcher symlink
+    if check.installed_version.is_none() && options.symlink {
+        steps.push(Step::Symlink {
+            path: check.symlink.clone(),
+        });
+    }
+    if options.apparmor_profile && apparmor::restricted() {
+        steps.push(Step::AppArmor {
+            binary: check.binary_path(),
+        });
+    }
+    Ok(Plan {
+        channel,
+        version: version.clone(),
+        steps,
+    })
+}
+
+/// The steps after the install itself, for an update interrupted once its version was in place
+pub fn integration(options: &Options, check: &UpdateCheck, version: &Version) -> Result<Plan> {
+    let mut plan = install(options, check, version)?;
+    plan.steps.retain(|step| step.integration().is_some());
+    // A fresh install that was interrupted may not have got as far as making its symlink
+    let has_symlink = plan.steps.iter().any(|s| matches!(s, Step::Symlink { .. }));
+    if options.symlink && !has_symlink && fs::symlink_metadata(&check.symlink).is_err() {
+        let at = (plan.steps.iter())
+            .position(|s| matches!(s, Step::AppArmor { .. }))
+            .unwrap_or(plan.steps.len());
+        let path = check.symlink.clone();
+        plan.steps.insert(at, Step::Symlink { path });
+    }
+    Ok(plan)
+}
+
+/// Print what `plan` would do, for `--dry-run`
+pub fn print(prog: &Progress, check: &UpdateCheck, plan: &Plan) -> Result<()> {
+    let from = match &check.installed_version {
+        Some(installed) => format!(" over {installed}"),
+        None => String::new(),
+    };
+    prog.result(format!(
+        "Installing Discord {} {}{from} would:",
+        plan.channel, plan.version
+    ))?;
+    for (i, step) in plan.steps.iter().enumerate() {
+        prog.result(format!("  {}. {step}", i + 1))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,177 @@
+//! What differs between operating systems, behind a trait so other backends can be added
+
+use crate::channel::Channel;
+use crate::{bash, home_dir, system, Error, Result, VersionPayload, BUILD_INFO};
+use futures::future::{FutureExt as _, LocalBoxFuture};
+use semver::Version;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+pub trait Platform: Sync {
+    /// Architectures Discord publishes builds for
+    fn official_archs(&self) -> &'static [&'static str];
+
+    /// Where to install the channel when there's no install already
+    fn default_install_path(&self, channel: Channel, system: bool) -> Result<PathBuf>;
+
+    /// Find the install the user launches the channel from
+    fn locate_install(&self, channel: Channel) -> LocalBoxFuture<'_, Result<PathBuf>>;
+
+    /// Every install of the channel that can be found, the one `locate_install` finds first
+    fn candidate_installs(&self, channel: Channel) -> LocalBoxFuture<'_, Vec<PathBuf>>;
+
+    /// The version of the install at `install_path`
+    fn installed_version<'a>(
+        &'a self,
+        install_path: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<Version>>;
+
+    /// The Discord executable inside an install
+    fn binary_path(&self, channel: Channel, install_path: &Path) -> PathBuf;
+
+    /// Where the launcher goes by default
+    fn launcher_dir(&self, system: bool) -> Result<PathBuf>;
+
+    /// Whether the channel's Discord client is running
+    fn is_running(&self, channel: Channel) -> bool;
+
+    /// The process of the channel's running client, when it can be seen from here
+    fn running_pid(&self, channel: Channel) -> Option<i32>;
+}
+
+/// Official tarballs unpacked under `~/bin/discord_bin`, or /opt for `--system`, and launched
+/// through a symlink
+pub struct Linux;
+
+impl Platform for Linux {
+    fn official_archs(&self) -> &'static [&'static str] {
+        &["x86_64"]
+    }
+
+    fn default_install_path(&self, channel: Channel, system: bool) -> Result<PathBuf> {
+        if system {
+            return Ok(system::default_install_path(channel));
+        }
+        let dir_name = channel.dir_name();
+        Ok(home_dir()?.join(format!("bin/discord_bin/{dir_name}/{dir_name}")))
+    }
+
+    /// Whichever install the channel's command runs in the user's shell
+    fn locate_install(&self, channel: Channel) -> LocalBoxFuture<'_, Result<PathBuf>> {
+        async move {
+            let install_path = PathBuf::from(
+                bash(&format!(
+                    "source ~/.profile ~/.bashrc ~/.zshrc; which {}",
+                    channel.command_name()
+                ))
+                .await?
+                .trim(),
+            );
+            Ok(tokio::fs::canonicalize(&install_path)
+                .await?
+                .parent()
+                .ok_or_else(|| Error::from("bad discord install path"))?
+                .into())
+        }
+        .boxed_local()
+    }
+
+    /// Besides what's on the PATH, the default locations and where distribution packages and
+    /// older copies are usually found
+    fn candidate_installs(&self, channel: Channel) -> LocalBoxFuture<'_, Vec<PathBuf>> {
+        async move {
+            let mut candidates: Vec<PathBuf> = self
+                .locate_install(channel)
+                .await
+                .ok()
+                .into_iter()
+                .collect();
+            candidates.extend(self.default_install_path(channel, false).ok());
+            candidates.extend(self.default_install_path(channel, true).ok());
+            candidates.extend(
+                home_dir()
+                    .ok()
+                    .map(|home| home.join(".local/share").join(channel.dir_name())),
+            );
+            for dir in ["/usr/share", "/usr/lib", "/opt"] {
+                candidates.push(Path::new(dir).join(channel.command_name()));
+            }
+            let mut found: Vec<PathBuf> = vec![];
+            for candidate in candidates {
+                if !self.binary_path(channel, &candidate).is_file() {
+                    continue;
+                }
+                let Ok(candidate) = tokio::fs::canonicalize(&candidate).await else {
+                    continue;
+                };
+                if !found.contains(&candidate) {
+                    found.push(candidate);
+                }
+            }
+            found
+        }
+        .boxed_local()
+    }
+
+    fn installed_version<'a>(
+        &'a self,
+        install_path: &'a Path,
+    ) -> LocalBoxFuture<'a, Result<Version>> {
+        async move {
+            let build_info = tokio::fs::read_to_string(install_path.join(BUILD_INFO)).await?;
+            let build_info: VersionPayload = serde_json::from_str(&build_info)?;
+            Ok(build_info.version)
+        }
+        .boxed_local()
+    }
+
+    fn binary_path(&self, channel: Channel, install_path: &Path) -> PathBuf {
+        install_path.join(channel.binary_name())
+    }
+
+    fn launcher_dir(&self, system: bool) -> Result<PathBuf> {
+        if system {
+            Ok(PathBuf::from(system::SYMLINK_DIR))
+        } else {
+            Ok(home_dir()?.join("bin"))
+        }
+    }
+
+    /// Goes by the singleton lock Discord, like any Chromium app, holds in its config
+    /// directory, which unlike a process list also sees clients in other containers
+    fn is_running(&self, channel: Channel) -> bool {
+        let Ok(dir) = channel.config_dir() else {
+            return false;
+        };
+        // Only answered while the client that made it is up, from whichever PID namespace
+        if UnixStream::connect(dir.join("SingletonSocket")).is_ok() {
+            return true;
+        }
+        // For when the socket's somewhere we can't reach
+        self.running_pid(channel).is_some()
+    }
+
+    /// The lock is a symlink to `hostname-pid`
+    fn running_pid(&self, channel: Channel) -> Option<i32> {
+        let lock = std::fs::read_link(channel.config_dir().ok()?.join("SingletonLock")).ok()?;
+        let lock = lock.to_string_lossy();
+        let (host, pid) = lock.rsplit_once('-')?;
+        let alive = host == hostname() && Path::new("/proc").join(pid).exists();
+        alive.then(|| pid.parse().ok()).flatten()
+    }
+}
+
+fn hostname() -> String {
+    let mut name = [0u8; 256];
+    // SAFETY: the buffer is as long as we say it is
+    if unsafe { libc::gethostname(name.as_mut_ptr().cast(), name.len()) } != 0 {
+        return String::new();
+    }
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    String::from_utf8_lossy(&name[..len]).into_owned()
+}
+
+/// The platform we're running on
+pub fn current() -> &'static dyn Platform {
+    &Linux
+}
@@ -0,0 +1,68 @@
+//! Making sure the directory with the launcher symlinks is on the user's PATH
+
+use crate::progress::Progress;
+use crate::{bash, confirm, home_dir, Result};
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+
+/// Whether `dir` is in a PATH-style list of directories
+fn in_path_list(path: &str, dir: &Path) -> bool {
+    std::env::split_paths(path).any(|entry| entry == dir)
+}
+
+/// Whether a shell set up the way the user's login shell is would find commands in `dir`
+///
+/// Our own PATH isn't enough to go on, cron and systemd run us with a minimal one.
+async fn on_path(dir: &Path) -> bool {
+    if std::env::var("PATH").is_ok_and(|path| in_path_list(&path, dir)) {
+        return true;
+    }
+    bash("source ~/.profile ~/.bashrc ~/.zshrc; printf %s \"$PATH\"")
+        .await
+        .is_ok_and(|path| in_path_list(&path, dir))
+}
+
+/// The startup file of the user's shell that PATH changes belong in
+fn profile_file() -> Result<PathBuf> {
+    let shell = std::env::var("SHELL").unwrap_or_default();
+    let name = match Path::new(&shell).file_name().and_then(|n| n.to_str()) {
+        Some("zsh") => ".zshrc",
+        Some("bash") => ".bashrc",
+        _ => ".profile",
+    };
+    Ok(home_dir()?.join(name))
+}
+
+/// Warn if `bin_dir` isn't on the PATH, and if `ask` offer to add it to the shell profile
+pub async fn check_on_path(prog: &Progress, bin_dir: &Path, ask: bool) -> Result<()> {
+    if on_path(bin_dir).await {
+        return Ok(());
+    }
+    let relative = bin_dir.strip_prefix(home_dir()?).ok();
+    let entry = match relative {
+        Some(relative) => format!("$HOME/{}", relative.display()),
+        None => bin_dir.display().to_string(),
+    };
+    let line = format!("export PATH=\"{entry}:$PATH\"");
+    prog.println(format!(
+        "Warning: {} isn't on your PATH, so the discord command won't be found",
+        bin_dir.display()
+    ))?;
+
+    let profile = profile_file()?;
+    let question = format!("Add `{line}` to {}?", profile.display());
+    if !ask || !confirm(prog, &question, false)? {
+        prog.println(format!("To fix this, add `{line}` to your shell profile"))?;
+        return Ok(());
+    }
+    let mut file = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&profile)?;
+    writeln!(file, "\n# Added by discord_update\n{line}")?;
+    prog.println(format!(
+        "Updated {}, open a new terminal for it to take effect",
+        profile.display()
+    ))?;
+    Ok(())
+}
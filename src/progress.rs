@@ -0,0 +1,216 @@
+//! Progress bars and log output
+//!
+//! Everything said to people, progress and prompts included, goes to stderr. stdout only gets
+//! results, what a command was asked for, so piping it into something like `jq` works.
+
+use crate::channel::Channel;
+use crate::notify;
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
+use std::io::{self, Write as _};
+use std::os::fd::AsFd as _;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Files at least this big get a bar of their own while they're written
+const LARGE_FILE: u64 = 8 * 1024 * 1024;
+/// How often plain mode says how far along a large file is
+const PLAIN_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Told the phase an update is in and how far along it is as a percentage, for front ends that
+/// draw their own progress
+pub type Steps = Arc<dyn Fn(&str, u64) + Send + Sync>;
+
+/// Where progress bars and log lines go
+///
+/// Normally this is an indicatif `MultiProgress` on stderr. In plain mode no bars are drawn and
+/// log lines are written straight to stderr, which is what we want when nobody is watching.
+#[derive(Clone)]
+pub struct Progress {
+    multi: MultiProgress,
+    plain: bool,
+    /// Put before log lines, to tell apart ones from things happening at the same time
+    prefix: Option<String>,
+    steps: Option<Steps>,
+}
+
+impl Progress {
+    pub fn new(plain: bool) -> Self {
+        let multi = if plain {
+            MultiProgress::with_draw_target(ProgressDrawTarget::hidden())
+        } else {
+            MultiProgress::new()
+        };
+        Self {
+            multi,
+            plain,
+            prefix: None,
+            steps: None,
+        }
+    }
+
+    /// The same output, with `prefix` before each log line
+    pub fn prefixed(&self, prefix: String) -> Self {
+        Self {
+            prefix: Some(prefix),
+            ..self.clone()
+        }
+    }
+
+    #[cfg(feature = "tui")]
+    pub fn with_draw_target(target: ProgressDrawTarget) -> Self {
+        Self {
+            multi: MultiProgress::with_draw_target(target),
+            plain: false,
+            prefix: None,
+            steps: None,
+        }
+    }
+
+    /// The same output, with updates' phases also told to `steps`
+    #[cfg(feature = "gui")]
+    pub fn with_steps(self, steps: Steps) -> Self {
+        Self {
+            steps: Some(steps),
+            ..self
+        }
+    }
+
+    pub fn println(&self, msg: impl AsRef<str>) -> io::Result<()> {
+        let msg = match &self.prefix {
+            Some(prefix) => format!("{prefix} {}", msg.as_ref()),
+            None => msg.as_ref().to_owned(),
+        };
+        if self.plain {
+            eprintln!("{msg}");
+            Ok(())
+        } else {
+            self.multi.println(msg)
+        }
+    }
+
+    /// Print a line of the command's result to stdout, never prefixed
+    pub fn result(&self, line: impl AsRef<str>) -> io::Result<()> {
+        self.suspend(|| writeln!(io::stdout(), "{}", line.as_ref()))
+    }
+
+    pub fn add(&self, bar: ProgressBar) -> ProgressBar {
+        self.multi.add(bar)
+    }
+
+    /// Add a ticking spinner for showing what we're up to
+    pub fn spinner(&self) -> ProgressBar {
+        let spinner = self.add(ProgressBar::new_spinner());
+        spinner.enable_steady_tick(Duration::from_millis(100));
+        spinner
+    }
+
+    /// Finish a spinner, leaving the message behind
+    pub fn finish_spinner(&self, spinner: &ProgressBar, msg: &'static str) -> io::Result<()> {
+        spinner.finish_with_message(msg);
+        if self.plain {
+            self.println(msg)?;
+        }
+        Ok(())
+    }
+
+    /// Follow the writing of an archive's large files, see `FileProgress`
+    pub fn file_progress(&self) -> FileProgress {
+        FileProgress {
+            prog: self.clone(),
+            current: None,
+            reported: Instant::now(),
+        }
+    }
+
+    /// Say which phase the channel's update is in and how far along it is, for the desktop
+    /// notification and whoever else asked with `with_steps`
+    pub fn step(&self, channel: Channel, phase: &str, percent: u64) {
+        notify::step(channel, phase, percent);
+        if let Some(steps) = &self.steps {
+            steps(phase, percent);
+        }
+    }
+
+    /// Hide the progress bars while running `f`, for talking to the user
+    pub fn suspend<R>(&self, f: impl FnOnce() -> R) -> R {
+        self.multi.suspend(f)
+    }
+}
+
+/// How far along writing a large file is, so a slow disk doesn't look like a hang
+///
+/// Files like `app.asar` get a bar while they're written, and in plain mode a line when they're
+/// started and then every few seconds.
+pub struct FileProgress {
+    prog: Progress,
+    /// The file being written and its bar
+    current: Option<(PathBuf, ProgressBar)>,
+    /// When plain mode last said how far along it is
+    reported: Instant,
+}
+
+impl FileProgress {
+    /// `written` bytes of the file at `path`, `size` bytes big, are written
+    pub fn update(&mut self, path: &Path, written: u64, size: u64) -> io::Result<()> {
+        if size < LARGE_FILE {
+            return Ok(());
+        }
+        if self
+            .current
+            .as_ref()
+            .is_none_or(|(current, _)| current != path)
+        {
+            self.finish();
+            let bar = self.prog.add(
+                ProgressBar::new(size)
+                    .with_style(
+                        ProgressStyle::with_template(
+                            "  {msg} {bar:30} {binary_bytes}/{binary_total_bytes}",
+                        )
+                        .unwrap(),
+                    )
+                    .with_message(format!("Writing {}", path.display())),
+            );
+            if self.prog.plain {
+                self.prog
+                    .println(format!("Writing {} ({})", path.display(), HumanBytes(size)))?;
+            }
+            self.reported = Instant::now();
+            self.current = Some((path.to_owned(), bar));
+        }
+        if let Some((_, bar)) = &self.current {
+            bar.set_position(written);
+        }
+        if written >= size {
+            self.finish();
+        } else if self.prog.plain && self.reported.elapsed() >= PLAIN_INTERVAL {
+            self.prog.println(format!(
+                "Wrote {} of {} of {}",
+                HumanBytes(written),
+                HumanBytes(size),
+                path.display()
+            ))?;
+            self.reported = Instant::now();
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) {
+        if let Some((_, bar)) = self.current.take() {
+            bar.finish_and_clear();
+        }
+    }
+}
+
+impl Drop for FileProgress {
+    fn drop(&mut self) {
+        self.finish();
+    }
+}
+
+/// For the stdout of programs we run, so what they say doesn't get mixed into our results
+pub fn stderr() -> io::Result<Stdio> {
+    Ok(io::stderr().as_fd().try_clone_to_owned()?.into())
+}
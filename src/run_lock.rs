@@ -0,0 +1,85 @@
+//! Keeping two runs from updating the same channel at once
+//!
+//! Every update of a channel holds a lock on a file in the state directory. A run that finds
+//! another one holding it, like a timer firing while an update started by hand is going, fails
+//! straight away, or with `--wait` waits its turn and then works out again what's left to do.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::state::state_dir;
+use crate::Result;
+use std::fs::File;
+use std::io;
+use std::os::fd::AsRawFd as _;
+use std::sync::Mutex;
+
+/// The channels this process holds the lock on
+static HELD: Mutex<Vec<Channel>> = Mutex::new(Vec::new());
+
+/// The lock on a channel, released when dropped
+pub struct RunLock {
+    _file: File,
+    channel: Channel,
+}
+
+impl RunLock {
+    fn new(file: File, channel: Channel) -> Self {
+        HELD.lock().unwrap().push(channel);
+        Self {
+            _file: file,
+            channel,
+        }
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        let mut held = HELD.lock().unwrap();
+        if let Some(i) = held.iter().position(|&c| c == self.channel) {
+            held.swap_remove(i);
+        }
+    }
+}
+
+/// Whether this process holds the lock on `channel`
+pub fn held(channel: Channel) -> bool {
+    HELD.lock().unwrap().contains(&channel)
+}
+
+/// flock(2) on `file`
+pub fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    // SAFETY: the fd is open for as long as file is
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Lock the channel, waiting for whoever holds it when `wait` is set
+pub async fn acquire(prog: &Progress, channel: Channel, wait: bool) -> Result<RunLock> {
+    let dir = state_dir()?;
+    std::fs::create_dir_all(&dir)?;
+    // Not the lock cron and the launcher take with flock(1), so their runs can still take this
+    let file = File::create(dir.join(format!("update-{channel}.lock")))?;
+    match flock(&file, libc::LOCK_EX | libc::LOCK_NB) {
+        Ok(()) => return Ok(RunLock::new(file, channel)),
+        Err(e) if e.raw_os_error() == Some(libc::EWOULDBLOCK) => {}
+        Err(e) => return Err(e.into()),
+    }
+    if !wait {
+        return Err(format!(
+            "another discord_update is already updating Discord {channel}, pass --wait to wait \
+             for it to finish"
+        )
+        .into());
+    }
+    prog.println(format!(
+        "Waiting for another discord_update to finish updating Discord {channel}"
+    ))?;
+    let file = tokio::task::spawn_blocking(move || {
+        flock(&file, libc::LOCK_EX)?;
+        io::Result::Ok(file)
+    })
+    .await??;
+    Ok(RunLock::new(file, channel))
+}
@@ -0,0 +1,112 @@
+//! Which version the channel's running client is, and the `running` subcommand
+//!
+//! Discord's IPC handshake doesn't say, so it's read from the process: from build_info.json if
+//! the client still has it open, or else from next to the executable it was started from. An
+//! update replaces that executable, which the kernel then reports as deleted, so a client
+//! started before the update is found even once its version can't be read anymore.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{find_install, platform, Options, Result, UpdateCheck, VersionPayload, BUILD_INFO};
+use semver::Version;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// What's known about the running client
+pub enum Running {
+    Version(Version),
+    /// Started from a binary an update has since replaced, of a version that can't be read
+    Replaced,
+}
+
+/// How /proc shows a link to a file that's been deleted
+pub const DELETED: &str = " (deleted)";
+
+fn parse(build_info: &Path) -> Option<Version> {
+    let contents = fs::read_to_string(build_info).ok()?;
+    let payload: VersionPayload = serde_json::from_str(&contents).ok()?;
+    Some(payload.version)
+}
+
+/// The client's open build_info.json, readable through the fd even once it's deleted
+fn open_build_info(proc: &Path) -> Option<PathBuf> {
+    fs::read_dir(proc.join("fd")).ok()?.find_map(|entry| {
+        let fd = entry.ok()?.path();
+        let target = fs::read_link(&fd).ok()?;
+        let target = target.to_string_lossy();
+        target
+            .strip_suffix(DELETED)
+            .unwrap_or(&target)
+            .ends_with(BUILD_INFO)
+            .then_some(fd)
+    })
+}
+
+/// The version of the channel's running client, when it's running where we can see it
+pub fn version(channel: Channel) -> Option<Running> {
+    let pid = platform::current().running_pid(channel)?;
+    let proc = Path::new("/proc").join(pid.to_string());
+    if let Some(version) = open_build_info(&proc).and_then(|fd| parse(&fd)) {
+        return Some(Running::Version(version));
+    }
+    let exe = fs::read_link(proc.join("exe")).ok()?;
+    if exe.to_string_lossy().ends_with(DELETED) {
+        return Some(Running::Replaced);
+    }
+    parse(&exe.parent()?.join(BUILD_INFO)).map(Running::Version)
+}
+
+/// Say so if the running client isn't the installed version, so needs restarting
+pub fn warn_if_stale(prog: &Progress, check: &UpdateCheck) -> Result<()> {
+    let (channel, Some(installed)) = (check.channel, &check.installed_version) else {
+        return Ok(());
+    };
+    match version(channel) {
+        Some(Running::Version(running)) if running != *installed => {
+            prog.println(format!(
+                "Discord {channel} {running} is still running, but {installed} is installed, \
+                 restart Discord to use it"
+            ))?;
+        }
+        Some(Running::Replaced) => {
+            prog.println(format!(
+                "Discord {channel} is still running from before {installed} was installed, \
+                 restart Discord to use it"
+            ))?;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// `running`, print the version of the channel's running client
+pub async fn run(prog: &Progress, options: &Options) -> Result<()> {
+    let channel = options.channel;
+    let install_path = find_install(prog, options).await?;
+    let installed = platform::current()
+        .installed_version(&install_path)
+        .await
+        .ok();
+    let message = match version(channel) {
+        Some(Running::Version(running)) => match &installed {
+            Some(installed) if *installed != running => format!(
+                "Discord {channel} {running} is running, restart it to use {installed}, which \
+                 is installed"
+            ),
+            _ => format!("Discord {channel} {running} is running"),
+        },
+        Some(Running::Replaced) => {
+            let installed = installed.map_or("the install".to_owned(), |v| v.to_string());
+            format!(
+                "Discord {channel} is running from before an update, restart it to use \
+                 {installed}"
+            )
+        }
+        None if platform::current().is_running(channel) => {
+            format!("Discord {channel} is running, but where its version can't be read")
+        }
+        None => format!("Discord {channel} isn't running"),
+    };
+    prog.result(message)?;
+    Ok(())
+}
@@ -0,0 +1,217 @@
+//! Confining the thread that unpacks downloaded archives
+//!
+//! The archive comes off the network, so if the gzip or tar code ever mishandles one it should
+//! be stuck inside the staging directory. Landlock limits the thread's filesystem access to
+//! that directory and a seccomp filter stops it starting programs or opening connections. Both
+//! only apply to the calling thread, and are skipped on kernels without them, saying so.
+
+use std::io;
+use std::path::Path;
+
+/// Every filesystem access right in the first Landlock ABI, from EXECUTE to MAKE_SYM
+const LANDLOCK_ACCESS_FS_ALL: u64 = (1 << 13) - 1;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+/// Syscalls extraction has no business making
+const DENIED: &[libc::c_long] = &[
+    libc::SYS_execve,
+    libc::SYS_execveat,
+    libc::SYS_socket,
+    libc::SYS_connect,
+    libc::SYS_ptrace,
+    libc::SYS_process_vm_writev,
+];
+
+/// Set in the numbers of x32 syscalls, which share x86_64's `AUDIT_ARCH`
+const X32_SYSCALL_BIT: u32 = 0x4000_0000;
+
+/// `AUDIT_ARCH_*` for the filter to check, syscall numbers mean nothing without it
+#[cfg(target_arch = "x86_64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_003e);
+#[cfg(target_arch = "aarch64")]
+const AUDIT_ARCH: Option<u32> = Some(0xc000_00b7);
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+const AUDIT_ARCH: Option<u32> = None;
+
+/// Whether an error means the kernel doesn't have the feature, rather than that it failed
+fn unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::ENOSYS | libc::EOPNOTSUPP | libc::EINVAL)
+    )
+}
+
+fn check(res: libc::c_long) -> io::Result<libc::c_long> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
+/// Only allow the thread to touch files under `dir`
+fn landlock(dir: &Path) -> io::Result<()> {
+    let attr = RulesetAttr {
+        handled_access_fs: LANDLOCK_ACCESS_FS_ALL,
+    };
+    // SAFETY: attr is a valid ruleset_attr of the given size
+    let ruleset = check(unsafe {
+        libc::syscall(
+            libc::SYS_landlock_create_ruleset,
+            &attr,
+            std::mem::size_of::<RulesetAttr>(),
+            0,
+        )
+    })? as libc::c_int;
+    let result = (|| {
+        let dir = std::fs::File::open(dir)?;
+        let rule = PathBeneathAttr {
+            allowed_access: LANDLOCK_ACCESS_FS_ALL,
+            parent_fd: std::os::fd::AsRawFd::as_raw_fd(&dir),
+        };
+        // SAFETY: rule is a valid path_beneath_attr
+        check(unsafe {
+            libc::syscall(
+                libc::SYS_landlock_add_rule,
+                ruleset,
+                LANDLOCK_RULE_PATH_BENEATH,
+                &rule,
+                0,
+            )
+        })?;
+        // SAFETY: ruleset is a ruleset fd we own
+        check(unsafe { libc::syscall(libc::SYS_landlock_restrict_self, ruleset, 0) })?;
+        Ok(())
+    })();
+    // SAFETY: ruleset is a fd we own and don't use again
+    unsafe { libc::close(ruleset) };
+    result
+}
+
+/// Make the syscalls in `DENIED` fail with EPERM for this thread
+fn seccomp(arch: u32) -> io::Result<()> {
+    let stmt = |code: u32, k: u32| libc::sock_filter {
+        code: code as u16,
+        jt: 0,
+        jf: 0,
+        k,
+    };
+    let jump = |jt: u8, jf: u8, k: u32| libc::sock_filter {
+        code: (libc::BPF_JMP | libc::BPF_JEQ | libc::BPF_K) as u16,
+        jt,
+        jf,
+        k,
+    };
+    let load = |offset: usize| stmt(libc::BPF_LD | libc::BPF_W | libc::BPF_ABS, offset as u32);
+    let ret = |k: u32| stmt(libc::BPF_RET | libc::BPF_K, k);
+    let deny = libc::SECCOMP_RET_ERRNO | libc::EPERM as u32;
+
+    let mut filter = vec![
+        load(std::mem::offset_of!(libc::seccomp_data, arch)),
+        // Another architecture's syscall numbers, which we can't judge
+        jump(1, 0, arch),
+        ret(deny),
+        load(std::mem::offset_of!(libc::seccomp_data, nr)),
+        // The x32 ABI's, like x32 execve, which the list below wouldn't catch
+        libc::sock_filter {
+            code: (libc::BPF_JMP | libc::BPF_JGE | libc::BPF_K) as u16,
+            jt: DENIED.len() as u8 + 1,
+            jf: 0,
+            k: X32_SYSCALL_BIT,
+        },
+    ];
+    for (i, &nr) in DENIED.iter().enumerate() {
+        // Jump to the deny after the allow at the end
+        let to_deny = (DENIED.len() - i) as u8;
+        filter.push(jump(to_deny, 0, nr as u32));
+    }
+    filter.push(ret(libc::SECCOMP_RET_ALLOW));
+    filter.push(ret(deny));
+    let program = libc::sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+    // SAFETY: program points at a valid filter that outlives the call
+    check(unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            libc::SECCOMP_SET_MODE_FILTER,
+            0,
+            &program,
+        )
+    })?;
+    Ok(())
+}
+
+/// Confine the calling thread to files under `dir`, as far as the kernel allows, returning
+/// what it doesn't support
+///
+/// This can't be undone, so only call it on a thread that exits afterwards.
+pub fn confine(dir: &Path) -> io::Result<Vec<&'static str>> {
+    // Needed to filter syscalls or restrict ourselves without privileges
+    // SAFETY: prctl with PR_SET_NO_NEW_PRIVS has no memory safety requirements
+    if unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut missing = vec![];
+    match landlock(dir) {
+        Err(e) if !unsupported(&e) => return Err(e),
+        Err(_) => missing.push("Landlock"),
+        Ok(()) => {}
+    }
+    match AUDIT_ARCH.map(seccomp) {
+        Some(Err(e)) if !unsupported(&e) => return Err(e),
+        Some(Err(_)) | None => missing.push("seccomp"),
+        Some(Ok(())) => {}
+    }
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Run `f` on a thread confined to a temporary directory
+    fn confined<T: Send + 'static>(f: impl FnOnce(&Path) -> T + Send + 'static) -> Option<T> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_owned();
+        std::thread::spawn(move || {
+            let missing = confine(&path).unwrap();
+            (!missing.contains(&"seccomp")).then(|| f(&path))
+        })
+        .join()
+        .unwrap()
+    }
+
+    #[test]
+    fn denies_starting_programs() {
+        let Some(result) = confined(|_| std::process::Command::new("/bin/true").status()) else {
+            return;
+        };
+        assert_eq!(result.unwrap_err().raw_os_error(), Some(libc::EPERM));
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn denies_x32_syscalls() {
+        let nr = (X32_SYSCALL_BIT as libc::c_long) | libc::SYS_getpid;
+        // SAFETY: getpid has no arguments or side effects
+        let errno =
+            confined(move |_| unsafe { libc::syscall(nr) < 0 }.then(io::Error::last_os_error));
+        let Some(errno) = errno else {
+            return;
+        };
+        assert_eq!(errno.unwrap().raw_os_error(), Some(libc::EPERM));
+    }
+}
@@ -0,0 +1,84 @@
+//! The shape of our machine-readable output, for `--schema`
+//!
+//! `--output json` reports and notification events both carry `schema_version`. It goes up
+//! whenever a field is removed or changes meaning, adding fields doesn't bump it.
+
+use serde_json::{json, Value};
+
+/// The current `schema_version`
+pub const VERSION: u32 = 1;
+
+/// JSON Schemas for the `--output json` report and for notification events
+pub fn describe() -> Value {
+    let version = json!({"type": ["string", "null"], "description": "A semver version"});
+    let channel = json!({"enum": ["stable", "ptb", "canary"]});
+    let schema_version = json!({"const": VERSION});
+    let plan = json!({
+        "type": "object",
+        "description": "What would have been installed, only with --dry-run",
+        "required": ["channel", "version", "steps"],
+        "properties": {
+            "channel": channel,
+            "version": {"type": "string", "description": "A semver version"},
+            "steps": {
+                "type": "array",
+                "description": "In the order they'd be taken",
+                "items": {
+                    "type": "object",
+                    "required": ["action"],
+                    "properties": {"action": {"enum": [
+                        "close-discord", "block-launches", "snapshot",
+                        "download-unprivileged", "download", "wait-for-exit",
+                        "install", "health-check", "keep-in-store", "desktop",
+                        "autostart", "launcher", "symlink", "apparmor"
+                    ]}}
+                }
+            }
+        }
+    });
+    json!({
+        "schema_version": VERSION,
+        "report": {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "description": "One line per channel run with --output json",
+            "type": "object",
+            "required": [
+                "schema_version", "channel", "install_path", "installed_version",
+                "latest_version", "symlink", "system", "damaged", "update_available", "updated"
+            ],
+            "properties": {
+                "schema_version": schema_version,
+                "channel": channel,
+                "install_path": {"type": "string"},
+                "installed_version": version,
+                "latest_version": {"type": "string", "description": "A semver version"},
+                "release_notes": {"type": ["string", "null"]},
+                "symlink": {"type": "string", "description": "The launcher symlink"},
+                "system": {"type": "boolean", "description": "A root-owned --system install"},
+                "damaged": {"type": "boolean", "description": "The install was missing files"},
+                "update_available": {"type": "boolean"},
+                "updated": {"type": "boolean", "description": "Something was installed"},
+                "plan": plan
+            }
+        },
+        "event": {
+            "$schema": "https://json-schema.org/draft/2020-12/schema",
+            "description": "Sent to --notify webhook (as json), command, and log",
+            "type": "object",
+            "required": [
+                "schema_version", "event", "channel", "installed_version", "latest_version",
+                "error", "message"
+            ],
+            "properties": {
+                "schema_version": schema_version,
+                "time": {"type": "integer", "description": "Unix time, only in the log"},
+                "event": {"enum": ["update_available", "updated", "failed"]},
+                "channel": channel,
+                "installed_version": version,
+                "latest_version": version,
+                "error": {"type": ["string", "null"]},
+                "message": {"type": "string"}
+            }
+        }
+    })
+}
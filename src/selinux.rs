@@ -0,0 +1,78 @@
+//! SELinux contexts for installed files, on systems like Fedora that enforce it
+//!
+//! Files renamed or copied into place keep the context of wherever they were staged, which the
+//! policy may not let anyone execute. After each install the contexts are reset to what the
+//! policy says with `restorecon`, and when a new version doesn't start, the context it has is
+//! pointed out, as an AVC denial only shows up in the audit log.
+
+use std::ffi::CString;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const SELINUXFS: &str = "/sys/fs/selinux";
+
+/// Whether SELinux is on at all, enforcing or not
+fn enabled() -> bool {
+    Path::new(SELINUXFS).join("enforce").exists()
+}
+
+fn enforcing() -> bool {
+    std::fs::read_to_string(Path::new(SELINUXFS).join("enforce"))
+        .is_ok_and(|enforce| enforce.trim() == "1")
+}
+
+/// The context of `path`, like `unconfined_u:object_r:user_home_t:s0`
+fn context(path: &Path) -> Option<String> {
+    let path = CString::new(path.as_os_str().as_encoded_bytes()).ok()?;
+    let mut buf = vec![0u8; 256];
+    // SAFETY: path and the attribute name are valid C strings, and buf is as long as we say
+    let len = unsafe {
+        libc::lgetxattr(
+            path.as_ptr(),
+            c"security.selinux".as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        )
+    };
+    let len = usize::try_from(len).ok()?;
+    buf.truncate(len);
+    let context = String::from_utf8(buf).ok()?;
+    Some(context.trim_end_matches('\0').to_owned())
+}
+
+/// Set the contexts of everything under `path` to what the policy says they should be
+///
+/// Does nothing without SELinux. The error says what went wrong, for a warning, as the install
+/// may well work regardless.
+pub fn restore(path: &Path) -> Result<(), String> {
+    if !enabled() {
+        return Ok(());
+    }
+    let output = Command::new("restorecon")
+        .arg("-R")
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .output()
+        .map_err(|e| format!("failed to run restorecon: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "restorecon failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    Ok(())
+}
+
+/// What to tell someone whose Discord at `binary` won't start, when SELinux might be why
+pub fn hint(binary: &Path) -> Option<String> {
+    if !enforcing() {
+        return None;
+    }
+    let context = context(binary).unwrap_or_else(|| "no context".to_owned());
+    Some(format!(
+        "SELinux is enforcing and {} has {context}, `ausearch -m avc -ts recent` shows whether \
+         it was denied, `restorecon -R` on the install resets its contexts",
+        binary.display()
+    ))
+}
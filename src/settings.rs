@@ -0,0 +1,133 @@
+//! The `settings` subcommand, for editing Discord's settings.json
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{platform, Result};
+use serde_json::{Map, Value};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// Friendly names for the toggles people most often want, and the keys Discord uses for them
+const KNOWN: &[(&str, &str, &str)] = &[
+    (
+        "hardware-acceleration",
+        "enableHardwareAcceleration",
+        "Use the GPU to render, turning it off fixes many display glitches",
+    ),
+    (
+        "minimize-to-tray",
+        "MINIMIZE_TO_TRAY",
+        "Closing the window keeps Discord running in the tray",
+    ),
+    (
+        "skip-host-update",
+        "SKIP_HOST_UPDATE",
+        "Don't ask to download updates on startup, leaving that to discord_update",
+    ),
+    (
+        "open-on-startup",
+        "OPEN_ON_STARTUP",
+        "Start Discord when logging in",
+    ),
+    (
+        "start-minimized",
+        "START_MINIMIZED",
+        "Start in the tray rather than with a window",
+    ),
+];
+
+fn settings_file(channel: Channel) -> Result<PathBuf> {
+    Ok(channel.config_dir()?.join("settings.json"))
+}
+
+/// The key in settings.json for a friendly name or a raw key, and whether it must be a bool
+fn resolve(key: &str) -> (&str, bool) {
+    match KNOWN.iter().find(|(name, _, _)| *name == key) {
+        Some((_, key, _)) => (key, true),
+        None => (key, false),
+    }
+}
+
+fn load(channel: Channel) -> Result<Map<String, Value>> {
+    let path = settings_file(channel)?;
+    match fs::read_to_string(&path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .map_err(|e| format!("can't parse {}: {e}", path.display()).into()),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Map::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Write the settings back in Discord's own layout, keeping the previous file as .bak
+fn save(prog: &Progress, channel: Channel, settings: &Map<String, Value>) -> Result<()> {
+    if platform::current().is_running(channel) {
+        prog.println(format!(
+            "Warning: Discord {channel} is running, and will overwrite this when it exits"
+        ))?;
+    }
+    let path = settings_file(channel)?;
+    let dir = path.parent().unwrap();
+    fs::create_dir_all(dir)?;
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    if path.exists() {
+        fs::copy(&path, &backup)?;
+    }
+    let temp = tempfile::NamedTempFile::new_in(dir)?;
+    serde_json::to_writer_pretty(temp.as_file(), settings)?;
+    temp.as_file().sync_all()?;
+    temp.persist(&path)?;
+    prog.println(format!(
+        "Updated {}, the old one is in {}. Restart Discord for it to take effect",
+        path.display(),
+        PathBuf::from(backup).display()
+    ))?;
+    Ok(())
+}
+
+/// Parse a value given on the command line, JSON if it is JSON and a string otherwise
+fn parse_value(value: &str, boolean: bool) -> Result<Value> {
+    let parsed = serde_json::from_str(value).unwrap_or_else(|_| Value::String(value.into()));
+    if boolean && !parsed.is_boolean() {
+        return Err(format!("expected true or false, got {value:?}").into());
+    }
+    Ok(parsed)
+}
+
+/// Run `settings [get|set|unset]`
+///
+/// Discord writes settings.json when it exits, so it should be closed for changes to stick.
+pub fn run(prog: &Progress, channel: Channel, action: Option<&str>, args: &[String]) -> Result<()> {
+    let mut settings = load(channel)?;
+    match action {
+        Some("get") => {
+            let (key, _) = resolve(&args[0]);
+            match settings.get(key) {
+                Some(value) => println!("{value}"),
+                None => return Err(format!("{key} isn't set").into()),
+            }
+        }
+        Some("set") => {
+            let (key, boolean) = resolve(&args[0]);
+            let value = parse_value(&args[1], boolean)?;
+            settings.insert(key.to_owned(), value);
+            save(prog, channel, &settings)?;
+        }
+        Some("unset") => {
+            let (key, _) = resolve(&args[0]);
+            if settings.shift_remove(key).is_none() {
+                prog.println(format!("{key} wasn't set"))?;
+                return Ok(());
+            }
+            save(prog, channel, &settings)?;
+        }
+        _ => {
+            for (name, key, help) in KNOWN {
+                let value = settings.get(*key).map_or("unset".into(), Value::to_string);
+                println!("{name:<24}{value:<8}{help}");
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,103 @@
+//! The first-run wizard, asking how Discord should be set up and saving the answers as the config
+//! file
+
+use crate::channel::Channel;
+use crate::cli::Matches;
+use crate::progress::Progress;
+use crate::{ask, choose, confirm, cron, platform, Result};
+use serde_json::{Map, Value};
+use std::io::IsTerminal as _;
+use std::path::Path;
+
+/// Options that say what to do, so there's nothing to ask
+const DECIDING: &[&str] = &[
+    "profile",
+    "install-path",
+    "ensure",
+    "system",
+    "all",
+    "print-latest-version",
+    "print-url",
+];
+
+/// Whether this is a first run worth asking about: a plain interactive update, with no config
+/// file and nothing installed yet
+pub async fn wanted(matches: &Matches, config_path: &Path) -> bool {
+    let interactive = !matches.flag("non-interactive")
+        && std::io::stdin().is_terminal()
+        && std::io::stderr().is_terminal();
+    if !interactive
+        || matches.subcommand(0).is_some()
+        || DECIDING.iter().any(|opt| matches.flag(opt))
+        || config_path.exists()
+    {
+        return false;
+    }
+    for channel in Channel::ALL {
+        if !platform::current()
+            .candidate_installs(channel)
+            .await
+            .is_empty()
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Ask the questions and write the config file
+///
+/// Returns the cron schedule to check for updates on, if they asked for one.
+pub fn run(prog: &Progress, matches: &Matches, config_path: &Path) -> Result<Option<String>> {
+    prog.println("No Discord install or config file found, let's set one up")?;
+    let mut config = Map::new();
+
+    let names: Vec<_> = Channel::ALL.iter().map(|c| c.title().to_owned()).collect();
+    let given = matches.value("channel").and_then(|c| c.parse().ok());
+    let channel = match given {
+        Some(channel) => channel,
+        None => Channel::ALL[choose(prog, "Which Discord?", &names)?],
+    };
+    config.insert("channel".into(), channel.name().into());
+
+    let platform = platform::current();
+    let default_path = platform.default_install_path(channel, false)?;
+    let install_path = ask(
+        prog,
+        "Where should it be installed?",
+        &default_path.to_string_lossy(),
+    )?;
+    if Path::new(&install_path) != default_path {
+        config.insert("install_path".into(), install_path.into());
+    }
+
+    let default_dir = platform.launcher_dir(false)?;
+    let symlink_dir = ask(
+        prog,
+        &format!(
+            "Which directory should the `{}` command go in?",
+            channel.command_name()
+        ),
+        &default_dir.to_string_lossy(),
+    )?;
+    if Path::new(&symlink_dir) != default_dir {
+        config.insert("symlink_dir".into(), symlink_dir.into());
+    }
+
+    if !confirm(prog, "Add it to the applications menu?", true)? {
+        config.insert("no_desktop_entry".into(), true.into());
+    }
+    let schedule = confirm(prog, "Check for updates automatically with cron?", false)?
+        .then(|| cron::DEFAULT_SCHEDULE.to_owned());
+
+    std::fs::create_dir_all(config_path.parent().unwrap())?;
+    std::fs::write(
+        config_path,
+        serde_json::to_string_pretty(&Value::Object(config))? + "\n",
+    )?;
+    prog.println(format!(
+        "Saved this to {}, edit it or see --help to change it later",
+        config_path.display()
+    ))?;
+    Ok(schedule)
+}
@@ -0,0 +1,247 @@
+//! Filesystem snapshots taken before updates, for rolling back on btrfs and ZFS
+//!
+//! With `--snapshot`, the btrfs subvolume or ZFS dataset holding the install is snapshotted
+//! before each update, and `rollback --snapshot` copies the install back out of it. Only the
+//! latest snapshot for each channel is kept.
+
+use crate::channel::Channel;
+use crate::install::{DirectoryTarget, InstallTarget};
+use crate::manifest::Manifest;
+use crate::progress::Progress;
+use crate::state::{self, State};
+use crate::{defer, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::ffi::CString;
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+
+const BTRFS_SUPER_MAGIC: i64 = 0x9123_683e;
+const ZFS_SUPER_MAGIC: i64 = 0x2fc1_2fc1;
+/// The inode number of the root directory of every btrfs subvolume
+const BTRFS_SUBVOLUME_INO: u64 = 256;
+
+/// A snapshot of the filesystem an install is on
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum Snapshot {
+    /// A read-only snapshot of `subvolume`, at `path`
+    Btrfs { subvolume: PathBuf, path: PathBuf },
+    /// `dataset@name`, with `dataset` mounted at `mountpoint`
+    Zfs {
+        dataset: String,
+        name: String,
+        mountpoint: PathBuf,
+    },
+}
+
+/// What's recorded about the latest snapshot of a channel
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Taken {
+    #[serde(flatten)]
+    pub snapshot: Snapshot,
+    pub install_path: PathBuf,
+    pub version: Version,
+    pub time: u64,
+}
+
+/// Run a command, failing with what it printed to stderr
+async fn run(command: &mut Command) -> Result<String> {
+    let program = command
+        .as_std()
+        .get_program()
+        .to_string_lossy()
+        .into_owned();
+    let output = command
+        .output()
+        .await
+        .map_err(|e| format!("failed to run {program}: {e}"))?;
+    if !output.status.success() {
+        return Err(format!(
+            "{program} failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        )
+        .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
+
+/// The `f_type` of the filesystem `path` is on
+fn filesystem_type(path: &Path) -> Option<i64> {
+    let path = CString::new(path.as_os_str().as_encoded_bytes()).ok()?;
+    // SAFETY: statfs is plain data, and path is a valid C string
+    unsafe {
+        let mut stat: libc::statfs = std::mem::zeroed();
+        if libc::statfs(path.as_ptr(), &mut stat) != 0 {
+            return None;
+        }
+        Some(stat.f_type as i64)
+    }
+}
+
+/// The root of the btrfs subvolume `path` is in
+fn btrfs_subvolume(path: &Path) -> Result<PathBuf> {
+    let dev = std::fs::metadata(path)?.dev();
+    for dir in path.ancestors() {
+        let meta = std::fs::metadata(dir)?;
+        if meta.dev() != dev {
+            break;
+        }
+        if meta.ino() == BTRFS_SUBVOLUME_INO {
+            return Ok(dir.to_owned());
+        }
+    }
+    Err(format!("couldn't find the btrfs subvolume {} is in", path.display()).into())
+}
+
+impl Snapshot {
+    /// Where the snapshot's copy of `path` is, `path` being somewhere in what was snapshotted
+    fn copy_of(&self, path: &Path) -> Result<PathBuf> {
+        let (root, snapshot) = match self {
+            Self::Btrfs { subvolume, path } => (subvolume, path.clone()),
+            Self::Zfs {
+                name, mountpoint, ..
+            } => (mountpoint, mountpoint.join(".zfs/snapshot").join(name)),
+        };
+        let relative = path
+            .strip_prefix(root)
+            .map_err(|_| format!("{} isn't in the snapshot", path.display()))?;
+        Ok(snapshot.join(relative))
+    }
+
+    async fn delete(&self) -> Result<()> {
+        match self {
+            Self::Btrfs { path, .. } => {
+                run(Command::new("btrfs")
+                    .args(["subvolume", "delete"])
+                    .arg(path))
+                .await?;
+            }
+            Self::Zfs { dataset, name, .. } => {
+                let snapshot = format!("{dataset}@{name}");
+                run(Command::new("zfs").args(["destroy", &snapshot])).await?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Snapshot the filesystem holding `install_path`, when it's btrfs or ZFS
+async fn create(channel: Channel, install_path: &Path, version: &Version) -> Result<Snapshot> {
+    let name = format!("discord_update-{channel}-{version}-{}", state::now());
+    match filesystem_type(install_path) {
+        Some(BTRFS_SUPER_MAGIC) => {
+            let subvolume = btrfs_subvolume(install_path)?;
+            let dir = subvolume.join(".discord_update-snapshots");
+            tokio::fs::create_dir_all(&dir).await?;
+            let path = dir.join(name);
+            run(Command::new("btrfs")
+                .args(["subvolume", "snapshot", "-r"])
+                .arg(&subvolume)
+                .arg(&path))
+            .await?;
+            Ok(Snapshot::Btrfs { subvolume, path })
+        }
+        Some(ZFS_SUPER_MAGIC) => {
+            let listed = run(Command::new("zfs")
+                .args(["list", "-H", "-o", "name,mountpoint"])
+                .arg(install_path))
+            .await?;
+            let (dataset, mountpoint) = listed
+                .trim()
+                .split_once('\t')
+                .ok_or_else(|| format!("unexpected zfs list output {listed:?}"))?;
+            let snapshot = format!("{dataset}@{name}");
+            run(Command::new("zfs").args(["snapshot", &snapshot])).await?;
+            Ok(Snapshot::Zfs {
+                dataset: dataset.to_owned(),
+                name,
+                mountpoint: mountpoint.into(),
+            })
+        }
+        _ => Err(format!("{} isn't on btrfs or ZFS", install_path.display()).into()),
+    }
+}
+
+/// Snapshot the install before it's updated, replacing the channel's last snapshot
+///
+/// Failing to only gets a warning, the update goes ahead either way.
+pub async fn before_update(
+    prog: &Progress,
+    channel: Channel,
+    install_path: &Path,
+    version: &Version,
+) -> Result<()> {
+    // Taken already by an attempt at this update that failed
+    let latest = State::load()?
+        .channels
+        .get(&channel)
+        .and_then(|c| c.snapshot.clone());
+    if let Some(taken) = latest.filter(|t| t.install_path == install_path && t.version == *version)
+    {
+        if taken.snapshot.copy_of(install_path)?.exists() {
+            prog.println(format!("Discord {version} was snapshotted already"))?;
+            return Ok(());
+        }
+    }
+    let snapshot = match create(channel, install_path, version).await {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            prog.println(format!("Not snapshotting Discord {version}: {e}"))?;
+            return Ok(());
+        }
+    };
+    prog.println(format!("Snapshotted Discord {version} before updating"))?;
+    let taken = Taken {
+        snapshot,
+        install_path: install_path.to_owned(),
+        version: version.clone(),
+        time: state::now(),
+    };
+    let mut old = None;
+    State::update(|state| old = state.channel(channel).snapshot.replace(taken))?;
+    if let Some(old) = old {
+        if let Err(e) = old.snapshot.delete().await {
+            prog.println(format!("Couldn't delete the previous snapshot: {e}"))?;
+        }
+    }
+    Ok(())
+}
+
+/// `rollback --snapshot`, put back the install from before the last update
+pub async fn rollback(prog: &Progress, channel: Channel) -> Result<()> {
+    let taken = State::load()?
+        .channels
+        .get(&channel)
+        .and_then(|c| c.snapshot.clone())
+        .ok_or_else(|| format!("there's no snapshot of Discord {channel}, see --snapshot"))?;
+    let copy = taken.snapshot.copy_of(&taken.install_path)?;
+    let target: Box<dyn InstallTarget> = Box::new(DirectoryTarget::new(&taken.install_path)?);
+    // Reflinks make this nearly free where the filesystem can share the blocks
+    let mut source = copy.clone().into_os_string();
+    source.push("/.");
+    run(Command::new("cp")
+        .args(["-a", "--reflink=auto"])
+        .arg(source)
+        .arg(target.staged()))
+    .await?;
+    let manifest = match Manifest::load(&copy)? {
+        Some(manifest) => manifest,
+        None => {
+            let (staged, version) = (target.staged().to_owned(), taken.version.clone());
+            tokio::task::spawn_blocking(move || Manifest::generate(&staged, version)).await??
+        }
+    };
+    tokio::task::spawn_blocking(move || {
+        let previous = target.commit(&manifest)?;
+        previous.map_or(Ok(()), |previous| previous.discard())
+    })
+    .await??;
+    prog.println(format!(
+        "Rolled Discord {channel} back to {} from the snapshot taken {}",
+        taken.version,
+        defer::format_time(taken.time)
+    ))?;
+    Ok(())
+}
@@ -0,0 +1,59 @@
+//! Builds from somewhere other than Discord, for architectures it doesn't make them for
+//!
+//! Nothing from these is installed without an Ed25519 signature from the configured key, over
+//! the SHA-256 digest of the tarball, in hex at `<url>.sig`.
+
+use crate::channel::{self, Channel};
+use crate::Result;
+use ring::signature::{UnparsedPublicKey, ED25519};
+use semver::Version;
+
+/// Where to download builds from instead of Discord's CDN
+#[derive(Clone, Debug)]
+pub struct BuildSource {
+    /// The tarball URL, with `{channel}`, `{version}`, and `{arch}` to fill in
+    pub url_template: String,
+    /// Hex Ed25519 public key the builds must be signed with
+    pub key: String,
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    let hex = hex.trim();
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+impl BuildSource {
+    pub fn new(url_template: &str, key: Option<&str>) -> Result<Self> {
+        let key = key.ok_or("--source-url needs --source-key, unsigned builds aren't installed")?;
+        channel::check_template("source-url", url_template, channel::DOWNLOAD_PLACEHOLDERS)?;
+        if decode_hex(key).is_none_or(|key| key.len() != 32) {
+            return Err(
+                format!("--source-key should be a hex Ed25519 public key, got {key:?}").into(),
+            );
+        }
+        Ok(Self {
+            url_template: url_template.to_owned(),
+            key: key.trim().to_owned(),
+        })
+    }
+
+    /// Where the tarball for the given version can be downloaded from
+    pub fn url(&self, channel: Channel, version: &Version) -> String {
+        channel.download_url(Some(&self.url_template), version)
+    }
+
+    /// Check the hex `signature` of a tarball with the given SHA-256 digest
+    pub fn verify(&self, sha256: &[u8], signature: &str) -> Result<()> {
+        let signature = decode_hex(signature).ok_or("the signature isn't hex")?;
+        let key = decode_hex(&self.key).unwrap();
+        UnparsedPublicKey::new(&ED25519, key)
+            .verify(sha256, &signature)
+            .map_err(|_| format!("the signature doesn't match key {}", self.key).into())
+    }
+}
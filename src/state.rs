@@ -0,0 +1,139 @@
+//! State persisted between runs
+
+use crate::channel::Channel;
+use crate::{home_dir, run_lock, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// What we remember about one channel
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChannelState {
+    /// When we last successfully asked the API for the latest version
+    pub last_check: Option<u64>,
+    /// Checks that have failed in a row
+    pub failed_checks: u32,
+    /// Scheduled runs don't check again before this, after failed checks
+    pub backoff_until: Option<u64>,
+    /// When we last installed an update
+    pub last_update: Option<u64>,
+    /// The launcher symlink we last pointed at this channel's install
+    pub symlink: Option<PathBuf>,
+    /// `--skip-version`, the release not to offer, until a newer one comes out
+    pub skipped_version: Option<Version>,
+    /// `defer`, automatic runs leave the install alone until then
+    pub deferred_until: Option<u64>,
+    /// `pin --max`, the newest version updates may install
+    pub max_version: Option<Version>,
+    /// The latest `--snapshot`, taken before the last update
+    pub snapshot: Option<crate::snapshot::Taken>,
+    /// How `install-launcher` made the launcher, to make it again after updates
+    pub launcher: Option<crate::launcher::Settings>,
+    /// `integrate-user`, the system install this user launches but leaves updating to root
+    pub shared_install: Option<PathBuf>,
+    /// The update under way, left behind when one is interrupted
+    pub transaction: Option<crate::transaction::Transaction>,
+    /// What the update under way redirected to the "updating" script, to put back
+    pub launch_block: Vec<crate::launch_block::Restore>,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub channels: BTreeMap<Channel, ChannelState>,
+}
+
+/// Seconds since the unix epoch
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+/// `$XDG_STATE_HOME/discord_update`, usually `~/.local/state/discord_update`
+pub fn state_dir() -> Result<PathBuf> {
+    let state_home = match std::env::var_os("XDG_STATE_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => home_dir()?.join(".local/state"),
+    };
+    Ok(state_home.join("discord_update"))
+}
+
+impl State {
+    fn path() -> Result<PathBuf> {
+        Ok(state_dir()?.join("state.json"))
+    }
+
+    /// Load the saved state, an absent file is an empty state
+    pub fn load() -> Result<Self> {
+        match std::fs::read_to_string(Self::path()?) {
+            Ok(contents) => Ok(serde_json::from_str(&contents)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the state out, atomically replacing the old one
+    fn save(&self) -> Result<()> {
+        let path = Self::path()?;
+        let dir = path.parent().unwrap();
+        std::fs::create_dir_all(dir)?;
+        let temp = tempfile::NamedTempFile::new_in(dir)?;
+        serde_json::to_writer_pretty(temp.as_file(), self)?;
+        temp.as_file().sync_all()?;
+        temp.persist(&path)?;
+        Ok(())
+    }
+
+    /// Load the state, change it, and save it again
+    ///
+    /// Loading afresh means changes saved elsewhere during a run aren't overwritten. The whole
+    /// thing holds a lock, so two runs changing different channels at once both keep theirs.
+    pub fn update(f: impl FnOnce(&mut Self)) -> Result<()> {
+        let dir = state_dir()?;
+        std::fs::create_dir_all(&dir)?;
+        let lock = std::fs::File::create(dir.join("state.json.lock"))?;
+        run_lock::flock(&lock, libc::LOCK_EX)?;
+        let mut state = Self::load()?;
+        f(&mut state);
+        state.save()
+    }
+
+    pub fn channel(&mut self, channel: Channel) -> &mut ChannelState {
+        self.channels.entry(channel).or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn updates_at_the_same_time_all_land() {
+        let dir = tempfile::tempdir().unwrap();
+        // No other unit test uses the state
+        std::env::set_var("XDG_STATE_HOME", dir.path());
+        let threads: Vec<_> = Channel::ALL
+            .into_iter()
+            .map(|channel| {
+                std::thread::spawn(move || {
+                    for _ in 0..50 {
+                        State::update(|state| state.channel(channel).failed_checks += 1).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        let state = State::load().unwrap();
+        std::env::remove_var("XDG_STATE_HOME");
+        for channel in state.channels.values() {
+            assert_eq!(channel.failed_checks, 50);
+        }
+        assert_eq!(state.channels.len(), 3);
+    }
+}
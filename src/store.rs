@@ -0,0 +1,215 @@
+//! A content-addressed store of installed versions, for reinstalling them without a download
+//!
+//! With `--store-versions N`, every file of the last N versions installed is kept under
+//! `$XDG_CACHE_HOME/discord_update/store`, in `objects/` by hash and mode, with each version's
+//! manifest in `trees/`. Objects are hard links to the install's files where they share a
+//! filesystem, so the current version costs nothing extra and older ones only what changed.
+//! Installing a stored version again, to repair or go back to it, puts it together from the
+//! objects instead of downloading it.
+
+use crate::channel::Channel;
+use crate::config::cache_home;
+use crate::manifest::{EntryKind, Manifest};
+use semver::Version;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::os::unix::fs::{symlink, MetadataExt as _, PermissionsExt as _};
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Debug)]
+pub struct Store {
+    root: PathBuf,
+    /// How many versions of each channel to keep
+    keep: usize,
+}
+
+/// Hard link `from` to `to`, or copy it when they're on different filesystems
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    match fs::hard_link(from, to) {
+        Err(e) if e.raw_os_error() == Some(libc::EXDEV) => {
+            fs::copy(from, to)?;
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+/// Make `file` a hard link to `object`, which has the same contents, so the install's copy
+/// doesn't take space of its own
+fn share(object: &Path, file: &Path) -> io::Result<()> {
+    let (object_meta, file_meta) = (fs::metadata(object)?, fs::symlink_metadata(file)?);
+    // On another filesystem there's nothing to share
+    if object_meta.dev() != file_meta.dev() || object_meta.ino() == file_meta.ino() {
+        return Ok(());
+    }
+    let temp = tempfile::Builder::new()
+        .make_in(file.parent().unwrap(), |path| fs::hard_link(object, path))?;
+    temp.persist(file)?;
+    Ok(())
+}
+
+impl Store {
+    pub fn new(keep: usize) -> crate::Result<Self> {
+        Ok(Self {
+            root: cache_home()?.join("discord_update/store"),
+            keep,
+        })
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Files with the same contents but different modes can't share an inode
+    fn object(&self, sha256: &str, mode: u32) -> PathBuf {
+        self.root
+            .join("objects")
+            .join(&sha256[..2])
+            .join(format!("{}-{mode:o}", &sha256[2..]))
+    }
+
+    fn tree(&self, channel: Channel, version: &Version) -> PathBuf {
+        self.root.join(format!("trees/{channel}-{version}.json"))
+    }
+
+    /// Add the install at `install_path`, described by `manifest`, then forget the oldest
+    /// versions beyond what's kept
+    pub fn add(
+        &self,
+        channel: Channel,
+        install_path: &Path,
+        manifest: &Manifest,
+    ) -> io::Result<()> {
+        for entry in &manifest.entries {
+            let EntryKind::File { sha256, .. } = &entry.kind else {
+                continue;
+            };
+            let object = self.object(sha256, entry.mode);
+            if object.exists() {
+                share(&object, &install_path.join(&entry.path))?;
+                continue;
+            }
+            let dir = object.parent().unwrap();
+            fs::create_dir_all(dir)?;
+            // Linked under a temporary name first, so a half copied object is never found
+            let temp = tempfile::Builder::new().make_in(dir, |path| {
+                link_or_copy(&install_path.join(&entry.path), path)
+            })?;
+            temp.persist(&object)?;
+        }
+        let tree = self.tree(channel, &manifest.version);
+        fs::create_dir_all(tree.parent().unwrap())?;
+        // Rewritten every time, its mtime says when the version was last installed
+        fs::write(&tree, serde_json::to_vec(manifest)?)?;
+        self.prune(channel)
+    }
+
+    /// Put together the stored copy of a version in `dest`, if there is a whole, undamaged one
+    ///
+    /// Only a copy extracted with the same `--exclude` patterns is used, anything else means
+    /// downloading it again. A damaged copy is forgotten and `dest` left empty.
+    pub fn restore(
+        &self,
+        channel: Channel,
+        version: &Version,
+        excluded: &[String],
+        dest: &Path,
+    ) -> io::Result<Option<Manifest>> {
+        let Ok(tree) = fs::read(self.tree(channel, version)) else {
+            return Ok(None);
+        };
+        let tree: Manifest = serde_json::from_slice(&tree)?;
+        let complete = tree.entries.iter().all(|entry| match &entry.kind {
+            EntryKind::File { sha256, .. } => self.object(sha256, entry.mode).exists(),
+            _ => true,
+        });
+        if tree.excluded != excluded || !complete {
+            return Ok(None);
+        }
+        let mut directories = vec![];
+        for entry in &tree.entries {
+            let path = dest.join(&entry.path);
+            match &entry.kind {
+                EntryKind::Directory => {
+                    fs::create_dir(&path)?;
+                    directories.push((path, entry.mode));
+                }
+                EntryKind::Symlink { target } => symlink(target, &path)?,
+                EntryKind::File { sha256, .. } => {
+                    link_or_copy(&self.object(sha256, entry.mode), &path)?;
+                    fs::set_permissions(&path, fs::Permissions::from_mode(entry.mode))?;
+                }
+            }
+        }
+        // Last, and deepest first, so read-only directories don't get in the way
+        for (path, mode) in directories.into_iter().rev() {
+            fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        }
+        // Objects share inodes with installs, so damage to one of those reaches the store too
+        let found = Manifest::generate(dest, version.clone())?;
+        let damaged: HashSet<_> = tree
+            .diff(&found)
+            .into_iter()
+            .map(|(_, path)| path)
+            .collect();
+        if !damaged.is_empty() {
+            for entry in tree
+                .entries
+                .iter()
+                .filter(|e| damaged.contains(e.path.as_path()))
+            {
+                if let EntryKind::File { sha256, .. } = &entry.kind {
+                    fs::remove_file(self.object(sha256, entry.mode))?;
+                }
+            }
+            fs::remove_file(self.tree(channel, version))?;
+            fs::remove_dir_all(dest)?;
+            fs::create_dir(dest)?;
+            return Ok(None);
+        }
+        Ok(Some(tree))
+    }
+
+    /// Forget all but the `keep` most recently installed versions of the channel, and delete
+    /// the objects no version uses any more
+    fn prune(&self, channel: Channel) -> io::Result<()> {
+        let prefix = format!("{channel}-");
+        let mut trees = vec![];
+        let mut wanted = HashSet::new();
+        for entry in fs::read_dir(self.root.join("trees"))? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(&prefix) {
+                trees.push((entry.metadata()?.modified()?, entry.path()));
+            } else {
+                wanted.insert(entry.path());
+            }
+        }
+        trees.sort();
+        let keep_from = trees.len().saturating_sub(self.keep);
+        for (_, tree) in trees.drain(..keep_from) {
+            fs::remove_file(tree)?;
+        }
+        wanted.extend(trees.into_iter().map(|(_, tree)| tree));
+
+        let mut used = HashSet::new();
+        for tree in wanted {
+            let tree: Manifest = serde_json::from_slice(&fs::read(tree)?)?;
+            for entry in tree.entries {
+                if let EntryKind::File { sha256, .. } = &entry.kind {
+                    used.insert(self.object(sha256, entry.mode));
+                }
+            }
+        }
+        for dir in fs::read_dir(self.root.join("objects"))? {
+            for object in fs::read_dir(dir?.path())? {
+                let object = object?.path();
+                if !used.contains(&object) {
+                    fs::remove_file(object)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,79 @@
+//! The `switch-channel` subcommand
+
+use crate::channel::Channel;
+use crate::cli::Matches;
+use crate::progress::Progress;
+use crate::user_data::copy_user_data;
+use crate::{
+    apply_update, lock_and_check, print_release_notes, profile, symlink, Options, Result,
+    UpdateCheck,
+};
+
+/// Parse `<CHANNEL>` or `<FROM>→<TO>` (`:` works too), `--from` fills in a missing source
+fn parse_channels(options: &Options, matches: &Matches) -> Result<(Channel, Channel)> {
+    let arg = &matches.positionals[0];
+    let (from, to) = match arg.split_once(['→', ':']) {
+        Some((from, to)) => (Some(from.parse()?), to.parse()?),
+        None => (None, arg.parse()?),
+    };
+    let from = match from {
+        Some(from) => from,
+        None => matches.parse_value("from")?.unwrap_or(options.channel),
+    };
+    if from == to {
+        return Err(format!("already on the {to} channel").into());
+    }
+    Ok((from, to))
+}
+
+/// Install the target channel (if needed), optionally bring settings along, and point the
+/// plain `discord` symlink at it
+pub async fn run(options: &Options, matches: &Matches) -> Result<()> {
+    let (from, to) = parse_channels(options, matches)?;
+    if !options.symlink {
+        return Err(
+            "switching channels means repointing the launcher symlink, which --no-symlink \
+                    rules out"
+                .into(),
+        );
+    }
+
+    let prog = Progress::new(options.non_interactive);
+    let target = Options {
+        channel: to,
+        ..options.clone()
+    };
+    let (_lock, check) = lock_and_check(&prog, &target).await?;
+    let spinner = prog.spinner();
+    if check.update_available() {
+        print_release_notes(&prog, &check)?;
+        apply_update(&prog, &spinner, &target, &check).await?;
+    } else {
+        spinner.finish_and_clear();
+    }
+
+    if matches.flag("copy-settings") {
+        copy_user_data(&prog, from, to)?;
+    }
+
+    let ask = !options.non_interactive;
+    symlink::sync(&prog, &check, true, options.force_symlink, ask).await?;
+    let check = UpdateCheck {
+        symlink: symlink::default_link(&check),
+        ..check
+    };
+    if !symlink::sync(&prog, &check, true, options.force_symlink, ask).await? {
+        return Err(format!(
+            "{} was left alone, so the switch isn't complete",
+            check.symlink.display()
+        )
+        .into());
+    }
+    profile::check_on_path(&prog, check.symlink.parent().unwrap(), ask).await?;
+    prog.println(format!(
+        "Switched from {from} to {to}, {} now launches {}",
+        check.symlink.display(),
+        check.binary_path().display()
+    ))?;
+    Ok(())
+}
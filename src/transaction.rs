@@ -0,0 +1,297 @@
+//! How far an update has got, kept in the state so an interrupted one can be dealt with
+//!
+//! An update records its phase as it goes: downloading into the staging directory, staged (the
+//! copy extracted, checked and synced to disk, with its manifest kept next to it), swapping it
+//! in, swapped, and integrating it, with each of the menu entry, launcher, symlink, and the like
+//! recorded as it's done. A crash or reboot leaves the record behind, which the next run finds
+//! before it checks for updates: a download starts over, and a swap that happened has the
+//! integration it missed done. An interrupted in-place update is finished, that install is a
+//! mix of the two versions until it is.
+//!
+//! A staged copy, or one a swap didn't get to, is left for the update, which installs it
+//! without downloading it again if it still matches its manifest, once pins, deferrals,
+//! `--when-closed` and the rest have said it may. So is one an update failed to install, like
+//! when `--when-closed-timeout` runs out. `--system` installs are changed by the helper as
+//! root, and aren't recorded.
+
+use crate::channel::Channel;
+use crate::manifest::Manifest;
+use crate::plan::Commit;
+use crate::progress::Progress;
+use crate::state::{self, State};
+use crate::{blocking, fsync_path, install, launch_block, platform, Options, Result};
+use semver::Version;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Downloading,
+    Staged,
+    Swapping,
+    /// The new version is in place, what's left in staging is the old one
+    Swapped,
+    /// The new version is in place and checked, and what's in `integrated` is done
+    Integrating,
+}
+
+/// An update that's under way
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Transaction {
+    pub version: Version,
+    pub install_path: PathBuf,
+    /// The target's staging directory, what's staged is `install` in it
+    pub staging: PathBuf,
+    pub commit: Commit,
+    pub phase: Phase,
+    /// When it started
+    pub time: u64,
+    /// The integration steps done, by `Step::integration`'s names
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub integrated: Vec<String>,
+}
+
+impl Transaction {
+    fn staged(&self) -> PathBuf {
+        self.staging.join("install")
+    }
+}
+
+/// What `recover` did about an interrupted update
+pub enum Recovered {
+    /// There wasn't one, or it was undone
+    Nothing,
+    /// Its version is in place now, but didn't get its menu entry, launcher, and the like, but
+    /// for the integration steps named
+    Installed {
+        version: Version,
+        integrated: Vec<String>,
+    },
+    /// It left the install half updated, so it has to be installed again
+    Broken,
+}
+
+/// Record that the channel's update to `version` is downloading into `staged`
+pub fn begin(
+    channel: Channel,
+    version: &Version,
+    install_path: &Path,
+    staged: &Path,
+    commit: Commit,
+) -> Result<()> {
+    let transaction = Transaction {
+        version: version.clone(),
+        install_path: install_path.to_owned(),
+        staging: staged.parent().unwrap().to_owned(),
+        commit,
+        phase: Phase::Downloading,
+        time: state::now(),
+        integrated: vec![],
+    };
+    State::update(|state| state.channel(channel).transaction = Some(transaction))
+}
+
+/// Record that the channel's update got to `phase`
+pub fn advance(channel: Channel, phase: Phase) -> Result<()> {
+    State::update(|state| {
+        if let Some(transaction) = &mut state.channel(channel).transaction {
+            transaction.phase = phase;
+        }
+    })
+}
+
+/// Record that the channel's update did the integration step called `step`
+pub fn integrated(channel: Channel, step: &str) -> Result<()> {
+    State::update(|state| {
+        if let Some(transaction) = &mut state.channel(channel).transaction {
+            transaction.phase = Phase::Integrating;
+            transaction.integrated.push(step.to_owned());
+        }
+    })
+}
+
+/// The channel's update under way, if there is one
+fn current(channel: Channel) -> Result<Option<Transaction>> {
+    Ok(State::load()?
+        .channels
+        .get(&channel)
+        .and_then(|c| c.transaction.clone()))
+}
+
+/// How far the channel's update has got, if one is under way
+pub fn phase(channel: Channel) -> Result<Option<Phase>> {
+    Ok(current(channel)?.map(|transaction| transaction.phase))
+}
+
+/// Forget the channel's update, deleting whatever it left in its staging directory
+pub fn end(channel: Channel) -> Result<()> {
+    let Some(transaction) = current(channel)? else {
+        return Ok(());
+    };
+    clean_up(&transaction.staging)?;
+    State::update(|state| state.channel(channel).transaction = None)
+}
+
+/// Move the copy of `version` an earlier run staged for `install_path` into `into`, instead of
+/// downloading it again, if it still matches its manifest
+///
+/// Anything else the earlier run left is deleted.
+pub fn take_staged(
+    channel: Channel,
+    version: &Version,
+    install_path: &Path,
+    commit: Commit,
+    into: &Path,
+) -> Result<Option<Manifest>> {
+    let Some(earlier) = current(channel)? else {
+        return Ok(None);
+    };
+    let usable = earlier.phase == Phase::Staged
+        && earlier.version == *version
+        && earlier.install_path == install_path
+        && earlier.commit == commit;
+    let mut manifest = if usable {
+        verified(&earlier.staged())?
+    } else {
+        None
+    };
+    if manifest.is_some() {
+        fs::remove_dir(into)?;
+        // Staged in another --temp-dir, on another filesystem
+        if fs::rename(earlier.staged(), into).is_err() {
+            fs::create_dir(into)?;
+            manifest = None;
+        }
+    }
+    end(channel)?;
+    Ok(manifest)
+}
+
+/// The manifest kept with a staged copy, if the copy still matches it
+fn verified(staged: &Path) -> Result<Option<Manifest>> {
+    let Some(manifest) = Manifest::load(staged)? else {
+        return Ok(None);
+    };
+    if !staged.is_dir() {
+        return Ok(None);
+    }
+    let found = Manifest::generate(staged, manifest.version.clone())?;
+    Ok(found.diff(&manifest).is_empty().then_some(manifest))
+}
+
+/// Put the staged copy in place, like the target would have
+fn commit(transaction: &Transaction, manifest: &Manifest) -> io::Result<()> {
+    let (staged, install_path) = (transaction.staged(), &transaction.install_path);
+    match transaction.commit {
+        Commit::Swap => {
+            install::swap_in(&staged, install_path)?;
+            fsync_path(install_path.parent().unwrap())?;
+            manifest.save(install_path)
+        }
+        Commit::InPlace => install::write_changes(&staged, install_path, manifest),
+        Commit::SystemHelper => Err(io::Error::other("system installs aren't resumed")),
+    }
+}
+
+/// Delete what an update left in its staging directory
+fn clean_up(staging: &Path) -> io::Result<()> {
+    match fs::remove_dir_all(staging) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    }
+}
+
+/// Finish or undo the channel's update, if one was interrupted
+pub async fn recover(prog: &Progress, options: &Options) -> Result<Recovered> {
+    let channel = options.channel;
+    launch_block::recover(prog, channel)?;
+    let Some(transaction) = current(channel)? else {
+        return Ok(Recovered::Nothing);
+    };
+    let version = transaction.version.clone();
+    // Whether a swap was under way when it stopped, RENAME_EXCHANGE makes one all or nothing
+    let swapped = match (transaction.phase, transaction.commit) {
+        (Phase::Swapped | Phase::Integrating, _) => true,
+        (Phase::Swapping, Commit::Swap) => {
+            let staged = platform::current()
+                .installed_version(&transaction.staged())
+                .await;
+            // The old version is in staging after the swap, or nothing is for a fresh install
+            staged.ok().as_ref() != Some(&version)
+        }
+        _ => false,
+    };
+    let recovered = if swapped {
+        prog.println(format!(
+            "Finishing the update to Discord {channel} {version}, which was interrupted"
+        ))?;
+        let integrated = transaction.integrated.clone();
+        let transaction = transaction.clone();
+        blocking(move || {
+            let manifest = Manifest::load(&transaction.staged())?;
+            if let Some(manifest) = manifest.filter(|_| transaction.phase == Phase::Swapping) {
+                manifest.save(&transaction.install_path)?;
+            }
+            Ok(())
+        })
+        .await?;
+        Recovered::Installed {
+            version,
+            integrated,
+        }
+    } else if transaction.phase == Phase::Swapping && transaction.commit == Commit::InPlace {
+        // Part of the install is the new version already, with no old copy to go back to
+        let staged = transaction.staged();
+        let manifest = Manifest::load(&staged)?.filter(|_| staged.is_dir());
+        match manifest {
+            Some(manifest) => {
+                prog.println(format!(
+                    "Finishing the update to Discord {channel} {version}, which was interrupted"
+                ))?;
+                let transaction = transaction.clone();
+                blocking(move || Ok(commit(&transaction, &manifest)?)).await?;
+                Recovered::Installed {
+                    version,
+                    integrated: vec![],
+                }
+            }
+            None => {
+                prog.println(format!(
+                    "Discord {channel} was left half updated to {version}, and what was \
+                     downloaded is gone"
+                ))?;
+                Recovered::Broken
+            }
+        }
+    } else if transaction.phase == Phase::Downloading {
+        prog.println(format!(
+            "The update to Discord {channel} {version} was interrupted while downloading, \
+             it has to start over"
+        ))?;
+        Recovered::Nothing
+    } else {
+        // Left for the update, which gets its say on whether this version goes in first
+        if transaction.phase == Phase::Swapping {
+            advance(channel, Phase::Staged)?;
+        }
+        prog.println(format!(
+            "Discord {channel} {version} was downloaded by an update that didn't finish, it's \
+             kept for the next update to install"
+        ))?;
+        return Ok(Recovered::Nothing);
+    };
+    match &recovered {
+        // Kept until the integration is done, so it's picked up again if that's interrupted
+        Recovered::Installed { .. } => {
+            clean_up(&transaction.staging)?;
+            if transaction.phase != Phase::Integrating {
+                advance(channel, Phase::Swapped)?;
+            }
+        }
+        _ => end(channel)?,
+    }
+    Ok(recovered)
+}
@@ -0,0 +1,363 @@
+//! Interactive terminal interface
+//!
+//! This draws directly with ANSI escapes. The regular update code reports through indicatif, so
+//! its output (log lines and progress bars alike) is rendered into a virtual terminal and shown
+//! in the log pane.
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{
+    apply_update, check_for_update, lock_and_check, print_release_notes, Options, Result,
+    UpdateCheck,
+};
+use indicatif::{ProgressDrawTarget, TermLike};
+use std::future::Future;
+use std::io::{self, Read as _, Write as _};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// How many lines of output the log pane keeps around
+const LOG_LINES: usize = 1000;
+
+/// Puts the terminal in raw mode on the alternate screen, restoring it when dropped
+struct RawTerminal {
+    original: libc::termios,
+}
+
+impl RawTerminal {
+    fn enter() -> Result<Self> {
+        // SAFETY: termios is plain old data, and is fully initialized by tcgetattr
+        let original = unsafe {
+            let mut original = std::mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut original) != 0 {
+                return Err(format!("not a terminal: {}", io::Error::last_os_error()).into());
+            }
+            let mut raw = original;
+            libc::cfmakeraw(&mut raw);
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &raw) != 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            original
+        };
+        // Alternate screen, hide the cursor
+        print!("\x1b[?1049h\x1b[?25l");
+        io::stdout().flush()?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        print!("\x1b[?25h\x1b[?1049l");
+        let _ = io::stdout().flush();
+        // SAFETY: restores the settings we read in `enter`
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.original);
+        }
+    }
+}
+
+/// The size of the terminal as (columns, rows)
+fn terminal_size() -> (usize, usize) {
+    // SAFETY: TIOCGWINSZ only writes into the winsize we pass
+    let size = unsafe {
+        let mut size: libc::winsize = std::mem::zeroed();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 {
+            return (80, 24);
+        }
+        size
+    };
+    (size.ws_col.max(20) as usize, size.ws_row.max(10) as usize)
+}
+
+#[derive(Debug, Default)]
+struct Screen {
+    lines: Vec<String>,
+    row: usize,
+    width: u16,
+}
+
+impl Screen {
+    fn line(&mut self) -> &mut String {
+        if self.row >= self.lines.len() {
+            self.lines.resize(self.row + 1, String::new());
+        }
+        &mut self.lines[self.row]
+    }
+
+    /// Write text at the cursor, wrapping long lines the way a real terminal would
+    fn put(&mut self, s: &str) {
+        for c in s.chars() {
+            if self.line().chars().count() >= self.width.max(1) as usize {
+                self.row += 1;
+                self.line().clear();
+            }
+            self.line().push(c);
+        }
+    }
+
+    fn newline(&mut self) {
+        self.row += 1;
+        self.line();
+        if self.lines.len() > LOG_LINES {
+            let excess = self.lines.len() - LOG_LINES;
+            self.lines.drain(..excess);
+            self.row = self.row.saturating_sub(excess);
+        }
+    }
+}
+
+/// A virtual terminal for indicatif to draw into
+#[derive(Debug, Default, Clone)]
+struct CaptureTerm {
+    screen: Arc<Mutex<Screen>>,
+}
+
+impl CaptureTerm {
+    fn set_width(&self, width: usize) {
+        self.screen.lock().unwrap().width = width as u16;
+    }
+
+    /// The last `n` lines written
+    fn tail(&self, n: usize) -> Vec<String> {
+        let screen = self.screen.lock().unwrap();
+        let start = screen.lines.len().saturating_sub(n);
+        screen.lines[start..].to_vec()
+    }
+}
+
+impl TermLike for CaptureTerm {
+    fn width(&self) -> u16 {
+        self.screen.lock().unwrap().width
+    }
+
+    fn move_cursor_up(&self, n: usize) -> io::Result<()> {
+        let mut screen = self.screen.lock().unwrap();
+        screen.row = screen.row.saturating_sub(n);
+        Ok(())
+    }
+
+    fn move_cursor_down(&self, n: usize) -> io::Result<()> {
+        self.screen.lock().unwrap().row += n;
+        Ok(())
+    }
+
+    fn move_cursor_right(&self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn move_cursor_left(&self, _n: usize) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn write_line(&self, s: &str) -> io::Result<()> {
+        let mut screen = self.screen.lock().unwrap();
+        screen.put(s);
+        screen.newline();
+        Ok(())
+    }
+
+    fn write_str(&self, s: &str) -> io::Result<()> {
+        self.screen.lock().unwrap().put(s);
+        Ok(())
+    }
+
+    fn clear_line(&self) -> io::Result<()> {
+        self.screen.lock().unwrap().line().clear();
+        Ok(())
+    }
+
+    fn flush(&self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+enum TaskOutput {
+    Checked(Result<UpdateCheck>),
+    Updated(Result<()>),
+}
+
+type Task<'a> = Pin<Box<dyn Future<Output = TaskOutput> + 'a>>;
+
+struct App {
+    check: Option<UpdateCheck>,
+    status: String,
+    updating: bool,
+    confirm_quit: bool,
+}
+
+/// Pad or truncate `s` to exactly `width` columns
+fn fit(s: &str, width: usize) -> String {
+    let mut out: String = s.chars().take(width).collect();
+    let len = out.chars().count();
+    out.extend(std::iter::repeat_n(' ', width - len));
+    out
+}
+
+fn boxed(out: &mut Vec<String>, title: &str, lines: &[String], width: usize) {
+    let inner = width - 2;
+    let top: String = format!("─ {title} ")
+        .chars()
+        .chain(std::iter::repeat('─'))
+        .take(inner)
+        .collect();
+    out.push(format!("┌{top}┐"));
+    for line in lines {
+        out.push(format!("│{}│", fit(line, inner)));
+    }
+    out.push(format!("└{}┘", "─".repeat(inner)));
+}
+
+fn channel_line(channel: Channel, check: &Option<UpdateCheck>) -> String {
+    let Some(check) = check else {
+        return format!(" > {channel:<9} checking...");
+    };
+    let installed = check
+        .installed_version
+        .as_ref()
+        .map_or("not installed".into(), |v| v.to_string());
+    let state = if check.update_available() {
+        "update available"
+    } else {
+        "up to date"
+    };
+    format!(
+        " > {channel:<9} installed {installed:<14} latest {:<14} {state}",
+        check.latest_version
+    )
+}
+
+fn draw(app: &App, channel: Channel, log: &CaptureTerm) -> io::Result<()> {
+    let (width, height) = terminal_size();
+    log.set_width(width - 4);
+
+    let mut frame = vec![];
+    boxed(
+        &mut frame,
+        "Channels",
+        &[channel_line(channel, &app.check)],
+        width,
+    );
+    let log_height = height.saturating_sub(frame.len() + 3);
+    let mut log_lines: Vec<String> = log
+        .tail(log_height)
+        .iter()
+        .map(|l| format!(" {l}"))
+        .collect();
+    log_lines.resize(log_height, String::new());
+    boxed(&mut frame, "Log", &log_lines, width);
+    frame.push(fit(
+        &format!(" [u] update  [c] check  [q] quit   {}", app.status),
+        width,
+    ));
+
+    let mut stdout = io::stdout().lock();
+    write!(stdout, "\x1b[H{}", frame.join("\r\n"))?;
+    stdout.flush()
+}
+
+/// Read keys from stdin on a separate thread, since stdin can't be read asynchronously
+fn spawn_key_reader() -> tokio::sync::mpsc::UnboundedReceiver<u8> {
+    let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        let mut buf = [0; 64];
+        while let Ok(n @ 1..) = io::stdin().read(&mut buf) {
+            if buf[..n].iter().any(|&byte| tx.send(byte).is_err()) {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+/// Run the interactive interface until the user quits
+pub async fn run(options: &Options) -> Result<()> {
+    let log = CaptureTerm::default();
+    let prog = Progress::with_draw_target(ProgressDrawTarget::term_like(Box::new(log.clone())));
+    let _terminal = RawTerminal::enter()?;
+    print!("\x1b[2J");
+
+    let mut keys = spawn_key_reader();
+    let mut tick = tokio::time::interval(Duration::from_millis(100));
+    let mut app = App {
+        check: None,
+        status: "Checking for updates".into(),
+        updating: false,
+        confirm_quit: false,
+    };
+    let mut task: Option<Task> = Some(Box::pin(async {
+        TaskOutput::Checked(check_for_update(&prog, options).await)
+    }));
+
+    loop {
+        draw(&app, options.channel, &log)?;
+        tokio::select! {
+            key = keys.recv() => {
+                let key = key.unwrap_or(b'q');
+                if key != b'q' {
+                    app.confirm_quit = false;
+                }
+                match key {
+                    // q, Ctrl-C
+                    b'q' | 3 if app.updating && !app.confirm_quit => {
+                        app.confirm_quit = true;
+                        app.status = "Update in progress, press q again to abort it".into();
+                    }
+                    b'q' | 3 => break,
+                    b'c' if task.is_none() => {
+                        app.status = "Checking for updates".into();
+                        task = Some(Box::pin(async {
+                            TaskOutput::Checked(check_for_update(&prog, options).await)
+                        }));
+                    }
+                    b'u' if task.is_none() => match &app.check {
+                        Some(check) if check.update_available() => {
+                            app.status = "Updating".into();
+                            app.updating = true;
+                            let prog = &prog;
+                            task = Some(Box::pin(async move {
+                                TaskOutput::Updated(async {
+                                    let (_lock, check) = lock_and_check(prog, options).await?;
+                                    // Whoever had the lock may have installed it already
+                                    if check.update_available() {
+                                        let spinner = prog.spinner();
+                                        apply_update(prog, &spinner, options, &check).await?;
+                                    }
+                                    Ok(())
+                                }.await)
+                            }));
+                        }
+                        _ => app.status = "No update available".into(),
+                    },
+                    _ => {}
+                }
+            }
+            output = async { task.as_mut().unwrap().await }, if task.is_some() => {
+                task = None;
+                match output {
+                    TaskOutput::Checked(Ok(check)) => {
+                        app.status = String::new();
+                        if check.update_available() {
+                            print_release_notes(&prog, &check)?;
+                        }
+                        app.check = Some(check);
+                    }
+                    TaskOutput::Updated(Ok(())) => {
+                        app.updating = false;
+                        app.status = "Update complete".into();
+                        task = Some(Box::pin(async {
+                            TaskOutput::Checked(check_for_update(&prog, options).await)
+                        }));
+                    }
+                    TaskOutput::Checked(Err(e)) | TaskOutput::Updated(Err(e)) => {
+                        app.updating = false;
+                        app.status = format!("Error: {e}");
+                    }
+                }
+            }
+            _ = tick.tick() => {}
+        }
+    }
+    Ok(())
+}
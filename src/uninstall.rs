@@ -0,0 +1,67 @@
+//! The `uninstall` subcommand
+
+use crate::manifest::metadata_dir;
+use crate::progress::Progress;
+use crate::state::State;
+use crate::{
+    apparmor, autostart, confirm, desktop, find_install, integrate, platform, symlink, Options,
+    Result,
+};
+
+/// Remove the channel's install and everything we set up around it
+///
+/// Discord's own settings are left in place, so reinstalling picks up where it left off.
+pub async fn run(options: &Options) -> Result<()> {
+    let prog = Progress::new(options.non_interactive);
+    let channel = options.channel;
+    if options.system {
+        return Err("uninstall doesn't handle --system installs yet".into());
+    }
+    let install_path = find_install(&prog, options).await?;
+    let binary = platform::current().binary_path(channel, &install_path);
+    if !binary.exists() {
+        return Err(format!(
+            "{} doesn't look like a Discord {channel} install",
+            install_path.display()
+        )
+        .into());
+    }
+    // Only this user's part of it, the install stays for everyone else
+    if integrate::is_shared(options, &install_path)? {
+        symlink::remove(&prog, &symlink::link_path(options)?, &binary).await?;
+        desktop::remove(&prog, channel)?;
+        autostart::remove(&prog, channel)?;
+        State::update(|state| {
+            state.channels.remove(&channel);
+        })?;
+        prog.println(format!(
+            "Removed your launcher for the shared install at {}",
+            install_path.display()
+        ))?;
+        return Ok(());
+    }
+    let question = format!("Remove {}?", install_path.display());
+    if !options.assume_yes && !confirm(&prog, &question, false)? {
+        prog.println("Nothing was removed")?;
+        return Ok(());
+    }
+
+    symlink::remove(&prog, &symlink::link_path(options)?, &binary).await?;
+    desktop::remove(&prog, channel)?;
+    autostart::remove(&prog, channel)?;
+    apparmor::remove(&prog, channel)?;
+    tokio::fs::remove_dir_all(&install_path).await?;
+    let metadata = metadata_dir(&install_path);
+    if metadata.exists() {
+        tokio::fs::remove_dir_all(&metadata).await?;
+    }
+    State::update(|state| {
+        state.channels.remove(&channel);
+    })?;
+    prog.println(format!(
+        "Removed {}, settings in {} were kept",
+        install_path.display(),
+        channel.config_dir()?.display()
+    ))?;
+    Ok(())
+}
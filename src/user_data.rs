@@ -0,0 +1,139 @@
+//! Settings and other user data in Discord's config directory
+
+use crate::channel::Channel;
+use crate::progress::Progress;
+use crate::{tar, Result};
+use std::fs;
+use std::io::{self, BufReader, BufWriter};
+use std::path::{Component, Path};
+
+/// What in Discord's config directory holds user preferences (rather than caches). Keybinds
+/// live in local storage.
+pub const USER_DATA: &[&str] = &["settings.json", "Local Storage"];
+
+/// Recursively copy a file or directory
+fn copy_all(source: &Path, dest: &Path) -> io::Result<()> {
+    if fs::metadata(source)?.is_dir() {
+        fs::create_dir_all(dest)?;
+        for entry in fs::read_dir(source)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &dest.join(entry.file_name()))?;
+        }
+    } else {
+        fs::copy(source, dest)?;
+    }
+    Ok(())
+}
+
+/// Move an existing file or directory out of the way, keeping it with a `.bak` suffix
+fn set_aside(prog: &Progress, path: &Path) -> Result<()> {
+    if !path.exists() {
+        return Ok(());
+    }
+    let mut backup = path.as_os_str().to_owned();
+    backup.push(".bak");
+    let backup = Path::new(&backup);
+    if backup.is_dir() {
+        fs::remove_dir_all(backup)?;
+    }
+    fs::rename(path, backup)?;
+    prog.println(format!(
+        "Moved existing {} to {}",
+        path.display(),
+        backup.display()
+    ))?;
+    Ok(())
+}
+
+/// Copy settings and keybinds from one channel's config directory to another's
+///
+/// Anything already in the destination is kept with a `.bak` suffix. Both clients should be
+/// closed while this happens, since Discord keeps local storage open.
+pub fn copy_user_data(prog: &Progress, from: Channel, to: Channel) -> Result<()> {
+    let source_dir = from.config_dir()?;
+    let dest_dir = to.config_dir()?;
+    fs::create_dir_all(&dest_dir)?;
+
+    for name in USER_DATA {
+        let source = source_dir.join(name);
+        if !source.exists() {
+            continue;
+        }
+        let dest = dest_dir.join(name);
+        set_aside(prog, &dest)?;
+        copy_all(&source, &dest)?;
+        prog.println(format!("Copied {} to {}", source.display(), dest.display()))?;
+    }
+    Ok(())
+}
+
+/// Write the channel's settings and local storage to a tar archive
+pub fn backup(prog: &Progress, channel: Channel, archive: &Path) -> Result<()> {
+    let config_dir = channel.config_dir()?;
+    let mut builder = tar::Builder::new(BufWriter::new(fs::File::create(archive)?));
+    let mut found = false;
+    for name in USER_DATA {
+        let path = config_dir.join(name);
+        if path.exists() {
+            builder.append_path(&path, Path::new(name))?;
+            found = true;
+        }
+    }
+    builder.finish()?;
+    if !found {
+        fs::remove_file(archive)?;
+        return Err(format!("no Discord settings found in {}", config_dir.display()).into());
+    }
+    prog.println(format!(
+        "Backed up {channel} settings to {}",
+        archive.display()
+    ))?;
+    Ok(())
+}
+
+/// Restore settings written by `backup` into the channel's config directory
+///
+/// What's currently there is kept with a `.bak` suffix.
+pub fn restore(prog: &Progress, channel: Channel, archive: &Path) -> Result<()> {
+    let config_dir = channel.config_dir()?;
+    fs::create_dir_all(&config_dir)?;
+
+    // Only restore what we'd have backed up, so an arbitrary archive can't drop files
+    // elsewhere in the config directory
+    let mut reader = tar::Archive::new(BufReader::new(fs::File::open(archive)?));
+    let mut contained = vec![];
+    while let Some(entry) = reader.next_entry()? {
+        let top = entry.path.components().next();
+        let Some(name) = USER_DATA
+            .iter()
+            .find(|name| top == Some(Component::Normal(name.as_ref())))
+        else {
+            return Err(format!(
+                "{} isn't a settings backup, it contains {}",
+                archive.display(),
+                entry.path.display()
+            )
+            .into());
+        };
+        if !contained.contains(name) {
+            contained.push(*name);
+        }
+    }
+
+    for name in contained {
+        set_aside(prog, &config_dir.join(name))?;
+    }
+    let mut files = prog.file_progress();
+    tar::extract(
+        BufReader::new(fs::File::open(archive)?),
+        &config_dir,
+        0,
+        |_| false,
+        |path, written, size| files.update(path, written, size),
+    )?;
+    prog.println(format!(
+        "Restored {channel} settings from {}",
+        archive.display()
+    ))?;
+    Ok(())
+}
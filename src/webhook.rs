@@ -0,0 +1,79 @@
+//! Posting update events to a webhook, for routing them into existing alerting
+//!
+//! Plain JSON looks like
+//! `{"event": "updated", "channel": "stable", "installed_version": "0.0.98",
+//! "latest_version": "0.0.99", "error": null, "message": "..."}`, with `event` one of
+//! `update_available`, `updated`, and `failed`. Discord webhooks get the message as `content`.
+
+use crate::notify::{Action, Event, Notifier};
+use crate::{fetch, Result};
+use futures::future::LocalBoxFuture;
+use serde_json::json;
+use std::str::FromStr;
+
+/// The shape of the body we POST
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Json,
+    /// A message for a Discord channel webhook
+    Discord,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "discord" => Ok(Self::Discord),
+            _ => Err("expected json or discord".into()),
+        }
+    }
+}
+
+impl Format {
+    /// Discord for Discord's own webhook URLs, JSON otherwise
+    fn guess(url: &str) -> Self {
+        if url.contains("discord.com/api/webhooks/") || url.contains("discordapp.com/api/webhooks/")
+        {
+            Self::Discord
+        } else {
+            Self::Json
+        }
+    }
+}
+
+/// POSTs events to a URL
+pub struct Webhook {
+    url: String,
+    /// Guessed from the URL when not given
+    format: Format,
+}
+
+impl Webhook {
+    pub fn new(url: &str, format: Option<Format>) -> Self {
+        Self {
+            url: url.to_owned(),
+            format: format.unwrap_or_else(|| Format::guess(url)),
+        }
+    }
+}
+
+impl Notifier for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    fn notify<'a>(&'a self, event: &'a Event) -> LocalBoxFuture<'a, Result<Option<Action>>> {
+        Box::pin(async move {
+            let body = match self.format {
+                Format::Json => serde_json::to_value(event)?,
+                Format::Discord => {
+                    json!({"username": "discord_update", "content": event.message})
+                }
+            };
+            fetch::post_json(&self.url, &body).await?;
+            Ok(None)
+        })
+    }
+}
@@ -0,0 +1,152 @@
+//! What to do about an install we aren't allowed to update
+//!
+//! An install found on the PATH can belong to someone else, like a distribution package's in
+//! `/usr/share/discord`. Rather than failing halfway with a permission error, the update stops
+//! before downloading and says whose it is, and interactively offers to update it with `--system`
+//! or to install a copy of the user's own and point the launcher at that.
+
+use crate::install::Strategy;
+use crate::progress::Progress;
+use crate::{choose, platform, Options, Result, UpdateCheck};
+use std::ffi::{CStr, CString};
+use std::os::unix::fs::MetadataExt as _;
+use std::path::{Path, PathBuf};
+
+fn writable(dir: &Path) -> bool {
+    let Ok(dir) = CString::new(dir.as_os_str().as_encoded_bytes()) else {
+        return false;
+    };
+    // SAFETY: dir is a valid C string
+    unsafe { libc::access(dir.as_ptr(), libc::W_OK) == 0 }
+}
+
+/// The first directory that updating the install at `install_path` writes to and we can't
+///
+/// Swapping writes next to the install, and renaming a directory updates its `..` entry, so
+/// both have to be writable. For a fresh install it's wherever it would be created.
+fn unwritable(install_path: &Path, strategy: Strategy) -> Option<PathBuf> {
+    let mut dirs = vec![install_path];
+    if strategy == Strategy::Swap {
+        dirs.extend(install_path.parent());
+    }
+    dirs.into_iter()
+        .filter_map(|dir| dir.ancestors().find(|d| d.exists()))
+        .find(|dir| !writable(dir))
+        .map(Path::to_owned)
+}
+
+/// The name of the user with `uid`, or the number if there isn't one
+fn user_name(uid: u32) -> String {
+    // SAFETY: the entry is read before any other passwd call
+    let entry = unsafe { libc::getpwuid(uid) };
+    if entry.is_null() {
+        return uid.to_string();
+    }
+    // SAFETY: checked it isn't null, and pw_name is a C string
+    unsafe { CStr::from_ptr((*entry).pw_name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// What could be done instead
+enum Remedy {
+    /// Update it as root, for installs root owns
+    System,
+    /// Install another copy at the path and point the launcher at it
+    OwnCopy(PathBuf),
+}
+
+/// Why the checked install can't be updated by us, and what could be done instead, if it can't
+fn diagnose(options: &Options, check: &UpdateCheck) -> Result<Option<(String, Vec<Remedy>)>> {
+    if check.system {
+        return Ok(None);
+    }
+    let Some(dir) = unwritable(&check.install_path, options.update_strategy) else {
+        return Ok(None);
+    };
+    let owner = std::fs::metadata(&dir)?.uid();
+    // SAFETY: getuid can't fail
+    let me = unsafe { libc::getuid() };
+    let problem = format!(
+        "Discord {} at {} can't be updated by you, {} belongs to {} and you're {}",
+        check.channel,
+        check.install_path.display(),
+        dir.display(),
+        user_name(owner),
+        user_name(me)
+    );
+    let mut remedies = vec![];
+    // The helper only makes root-owned installs, and can't do in-place ones
+    if owner == 0 && options.update_strategy == Strategy::Swap {
+        remedies.push(Remedy::System);
+    }
+    let own = platform::current().default_install_path(check.channel, false)?;
+    if own != check.install_path && unwritable(&own, options.update_strategy).is_none() {
+        remedies.push(Remedy::OwnCopy(own));
+    }
+    Ok(Some((problem, remedies)))
+}
+
+/// Fail with what's wrong and what to run instead when the install can't be updated by us
+pub fn check(options: &Options, check: &UpdateCheck) -> Result<()> {
+    let Some((problem, remedies)) = diagnose(options, check)? else {
+        return Ok(());
+    };
+    let hints: Vec<_> = remedies
+        .iter()
+        .map(|remedy| match remedy {
+            Remedy::System => format!(
+                "--system --install-path {} to update it as root",
+                check.install_path.display()
+            ),
+            Remedy::OwnCopy(own) => format!(
+                "--install-path {} to install a copy of your own",
+                own.display()
+            ),
+        })
+        .collect();
+    if hints.is_empty() {
+        return Err(problem.into());
+    }
+    Err(format!("{problem}. Run again with {}", hints.join(", or with ")).into())
+}
+
+/// The options to update with instead when the install can't be updated by us, after asking
+///
+/// `None` means it can be updated as it is. Without anyone to ask, or when they'd rather leave
+/// it, this fails like `check`.
+pub fn remedy(prog: &Progress, options: &Options, update: &UpdateCheck) -> Result<Option<Options>> {
+    let Some((problem, remedies)) = diagnose(options, update)? else {
+        return Ok(None);
+    };
+    if options.non_interactive || remedies.is_empty() {
+        return check(options, update).map(|()| None);
+    }
+    prog.println(&problem)?;
+    let mut choices: Vec<_> = remedies
+        .iter()
+        .map(|remedy| match remedy {
+            Remedy::System => "Update it as root with --system".to_owned(),
+            Remedy::OwnCopy(own) => format!(
+                "Install a copy of your own at {} and point {} at it",
+                own.display(),
+                update.symlink.display()
+            ),
+        })
+        .collect();
+    choices.push("Leave it alone".to_owned());
+    let chosen = choose(prog, "What should be done instead?", &choices)?;
+    match remedies.get(chosen) {
+        Some(Remedy::System) => Ok(Some(Options {
+            system: true,
+            install_path: Some(update.install_path.clone()),
+            ..options.clone()
+        })),
+        Some(Remedy::OwnCopy(own)) => Ok(Some(Options {
+            install_path: Some(own.clone()),
+            claim_symlink: true,
+            ..options.clone()
+        })),
+        None => Err(problem.into()),
+    }
+}
@@ -0,0 +1,425 @@
+//! A mock updates API and CDN, fake tarballs, and a home directory to run the updater in
+//!
+//! Requests go to an in-process `MockServer` through the `HttpFetcher` seam, and installs through
+//! a `Target` wrapping the real one, which records what it put in place. Runs touch `$HOME` and
+//! the process-wide fetcher, so `Home` keeps each test to itself.
+
+#![allow(dead_code)]
+
+use bytes::Bytes;
+use discord_update::fetch::{self, HttpFetcher, RateLimited, Response};
+use discord_update::install::{self, InstallTarget, Previous};
+use discord_update::manifest::Manifest;
+use discord_update::{Outcome, Result};
+use futures::future::LocalBoxFuture;
+use futures::stream::{self, StreamExt as _};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::{Mutex, MutexGuard, Once, OnceLock};
+use std::time::Duration;
+
+/// What a URL serves
+#[derive(Clone, Default)]
+pub struct Route {
+    pub body: Vec<u8>,
+    /// The MD5 sent with it, which needn't be the body's
+    pub md5: Option<[u8; 16]>,
+    /// Break off after this many bytes, the first time it's fetched
+    pub break_after: Option<usize>,
+    /// Stop sending after this many bytes, and never finish
+    pub hang_after: Option<usize>,
+    /// Answer with a 429
+    pub rate_limited: bool,
+}
+
+/// A request the server saw
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    Get(String),
+    /// A resumed GET, from this offset
+    GetFrom(String, u64),
+    Head(String),
+}
+
+#[derive(Default)]
+pub struct MockServer {
+    routes: Mutex<BTreeMap<String, Route>>,
+    requests: Mutex<Vec<Request>>,
+}
+
+static SERVER: MockServer = MockServer {
+    routes: Mutex::new(BTreeMap::new()),
+    requests: Mutex::new(vec![]),
+};
+
+/// The server the fetcher sends requests to
+pub fn server() -> &'static MockServer {
+    &SERVER
+}
+
+impl MockServer {
+    pub fn route(&self, url: &str, route: Route) {
+        self.routes.lock().unwrap().insert(url.to_owned(), route);
+    }
+
+    /// Serve `version` as the latest, and its tarball
+    pub fn release(&self, version: &str) {
+        let api = Route {
+            body: format!(r#"{{"name": "{version}"}}"#).into(),
+            ..Route::default()
+        };
+        self.route(API_URL, api);
+        let tarball = Route {
+            body: tarball(version),
+            ..Route::default()
+        };
+        self.route(&tarball_url(version), tarball);
+    }
+
+    pub fn requests(&self) -> Vec<Request> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn respond(&self, request: Request, url: &str, offset: u64) -> Result<Response> {
+        self.requests.lock().unwrap().push(request);
+        let mut routes = self.routes.lock().unwrap();
+        let route = routes
+            .get_mut(url)
+            .ok_or_else(|| format!("HTTP status client error (404 Not Found) for url ({url})"))?;
+        if route.rate_limited {
+            return Err(Box::new(RateLimited {
+                url: url.to_owned(),
+                retry_after: Some(Duration::from_secs(600)),
+            }));
+        }
+        let body = Bytes::from(route.body[offset as usize..].to_vec());
+        let (length, partial) = (route.body.len() as u64 - offset, offset > 0);
+        let chunks: Vec<Result<Bytes>> = body
+            .chunks(4096)
+            .map(|chunk| Ok(Bytes::copy_from_slice(chunk)))
+            .collect();
+        let body = match (route.break_after.take(), route.hang_after) {
+            (Some(after), _) => {
+                let sent = chunks.into_iter().take(after.div_ceil(4096));
+                let broke = Err("connection reset by peer".into());
+                stream::iter(sent.chain([broke])).boxed_local()
+            }
+            (None, Some(after)) => {
+                mark_hung();
+                let sent = chunks.into_iter().take(after.div_ceil(4096));
+                stream::iter(sent).chain(stream::pending()).boxed_local()
+            }
+            (None, None) => stream::iter(chunks).boxed_local(),
+        };
+        Ok(Response {
+            content_length: Some(length),
+            validator: Some("\"mock\"".to_owned()),
+            partial,
+            md5: route.md5.filter(|_| !partial),
+            body,
+        })
+    }
+}
+
+struct Fetcher;
+
+impl HttpFetcher for Fetcher {
+    fn get<'a>(&'a self, url: &'a str) -> LocalBoxFuture<'a, Result<Response>> {
+        Box::pin(async move { server().respond(Request::Get(url.to_owned()), url, 0) })
+    }
+
+    fn get_from<'a>(
+        &'a self,
+        url: &'a str,
+        offset: u64,
+        _validator: &'a str,
+    ) -> LocalBoxFuture<'a, Result<Response>> {
+        Box::pin(
+            async move { server().respond(Request::GetFrom(url.to_owned(), offset), url, offset) },
+        )
+    }
+
+    fn content_length<'a>(&'a self, url: &'a str) -> LocalBoxFuture<'a, Result<Option<u64>>> {
+        Box::pin(async move {
+            server()
+                .requests
+                .lock()
+                .unwrap()
+                .push(Request::Head(url.to_owned()));
+            let routes = server().routes.lock().unwrap();
+            Ok(routes.get(url).map(|route| route.body.len() as u64))
+        })
+    }
+}
+
+/// Where a child process that's going to be killed stops
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Hang {
+    /// Part way through downloading the tarball
+    Download,
+    /// When the `Target` is committed
+    BeforeCommit,
+    AfterCommit,
+}
+
+/// The variable a child process is told where to hang in
+pub const HANG: &str = "DISCORD_UPDATE_TEST_HANG";
+
+pub fn hang() -> Option<Hang> {
+    match std::env::var(HANG).as_deref() {
+        Ok("download") => Some(Hang::Download),
+        Ok("before-commit") => Some(Hang::BeforeCommit),
+        Ok("after-commit") => Some(Hang::AfterCommit),
+        _ => None,
+    }
+}
+
+/// The file a child process creates once it's where it hangs, in the home
+pub const HUNG: &str = "hung";
+
+/// Say the child got where it was meant to stop
+pub fn mark_hung() {
+    let home = std::env::var_os("HOME").unwrap();
+    fs::write(Path::new(&home).join(HUNG), "").unwrap();
+}
+
+fn hang_forever() -> ! {
+    mark_hung();
+    loop {
+        std::thread::sleep(Duration::from_secs(60));
+    }
+}
+
+static COMMITS: Mutex<Vec<String>> = Mutex::new(vec![]);
+
+/// The versions put in place, in the order they were
+pub fn commits() -> Vec<String> {
+    COMMITS.lock().unwrap().clone()
+}
+
+/// The real target, recording what's committed, and stopping where `HANG` says
+pub struct Target {
+    inner: Box<dyn InstallTarget>,
+}
+
+impl InstallTarget for Target {
+    fn path(&self) -> &Path {
+        self.inner.path()
+    }
+
+    fn staged(&self) -> &Path {
+        self.inner.staged()
+    }
+
+    fn commit(self: Box<Self>, manifest: &Manifest) -> io::Result<Option<Previous>> {
+        if hang() == Some(Hang::BeforeCommit) {
+            hang_forever();
+        }
+        let previous = self.inner.commit(manifest)?;
+        COMMITS.lock().unwrap().push(manifest.version.to_string());
+        if hang() == Some(Hang::AfterCommit) {
+            hang_forever();
+        }
+        Ok(previous)
+    }
+
+    fn keep(self: Box<Self>) {
+        self.inner.keep()
+    }
+}
+
+/// What `--api-url` is, and where the stable channel's latest version is asked for under it
+pub const API_BASE: &str = "https://mock.invalid/api";
+pub const API_URL: &str = "https://mock.invalid/api/updates/stable?platform=linux";
+pub const TEMPLATE: &str = "https://mock.invalid/{version}.tar.gz";
+
+pub fn tarball_url(version: &str) -> String {
+    TEMPLATE.replace("{version}", version)
+}
+
+/// A tarball laid out like Discord's, with a file named after the version
+pub fn tarball(version: &str) -> Vec<u8> {
+    let dir = scratch();
+    let top = dir.path().join("Discord");
+    fs::create_dir_all(top.join("resources")).unwrap();
+    fs::write(top.join("Discord"), "#!/bin/sh\nexit 0\n").unwrap();
+    let executable = std::os::unix::fs::PermissionsExt::from_mode(0o755);
+    fs::set_permissions(top.join("Discord"), executable).unwrap();
+    let build_info = format!(r#"{{"releaseChannel": "stable", "version": "{version}"}}"#);
+    fs::write(top.join("resources/build_info.json"), build_info).unwrap();
+    // Big enough to come in a few chunks
+    let asar: Vec<u8> = (0..50_000u32).map(|i| (i % 251) as u8).collect();
+    fs::write(top.join("resources/app.asar"), asar).unwrap();
+    fs::write(top.join(format!("only-in-{version}")), version).unwrap();
+    let archive = dir.path().join("discord.tar.gz");
+    let status = Command::new("tar")
+        .arg("-czf")
+        .arg(&archive)
+        .arg("-C")
+        .arg(dir.path())
+        .arg("Discord")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    fs::read(archive).unwrap()
+}
+
+/// MD5 of `data`, what the CDN would send with it
+pub fn md5(data: &[u8]) -> [u8; 16] {
+    let dir = scratch();
+    let path = dir.path().join("data");
+    fs::write(&path, data).unwrap();
+    let output = Command::new("md5sum").arg(&path).output().unwrap();
+    let hex = String::from_utf8(output.stdout).unwrap();
+    std::array::from_fn(|i| u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16).unwrap())
+}
+
+/// A temporary directory outside the homes, which point `TMPDIR` into themselves
+pub fn scratch() -> tempfile::TempDir {
+    static TEMP: OnceLock<PathBuf> = OnceLock::new();
+    tempfile::tempdir_in(TEMP.get_or_init(std::env::temp_dir)).unwrap()
+}
+
+static SETUP: Once = Once::new();
+
+/// Send requests to the mock server and installs through `Target`
+pub fn seams() {
+    SETUP.call_once(|| {
+        fetch::set_fetcher(Box::new(Fetcher));
+        install::wrap_targets(Box::new(|inner| Box::new(Target { inner })));
+    });
+}
+static ONE_AT_A_TIME: Mutex<()> = Mutex::new(());
+
+/// A home directory of its own for a test, which has the updater to itself until it's dropped
+pub struct Home {
+    dir: tempfile::TempDir,
+    _alone: MutexGuard<'static, ()>,
+}
+
+impl Home {
+    pub fn new() -> Self {
+        seams();
+        let alone = ONE_AT_A_TIME.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = scratch();
+        Self::enter(dir.path());
+        server().routes.lock().unwrap().clear();
+        server().requests.lock().unwrap().clear();
+        COMMITS.lock().unwrap().clear();
+        Self { dir, _alone: alone }
+    }
+
+    /// Point the environment at `home`, for this process and the ones it starts
+    pub fn enter(home: &Path) {
+        static PATH: OnceLock<std::ffi::OsString> = OnceLock::new();
+        let path = PATH.get_or_init(|| std::env::var_os("PATH").unwrap_or_default());
+        let mut paths = vec![home.join("fake-bin")];
+        paths.extend(std::env::split_paths(path));
+        std::env::set_var("PATH", std::env::join_paths(paths).unwrap());
+        std::env::set_var("HOME", home);
+        std::env::set_var("TMPDIR", home.join("tmp"));
+        fs::create_dir_all(home.join("tmp")).unwrap();
+        for var in [
+            "XDG_CONFIG_HOME",
+            "XDG_CACHE_HOME",
+            "XDG_DATA_HOME",
+            "XDG_STATE_HOME",
+        ] {
+            std::env::remove_var(var);
+        }
+        for var in ["DISPLAY", "WAYLAND_DISPLAY", "DBUS_SESSION_BUS_ADDRESS"] {
+            std::env::remove_var(var);
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    /// Put a shell script called `name` first on `PATH`, in place of the real command
+    pub fn fake_command(&self, name: &str, script: &str) {
+        use std::os::unix::fs::PermissionsExt as _;
+        let bin = self.path().join("fake-bin");
+        fs::create_dir_all(&bin).unwrap();
+        fs::write(bin.join(name), format!("#!/bin/sh\n{script}")).unwrap();
+        fs::set_permissions(bin.join(name), fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    /// The updater's arguments, pointed at the mock server
+    pub fn args(extra: &[&str]) -> Vec<String> {
+        let mut args = vec![
+            "--non-interactive",
+            "--api-url",
+            API_BASE,
+            "--download-url-template",
+            TEMPLATE,
+        ];
+        args.extend(extra);
+        args.into_iter().map(str::to_owned).collect()
+    }
+
+    /// Run the updater with `extra` arguments
+    pub fn run(&self, extra: &[&str]) -> Result<Outcome> {
+        run(extra)
+    }
+
+    /// Where the channel is installed
+    pub fn install_path(&self) -> PathBuf {
+        install_path(self.path())
+    }
+
+    /// The version the install says it is
+    pub fn installed(&self) -> Option<String> {
+        installed(self.path())
+    }
+
+    /// What's left in the staging directories
+    pub fn leftovers(&self) -> Vec<PathBuf> {
+        let parent = self.install_path().parent().unwrap().to_owned();
+        [self.path().join("tmp"), parent]
+            .iter()
+            .filter_map(|dir| fs::read_dir(dir).ok())
+            .flatten()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| {
+                let name = path.file_name().unwrap().to_string_lossy();
+                name.starts_with("discord_update-") || name.starts_with(".discord_update-")
+            })
+            .collect()
+    }
+
+    pub fn state(&self) -> serde_json::Value {
+        state(self.path())
+    }
+}
+
+/// Run the updater with `extra` arguments, in whatever home the environment says
+pub fn run(extra: &[&str]) -> Result<Outcome> {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .unwrap();
+    runtime.block_on(discord_update::run_with_args(Home::args(extra)))
+}
+
+pub fn install_path(home: &Path) -> PathBuf {
+    home.join("bin/discord_bin/Discord/Discord")
+}
+
+pub fn installed(home: &Path) -> Option<String> {
+    let build_info = install_path(home).join("resources/build_info.json");
+    let build_info: serde_json::Value =
+        serde_json::from_str(&fs::read_to_string(build_info).ok()?).unwrap();
+    Some(build_info["version"].as_str().unwrap().to_owned())
+}
+
+pub fn state(home: &Path) -> serde_json::Value {
+    let path = home.join(".local/state/discord_update/state.json");
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).unwrap(),
+        Err(_) => serde_json::Value::Null,
+    }
+}
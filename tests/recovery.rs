@@ -0,0 +1,416 @@
+//! Killing a run at each phase of an update, and checking the next run picks up after it
+//!
+//! The run that's killed is this test binary again, running `child` in the same home, stopped
+//! where `HANG` or a stand-in command says. Its state file is watched for the phase.
+
+mod common;
+
+use common::{server, tarball_url, Home, Request, Route, HANG, HUNG};
+use std::os::unix::net::UnixListener;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+use std::{env, fs};
+
+/// The version a child releases and updates to, set only in children
+const CHILD: &str = "DISCORD_UPDATE_TEST_CHILD";
+/// The child's extra arguments, separated by spaces
+const CHILD_ARGS: &str = "DISCORD_UPDATE_TEST_ARGS";
+
+/// Not a test of its own, a run for the other tests to kill
+#[test]
+fn child() {
+    let Ok(version) = env::var(CHILD) else {
+        return;
+    };
+    common::seams();
+    server().release(&version);
+    if common::hang() == Some(common::Hang::Download) {
+        let route = Route {
+            body: common::tarball(&version),
+            hang_after: Some(8192),
+            ..Route::default()
+        };
+        server().route(&tarball_url(&version), route);
+    }
+    let args = env::var(CHILD_ARGS).unwrap_or_default();
+    let args: Vec<_> = args.split_whitespace().collect();
+    let _ = common::run(&args);
+}
+
+/// Where the child is to stop
+enum Until {
+    /// Its stand-in, `HANG`, or command says it's there
+    Hung,
+    /// Anywhere in the phase will do
+    Phase,
+}
+
+/// Update to `version` in a child run with `extra` arguments, and kill it once its update is in
+/// `phase`
+fn kill_in(home: &Home, phase: &str, until: Until, version: &str, hang: &str, extra: &[&str]) {
+    kill(home, start_in(home, phase, until, version, hang, extra));
+}
+
+/// A child that's killed when dropped, so a failing test doesn't leave it hanging
+struct Running(Child);
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+/// `kill_in`, but leaving the child running once it's there
+fn start_in(
+    home: &Home,
+    phase: &str,
+    until: Until,
+    version: &str,
+    hang: &str,
+    extra: &[&str],
+) -> Running {
+    let mut child = Running(
+        Command::new(env::current_exe().unwrap())
+            .args(["--exact", "child", "--nocapture", "--test-threads=1"])
+            .env(CHILD, version)
+            .env(HANG, hang)
+            .env(CHILD_ARGS, extra.join(" "))
+            .stdout(Stdio::null())
+            .spawn()
+            .unwrap(),
+    );
+    let started = Instant::now();
+    loop {
+        let state = home.state();
+        let there = state["channels"]["stable"]["transaction"]["phase"] == phase
+            && (matches!(until, Until::Phase) || home.path().join(HUNG).exists());
+        if there {
+            break;
+        }
+        if let Some(status) = child.0.try_wait().unwrap() {
+            panic!("the child exited with {status} before getting to {phase}: {state}");
+        }
+        assert!(
+            started.elapsed() < Duration::from_secs(60),
+            "never got to {phase}: {state}"
+        );
+        std::thread::sleep(Duration::from_millis(20));
+    }
+    child
+}
+
+fn kill(home: &Home, child: Running) {
+    drop(child);
+    let _ = fs::remove_file(home.path().join(HUNG));
+}
+
+/// A stand-in command that waits for as long as the process that ran it lives
+fn hanging_command(home: &Home, name: &str) {
+    let hung = home.path().join(HUNG);
+    let script = format!(
+        ": > {}\nwhile kill -0 $PPID 2>/dev/null; do sleep 0.1; done\n",
+        hung.display()
+    );
+    home.fake_command(name, &script);
+}
+
+fn fetched(url: &str) -> bool {
+    server()
+        .requests()
+        .iter()
+        .any(|r| matches!(r, Request::Get(u) | Request::GetFrom(u, _) if u == url))
+}
+
+fn assert_finished(home: &Home, version: &str) {
+    assert_eq!(home.installed().as_deref(), Some(version));
+    assert!(home.leftovers().is_empty(), "{:?}", home.leftovers());
+    let state = home.state();
+    assert!(
+        state["channels"]["stable"]["transaction"].is_null(),
+        "{state}"
+    );
+    assert!(home
+        .install_path()
+        .join(format!("only-in-{version}"))
+        .exists());
+}
+
+#[test]
+fn killed_while_downloading() {
+    let home = Home::new();
+    kill_in(&home, "downloading", Until::Hung, "0.0.10", "download", &[]);
+    assert_eq!(home.installed(), None);
+
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    assert_finished(&home, "0.0.10");
+    // Started over
+    assert!(fetched(&tarball_url("0.0.10")));
+}
+
+#[test]
+fn killed_while_staged() {
+    let home = Home::new();
+    // Discord's running, so --when-closed holds on to what it staged
+    let config = home.path().join(".config/discord");
+    fs::create_dir_all(&config).unwrap();
+    let running = UnixListener::bind(config.join("SingletonSocket")).unwrap();
+    kill_in(
+        &home,
+        "staged",
+        Until::Phase,
+        "0.0.10",
+        "",
+        &["--when-closed"],
+    );
+
+    // Still running, it's kept for later
+    server().release("0.0.10");
+    home.run(&["--when-closed", "--when-closed-timeout", "1"])
+        .unwrap_err();
+    assert_eq!(home.installed(), None);
+    assert!(!fetched(&tarball_url("0.0.10")));
+    let state = home.state();
+    assert_eq!(
+        state["channels"]["stable"]["transaction"]["phase"],
+        "staged"
+    );
+
+    drop(running);
+    fs::remove_file(config.join("SingletonSocket")).unwrap();
+    home.run(&[]).unwrap();
+    assert_finished(&home, "0.0.10");
+    assert!(!fetched(&tarball_url("0.0.10")));
+}
+
+#[test]
+fn killed_while_staged_past_a_pin() {
+    let home = Home::new();
+    server().release("0.0.9");
+    home.run(&[]).unwrap();
+    let config = home.path().join(".config/discord");
+    fs::create_dir_all(&config).unwrap();
+    let running = UnixListener::bind(config.join("SingletonSocket")).unwrap();
+    kill_in(
+        &home,
+        "staged",
+        Until::Phase,
+        "0.0.10",
+        "",
+        &["--when-closed"],
+    );
+    drop(running);
+    fs::remove_file(config.join("SingletonSocket")).unwrap();
+
+    // The pin keeps what was staged from going in
+    home.run(&["pin", "--max", "0.0.9"]).unwrap();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    assert_eq!(home.installed().as_deref(), Some("0.0.9"));
+    let state = home.state();
+    assert_eq!(
+        state["channels"]["stable"]["transaction"]["phase"],
+        "staged"
+    );
+
+    home.run(&["unpin"]).unwrap();
+    home.run(&[]).unwrap();
+    assert_finished(&home, "0.0.10");
+    assert!(!fetched(&tarball_url("0.0.10")));
+}
+
+#[test]
+fn killed_while_swapping_before_the_swap() {
+    let home = Home::new();
+    kill_in(
+        &home,
+        "swapping",
+        Until::Hung,
+        "0.0.10",
+        "before-commit",
+        &[],
+    );
+    assert_eq!(home.installed(), None);
+
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    assert_finished(&home, "0.0.10");
+    assert!(!fetched(&tarball_url("0.0.10")));
+    assert!(home.path().join("bin/discord").is_symlink());
+}
+
+#[test]
+fn killed_while_swapping_after_the_swap() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    kill_in(
+        &home,
+        "swapping",
+        Until::Hung,
+        "0.0.11",
+        "after-commit",
+        &[],
+    );
+    assert_eq!(home.installed().as_deref(), Some("0.0.11"));
+
+    server().release("0.0.11");
+    home.run(&[]).unwrap();
+    assert_finished(&home, "0.0.11");
+    assert!(!home.install_path().join("only-in-0.0.10").exists());
+    assert!(!fetched(&tarball_url("0.0.11")));
+}
+
+#[test]
+fn killed_in_place_while_swapping() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    let in_place = ["--update-strategy", "in-place"];
+    kill_in(
+        &home,
+        "swapping",
+        Until::Hung,
+        "0.0.11",
+        "before-commit",
+        &in_place,
+    );
+
+    server().release("0.0.11");
+    home.run(&in_place).unwrap();
+    assert_finished(&home, "0.0.11");
+    assert!(!home.install_path().join("only-in-0.0.10").exists());
+    assert!(!fetched(&tarball_url("0.0.11")));
+}
+
+#[test]
+fn kept_while_discord_is_running() {
+    let home = Home::new();
+    let config = home.path().join(".config/discord");
+    fs::create_dir_all(&config).unwrap();
+    let _running = UnixListener::bind(config.join("SingletonSocket")).unwrap();
+    kill_in(
+        &home,
+        "staged",
+        Until::Phase,
+        "0.0.10",
+        "",
+        &["--when-closed"],
+    );
+
+    // A run that doesn't install leaves the copy where it is
+    server().release("0.0.10");
+    home.run(&["--notify-only"]).unwrap();
+    assert_eq!(home.installed(), None);
+    let state = home.state();
+    let transaction = &state["channels"]["stable"]["transaction"];
+    assert_eq!(transaction["phase"], "staged");
+    let staging = Path::new(transaction["staging"].as_str().unwrap());
+    assert!(staging.join("install").is_dir());
+    assert!(!fetched(&tarball_url("0.0.10")));
+}
+
+#[test]
+fn killed_in_place_once_its_download_is_gone() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    let in_place = ["--update-strategy", "in-place"];
+    kill_in(
+        &home,
+        "swapping",
+        Until::Hung,
+        "0.0.11",
+        "after-commit",
+        &in_place,
+    );
+    // The commit cleaned up the download, as a reboot clearing the temporary directory would
+    let state = home.state();
+    let staging = state["channels"]["stable"]["transaction"]["staging"]
+        .as_str()
+        .unwrap();
+    assert!(!Path::new(staging).exists());
+
+    // There's no telling what's in the install, so it's installed again
+    server().release("0.0.11");
+    home.run(&["--update-strategy", "in-place", "--auto-repair"])
+        .unwrap();
+    assert_finished(&home, "0.0.11");
+    assert!(fetched(&tarball_url("0.0.11")));
+}
+
+#[test]
+fn killed_once_swapped() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    // Stopped in the health check, which comes after the swap
+    hanging_command(&home, "ldd");
+    kill_in(&home, "swapped", Until::Hung, "0.0.11", "", &[]);
+    fs::remove_file(home.path().join("fake-bin/ldd")).unwrap();
+    assert_eq!(home.installed().as_deref(), Some("0.0.11"));
+    assert!(!home.leftovers().is_empty());
+
+    server().release("0.0.11");
+    home.run(&[]).unwrap();
+    assert_finished(&home, "0.0.11");
+    assert!(!fetched(&tarball_url("0.0.11")));
+}
+
+#[test]
+fn killed_while_integrating() {
+    let home = Home::new();
+    let args = ["--store-versions", "2"];
+    // Stopped writing the menu entry, once the store has it
+    hanging_command(&home, "update-desktop-database");
+    kill_in(&home, "integrating", Until::Hung, "0.0.10", "", &args);
+    fs::remove_file(home.path().join("fake-bin/update-desktop-database")).unwrap();
+    let state = home.state();
+    let transaction = &state["channels"]["stable"]["transaction"];
+    assert_eq!(
+        transaction["integrated"],
+        serde_json::json!(["keep-in-store"])
+    );
+
+    server().release("0.0.10");
+    home.run(&args).unwrap();
+    assert_finished(&home, "0.0.10");
+    let entry = home
+        .path()
+        .join(".local/share/applications/com.discordapp.Discord.desktop");
+    assert!(entry.exists());
+    assert!(home.path().join("bin/discord").is_symlink());
+}
+
+#[test]
+fn two_updates_at_once() {
+    let home = Home::new();
+    let first = start_in(
+        &home,
+        "swapping",
+        Until::Hung,
+        "0.0.10",
+        "before-commit",
+        &[],
+    );
+    server().release("0.0.10");
+    let error = home.run(&[]).unwrap_err().to_string();
+    assert!(error.contains("already updating"), "{error}");
+
+    // One that waits leaves the first one's update alone until it's gone
+    let second = std::thread::spawn(|| common::run(&["--wait"]).map_err(|e| e.to_string()));
+    std::thread::sleep(Duration::from_millis(500));
+    assert!(!second.is_finished());
+    assert_eq!(
+        home.state()["channels"]["stable"]["transaction"]["phase"],
+        "swapping"
+    );
+    assert_eq!(home.installed(), None);
+
+    kill(&home, first);
+    second.join().unwrap().unwrap();
+    assert_finished(&home, "0.0.10");
+    assert!(!fetched(&tarball_url("0.0.10")));
+}
@@ -0,0 +1,249 @@
+//! The whole check, download, extract, install, and link flow, against the mock server
+
+mod common;
+
+use common::{server, tarball, tarball_url, Home, Request, Route, API_URL};
+use discord_update::Outcome;
+use std::fs;
+
+#[test]
+fn fresh_install() {
+    let home = Home::new();
+    server().release("0.0.10");
+    assert_eq!(home.run(&[]).unwrap(), Outcome::Done);
+
+    assert_eq!(home.installed().as_deref(), Some("0.0.10"));
+    assert!(home.install_path().join("only-in-0.0.10").exists());
+    assert_eq!(common::commits(), ["0.0.10"]);
+    let link = fs::read_link(home.path().join("bin/discord")).unwrap();
+    assert_eq!(link, home.install_path().join("Discord"));
+    assert!(home.leftovers().is_empty(), "{:?}", home.leftovers());
+    assert!(home.state()["channels"]["stable"]["transaction"].is_null());
+}
+
+#[test]
+fn update_replaces_the_old_version() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    server().release("0.0.11");
+    home.run(&[]).unwrap();
+
+    assert_eq!(home.installed().as_deref(), Some("0.0.11"));
+    assert!(!home.install_path().join("only-in-0.0.10").exists());
+    assert_eq!(common::commits(), ["0.0.10", "0.0.11"]);
+    assert!(home.leftovers().is_empty(), "{:?}", home.leftovers());
+
+    // Nothing to do the second time
+    home.run(&[]).unwrap();
+    assert_eq!(common::commits(), ["0.0.10", "0.0.11"]);
+}
+
+#[test]
+fn ensure_says_whether_it_changed_anything() {
+    let home = Home::new();
+    server().release("0.0.10");
+    assert_eq!(home.run(&["--ensure", "latest"]).unwrap(), Outcome::Changed);
+    assert_eq!(home.run(&["--ensure", "latest"]).unwrap(), Outcome::Done);
+}
+
+#[test]
+fn resumes_a_download_that_broke_off() {
+    let home = Home::new();
+    server().release("0.0.10");
+    let url = tarball_url("0.0.10");
+    let body = tarball("0.0.10");
+    let route = Route {
+        md5: Some(common::md5(&body)),
+        break_after: Some(body.len() / 2),
+        body,
+        ..Route::default()
+    };
+    server().route(&url, route);
+    home.run(&[]).unwrap();
+
+    assert_eq!(home.installed().as_deref(), Some("0.0.10"));
+    let resumed = server().requests().into_iter().any(
+        |request| matches!(request, Request::GetFrom(ref u, offset) if *u == url && offset > 0),
+    );
+    assert!(resumed, "{:?}", server().requests());
+}
+
+#[test]
+fn backs_off_when_rate_limited() {
+    let home = Home::new();
+    let route = Route {
+        rate_limited: true,
+        ..Route::default()
+    };
+    server().route(API_URL, route);
+    let err = home.run(&[]).unwrap_err();
+    assert!(err.to_string().contains("rate limiting"), "{err}");
+    assert!(home.state()["channels"]["stable"]["backoff_until"].is_u64());
+
+    // Scheduled runs wait, without asking the API again
+    let asked = server().requests().len();
+    server().release("0.0.10");
+    assert_eq!(home.run(&[]).unwrap(), Outcome::Done);
+    assert_eq!(server().requests().len(), asked);
+    assert_eq!(home.installed(), None);
+}
+
+#[test]
+fn refuses_a_download_with_the_wrong_md5() {
+    let home = Home::new();
+    server().release("0.0.10");
+    let url = tarball_url("0.0.10");
+    let route = Route {
+        body: tarball("0.0.10"),
+        md5: Some([0; 16]),
+        ..Route::default()
+    };
+    server().route(&url, route);
+    let err = home.run(&[]).unwrap_err();
+    assert!(err.to_string().contains("damaged on the way"), "{err}");
+
+    assert!(!home.install_path().exists());
+    assert!(common::commits().is_empty());
+    assert!(home.leftovers().is_empty(), "{:?}", home.leftovers());
+}
+
+#[test]
+fn keeps_the_old_version_when_the_new_one_is_bad() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    server().release("0.0.11");
+    let route = Route {
+        body: b"not a tarball".to_vec(),
+        ..Route::default()
+    };
+    server().route(&tarball_url("0.0.11"), route);
+    assert!(home.run(&[]).is_err());
+
+    assert_eq!(home.installed().as_deref(), Some("0.0.10"));
+    assert!(home.leftovers().is_empty(), "{:?}", home.leftovers());
+}
+
+#[test]
+fn puts_back_a_launch_block_a_killed_run_left() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    let link = home.path().join("bin/discord");
+    let target = fs::read_link(&link).unwrap();
+    let script = home.path().join(".local/state/discord_update/updating.sh");
+    fs::remove_file(&link).unwrap();
+    std::os::unix::fs::symlink(&script, &link).unwrap();
+    let path = home.path().join(".local/state/discord_update/state.json");
+    let mut state = home.state();
+    state["channels"]["stable"]["launch_block"] =
+        serde_json::json!([{"symlink": {"link": link, "target": target}}]);
+    fs::write(&path, state.to_string()).unwrap();
+
+    home.run(&[]).unwrap();
+    assert_eq!(fs::read_link(&link).unwrap(), target);
+    assert_eq!(
+        home.state()["channels"]["stable"]["launch_block"],
+        serde_json::json!([])
+    );
+}
+
+#[test]
+fn rolls_back_a_damaged_install_with_no_version() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    fs::remove_file(home.install_path().join("resources/build_info.json")).unwrap();
+    let manifest = discord_update::manifest::metadata_dir(&home.install_path());
+    fs::remove_file(manifest.join("manifest.json")).unwrap();
+    // The new version fails its health check, needing a library that isn't there
+    home.fake_command("ldd", "echo '\tlibmissing.so => not found'\n");
+    server().release("0.0.11");
+
+    let err = home.run(&[]).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("rolled back to the previous install"),
+        "{err}"
+    );
+    assert!(home.install_path().join("only-in-0.0.10").exists());
+}
+
+#[test]
+fn doesnt_hold_the_lock_while_a_notification_waits() {
+    let home = Home::new();
+    server().release("0.0.10");
+    // Clicks install if it can take the lock itself, like a run started meanwhile could
+    let lock = home
+        .path()
+        .join(".local/state/discord_update/update-stable.lock");
+    let script = format!(
+        "case \"$*\" in *--wait*) flock -n {} true && echo install || echo later ;; esac\n",
+        lock.display()
+    );
+    home.fake_command("notify-send", &script);
+    home.run(&["--notify", "desktop", "--notify-only"]).unwrap();
+    assert_eq!(home.installed().as_deref(), Some("0.0.10"));
+}
+
+/// Leave a transaction behind as if a run was stopped while integrating `version`, having done
+/// the `integrated` steps
+fn interrupted_integrating(home: &Home, version: &str, integrated: &[&str]) {
+    let mut state = home.state();
+    state["channels"]["stable"]["transaction"] = serde_json::json!({
+        "version": version,
+        "install_path": home.install_path(),
+        "staging": home.path().join("tmp/discord_update-gone"),
+        "commit": "swap",
+        "phase": "integrating",
+        "time": 0,
+        "integrated": integrated,
+    });
+    let path = home.path().join(".local/state/discord_update/state.json");
+    fs::write(path, state.to_string()).unwrap();
+}
+
+#[test]
+fn finishes_integrating_what_an_interrupted_run_didnt() {
+    let home = Home::new();
+    server().release("0.0.10");
+    home.run(&[]).unwrap();
+    let entry = home
+        .path()
+        .join(".local/share/applications/com.discordapp.Discord.desktop");
+
+    fs::remove_file(&entry).unwrap();
+    interrupted_integrating(&home, "0.0.10", &[]);
+    home.run(&[]).unwrap();
+    assert!(entry.exists());
+    assert!(home.state()["channels"]["stable"]["transaction"].is_null());
+
+    // What it had done isn't done again
+    fs::remove_file(&entry).unwrap();
+    interrupted_integrating(&home, "0.0.10", &["desktop"]);
+    home.run(&[]).unwrap();
+    assert!(!entry.exists());
+    assert!(home.state()["channels"]["stable"]["transaction"].is_null());
+}
+
+#[test]
+fn kept_versions_share_the_files_they_have_in_common() {
+    let home = Home::new();
+    for version in ["0.0.10", "0.0.11", "0.0.12"] {
+        server().release(version);
+        home.run(&["--store-versions", "3"]).unwrap();
+    }
+
+    let store = home.path().join(".cache/discord_update/store");
+    assert_eq!(fs::read_dir(store.join("trees")).unwrap().count(), 3);
+    let objects: Vec<_> = fs::read_dir(store.join("objects"))
+        .unwrap()
+        .flat_map(|dir| fs::read_dir(dir.unwrap().path()).unwrap())
+        .collect();
+    // The binary and app.asar once, build_info.json and only-in-<version> for each version
+    assert_eq!(objects.len(), 2 + 2 * 3, "{objects:?}");
+    // And the install's copy is the stored one, not another
+    let asar = fs::metadata(home.install_path().join("resources/app.asar")).unwrap();
+    assert_eq!(std::os::unix::fs::MetadataExt::nlink(&asar), 2);
+}